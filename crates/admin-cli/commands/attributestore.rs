@@ -5,7 +5,7 @@ use clap::{Parser, Subcommand};
 use minerva::attribute_store::{
     load_attribute_store, load_attribute_store_from_file, AddAttributeStore, AttributeStore,
 };
-use minerva::change::Change;
+use minerva::audit::{apply_audited, AuditContext};
 use minerva::error::{Error, RuntimeError};
 
 use super::common::{connect_db, CmdResult};
@@ -54,7 +54,7 @@ async fn run_attribute_store_create_cmd(args: &AttributeStoreCreate) -> CmdResul
 
     let change = AddAttributeStore { attribute_store };
 
-    let result = change.apply(&mut client).await;
+    let result = apply_audited(&change, &mut client, &AuditContext::cli()).await;
 
     match result {
         Ok(_) => {
@@ -88,7 +88,8 @@ async fn run_attribute_store_update_cmd(args: &AttributeStoreUpdate) -> CmdResul
         println!("Updating attribute store");
 
         for change in changes {
-            let apply_result = change.apply(&mut client).await;
+            let apply_result =
+                apply_audited(change.as_ref(), &mut client, &AuditContext::cli()).await;
 
             match apply_result {
                 Ok(_) => {
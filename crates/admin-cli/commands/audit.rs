@@ -0,0 +1,170 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+
+use comfy_table::Table;
+
+use minerva::audit::{load_audit_record, load_audit_records, AuditFilter, AuditSource};
+use minerva::error::{Error, RuntimeError};
+
+use super::common::{connect_db, Cmd, CmdResult};
+
+fn parse_source(value: &str) -> Result<AuditSource, String> {
+    value.parse().map_err(|e| format!("{e}"))
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct AuditList {
+    #[arg(long, help = "only changes applied by this user")]
+    actor: Option<String>,
+    #[arg(
+        long,
+        help = "only changes applied through this tool (cli or service)",
+        value_parser = parse_source
+    )]
+    source: Option<AuditSource>,
+    #[arg(
+        long,
+        help = "only changes of which the description contains this text"
+    )]
+    change: Option<String>,
+    #[arg(long, help = "only changes that failed", conflicts_with = "succeeded")]
+    failed: bool,
+    #[arg(long, help = "only changes that succeeded")]
+    succeeded: bool,
+    #[arg(long, help = "only changes applied at or after this moment (RFC 3339)")]
+    since: Option<DateTime<Utc>>,
+    #[arg(long, help = "only changes applied before this moment (RFC 3339)")]
+    until: Option<DateTime<Utc>>,
+    #[arg(long, help = "maximum number of changes to show", default_value_t = 50)]
+    limit: i64,
+}
+
+#[async_trait]
+impl Cmd for AuditList {
+    async fn run(&self) -> CmdResult {
+        let mut client = connect_db().await?;
+
+        let success = match (self.failed, self.succeeded) {
+            (true, _) => Some(false),
+            (_, true) => Some(true),
+            _ => None,
+        };
+
+        let filter = AuditFilter {
+            actor: self.actor.clone(),
+            source: self.source,
+            change: self.change.clone(),
+            success,
+            since: self.since,
+            until: self.until,
+            limit: Some(self.limit),
+        };
+
+        let records = load_audit_records(&mut client, &filter).await?;
+
+        let mut table = Table::new();
+        let style = "     ═╪ ┆          ";
+        table.load_preset(style);
+        table.set_header(vec![
+            "Id",
+            "Timestamp",
+            "Actor",
+            "Source",
+            "Change",
+            "Result",
+        ]);
+
+        for record in records {
+            table.add_row(vec![
+                record.id.to_string(),
+                record.timestamp.to_rfc3339(),
+                record.actor,
+                record.source,
+                record.change,
+                if record.success { "ok" } else { "failed" }.to_string(),
+            ]);
+        }
+
+        println!("{table}");
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct AuditShow {
+    #[arg(help = "audit record Id")]
+    id: i64,
+}
+
+#[async_trait]
+impl Cmd for AuditShow {
+    async fn run(&self) -> CmdResult {
+        let mut client = connect_db().await?;
+
+        let record = load_audit_record(&mut client, self.id)
+            .await?
+            .ok_or_else(|| {
+                Error::Runtime(RuntimeError::from_msg(format!(
+                    "No audit record with Id {}",
+                    self.id
+                )))
+            })?;
+
+        let mut table = Table::new();
+        let style = "     ═╪ ┆          ";
+        table.load_preset(style);
+
+        table.add_row(vec!["Id".to_string(), record.id.to_string()]);
+        table.add_row(vec!["Timestamp".to_string(), record.timestamp.to_rfc3339()]);
+        table.add_row(vec!["Actor".to_string(), record.actor]);
+        table.add_row(vec!["Database user".to_string(), record.db_user]);
+        table.add_row(vec!["Source".to_string(), record.source]);
+        table.add_row(vec!["Change".to_string(), record.change]);
+        table.add_row(vec![
+            "Result".to_string(),
+            if record.success { "ok" } else { "failed" }.to_string(),
+        ]);
+        table.add_row(vec![
+            "Message".to_string(),
+            record.message.unwrap_or_default(),
+        ]);
+
+        println!("{table}");
+
+        if let Some(payload) = record.payload {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&payload).map_err(|e| {
+                    RuntimeError::from_msg(format!("Could not format change payload: {e}"))
+                })?
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct AuditOpt {
+    #[command(subcommand)]
+    command: AuditOptCommands,
+}
+
+#[derive(Debug, Subcommand, PartialEq)]
+pub enum AuditOptCommands {
+    #[command(about = "list applied changes, most recent first")]
+    List(AuditList),
+    #[command(about = "show the details and payload of an applied change")]
+    Show(AuditShow),
+}
+
+impl AuditOpt {
+    pub async fn run(&self) -> CmdResult {
+        match &self.command {
+            AuditOptCommands::List(list) => list.run().await,
+            AuditOptCommands::Show(show) => show.run().await,
+        }
+    }
+}
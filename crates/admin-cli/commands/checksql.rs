@@ -0,0 +1,159 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use chrono::{DateTime, DurationRound, Utc};
+use clap::{Parser, ValueHint};
+use glob::glob;
+
+use minerva::error::{ConfigurationError, Error, RuntimeError};
+use minerva::sql_check::{check_materialization_sql, check_trigger_sql, SqlCheckIssue};
+use minerva::trend_materialization::trend_materialization_from_config;
+use minerva::trigger::load_trigger_from_file;
+use minerva::trigger_template::load_triggers_from_template_file;
+
+use super::common::{connect_db, Cmd, CmdResult, ENV_MINERVA_INSTANCE_ROOT};
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct CheckSqlOpt {
+    #[arg(long = "instance-root", help = "Minerva instance root directory", value_hint = ValueHint::DirPath)]
+    instance_root: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "sample timestamp used for planning the queries (default: start of the current hour)"
+    )]
+    timestamp: Option<DateTime<Utc>>,
+    #[arg(help = "materialization or trigger definition files (default: all definitions of the instance)", value_hint = ValueHint::FilePath)]
+    definitions: Vec<PathBuf>,
+}
+
+enum Definition {
+    Materialization(Box<minerva::trend_materialization::TrendMaterialization>),
+    Triggers(Vec<minerva::trigger::Trigger>),
+}
+
+fn load_definition(path: &PathBuf) -> Result<Definition, Error> {
+    match trend_materialization_from_config(path) {
+        Ok(materialization) => Ok(Definition::Materialization(Box::new(materialization))),
+        Err(materialization_error) => match load_trigger_from_file(path) {
+            Ok(trigger) => Ok(Definition::Triggers(vec![trigger])),
+            Err(trigger_error) => match load_triggers_from_template_file(path) {
+                Ok(triggers) => Ok(Definition::Triggers(triggers)),
+                Err(template_error) => Err(Error::Configuration(ConfigurationError::from_msg(
+                    format!(
+                        "Not a materialization ({materialization_error}), trigger ({trigger_error}) or trigger template ({template_error}) definition"
+                    ),
+                ))),
+            },
+        },
+    }
+}
+
+fn instance_definitions(instance_root: &Path) -> Vec<PathBuf> {
+    [
+        "materialization/*.yaml",
+        "trigger/*.yaml",
+        "trigger/*.json",
+        "trigger-template/*.yaml",
+    ]
+        .iter()
+        .flat_map(|pattern| {
+            let glob_path = format!("{}/{}", instance_root.to_string_lossy(), pattern);
+
+            glob(&glob_path)
+                .expect("Failed to read glob pattern")
+                .filter_map(Result::ok)
+                .collect::<Vec<PathBuf>>()
+        })
+        .collect()
+}
+
+#[async_trait]
+impl Cmd for CheckSqlOpt {
+    async fn run(&self) -> CmdResult {
+        let definitions = if self.definitions.is_empty() {
+            let instance_root = match &self.instance_root {
+                Some(root) => root.clone(),
+                None => match env::var(ENV_MINERVA_INSTANCE_ROOT) {
+                    Ok(v) => PathBuf::from(v),
+                    Err(e) => {
+                        return Err(Error::Configuration(ConfigurationError {
+                            msg: format!(
+                                "No definition files specified and environment variable '{}' could not be read: {}",
+                                &ENV_MINERVA_INSTANCE_ROOT, e
+                            ),
+                        }));
+                    }
+                },
+            };
+
+            instance_definitions(&instance_root)
+        } else {
+            self.definitions.clone()
+        };
+
+        let timestamp = match self.timestamp {
+            Some(timestamp) => timestamp,
+            None => Utc::now()
+                .duration_trunc(chrono::Duration::hours(1))
+                .unwrap(),
+        };
+
+        let mut client = connect_db().await?;
+
+        println!(
+            "Checking SQL for sample timestamp {}",
+            timestamp.to_rfc3339()
+        );
+
+        let mut error_count: usize = 0;
+        let mut warning_count: usize = 0;
+
+        for path in definitions {
+            let issues = match load_definition(&path) {
+                Ok(Definition::Materialization(materialization)) => {
+                    check_materialization_sql(&mut client, &materialization, &timestamp).await?
+                }
+                Ok(Definition::Triggers(triggers)) => {
+                    let mut issues = Vec::new();
+
+                    for trigger in triggers {
+                        issues.extend(check_trigger_sql(&mut client, &trigger, &timestamp).await?);
+                    }
+
+                    issues
+                }
+                Err(e) => vec![SqlCheckIssue::Error {
+                    object: "definition".to_string(),
+                    message: e.to_string(),
+                }],
+            };
+
+            if issues.is_empty() {
+                println!("{}: OK", path.display());
+            } else {
+                println!("{}:", path.display());
+
+                for issue in issues {
+                    if issue.is_error() {
+                        error_count += 1;
+                    } else {
+                        warning_count += 1;
+                    }
+
+                    println!("  {issue}");
+                }
+            }
+        }
+
+        if error_count > 0 {
+            return Err(Error::Runtime(RuntimeError::from_msg(format!(
+                "SQL check failed: {error_count} error(s), {warning_count} warning(s)"
+            ))));
+        }
+
+        println!("SQL check passed: {warning_count} warning(s)");
+
+        Ok(())
+    }
+}
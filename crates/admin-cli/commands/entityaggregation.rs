@@ -0,0 +1,115 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use clap::{Parser, Subcommand, ValueHint};
+
+use minerva::audit::{generic_apply_audited, AuditContext};
+use minerva::changes::trend_store::AddTrendStore;
+use minerva::entity_aggregation::load_entity_aggregation_from_file;
+use minerva::error::{Error, RuntimeError};
+use minerva::trend_materialization::AddTrendMaterialization;
+use minerva::trend_store::load_trend_stores;
+
+use super::common::{connect_db, Cmd, CmdResult};
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct EntityAggregationGenerate {
+    #[arg(help = "entity aggregation definition file", value_hint = ValueHint::FilePath)]
+    definition: PathBuf,
+}
+
+#[async_trait]
+impl Cmd for EntityAggregationGenerate {
+    async fn run(&self) -> CmdResult {
+        let entity_aggregation = load_entity_aggregation_from_file(&self.definition)?;
+
+        let mut client = connect_db().await?;
+
+        let trend_stores = load_trend_stores(&mut client).await?;
+
+        let (trend_store, materializations) = entity_aggregation.generate(&trend_stores)?;
+
+        println!("{}", trend_store.dump()?);
+
+        for materialization in materializations {
+            println!("---");
+            println!("{}", materialization.dump()?);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct EntityAggregationCreate {
+    #[arg(help = "entity aggregation definition file", value_hint = ValueHint::FilePath)]
+    definition: PathBuf,
+}
+
+#[async_trait]
+impl Cmd for EntityAggregationCreate {
+    async fn run(&self) -> CmdResult {
+        let entity_aggregation = load_entity_aggregation_from_file(&self.definition)?;
+
+        println!("Loaded definition, creating entity aggregation");
+        let mut client = connect_db().await?;
+
+        let trend_stores = load_trend_stores(&mut client).await?;
+
+        let (trend_store, materializations) = entity_aggregation.generate(&trend_stores)?;
+
+        let mut transaction = client.transaction().await?;
+
+        let change = AddTrendStore { trend_store };
+
+        let message = generic_apply_audited(&change, &mut transaction, &AuditContext::cli())
+            .await
+            .map_err(|e| {
+                Error::Runtime(RuntimeError {
+                    msg: format!("Error creating target trend store: {e}"),
+                })
+            })?;
+
+        println!("{message}");
+
+        for trend_materialization in materializations {
+            let change = AddTrendMaterialization {
+                trend_materialization,
+            };
+
+            let message =
+                generic_apply_audited(&change, &mut transaction, &AuditContext::cli()).await?;
+
+            println!("{message}");
+        }
+
+        transaction.commit().await?;
+
+        println!("Created entity aggregation '{}'", &entity_aggregation.name);
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct EntityAggregationOpt {
+    #[command(subcommand)]
+    command: EntityAggregationOptCommands,
+}
+
+#[derive(Debug, Subcommand, PartialEq)]
+pub enum EntityAggregationOptCommands {
+    #[command(about = "show the trend store and materializations generated for an entity aggregation")]
+    Generate(EntityAggregationGenerate),
+    #[command(about = "create the trend store and materializations of an entity aggregation")]
+    Create(EntityAggregationCreate),
+}
+
+impl EntityAggregationOpt {
+    pub async fn run(&self) -> CmdResult {
+        match &self.command {
+            EntityAggregationOptCommands::Generate(generate) => generate.run().await,
+            EntityAggregationOptCommands::Create(create) => create.run().await,
+        }
+    }
+}
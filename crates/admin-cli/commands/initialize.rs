@@ -6,6 +6,7 @@ use std::path::PathBuf;
 use async_trait::async_trait;
 use clap::Parser;
 
+use minerva::audit::AuditContext;
 use minerva::database::{create_database, ClusterConfig};
 use minerva::error::{ConfigurationError, Error};
 use minerva::instance::MinervaInstance;
@@ -119,7 +120,7 @@ impl Cmd for InitializeOpt {
             std::env::set_var(&ENV_MINERVA_INSTANCE_ROOT, &root);
 
             MinervaInstance::load_from(&root)
-                .initialize(&mut client)
+                .initialize(&mut client, &AuditContext::cli())
                 .await;
         }
 
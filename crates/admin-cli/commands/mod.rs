@@ -1,12 +1,17 @@
 pub mod attributestore;
+pub mod audit;
+pub mod checksql;
 pub mod common;
 pub mod diff;
 pub mod dump;
+pub mod entityaggregation;
 pub mod initialize;
 pub mod loaddata;
+pub mod notification;
 pub mod trendmaterialization;
 pub mod trendstore;
 pub mod trigger;
+pub mod triggerexception;
 pub mod update;
 pub mod schema;
 pub mod relation;
@@ -0,0 +1,321 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use clap::{Parser, Subcommand, ValueHint};
+
+use comfy_table::Table;
+
+use minerva::audit::{apply_audited, AuditContext};
+use minerva::error::{Error, RuntimeError};
+use minerva::notification_forward::{load_forwarder_config, ForwardResult, NotificationForwarder};
+use minerva::notification_incident::{
+    load_incident, load_incidents, AcknowledgeIncident, AssignIncident, CloseIncident,
+    IncidentFilter, IncidentStatus,
+};
+
+use super::common::{connect_db, Cmd, CmdResult};
+
+fn parse_status(value: &str) -> Result<IncidentStatus, String> {
+    value.parse().map_err(|e| format!("{e}"))
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct NotificationList {
+    #[arg(long, help = "only incidents of this trigger")]
+    trigger: Option<String>,
+    #[arg(long, help = "only incidents of this entity")]
+    entity: Option<String>,
+    #[arg(
+        long,
+        help = "only incidents with this status (open, acknowledged or closed)",
+        value_parser = parse_status
+    )]
+    status: Option<IncidentStatus>,
+}
+
+#[async_trait]
+impl Cmd for NotificationList {
+    async fn run(&self) -> CmdResult {
+        let mut client = connect_db().await?;
+
+        let filter = IncidentFilter {
+            trigger: self.trigger.clone(),
+            entity: self.entity.clone(),
+            status: self.status,
+        };
+
+        let incidents = load_incidents(&mut client, &filter).await?;
+
+        let mut table = Table::new();
+        let style = "     ═╪ ┆          ";
+        table.load_preset(style);
+        table.set_header(vec![
+            "Id", "Trigger", "Entity", "First", "Last", "Count", "Status", "Assignee",
+        ]);
+
+        for incident in incidents {
+            table.add_row(vec![
+                incident.id.to_string(),
+                incident.trigger,
+                incident
+                    .entity
+                    .unwrap_or_else(|| incident.entity_id.to_string()),
+                incident.first_timestamp.to_rfc3339(),
+                incident.last_timestamp.to_rfc3339(),
+                incident.notification_count.to_string(),
+                incident.status.to_string(),
+                incident.assignee.unwrap_or_default(),
+            ]);
+        }
+
+        println!("{table}");
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct NotificationShow {
+    #[arg(help = "incident Id")]
+    id: i32,
+}
+
+#[async_trait]
+impl Cmd for NotificationShow {
+    async fn run(&self) -> CmdResult {
+        let mut client = connect_db().await?;
+
+        let incident = load_incident(&mut client, self.id).await?;
+
+        let mut table = Table::new();
+        let style = "     ═╪ ┆          ";
+        table.load_preset(style);
+
+        table.add_row(vec!["Id".to_string(), incident.id.to_string()]);
+        table.add_row(vec!["Trigger".to_string(), incident.trigger]);
+        table.add_row(vec![
+            "Entity".to_string(),
+            incident
+                .entity
+                .unwrap_or_else(|| incident.entity_id.to_string()),
+        ]);
+        table.add_row(vec![
+            "First".to_string(),
+            incident.first_timestamp.to_rfc3339(),
+        ]);
+        table.add_row(vec![
+            "Last".to_string(),
+            incident.last_timestamp.to_rfc3339(),
+        ]);
+        table.add_row(vec![
+            "Count".to_string(),
+            incident.notification_count.to_string(),
+        ]);
+        table.add_row(vec!["Status".to_string(), incident.status.to_string()]);
+        table.add_row(vec![
+            "Assignee".to_string(),
+            incident.assignee.unwrap_or_default(),
+        ]);
+        table.add_row(vec![
+            "Comment".to_string(),
+            incident.comment.unwrap_or_default(),
+        ]);
+        table.add_row(vec!["Created".to_string(), incident.created.to_rfc3339()]);
+        table.add_row(vec!["Modified".to_string(), incident.modified.to_rfc3339()]);
+        table.add_row(vec![
+            "Closed".to_string(),
+            incident
+                .closed
+                .map(|closed| closed.to_rfc3339())
+                .unwrap_or_default(),
+        ]);
+
+        println!("{table}");
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct NotificationAcknowledge {
+    #[arg(help = "incident Id")]
+    id: i32,
+    #[arg(long, help = "person or team that handles the incident")]
+    assignee: Option<String>,
+    #[arg(long, help = "comment on the incident")]
+    comment: Option<String>,
+}
+
+#[async_trait]
+impl Cmd for NotificationAcknowledge {
+    async fn run(&self) -> CmdResult {
+        let mut client = connect_db().await?;
+
+        let change = AcknowledgeIncident {
+            id: self.id,
+            assignee: self.assignee.clone(),
+            comment: self.comment.clone(),
+        };
+
+        let message = apply_audited(&change, &mut client, &AuditContext::cli()).await?;
+
+        println!("{message}");
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct NotificationAssign {
+    #[arg(help = "incident Id")]
+    id: i32,
+    #[arg(help = "person or team that handles the incident")]
+    assignee: String,
+    #[arg(long, help = "comment on the incident")]
+    comment: Option<String>,
+}
+
+#[async_trait]
+impl Cmd for NotificationAssign {
+    async fn run(&self) -> CmdResult {
+        let mut client = connect_db().await?;
+
+        let change = AssignIncident {
+            id: self.id,
+            assignee: self.assignee.clone(),
+            comment: self.comment.clone(),
+        };
+
+        let message = apply_audited(&change, &mut client, &AuditContext::cli()).await?;
+
+        println!("{message}");
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct NotificationClose {
+    #[arg(help = "incident Id")]
+    id: i32,
+    #[arg(long, help = "comment on the incident")]
+    comment: Option<String>,
+}
+
+#[async_trait]
+impl Cmd for NotificationClose {
+    async fn run(&self) -> CmdResult {
+        let mut client = connect_db().await?;
+
+        let change = CloseIncident {
+            id: self.id,
+            comment: self.comment.clone(),
+        };
+
+        let message = apply_audited(&change, &mut client, &AuditContext::cli()).await?;
+
+        println!("{message}");
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct NotificationForward {
+    #[arg(
+        long,
+        help = "file with the definition of the sinks",
+        value_hint = ValueHint::FilePath
+    )]
+    config: PathBuf,
+    #[arg(
+        long,
+        help = "time between polls of the notification stores",
+        value_parser = humantime::parse_duration,
+        default_value = "10s"
+    )]
+    interval: Duration,
+    #[arg(long, help = "forward the pending notifications once and exit")]
+    once: bool,
+}
+
+#[async_trait]
+impl Cmd for NotificationForward {
+    async fn run(&self) -> CmdResult {
+        let config = load_forwarder_config(&self.config)?;
+
+        let forwarder = NotificationForwarder::new(config);
+
+        let mut client = connect_db().await?;
+
+        loop {
+            let results = forwarder.forward(&mut client).await;
+
+            for result in results.iter().filter(|result| {
+                !matches!(
+                    result,
+                    ForwardResult::Forwarded {
+                        forwarded: 0,
+                        skipped: 0,
+                        ..
+                    }
+                )
+            }) {
+                println!("{result}");
+            }
+
+            if self.once {
+                let failed_count = results
+                    .iter()
+                    .filter(|result| matches!(result, ForwardResult::Failed { .. }))
+                    .count();
+
+                if failed_count > 0 {
+                    return Err(Error::Runtime(RuntimeError::from_msg(format!(
+                        "Forwarding failed for {failed_count} sink(s)"
+                    ))));
+                }
+
+                return Ok(());
+            }
+
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct NotificationOpt {
+    #[command(subcommand)]
+    command: NotificationOptCommands,
+}
+
+#[derive(Debug, Subcommand, PartialEq)]
+pub enum NotificationOptCommands {
+    #[command(about = "list notification incidents")]
+    List(NotificationList),
+    #[command(about = "show the details of a notification incident")]
+    Show(NotificationShow),
+    #[command(about = "acknowledge a notification incident")]
+    Acknowledge(NotificationAcknowledge),
+    #[command(about = "assign a notification incident")]
+    Assign(NotificationAssign),
+    #[command(about = "close a notification incident")]
+    Close(NotificationClose),
+    #[command(about = "forward notifications to webhooks, files or syslog")]
+    Forward(NotificationForward),
+}
+
+impl NotificationOpt {
+    pub async fn run(&self) -> CmdResult {
+        match &self.command {
+            NotificationOptCommands::List(list) => list.run().await,
+            NotificationOptCommands::Show(show) => show.run().await,
+            NotificationOptCommands::Acknowledge(acknowledge) => acknowledge.run().await,
+            NotificationOptCommands::Assign(assign) => assign.run().await,
+            NotificationOptCommands::Close(close) => close.run().await,
+            NotificationOptCommands::Forward(forward) => forward.run().await,
+        }
+    }
+}
@@ -2,10 +2,10 @@ use std::path::PathBuf;
 
 use async_trait::async_trait;
 
+use minerva::audit::{apply_audited, AuditContext};
 use minerva::relation::{
     AddRelation, load_relation_from_file,
 };
-use minerva::change::Change;
 
 use clap::{Parser, Subcommand};
 
@@ -30,7 +30,7 @@ impl Cmd for RelationCreate {
             relation
         };
 
-        let message = change.apply(&mut client).await?;
+        let message = apply_audited(&change, &mut client, &AuditContext::cli()).await?;
 
         println!("{message}");
 
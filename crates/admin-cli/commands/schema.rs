@@ -1,17 +1,39 @@
 use async_trait::async_trait;
 use clap::Parser;
 
-use minerva::schema::schema;
+use minerva::schema::{schema, upgrade_schema, SCHEMA_VERSION};
 
-use super::common::{Cmd, CmdResult};
+use super::common::{connect_db, Cmd, CmdResult};
 
 #[derive(Debug, Parser, PartialEq)]
-pub struct SchemaOpt {}
+pub struct SchemaOpt {
+    #[arg(
+        long,
+        help = "upgrade the schema of the connected database instead of showing the definition"
+    )]
+    upgrade: bool,
+}
 
 #[async_trait]
 impl Cmd for SchemaOpt {
     async fn run(&self) -> CmdResult {
-        print!("{}", schema());
+        if !self.upgrade {
+            print!("{}", schema());
+
+            return Ok(());
+        }
+
+        let mut client = connect_db().await?;
+
+        let applied = upgrade_schema(&mut client).await?;
+
+        if applied.is_empty() {
+            println!("Schema is already at version {SCHEMA_VERSION}");
+        }
+
+        for version in applied {
+            println!("Upgraded schema to version {version}");
+        }
 
         Ok(())
     }
@@ -1,17 +1,24 @@
 use std::path::PathBuf;
 
+use std::time::Duration;
+
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use clap::{Parser, Subcommand, ValueHint};
+use comfy_table::Table;
 
-use minerva::change::GenericChange;
-use minerva::error::{Error, RuntimeError};
+use minerva::audit::{generic_apply_audited, AuditContext};
+use minerva::error::{ConfigurationError, Error, RuntimeError};
+use minerva::instance::MinervaInstance;
+use minerva::materialization_test::test_materialization;
 use minerva::trend_materialization::{self, TrendMaterialization};
 use minerva::trend_materialization::{
     reset_source_fingerprint, populate_source_fingerprint, trend_materialization_from_config, AddTrendMaterialization,
-    UpdateTrendMaterialization, load_materializations,
+    UpdateTrendMaterialization, load_materializations, load_materialization_metrics,
+    EnableTrendMaterialization, DisableTrendMaterialization,
 };
 
-use super::common::{connect_db, Cmd, CmdResult};
+use super::common::{connect_db, Cmd, CmdResult, ENV_MINERVA_INSTANCE_ROOT};
 
 #[derive(Debug, Parser, PartialEq)]
 pub struct TrendMaterializationCreate {
@@ -34,7 +41,7 @@ impl Cmd for TrendMaterializationCreate {
             trend_materialization,
         };
 
-        let result = change.generic_apply(&mut transaction).await;
+        let result = generic_apply_audited(&change, &mut transaction, &AuditContext::cli()).await;
 
         transaction.commit().await?;
 
@@ -71,7 +78,7 @@ impl Cmd for TrendMaterializationUpdate {
             trend_materialization,
         };
 
-        let result = change.generic_apply(&mut transaction).await;
+        let result = generic_apply_audited(&change, &mut transaction, &AuditContext::cli()).await;
 
         transaction.commit().await?;
 
@@ -166,6 +173,76 @@ impl Cmd for TrendMaterializationDump {
     }
 }
 
+#[derive(Debug, Parser, PartialEq)]
+pub struct TrendMaterializationEnable {
+    #[arg(help = "materialization name")]
+    name: String,
+}
+
+#[async_trait]
+impl Cmd for TrendMaterializationEnable {
+    async fn run(&self) -> CmdResult {
+        let mut client = connect_db().await?;
+
+        let change = EnableTrendMaterialization {
+            name: self.name.clone(),
+        };
+
+        let message = generic_apply_audited(&change, &mut client, &AuditContext::cli()).await?;
+
+        println!("{message}");
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct TrendMaterializationDisable {
+    #[arg(help = "materialization name")]
+    name: String,
+    #[arg(long, help = "reason for disabling the materialization")]
+    reason: Option<String>,
+    #[arg(
+        long = "for",
+        help = "enable the materialization again after this period (e.g. '4h')",
+        value_parser = humantime::parse_duration,
+        conflicts_with = "until"
+    )]
+    period: Option<Duration>,
+    #[arg(long, help = "enable the materialization again after this moment (RFC 3339)")]
+    until: Option<DateTime<Utc>>,
+}
+
+#[async_trait]
+impl Cmd for TrendMaterializationDisable {
+    async fn run(&self) -> CmdResult {
+        let expires = match (self.period, self.until) {
+            (Some(period), _) => {
+                let period = chrono::Duration::from_std(period).map_err(|e| {
+                    ConfigurationError::from_msg(format!("Invalid disable period: {e}"))
+                })?;
+
+                Some(Utc::now() + period)
+            }
+            (None, until) => until,
+        };
+
+        let mut client = connect_db().await?;
+
+        let change = DisableTrendMaterialization {
+            name: self.name.clone(),
+            reason: self.reason.clone(),
+            expires,
+        };
+
+        let message = generic_apply_audited(&change, &mut client, &AuditContext::cli()).await?;
+
+        println!("{message}");
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Parser, PartialEq)]
 pub struct TrendMaterializationList {
 }
@@ -192,6 +269,135 @@ impl Cmd for TrendMaterializationList {
     }
 }
 
+#[derive(Debug, Parser, PartialEq)]
+pub struct TrendMaterializationStats {
+    #[arg(
+        long = "window",
+        help = "time window to report on, ending now",
+        default_value = "1d",
+        value_parser = humantime::parse_duration
+    )]
+    window: Duration,
+}
+
+#[async_trait]
+impl Cmd for TrendMaterializationStats {
+    async fn run(&self) -> CmdResult {
+        let mut client = connect_db().await?;
+
+        let to = Utc::now();
+        let from = to
+            - chrono::Duration::from_std(self.window).map_err(|e| {
+                Error::Runtime(RuntimeError::from_msg(format!("Invalid window: {e}")))
+            })?;
+
+        let metrics = load_materialization_metrics(&mut client, &from, &to).await?;
+
+        let mut table = Table::new();
+        let style = "     ═╪ ┆          ";
+        table.load_preset(style);
+        table.set_header(vec![
+            "Materialization",
+            "Enabled",
+            "Executions",
+            "Duration",
+            "Avg Duration",
+            "Max Duration",
+            "Rows",
+            "Failures",
+            "Total Executions",
+            "Total Duration",
+        ]);
+
+        for metric in metrics {
+            table.add_row(vec![
+                metric.materialization,
+                metric.enabled.to_string(),
+                metric.execution_count.to_string(),
+                format_duration_millis(metric.duration),
+                format_duration_millis(metric.average_duration),
+                format_duration_millis(metric.max_duration),
+                metric.rows.to_string(),
+                metric.failures.to_string(),
+                metric.total_execution_count.to_string(),
+                format_duration_millis(metric.total_duration),
+            ]);
+        }
+
+        println!("{table}");
+
+        Ok(())
+    }
+}
+
+/// Format a duration rounded to milliseconds, because the full precision is just noise in a report
+fn format_duration_millis(duration: Duration) -> String {
+    humantime::format_duration(Duration::from_millis(duration.as_millis() as u64)).to_string()
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct TrendMaterializationTest {
+    #[arg(help = "trend materialization definition file", value_hint = ValueHint::FilePath)]
+    definition: PathBuf,
+    #[arg(
+        long = "fixtures",
+        help = "directory with fixture.yaml, fixture data and expected data",
+        value_hint = ValueHint::DirPath
+    )]
+    fixtures: PathBuf,
+    #[arg(long = "instance-root", help = "Minerva instance definition root directory")]
+    instance_root: Option<PathBuf>,
+    #[arg(long = "keep-database", help = "keep the scratch database after the test")]
+    keep_database: bool,
+}
+
+#[async_trait]
+impl Cmd for TrendMaterializationTest {
+    async fn run(&self) -> CmdResult {
+        let trend_materialization = trend_materialization_from_config(&self.definition)?;
+
+        let instance_root = match &self.instance_root {
+            Some(root) => root.clone(),
+            None => std::env::var(ENV_MINERVA_INSTANCE_ROOT)
+                .map(PathBuf::from)
+                .map_err(|_| {
+                    ConfigurationError::from_msg(format!(
+                        "No instance root specified and {ENV_MINERVA_INSTANCE_ROOT} is not set"
+                    ))
+                })?,
+        };
+
+        let instance = MinervaInstance::load_from(&instance_root);
+
+        let mut client = connect_db().await?;
+
+        let differences = test_materialization(
+            &mut client,
+            &instance,
+            &trend_materialization,
+            &self.fixtures,
+            self.keep_database,
+        )
+        .await?;
+
+        if differences.is_empty() {
+            println!("Materialization '{}' produced the expected data", trend_materialization.name());
+
+            return Ok(());
+        }
+
+        for difference in &differences {
+            println!("{difference}");
+        }
+
+        Err(Error::Runtime(RuntimeError::from_msg(format!(
+            "Materialization '{}' test failed with {} differences",
+            trend_materialization.name(),
+            differences.len()
+        ))))
+    }
+}
+
 #[derive(Debug, Parser, PartialEq)]
 pub struct TrendMaterializationOpt {
     #[command(subcommand)]
@@ -212,6 +418,14 @@ pub enum TrendMaterializationOptCommand {
     Dump(TrendMaterializationDump),
     #[command(about = "list trend materializations")]
     List(TrendMaterializationList),
+    #[command(about = "show execution statistics of trend materializations")]
+    Stats(TrendMaterializationStats),
+    #[command(about = "test a trend materialization against fixture data")]
+    Test(TrendMaterializationTest),
+    #[command(about = "enable a trend materialization")]
+    Enable(TrendMaterializationEnable),
+    #[command(about = "disable a trend materialization, optionally for a limited period")]
+    Disable(TrendMaterializationDisable),
 }
 
 impl TrendMaterializationOpt {
@@ -235,6 +449,18 @@ impl TrendMaterializationOpt {
             Some(TrendMaterializationOptCommand::List(list)) => {
                 list.run().await
             }
+            Some(TrendMaterializationOptCommand::Stats(stats)) => {
+                stats.run().await
+            }
+            Some(TrendMaterializationOptCommand::Test(test)) => {
+                test.run().await
+            }
+            Some(TrendMaterializationOptCommand::Enable(enable)) => {
+                enable.run().await
+            }
+            Some(TrendMaterializationOptCommand::Disable(disable)) => {
+                disable.run().await
+            }
             None => Ok(())
         }
     }
@@ -18,7 +18,7 @@ use term_table::{
     Table, TableStyle,
 };
 
-use minerva::change::Change;
+use minerva::audit::{apply_audited, AuditContext};
 use minerva::changes::trend_store::AddTrendStore;
 use minerva::error::{Error, RuntimeError};
 use minerva::trend_store::{
@@ -50,7 +50,7 @@ impl Cmd for TrendStoreCreate {
 
         let change = AddTrendStore { trend_store };
 
-        change.apply(&mut client).await?;
+        apply_audited(&change, &mut client, &AuditContext::cli()).await?;
 
         println!("Created trend store");
 
@@ -142,7 +142,12 @@ impl Cmd for TrendStoreUpdate {
                                     })
                                 })?
                         {
-                            let apply_result = change.apply(&mut client).await;
+                            let apply_result = apply_audited(
+                                change.as_ref(),
+                                &mut client,
+                                &AuditContext::cli(),
+                            )
+                            .await;
 
                             match apply_result {
                                 Ok(_) => {
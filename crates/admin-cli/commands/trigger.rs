@@ -1,32 +1,64 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use async_trait::async_trait;
-use chrono::{DateTime, Local};
-use clap::{Parser, Subcommand};
+use chrono::{DateTime, Local, Utc};
+use chrono_tz::Tz;
+use clap::{Parser, Subcommand, ValueHint};
 
 use comfy_table::Table;
 
-use minerva::change::Change;
-use minerva::error::{DatabaseError, Error, RuntimeError};
+use minerva::audit::{apply_audited, AuditContext};
+use minerva::error::{ConfigurationError, DatabaseError, Error, RuntimeError};
 use minerva::trigger::{
-    dump_trigger, get_notifications, list_triggers, load_trigger, load_trigger_from_file,
-    AddTrigger, CreateNotifications, DeleteTrigger, DisableTrigger, EnableTrigger, RenameTrigger,
+    dump_trigger, get_notifications, load_trigger, load_trigger_from_file, AddTrigger,
+    CreateNotifications, DeleteTrigger, DisableTrigger, EnableTrigger, RenameTrigger, Trigger,
     UpdateTrigger, VerifyTrigger,
 };
+use minerva::trigger_backtest::{backtest_trigger, BacktestReport};
+use minerva::trigger_runner::{run_enabled_triggers, TriggerRunResult};
+use minerva::trigger_status::{load_trigger_status, TriggerStatusFilter, MAX_PERIODS};
+use minerva::trigger_template::load_triggers_from_template_file;
+use minerva::trigger_test::test_trigger;
 
 use super::common::{connect_db, Cmd, CmdResult};
+use super::triggerexception::{parse_threshold_override, TriggerExceptionOpt};
 
 #[derive(Debug, Parser, PartialEq)]
-pub struct TriggerList {}
+pub struct TriggerList {
+    #[arg(long, help = "only triggers with this tag")]
+    tag: Option<String>,
+    #[arg(long, help = "only enabled triggers", conflicts_with = "disabled")]
+    enabled: bool,
+    #[arg(long, help = "only disabled triggers")]
+    disabled: bool,
+    #[arg(
+        long,
+        help = "number of recent periods to count notifications for",
+        default_value_t = 4,
+        value_parser = clap::value_parser!(u32).range(..=MAX_PERIODS as i64)
+    )]
+    periods: u32,
+}
 
 #[async_trait]
 impl Cmd for TriggerList {
     async fn run(&self) -> CmdResult {
         let mut client = connect_db().await?;
 
-        let triggers = list_triggers(&mut client)
-            .await
-            .map_err(|e| DatabaseError::from_msg(format!("Error listing triggers: {e}")))?;
+        let filter = TriggerStatusFilter {
+            tag: self.tag.clone(),
+            enabled: match (self.enabled, self.disabled) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            },
+            ids: None,
+        };
+
+        let triggers = load_trigger_status(&mut client, &filter, &Utc::now(), self.periods).await?;
 
         let mut table = Table::new();
         let style = "     ═╪ ┆          ";
@@ -37,14 +69,37 @@ impl Cmd for TriggerList {
             "Granularity",
             "Default Interval",
             "Enabled",
+            "Tags",
+            "Trend Store Parts",
+            "Last Evaluated",
+            "Last Notification",
+            "Notifications",
         ]);
         for trigger in triggers {
             table.add_row(vec![
-                trigger.0,
-                trigger.1,
-                trigger.2,
-                trigger.3,
-                trigger.5.to_string(),
+                trigger.name,
+                trigger
+                    .notification_store
+                    .unwrap_or("UNDEFINED".to_string()),
+                trigger.granularity.unwrap_or("UNDEFINED".to_string()),
+                trigger.default_interval.unwrap_or("UNDEFINED".to_string()),
+                trigger.enabled.to_string(),
+                trigger.tags.join(", "),
+                trigger.trend_store_parts.join("\n"),
+                trigger
+                    .last_evaluated
+                    .map(|timestamp| timestamp.to_rfc3339())
+                    .unwrap_or_default(),
+                trigger
+                    .last_notification
+                    .map(|timestamp| timestamp.to_rfc3339())
+                    .unwrap_or_default(),
+                trigger
+                    .notification_counts
+                    .iter()
+                    .map(|count| count.count.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" "),
             ]);
         }
 
@@ -83,7 +138,7 @@ impl Cmd for TriggerCreate {
             enable: self.enable,
         };
 
-        let message = change.apply(&mut client).await?;
+        let message = apply_audited(&change, &mut client, &AuditContext::cli()).await?;
 
         println!("{message}");
 
@@ -106,7 +161,7 @@ impl Cmd for TriggerDelete {
             trigger_name: self.name.clone(),
         };
 
-        change.apply(&mut client).await?;
+        apply_audited(&change, &mut client, &AuditContext::cli()).await?;
 
         println!("Deleted trigger '{}'", &self.name);
 
@@ -138,7 +193,7 @@ impl Cmd for TriggerUpdate {
             verify: self.verify,
         };
 
-        let message = change.apply(&mut client).await?;
+        let message = apply_audited(&change, &mut client, &AuditContext::cli()).await?;
 
         println!("{message}");
 
@@ -180,7 +235,7 @@ impl Cmd for TriggerRename {
             old_name: self.old_name.clone(),
         };
 
-        let message = change.apply(&mut client).await?;
+        let message = apply_audited(&change, &mut client, &AuditContext::cli()).await?;
 
         println!("{message}");
 
@@ -203,7 +258,7 @@ impl Cmd for TriggerVerify {
             trigger_name: self.name.clone(),
         };
 
-        let message = change.apply(&mut client).await?;
+        let message = apply_audited(&change, &mut client, &AuditContext::cli()).await?;
 
         println!("{message}");
 
@@ -226,7 +281,7 @@ impl Cmd for TriggerEnable {
             trigger_name: self.name.clone(),
         };
 
-        let message = change.apply(&mut client).await?;
+        let message = apply_audited(&change, &mut client, &AuditContext::cli()).await?;
 
         println!("{message}");
 
@@ -249,7 +304,7 @@ impl Cmd for TriggerDisable {
             trigger_name: self.name.clone(),
         };
 
-        let message = change.apply(&mut client).await?;
+        let message = apply_audited(&change, &mut client, &AuditContext::cli()).await?;
 
         println!("{message}");
 
@@ -312,7 +367,7 @@ impl Cmd for TriggerCreateNotifications {
             timestamp: self.timestamp,
         };
 
-        let message = change.apply(&mut client).await?;
+        let message = apply_audited(&change, &mut client, &AuditContext::cli()).await?;
 
         println!("{message}");
 
@@ -320,18 +375,409 @@ impl Cmd for TriggerCreateNotifications {
     }
 }
 
+/// Load a trigger definition file, or one of the triggers of a trigger template file
+fn load_definition(definition: &PathBuf, template_instance: Option<&str>) -> Result<Trigger, Error> {
+    match template_instance {
+        None => load_trigger_from_file(definition),
+        Some(name) => load_triggers_from_template_file(definition)?
+            .into_iter()
+            .find(|trigger| trigger.name == name)
+            .ok_or_else(|| {
+                Error::Configuration(ConfigurationError::from_msg(format!(
+                    "Trigger template '{}' has no instance named '{}'",
+                    definition.display(),
+                    name
+                )))
+            }),
+    }
+}
+
 #[derive(Debug, Parser, PartialEq)]
-pub struct TriggerDump {
+pub struct TriggerDiff {
+    #[arg(help = "trigger definition file", value_hint = ValueHint::FilePath)]
+    definition: PathBuf,
+    #[arg(
+        long = "template-instance",
+        help = "treat the definition as a trigger template and compare the expanded trigger with this name"
+    )]
+    template_instance: Option<String>,
+    #[arg(long = "apply", help = "apply the changes")]
+    apply: bool,
+}
+
+#[async_trait]
+impl Cmd for TriggerDiff {
+    async fn run(&self) -> CmdResult {
+        let trigger = load_definition(&self.definition, self.template_instance.as_deref())?;
+
+        let mut client = connect_db().await?;
+
+        let current = load_trigger(&mut client, &trigger.name).await?;
+
+        let differences = current.differences(&trigger);
+
+        if differences.is_empty() {
+            println!("Trigger '{}' is up-to-date", &trigger.name);
+
+            return Ok(());
+        }
+
+        println!("Differences:");
+
+        for difference in &differences {
+            println!("* {difference}");
+        }
+
+        println!("Changes:");
+
+        for change in current.diff(&trigger) {
+            println!("* {change}");
+
+            if self.apply {
+                let message =
+                    apply_audited(change.as_ref(), &mut client, &AuditContext::cli()).await?;
+
+                println!("> {message}");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct TriggerTest {
+    #[arg(help = "trigger definition file", value_hint = ValueHint::FilePath)]
+    definition: PathBuf,
+    #[arg(
+        long = "fixtures",
+        help = "directory with fixture.yaml and fixture data",
+        value_hint = ValueHint::DirPath
+    )]
+    fixtures: PathBuf,
+    #[arg(
+        long = "template-instance",
+        help = "treat the definition as a trigger template and test the expanded trigger with this name"
+    )]
+    template_instance: Option<String>,
+}
+
+#[async_trait]
+impl Cmd for TriggerTest {
+    async fn run(&self) -> CmdResult {
+        let trigger = load_definition(&self.definition, self.template_instance.as_deref())?;
+
+        let mut client = connect_db().await?;
+
+        let differences = test_trigger(&mut client, &trigger, &self.fixtures).await?;
+
+        if differences.is_empty() {
+            println!("Trigger '{}' produced the expected notifications", &trigger.name);
+
+            return Ok(());
+        }
+
+        for difference in &differences {
+            println!("{difference}");
+        }
+
+        Err(Error::Runtime(RuntimeError::from_msg(format!(
+            "Trigger '{}' test failed with {} differences",
+            &trigger.name,
+            differences.len()
+        ))))
+    }
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct TriggerBacktest {
     #[arg(help = "trigger name")]
     name: String,
+    #[arg(long, help = "first timestamp of the range")]
+    from: DateTime<Local>,
+    #[arg(long, help = "last timestamp of the range")]
+    to: DateTime<Local>,
+    #[arg(
+        short,
+        long = "threshold",
+        help = "proposed threshold value as NAME=VALUE to compare with the current thresholds",
+        value_parser = parse_threshold_override
+    )]
+    thresholds: Vec<(String, String)>,
+}
+
+/// Build a table with a count column for the current and, if available, the proposed thresholds
+fn backtest_table<K: Ord + Clone + Display>(
+    key_header: &str,
+    keys: Vec<K>,
+    current: &BTreeMap<K, usize>,
+    proposed: Option<&BTreeMap<K, usize>>,
+) -> Table {
+    let mut table = Table::new();
+    let style = "     ═╪ ┆          ";
+    table.load_preset(style);
+
+    match proposed {
+        Some(_) => table.set_header(vec![key_header, "Current", "Proposed"]),
+        None => table.set_header(vec![key_header, "Count"]),
+    };
+
+    for key in keys {
+        let mut row = vec![
+            key.to_string(),
+            current.get(&key).copied().unwrap_or_default().to_string(),
+        ];
+
+        if let Some(proposed) = proposed {
+            row.push(proposed.get(&key).copied().unwrap_or_default().to_string());
+        }
+
+        table.add_row(row);
+    }
+
+    table
+}
+
+#[async_trait]
+impl Cmd for TriggerBacktest {
+    async fn run(&self) -> CmdResult {
+        let mut client = connect_db().await?;
+
+        let proposed_thresholds: BTreeMap<String, String> =
+            self.thresholds.iter().cloned().collect();
+
+        let result = backtest_trigger(
+            &mut client,
+            &self.name,
+            &self.from.with_timezone(&Utc),
+            &self.to.with_timezone(&Utc),
+            &proposed_thresholds,
+        )
+        .await?;
+
+        let current = &result.current;
+        let proposed: Option<&BacktestReport> = result.proposed.as_ref();
+
+        println!("Notifications per timestamp:");
+        println!(
+            "{}",
+            backtest_table(
+                "Timestamp",
+                result.timestamps.clone(),
+                &current.per_timestamp,
+                proposed.map(|report| &report.per_timestamp),
+            )
+        );
+
+        let mut entities: Vec<String> = current
+            .per_entity
+            .keys()
+            .chain(proposed.iter().flat_map(|report| report.per_entity.keys()))
+            .cloned()
+            .collect::<BTreeSet<String>>()
+            .into_iter()
+            .collect();
+
+        entities.sort_by_key(|entity| {
+            std::cmp::Reverse(current.per_entity.get(entity).copied().unwrap_or_default())
+        });
+
+        println!("Notifications per entity:");
+        println!(
+            "{}",
+            backtest_table(
+                "Entity",
+                entities,
+                &current.per_entity,
+                proposed.map(|report| &report.per_entity),
+            )
+        );
+
+        let weights: Vec<i32> = current
+            .weights
+            .keys()
+            .chain(proposed.iter().flat_map(|report| report.weights.keys()))
+            .copied()
+            .collect::<BTreeSet<i32>>()
+            .into_iter()
+            .collect();
+
+        println!("Weight distribution:");
+        println!(
+            "{}",
+            backtest_table(
+                "Weight",
+                weights,
+                &current.weights,
+                proposed.map(|report| &report.weights),
+            )
+        );
+
+        match proposed {
+            Some(proposed) => println!(
+                "Total notifications over {} timestamps: {} current, {} proposed",
+                result.timestamps.len(),
+                current.total,
+                proposed.total
+            ),
+            None => println!(
+                "Total notifications over {} timestamps: {}",
+                result.timestamps.len(),
+                current.total
+            ),
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct TriggerRun {
+    #[arg(
+        long,
+        help = "time between runs",
+        value_parser = humantime::parse_duration,
+        default_value = "1m"
+    )]
+    interval: Duration,
+    #[arg(
+        long = "catch-up",
+        help = "how far back to look for timestamps that have not been processed yet",
+        value_parser = humantime::parse_duration,
+        default_value = "6h"
+    )]
+    catch_up: Duration,
+    #[arg(long, help = "run once and exit instead of running continuously")]
+    once: bool,
+    #[arg(
+        long,
+        help = "timezone to align periods in, like 'Europe/Amsterdam' (default: the timezone of the database)",
+        value_parser = parse_timezone
+    )]
+    timezone: Option<Tz>,
+}
+
+fn parse_timezone(value: &str) -> Result<Tz, String> {
+    value
+        .parse::<Tz>()
+        .map_err(|e| format!("Invalid timezone '{value}': {e}"))
+}
+
+/// Timezone of the database session, in which Minerva aligns periods
+async fn database_timezone(client: &mut tokio_postgres::Client) -> Result<Tz, Error> {
+    let row = client
+        .query_one("SELECT current_setting('TimeZone')", &[])
+        .await
+        .map_err(|e| {
+            DatabaseError::from_msg(format!("Could not determine database timezone: {e}"))
+        })?;
+
+    let name: String = row.get(0);
+
+    name.parse::<Tz>().map_err(|e| {
+        Error::Configuration(ConfigurationError::from_msg(format!(
+            "Unsupported database timezone '{name}', specify one with --timezone: {e}"
+        )))
+    })
+}
+
+/// Longest time to wait before trying again after a failed run
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+#[async_trait]
+impl Cmd for TriggerRun {
+    async fn run(&self) -> CmdResult {
+        let mut client = connect_db().await?;
+        let mut retry_delay = Duration::from_secs(1);
+
+        let timezone = match self.timezone {
+            Some(timezone) => timezone,
+            None => database_timezone(&mut client).await?,
+        };
+
+        loop {
+            let now = Utc::now().with_timezone(&timezone);
+
+            let results = match run_enabled_triggers(&mut client, &now, self.catch_up).await {
+                Ok(results) => results,
+                Err(e) if self.once => return Err(e),
+                Err(e) => {
+                    // Most likely the database connection was lost, so keep trying to
+                    // reconnect instead of stopping the runner
+                    eprintln!("Could not run triggers, retrying in {retry_delay:?}: {e}");
+
+                    tokio::time::sleep(retry_delay).await;
+                    retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+
+                    if client.is_closed() {
+                        match connect_db().await {
+                            Ok(new_client) => client = new_client,
+                            Err(e) => eprintln!("Could not reconnect: {e}"),
+                        }
+                    }
+
+                    continue;
+                }
+            };
+
+            retry_delay = Duration::from_secs(1);
+
+            for result in results
+                .iter()
+                .filter(|result| !matches!(result, TriggerRunResult::Unchanged { .. }))
+            {
+                println!("{result}");
+            }
+
+            if self.once {
+                let failed_count = results
+                    .iter()
+                    .filter(|result| matches!(result, TriggerRunResult::Failed { .. }))
+                    .count();
+
+                if failed_count > 0 {
+                    return Err(Error::Runtime(RuntimeError::from_msg(format!(
+                        "Notification creation failed for {failed_count} trigger timestamp(s)"
+                    ))));
+                }
+
+                return Ok(());
+            }
+
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct TriggerDump {
+    #[arg(help = "trigger name", required_unless_present = "template")]
+    name: Option<String>,
+    #[arg(
+        long,
+        help = "dump the triggers expanded from a trigger template file instead",
+        value_hint = ValueHint::FilePath,
+        conflicts_with = "name"
+    )]
+    template: Option<PathBuf>,
 }
 
 #[async_trait]
 impl Cmd for TriggerDump {
     async fn run(&self) -> CmdResult {
+        if let Some(template) = &self.template {
+            let triggers = load_triggers_from_template_file(template)?;
+
+            for trigger in triggers {
+                println!("{}", dump_trigger(&trigger));
+            }
+
+            return Ok(());
+        }
+
         let mut client = connect_db().await?;
 
-        let trigger = load_trigger(&mut client, &self.name).await?;
+        let trigger = load_trigger(&mut client, self.name.as_deref().unwrap_or_default()).await?;
 
         let trigger_definition = dump_trigger(&trigger);
 
@@ -371,6 +817,16 @@ pub enum TriggerOptCommands {
     PreviewNotifications(TriggerPreviewNotifications),
     #[command(about = "create notifications of a trigger")]
     CreateNotifications(TriggerCreateNotifications),
+    #[command(about = "evaluate a trigger over a range of timestamps without creating notifications")]
+    Backtest(TriggerBacktest),
+    #[command(about = "show the differences between a trigger definition and the database")]
+    Diff(TriggerDiff),
+    #[command(about = "test the notifications of a trigger against fixture data")]
+    Test(TriggerTest),
+    #[command(about = "continuously create notifications for all enabled triggers")]
+    Run(TriggerRun),
+    #[command(about = "manage per-entity threshold exceptions of a trigger")]
+    Exception(TriggerExceptionOpt),
 }
 
 impl TriggerOpt {
@@ -391,6 +847,11 @@ impl TriggerOpt {
             TriggerOptCommands::CreateNotifications(create_notifications) => {
                 create_notifications.run().await
             }
+            TriggerOptCommands::Backtest(backtest) => backtest.run().await,
+            TriggerOptCommands::Diff(diff) => diff.run().await,
+            TriggerOptCommands::Test(test) => test.run().await,
+            TriggerOptCommands::Run(run) => run.run().await,
+            TriggerOptCommands::Exception(exception) => exception.run().await,
         }
     }
 }
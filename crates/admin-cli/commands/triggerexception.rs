@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand, ValueHint};
+
+use comfy_table::Table;
+
+use minerva::audit::{generic_apply_audited, AuditContext};
+use minerva::error::{ConfigurationError, Error};
+use minerva::trigger_exception::{
+    load_threshold_exceptions, AddThresholdException, RemoveThresholdExceptions, ThresholdException,
+};
+
+use super::common::{connect_db, Cmd, CmdResult};
+
+pub(super) fn parse_threshold_override(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((name, value)) => Ok((name.trim().to_string(), value.trim().to_string())),
+        None => Err(format!("expected NAME=VALUE, got '{value}'")),
+    }
+}
+
+fn load_exceptions_from_file(path: &PathBuf) -> Result<Vec<ThresholdException>, Error> {
+    let f = std::fs::File::open(path).map_err(|e| {
+        ConfigurationError::from_msg(format!(
+            "Could not open exceptions file '{}': {}",
+            path.display(),
+            e
+        ))
+    })?;
+
+    serde_yaml::from_reader(f).map_err(|e| {
+        Error::Configuration(ConfigurationError::from_msg(format!(
+            "Could not read exceptions from file '{}': {}",
+            path.display(),
+            e
+        )))
+    })
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct TriggerExceptionAdd {
+    #[arg(help = "trigger name")]
+    trigger: String,
+    #[arg(help = "name of the entity", required_unless_present = "file")]
+    entity: Option<String>,
+    #[arg(
+        short,
+        long = "threshold",
+        help = "threshold override as NAME=VALUE",
+        value_parser = parse_threshold_override
+    )]
+    thresholds: Vec<(String, String)>,
+    #[arg(long, help = "start of the exception (RFC 3339)")]
+    start: Option<DateTime<Utc>>,
+    #[arg(long, help = "expiry of the exception (RFC 3339)")]
+    expires: Option<DateTime<Utc>>,
+    #[arg(long, help = "remark explaining the exception")]
+    remark: Option<String>,
+    #[arg(
+        long,
+        help = "YAML file with a list of exceptions",
+        value_hint = ValueHint::FilePath,
+        conflicts_with_all = ["entity", "thresholds", "start", "expires", "remark"]
+    )]
+    file: Option<PathBuf>,
+}
+
+#[async_trait]
+impl Cmd for TriggerExceptionAdd {
+    async fn run(&self) -> CmdResult {
+        let exceptions = match (&self.file, &self.entity) {
+            (Some(file), _) => load_exceptions_from_file(file)?,
+            (None, Some(entity)) => vec![ThresholdException {
+                entity: entity.clone(),
+                thresholds: self.thresholds.iter().cloned().collect::<BTreeMap<_, _>>(),
+                start: self.start,
+                expires: self.expires,
+                remark: self.remark.clone(),
+            }],
+            (None, None) => Vec::new(),
+        };
+
+        let mut client = connect_db().await?;
+
+        let mut transaction = client.transaction().await?;
+
+        for exception in exceptions {
+            let change = AddThresholdException {
+                trigger_name: self.trigger.clone(),
+                exception,
+            };
+
+            let message =
+                generic_apply_audited(&change, &mut transaction, &AuditContext::cli()).await?;
+
+            println!("{message}");
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct TriggerExceptionList {
+    #[arg(help = "trigger name")]
+    trigger: String,
+}
+
+#[async_trait]
+impl Cmd for TriggerExceptionList {
+    async fn run(&self) -> CmdResult {
+        let mut client = connect_db().await?;
+
+        let exceptions = load_threshold_exceptions(&mut client, &self.trigger).await?;
+
+        let mut table = Table::new();
+        let style = "     ═╪ ┆          ";
+        table.load_preset(style);
+        table.set_header(vec![
+            "Id",
+            "Entity",
+            "Thresholds",
+            "Start",
+            "Expires",
+            "Remark",
+        ]);
+
+        for record in exceptions {
+            let exception = record.exception;
+
+            table.add_row(vec![
+                record.id.to_string(),
+                exception.entity,
+                exception
+                    .thresholds
+                    .iter()
+                    .map(|(name, value)| format!("{name}={value}"))
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                exception
+                    .start
+                    .map(|start| start.to_rfc3339())
+                    .unwrap_or_default(),
+                exception
+                    .expires
+                    .map(|expires| expires.to_rfc3339())
+                    .unwrap_or_default(),
+                exception.remark.unwrap_or_default(),
+            ]);
+        }
+
+        println!("{table}");
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct TriggerExceptionRemove {
+    #[arg(help = "trigger name")]
+    trigger: String,
+    #[arg(help = "name of the entity")]
+    entity: String,
+}
+
+#[async_trait]
+impl Cmd for TriggerExceptionRemove {
+    async fn run(&self) -> CmdResult {
+        let mut client = connect_db().await?;
+
+        let change = RemoveThresholdExceptions {
+            trigger_name: self.trigger.clone(),
+            entity: self.entity.clone(),
+        };
+
+        let message = generic_apply_audited(&change, &mut client, &AuditContext::cli()).await?;
+
+        println!("{message}");
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser, PartialEq)]
+pub struct TriggerExceptionOpt {
+    #[command(subcommand)]
+    command: TriggerExceptionOptCommands,
+}
+
+#[derive(Debug, Subcommand, PartialEq)]
+pub enum TriggerExceptionOptCommands {
+    #[command(about = "add threshold exceptions for an entity or from a file")]
+    Add(TriggerExceptionAdd),
+    #[command(about = "list the threshold exceptions of a trigger")]
+    List(TriggerExceptionList),
+    #[command(about = "remove the threshold exceptions of an entity")]
+    Remove(TriggerExceptionRemove),
+}
+
+impl TriggerExceptionOpt {
+    pub async fn run(&self) -> CmdResult {
+        match &self.command {
+            TriggerExceptionOptCommands::Add(add) => add.run().await,
+            TriggerExceptionOptCommands::List(list) => list.run().await,
+            TriggerExceptionOptCommands::Remove(remove) => remove.run().await,
+        }
+    }
+}
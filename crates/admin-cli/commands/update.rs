@@ -9,6 +9,7 @@ use clap::Parser;
 
 use tokio_postgres::Client;
 
+use minerva::audit::{apply_audited, AuditContext};
 use minerva::error::{ConfigurationError, Error, RuntimeError};
 use minerva::instance::MinervaInstance;
 
@@ -95,7 +96,7 @@ async fn update(
                     })
                 })?
         {
-            match change.apply(client).await {
+            match apply_audited(change.as_ref(), client, &AuditContext::cli()).await {
                 Ok(message) => println!("> {}", &message),
                 Err(err) => println!("! Error applying change: {}", &err),
             }
@@ -5,12 +5,16 @@ use clap_complete::{generate, Generator, Shell};
 pub mod commands;
 
 use crate::commands::attributestore::AttributeStoreOpt;
+use crate::commands::audit::AuditOpt;
+use crate::commands::checksql::CheckSqlOpt;
 use crate::commands::common::Cmd;
 use crate::commands::diff::DiffOpt;
 use crate::commands::schema::SchemaOpt;
 use crate::commands::dump::DumpOpt;
+use crate::commands::entityaggregation::EntityAggregationOpt;
 use crate::commands::initialize::InitializeOpt;
 use crate::commands::loaddata::LoadDataOpt;
+use crate::commands::notification::NotificationOpt;
 use crate::commands::trendmaterialization::TrendMaterializationOpt;
 use crate::commands::trendstore::TrendStoreOpt;
 use crate::commands::trigger::TriggerOpt;
@@ -42,6 +46,8 @@ enum Commands {
     TrendStore(TrendStoreOpt),
     #[command(about = "Manage triggers")]
     Trigger(TriggerOpt),
+    #[command(about = "Manage the lifecycle of trigger notifications")]
+    Notification(NotificationOpt),
     #[command(about = "Manage attribute stores")]
     AttributeStore(AttributeStoreOpt),
     #[command(about = "Manage trend materrializations")]
@@ -50,6 +56,12 @@ enum Commands {
     LoadData(LoadDataOpt),
     #[command(about = "Manage relations")]
     Relation(RelationOpt),
+    #[command(about = "Manage entity aggregations")]
+    EntityAggregation(EntityAggregationOpt),
+    #[command(about = "Check the SQL of materialization and trigger definitions against a database")]
+    CheckSql(CheckSqlOpt),
+    #[command(about = "Show the log of changes applied to the Minerva database")]
+    Audit(AuditOpt),
 }
 
 fn print_completions<G: Generator>(gen: G, cmd: &mut Command) {
@@ -75,14 +87,20 @@ async fn main() {
         Some(Commands::Initialize(initialize)) => initialize.run().await,
         Some(Commands::TrendStore(trend_store)) => trend_store.run().await,
         Some(Commands::Trigger(trigger)) => trigger.run().await,
+        Some(Commands::Notification(notification)) => notification.run().await,
         Some(Commands::AttributeStore(attribute_store)) => attribute_store.run().await,
         Some(Commands::TrendMaterialization(trend_materialization)) => trend_materialization.run().await,
         Some(Commands::LoadData(load_data)) => load_data.run().await,
         Some(Commands::Relation(relation)) => relation.run().await,
+        Some(Commands::EntityAggregation(entity_aggregation)) => entity_aggregation.run().await,
+        Some(Commands::CheckSql(check_sql)) => check_sql.run().await,
+        Some(Commands::Audit(audit)) => audit.run().await,
         None => return
     };
 
     if let Err(e) = result {
         println!("{e}");
+
+        std::process::exit(1);
     }
 }
@@ -0,0 +1,229 @@
+use deadpool_postgres::Pool;
+use std::ops::DerefMut;
+
+use actix_web::{delete, get, post, put, web::Data, web::Path, HttpResponse};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use minerva::attribute_store::{
+    load_attribute_store, load_attribute_stores, AddAttributeStore, Attribute, AttributeStore,
+    DeleteAttributeStore,
+};
+use minerva::audit::apply_audited;
+use minerva::meas_value::DataType;
+
+use super::auth::{AdminAccess, ReadAccess};
+use super::serviceerror::{ServiceError, ServiceErrorKind};
+use crate::error::Success;
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct AttributeData {
+    name: String,
+    data_type: String,
+    #[serde(default)]
+    description: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct AttributeStoreData {
+    data_source: String,
+    entity_type: String,
+    attributes: Vec<AttributeData>,
+}
+
+impl From<AttributeStore> for AttributeStoreData {
+    fn from(attribute_store: AttributeStore) -> Self {
+        AttributeStoreData {
+            data_source: attribute_store.data_source,
+            entity_type: attribute_store.entity_type,
+            attributes: attribute_store
+                .attributes
+                .into_iter()
+                .map(|attribute| AttributeData {
+                    name: attribute.name,
+                    data_type: attribute.data_type.to_string(),
+                    description: attribute.description,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl AttributeStoreData {
+    fn as_minerva(&self) -> AttributeStore {
+        AttributeStore {
+            data_source: self.data_source.clone(),
+            entity_type: self.entity_type.clone(),
+            attributes: self
+                .attributes
+                .iter()
+                .map(|attribute| Attribute {
+                    name: attribute.name.clone(),
+                    data_type: DataType::from(attribute.data_type.as_str()),
+                    description: attribute.description.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+fn parse_attribute_store_data(post: &str) -> Result<AttributeStoreData, ServiceError> {
+    serde_json::from_str(post).map_err(|e| ServiceError {
+        kind: ServiceErrorKind::BadRequest,
+        message: e.to_string(),
+    })
+}
+
+#[utoipa::path(
+    get,
+    path="/attribute-stores",
+    responses(
+    (status = 200, description = "List of existing attribute stores", body = [AttributeStoreData]),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
+    )
+)]
+#[get("/attribute-stores")]
+pub(super) async fn get_attribute_stores(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let attribute_stores: Vec<AttributeStoreData> = load_attribute_stores(client)
+        .await?
+        .into_iter()
+        .map(AttributeStoreData::from)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(attribute_stores))
+}
+
+#[utoipa::path(
+    get,
+    path="/attribute-stores/{data_source}/{entity_type}",
+    responses(
+    (status = 200, description = "Attribute store", body = AttributeStoreData),
+    (status = 404, description = "Attribute store not found", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
+    )
+)]
+#[get("/attribute-stores/{data_source}/{entity_type}")]
+pub(super) async fn get_attribute_store(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+    args: Path<(String, String)>,
+) -> Result<HttpResponse, ServiceError> {
+    let (data_source, entity_type) = args.into_inner();
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let attribute_store = load_attribute_store(client, &data_source, &entity_type).await?;
+
+    Ok(HttpResponse::Ok().json(AttributeStoreData::from(attribute_store)))
+}
+
+#[utoipa::path(
+    post,
+    path="/attribute-stores",
+    request_body = AttributeStoreData,
+    responses(
+    (status = 200, description = "Created attribute store", body = Success),
+    (status = 400, description = "Input format incorrect", body = ProblemDetails),
+    (status = 500, description = "Attribute store could not be created", body = ProblemDetails),
+    )
+)]
+#[post("/attribute-stores")]
+pub(super) async fn post_attribute_store(
+    access: AdminAccess,
+    pool: Data<Pool>,
+    post: String,
+) -> Result<HttpResponse, ServiceError> {
+    let data = parse_attribute_store_data(&post)?;
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let change = AddAttributeStore {
+        attribute_store: data.as_minerva(),
+    };
+
+    let message = apply_audited(&change, client, &access.audit_context()).await?;
+
+    Ok(HttpResponse::Ok().json(Success { code: 200, message }))
+}
+
+/// Update an attribute store by adding new attributes and changing the data type of existing
+/// attributes. Attributes that are not in the definition are kept.
+#[utoipa::path(
+    put,
+    path="/attribute-stores",
+    request_body = AttributeStoreData,
+    responses(
+    (status = 200, description = "Updated attribute store", body = [Success]),
+    (status = 400, description = "Input format incorrect", body = ProblemDetails),
+    (status = 404, description = "Attribute store not found", body = ProblemDetails),
+    (status = 500, description = "Attribute store could not be updated", body = ProblemDetails),
+    )
+)]
+#[put("/attribute-stores")]
+pub(super) async fn update_attribute_store(
+    access: AdminAccess,
+    pool: Data<Pool>,
+    post: String,
+) -> Result<HttpResponse, ServiceError> {
+    let data = parse_attribute_store_data(&post)?;
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let current = load_attribute_store(client, &data.data_source, &data.entity_type).await?;
+
+    let context = access.audit_context();
+    let mut results: Vec<Success> = Vec::new();
+
+    for change in current.diff(&data.as_minerva()) {
+        let message = apply_audited(change.as_ref(), client, &context).await?;
+
+        results.push(Success { code: 200, message });
+    }
+
+    Ok(HttpResponse::Ok().json(results))
+}
+
+#[utoipa::path(
+    delete,
+    path="/attribute-stores/{data_source}/{entity_type}",
+    responses(
+    (status = 200, description = "Deleted attribute store", body = Success),
+    (status = 404, description = "Attribute store not found", body = ProblemDetails),
+    (status = 500, description = "Attribute store could not be deleted", body = ProblemDetails),
+    )
+)]
+#[delete("/attribute-stores/{data_source}/{entity_type}")]
+pub(super) async fn delete_attribute_store(
+    access: AdminAccess,
+    pool: Data<Pool>,
+    args: Path<(String, String)>,
+) -> Result<HttpResponse, ServiceError> {
+    let (data_source, entity_type) = args.into_inner();
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let change = DeleteAttributeStore {
+        data_source,
+        entity_type,
+    };
+
+    let message = apply_audited(&change, client, &access.audit_context()).await?;
+
+    Ok(HttpResponse::Ok().json(Success { code: 200, message }))
+}
@@ -0,0 +1,147 @@
+use deadpool_postgres::Pool;
+use std::ops::DerefMut;
+
+use actix_web::{get, web::Data, web::Path, web::Query, HttpResponse};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use minerva::audit::{
+    load_audit_record, load_audit_records, AuditFilter, AuditRecord, AuditSource,
+};
+
+use super::auth::AdminAccess;
+use super::serviceerror::{ServiceError, ServiceErrorKind};
+
+const DEFAULT_RECORD_LIMIT: i64 = 100;
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct AuditRecordFull {
+    id: i64,
+    timestamp: DateTime<Utc>,
+    /// User on whose behalf the change was applied
+    actor: String,
+    /// Database user that applied the change
+    db_user: String,
+    /// One of 'cli' or 'service'
+    source: String,
+    change: String,
+    payload: Option<serde_json::Value>,
+    success: bool,
+    message: Option<String>,
+}
+
+impl From<AuditRecord> for AuditRecordFull {
+    fn from(record: AuditRecord) -> Self {
+        AuditRecordFull {
+            id: record.id,
+            timestamp: record.timestamp,
+            actor: record.actor,
+            db_user: record.db_user,
+            source: record.source,
+            change: record.change,
+            payload: record.payload,
+            success: record.success,
+            message: record.message,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct AuditQuery {
+    /// Only changes applied by this user
+    actor: Option<String>,
+    /// Only changes applied through this tool: cli or service
+    source: Option<String>,
+    /// Only changes of which the description contains this text
+    change: Option<String>,
+    /// Only successful or only failed changes
+    success: Option<bool>,
+    /// Only changes applied at or after this moment
+    since: Option<DateTime<Utc>>,
+    /// Only changes applied before this moment
+    until: Option<DateTime<Utc>>,
+    /// Maximum number of records to return, default 100
+    limit: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path="/audit",
+    params(AuditQuery),
+    responses(
+    (status = 200, description = "Applied changes, most recent first", body = [AuditRecordFull]),
+    (status = 400, description = "Unknown source", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
+    )
+)]
+#[get("/audit")]
+pub(super) async fn get_audit_records(
+    _access: AdminAccess,
+    pool: Data<Pool>,
+    query: Query<AuditQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let source = query
+        .source
+        .as_deref()
+        .map(str::parse::<AuditSource>)
+        .transpose()
+        .map_err(|e| ServiceError {
+            kind: ServiceErrorKind::BadRequest,
+            message: e.to_string(),
+        })?;
+
+    let filter = AuditFilter {
+        actor: query.actor.clone(),
+        source,
+        change: query.change.clone(),
+        success: query.success,
+        since: query.since,
+        until: query.until,
+        limit: Some(query.limit.unwrap_or(DEFAULT_RECORD_LIMIT)),
+    };
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let records: Vec<AuditRecordFull> = load_audit_records(client, &filter)
+        .await?
+        .into_iter()
+        .map(AuditRecordFull::from)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(records))
+}
+
+#[utoipa::path(
+    get,
+    path="/audit/{id}",
+    responses(
+    (status = 200, description = "Applied change", body = AuditRecordFull),
+    (status = 404, description = "Audit record not found", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
+    )
+)]
+#[get("/audit/{id}")]
+pub(super) async fn get_audit_record(
+    _access: AdminAccess,
+    pool: Data<Pool>,
+    id: Path<i64>,
+) -> Result<HttpResponse, ServiceError> {
+    let id = id.into_inner();
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let record = load_audit_record(client, id)
+        .await?
+        .ok_or_else(|| ServiceError {
+            kind: ServiceErrorKind::NotFound,
+            message: format!("No audit record with Id {id}"),
+        })?;
+
+    Ok(HttpResponse::Ok().json(AuditRecordFull::from(record)))
+}
@@ -5,7 +5,7 @@ use actix_web::{get, web::Data, web::Path, HttpResponse};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
-use super::error::Error;
+use super::auth::ReadAccess;
 use super::serviceerror::{ServiceError, ServiceErrorKind};
 
 #[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
@@ -20,15 +20,15 @@ pub struct DataSource {
     path="/data-sources",
     responses(
     (status = 200, description = "List all data sources", body = [DataSource]),
-    (status = 500, description = "Problem interacting with database", body = Error)
+    (status = 500, description = "Problem interacting with database", body = ProblemDetails)
     )
 )]
 #[get("/data-sources")]
-pub(super) async fn get_data_sources(pool: Data<Pool>) -> Result<HttpResponse, ServiceError> {
-    let client = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+pub(super) async fn get_data_sources(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client = pool.get().await?;
 
     let data_sources: Vec<DataSource> = client
         .query(
@@ -36,10 +36,6 @@ pub(super) async fn get_data_sources(pool: Data<Pool>) -> Result<HttpResponse, S
             &[],
         )
         .await
-        .map_err(|e| Error {
-            code: 500,
-            message: e.to_string(),
-        })
         .map(|rows| {
             rows.iter()
                 .map(|row| DataSource {
@@ -58,21 +54,19 @@ pub(super) async fn get_data_sources(pool: Data<Pool>) -> Result<HttpResponse, S
     path="/data-sources/{id}",
     responses(
     (status = 200, description = "Get a specific data source", body = TrendStorePart),
-    (status = 404, description = "Data source not found", body = Error),
-    (status = 500, description = "Problem interacting with database", body = Error),
+    (status = 404, description = "Data source not found", body = ProblemDetails),
+    (status = 500, description = "Problem interacting with database", body = ProblemDetails),
     )
 )]
 #[get("/data-sources/{id}")]
 pub(super) async fn get_data_source(
+    _access: ReadAccess,
     pool: Data<Pool>,
     id: Path<i32>,
 ) -> Result<HttpResponse, ServiceError> {
     let ds_id = id.into_inner();
 
-    let client = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+    let client = pool.get().await?;
 
     let data_source = client
         .query_one(
@@ -80,8 +74,8 @@ pub(super) async fn get_data_source(
             &[&ds_id],
         )
         .await
-        .map_err(|_| Error {
-            code: 404,
+        .map_err(|_| ServiceError {
+            kind: ServiceErrorKind::NotFound,
             message: format!("Data source with id {} not found", &ds_id),
         })
         .map(|row| DataSource {
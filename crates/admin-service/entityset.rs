@@ -1,16 +1,18 @@
 use serde::{Deserialize, Serialize};
 use deadpool_postgres::Pool;
-use std::ops::DerefMut;
-use utoipa::ToSchema;
+use std::ops::{Deref, DerefMut};
+use utoipa::{IntoParams, ToSchema};
 
-use actix_web::{get, put, post, web::Data, HttpResponse, Responder};
+use actix_web::{get, put, post, web::Data, web::Query, HttpRequest, HttpResponse};
 use chrono::{DateTime, Utc};
 
-use minerva::entity_set::{EntitySet, load_entity_sets, ChangeEntitySet, CreateEntitySet};
-use minerva::change::GenericChange;
+use minerva::entity_set::{EntitySet, load_entity_set_members, ChangeEntitySet, CreateEntitySet};
+use minerva::audit::{generic_apply_audited, AuditContext};
 
+use super::auth::{AdminAccess, ReadAccess};
+use super::listing::{page_response, Listing, PageRequest, SortColumn};
 use super::serviceerror::{ServiceError, ServiceErrorKind};
-use crate::error::{Error, Success};
+use crate::error::Success;
 
 type PostgresName = String;
 
@@ -53,41 +55,110 @@ impl EntitySetData {
     }
 }
 
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct EntitySetListQuery {
+    /// Only entity sets of this owner
+    owner: Option<String>,
+    /// Only entity sets in this group
+    group: Option<String>,
+    /// Only entity sets of this entity type
+    entity_type: Option<String>,
+    /// Only entity sets with a name matching this pattern, in which '*' matches any characters
+    name: Option<String>,
+    /// Field to sort on: name (default), owner or id, prefixed with '-' for descending order
+    sort: Option<String>,
+    /// Cursor from the Link header of the previous page
+    cursor: Option<String>,
+    /// Maximum number of entity sets to return, all when not specified
+    limit: Option<i64>,
+}
+
+const ENTITY_SET_SORT_COLUMNS: [SortColumn; 3] = [
+    SortColumn { name: "name", expression: "item.name", sql_type: "text" },
+    SortColumn { name: "owner", expression: "item.owner", sql_type: "text" },
+    SortColumn { name: "id", expression: "item.id", sql_type: "integer" },
+];
+
 #[utoipa::path(
     get,
     path="/entitysets",
+    params(EntitySetListQuery),
     responses(
-    (status = 200, description = "List of existing triggers", body = [EntitySet]),
-    (status = 500, description = "Database unreachable", body = Error),
+    (status = 200, description = "List of existing entity sets", body = [EntitySet],
+        headers(("Link" = String, description = "Link to the next page when there are more entity sets"))),
+    (status = 400, description = "Invalid filter, sort order, cursor or limit", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
     )
 )]
 #[get("/entitysets")]
-pub(super) async fn get_entity_sets(pool: Data<Pool>) -> Result<HttpResponse, ServiceError> {
-    let mut manager = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
-
-    let mut client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
-
-    let data = load_entity_sets(&mut client).await.map_err(|e| Error { code: 500, message: e.to_string() } )?;
-
-    Ok(HttpResponse::Ok().json(data))
+pub(super) async fn get_entity_sets(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+    query: Query<EntitySetListQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let manager = pool.get().await?;
+
+    let client: &tokio_postgres::Client = manager.deref();
+
+    let mut listing = Listing::new(
+        concat!(
+            "SELECT es.id, es.name, es.\"group\", es.source_entity_type AS entity_type, ",
+            "es.owner, es.description, es.first_appearance, es.modified ",
+            "FROM attribute.minerva_entity_set es"
+        ),
+        &ENTITY_SET_SORT_COLUMNS,
+        &ENTITY_SET_SORT_COLUMNS[2],
+    );
+
+    listing
+        .filter("item.owner = $?", query.owner.as_deref())
+        .filter("item.\"group\" = $?", query.group.as_deref())
+        .filter("item.entity_type = $?", query.entity_type.as_deref())
+        .filter_pattern("item.name", query.name.as_deref());
+
+    let page = listing
+        .fetch(client, &PageRequest {
+            sort: query.sort.as_deref(),
+            cursor: query.cursor.as_deref(),
+            limit: query.limit,
+        })
+        .await?;
+
+    let ids: Vec<i32> = page.rows.iter().map(|row| row.get("id")).collect();
+
+    let mut members = load_entity_set_members(client, &ids)
+        .await
+        .map_err(|e| ServiceError { kind: ServiceErrorKind::DbError, message: e })?;
+
+    let data: Vec<EntitySet> = page.rows
+        .iter()
+        .map(|row| EntitySet {
+            name: row.get("name"),
+            group: row.get("group"),
+            entity_type: row.get("entity_type"),
+            owner: row.get("owner"),
+            description: row.try_get("description").unwrap_or("".into()),
+            entities: members.remove(&row.get("id")).unwrap_or_default(),
+            created: row.get("first_appearance"),
+            modified: row.get("modified"),
+        })
+        .collect();
+
+    Ok(page_response(&req, &data, page.next_cursor))
 }
 
 async fn change_entity_set_fn(
     pool: Data<Pool>,
     post: String,
-) -> Result<HttpResponse, Error> {
-    let data: EntitySetData = serde_json::from_str(&post).map_err(|e| Error {
-        code: 400,
+    context: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    let data: EntitySetData = serde_json::from_str(&post).map_err(|e| ServiceError {
+        kind: ServiceErrorKind::BadRequest,
         message: e.to_string(),
     })?;
 
-    let mut manager = pool.get().await.map_err(|e| Error {
-        code: 500,
-        message: e.to_string(),
-    })?;
+    let mut manager = pool.get().await?;
 
     let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
 
@@ -96,10 +167,7 @@ async fn change_entity_set_fn(
         entities: data.entities
     };
 
-    action.generic_apply(client).await.map_err(|e| Error {
-        code: 409,
-        message: format!("Change of entity set failed: {e}"),
-    })?;
+    generic_apply_audited(&action, client, &context).await?;
 
     Ok(HttpResponse::Ok().json(Success {
         code: 200,
@@ -112,46 +180,31 @@ async fn change_entity_set_fn(
     path="/entitysets",
     responses(
     (status = 200, description = "Changing trigger set succeeded", body = Success),
-    (status = 400, description = "Request could not be parsed", body = Error),
-    (status = 409, description = "Changing trigger set failed", body = Error),
-    (status = 500, description = "Database unreachable", body = Error),
+    (status = 400, description = "Request could not be parsed", body = ProblemDetails),
+    (status = 404, description = "Entities do not exist", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
     )
 )]
 #[put("/entitysets")]
 pub(super) async fn change_entity_set(
+    access: AdminAccess,
     pool: Data<Pool>,
     post: String,
-) -> impl Responder {
-    let result = change_entity_set_fn(pool, post).await;
-    match result {
-        Ok(res) => res,
-        Err(e) => {
-            let err = Error{
-                code: e.code,
-                message: e.message
-            };
-            match err.code {
-                400 => HttpResponse::BadRequest().json(err),
-                409 => HttpResponse::Conflict().json(err),
-                _ => HttpResponse::InternalServerError().json(err)
-            }
-        }
-    }
+) -> Result<HttpResponse, ServiceError> {
+    change_entity_set_fn(pool, post, access.audit_context()).await
 }
 
 async fn create_entity_set_fn(
     pool: Data<Pool>,
     post: String,
-) -> Result<HttpResponse, Error> {
-    let data: EntitySetData = serde_json::from_str(&post).map_err(|e| Error {
-        code: 400,
+    context: AuditContext,
+) -> Result<HttpResponse, ServiceError> {
+    let data: EntitySetData = serde_json::from_str(&post).map_err(|e| ServiceError {
+        kind: ServiceErrorKind::BadRequest,
         message: e.to_string(),
     })?;
 
-    let mut manager = pool.get().await.map_err(|e| Error {
-        code: 500,
-        message: e.to_string(),
-    })?;
+    let mut manager = pool.get().await?;
 
     let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
 
@@ -159,10 +212,7 @@ async fn create_entity_set_fn(
         entity_set: data.entity_set()
     };
 
-    action.generic_apply(client).await.map_err(|e| Error {
-        code: 409,
-        message: format!("Creation of entity set failed: {e}"),
-    })?;
+    generic_apply_audited(&action, client, &context).await?;
 
     Ok(HttpResponse::Ok().json(Success {
         code: 200,
@@ -175,29 +225,17 @@ async fn create_entity_set_fn(
     path="/entitysets",
     responses(
     (status = 200, description = "Changing trigger set succeeded", body = Success),
-    (status = 400, description = "Request could not be parsed", body = Error),
-    (status = 409, description = "Changing trigger set failed", body = Error),
-    (status = 500, description = "Database unreachable", body = Error),
+    (status = 400, description = "Request could not be parsed", body = ProblemDetails),
+    (status = 404, description = "Entities do not exist", body = ProblemDetails),
+    (status = 409, description = "Entity set already exists", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
     )
 )]
 #[post("/entitysets")]
 pub(super) async fn create_entity_set(
+    access: AdminAccess,
     pool: Data<Pool>,
     post: String,
-) -> impl Responder {
-    let result = create_entity_set_fn(pool, post).await;
-    match result {
-        Ok(res) => res,
-        Err(e) => {
-            let err = Error{
-                code: e.code,
-                message: e.message
-            };
-            match err.code {
-                400 => HttpResponse::BadRequest().json(err),
-                409 => HttpResponse::Conflict().json(err),
-                _ => HttpResponse::InternalServerError().json(err)
-            }
-        }
-    }
+) -> Result<HttpResponse, ServiceError> {
+    create_entity_set_fn(pool, post, access.audit_context()).await
 }
@@ -1,11 +1,12 @@
 use deadpool_postgres::Pool;
 
-use actix_web::{get, web::Data, web::Path, HttpResponse, Responder};
+use actix_web::{get, web::Data, web::Path, HttpResponse};
 
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
-use super::error::Error;
+use super::auth::ReadAccess;
+use super::serviceerror::{ServiceError, ServiceErrorKind};
 
 #[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct EntityType {
@@ -19,44 +20,33 @@ pub struct EntityType {
     path="/entity-types",
     responses(
     (status = 200, description = "List all entity types", body = [EntityType]),
-    (status = 500, description = "Unable to interact with database", body = Error)
+    (status = 500, description = "Unable to interact with database", body = ProblemDetails)
     )
 )]
 #[get("/entity-types")]
-pub(super) async fn get_entity_types(pool: Data<Pool>) -> impl Responder {
-    let mut m: Vec<EntityType> = vec![];
-    let result = pool.get().await;
-    match result {
-        Err(e) => HttpResponse::InternalServerError().json(Error {
-            code: 500,
-            message: e.to_string(),
-        }),
-        Ok(client) => {
-            let query = client
-                .query(
-                    "SELECT id, name, description FROM directory.entity_type",
-                    &[],
-                )
-                .await;
-            match query {
-                Err(e) => HttpResponse::InternalServerError().json(Error {
-                    code: 500,
-                    message: e.to_string(),
-                }),
-                Ok(result) => {
-                    for row in result {
-                        let entitytype = EntityType {
-                            id: row.get(0),
-                            name: row.get(1),
-                            description: row.get(2),
-                        };
-                        m.push(entitytype)
-                    }
-                    HttpResponse::Ok().json(m)
-                }
-            }
-        }
-    }
+pub(super) async fn get_entity_types(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client = pool.get().await?;
+
+    let entity_types: Vec<EntityType> = client
+        .query(
+            "SELECT id, name, description FROM directory.entity_type",
+            &[],
+        )
+        .await
+        .map(|rows| {
+            rows.iter()
+                .map(|row| EntityType {
+                    id: row.get(0),
+                    name: row.get(1),
+                    description: row.get(2),
+                })
+                .collect()
+        })?;
+
+    Ok(HttpResponse::Ok().json(entity_types))
 }
 
 #[utoipa::path(
@@ -64,43 +54,35 @@ pub(super) async fn get_entity_types(pool: Data<Pool>) -> impl Responder {
     path="/entity-types/{id}",
     responses(
     (status = 200, description = "Get a specific entity type", body = TrendStorePart),
-    (status = 404, description = "Entity type not found", body = Error),
-    (status = 500, description = "Problem interacting with database", body = Error),
+    (status = 404, description = "Entity type not found", body = ProblemDetails),
+    (status = 500, description = "Problem interacting with database", body = ProblemDetails),
     )
 )]
 #[get("/entity-types/{id}")]
-pub(super) async fn get_entity_type(pool: Data<Pool>, id: Path<i32>) -> impl Responder {
+pub(super) async fn get_entity_type(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+    id: Path<i32>,
+) -> Result<HttpResponse, ServiceError> {
     let et_id = id.into_inner();
 
-    let result = pool.get().await;
-    match result {
-        Err(e) => HttpResponse::InternalServerError().json(Error {
-            code: 500,
-            message: e.to_string(),
-        }),
-        Ok(client) => {
-            let query_result = client
-                .query_one(
-                    "SELECT id, name, description FROM directory.entity_type WHERE id=$1",
-                    &[&et_id],
-                )
-                .await;
+    let client = pool.get().await?;
 
-            match query_result {
-                Ok(row) => {
-                    let entitytype = EntityType {
-                        id: row.get(0),
-                        name: row.get(1),
-                        description: row.get(2),
-                    };
+    let entity_type = client
+        .query_opt(
+            "SELECT id, name, description FROM directory.entity_type WHERE id=$1",
+            &[&et_id],
+        )
+        .await?
+        .map(|row| EntityType {
+            id: row.get(0),
+            name: row.get(1),
+            description: row.get(2),
+        })
+        .ok_or_else(|| ServiceError {
+            kind: ServiceErrorKind::NotFound,
+            message: format!("Entity type with id {} not found", &et_id),
+        })?;
 
-                    HttpResponse::Ok().json(entitytype)
-                }
-                Err(_e) => HttpResponse::NotFound().json(Error {
-                    code: 404,
-                    message: format!("Entity type with id {} not found", &et_id),
-                }),
-            }
-        }
-    }
+    Ok(HttpResponse::Ok().json(entity_type))
 }
@@ -2,10 +2,20 @@ use derive_more::{Display, From};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
+/// Problem details of a failed request as defined in RFC 7807, returned with content type
+/// `application/problem+json`
 #[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
-pub struct Error {
-    pub code: i32,
-    pub message: String,
+pub struct ProblemDetails {
+    /// URI reference identifying the problem type, 'about:blank' when only the status applies
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    /// Short summary of the problem type
+    pub title: String,
+    /// HTTP status code
+    pub status: u16,
+    /// Explanation specific to this occurrence of the problem
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
@@ -0,0 +1,408 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web::{get, web::Bytes, web::Data, web::Query, HttpRequest, HttpResponse};
+use log::{error, info, warn};
+use serde::Deserialize;
+use tokio::sync::{broadcast, mpsc};
+use tokio_postgres::{AsyncMessage, Config};
+use tokio_postgres_rustls::MakeRustlsConnect;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::StreamExt;
+use utoipa::IntoParams;
+
+use super::auth::ReadAccess;
+use super::serviceerror::{ServiceError, ServiceErrorKind};
+
+/// Channel on which the database sends events with NOTIFY
+pub const EVENT_CHANNEL: &str = "minerva_event";
+
+/// Types of events sent by the database
+const EVENT_TYPES: [&str; 3] = ["notification", "change", "materialization"];
+
+/// Number of recent events kept to replay for reconnecting clients
+const REPLAY_SIZE: usize = 1000;
+
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Event received from the database
+#[derive(Debug, PartialEq)]
+pub struct Event {
+    pub id: u64,
+    pub event_type: String,
+    /// Name of the trigger, changed object or materialized trend store part
+    pub subject: String,
+    /// JSON payload as sent by the database
+    pub data: String,
+}
+
+impl Event {
+    fn from_payload(id: u64, payload: &str) -> Result<Event, String> {
+        let data: serde_json::Value =
+            serde_json::from_str(payload).map_err(|e| format!("Invalid event payload: {e}"))?;
+
+        let field = |name: &str| -> Result<String, String> {
+            data.get(name)
+                .and_then(|value| value.as_str())
+                .map(String::from)
+                .ok_or_else(|| format!("No '{name}' in event payload"))
+        };
+
+        Ok(Event {
+            id,
+            event_type: field("type")?,
+            subject: field("subject")?,
+            data: payload.to_string(),
+        })
+    }
+
+    /// Format as a server-sent event message
+    fn to_message(&self, instance: &str) -> String {
+        let data: String = self
+            .data
+            .lines()
+            .map(|line| format!("data: {line}\n"))
+            .collect();
+
+        format!(
+            "id: {instance}-{}\nevent: {}\n{data}\n",
+            self.id, self.event_type
+        )
+    }
+}
+
+struct EventLog {
+    next_id: u64,
+    recent: VecDeque<Arc<Event>>,
+}
+
+/// Distributes the events from the database to the connected clients
+///
+/// Event Ids are prefixed with an instance Id, so that after a restart of the service the
+/// Ids of a previous run are recognized and all recent events are replayed.
+pub struct EventHub {
+    instance: String,
+    log: Mutex<EventLog>,
+    sender: broadcast::Sender<Arc<Event>>,
+}
+
+impl EventHub {
+    pub fn new() -> EventHub {
+        let (sender, _) = broadcast::channel(REPLAY_SIZE);
+
+        EventHub {
+            instance: format!("{:x}", chrono::Utc::now().timestamp_millis()),
+            log: Mutex::new(EventLog {
+                next_id: 1,
+                recent: VecDeque::with_capacity(REPLAY_SIZE),
+            }),
+            sender,
+        }
+    }
+
+    fn publish(&self, payload: &str) -> Result<(), String> {
+        let mut log = self.log.lock().unwrap();
+
+        let event = Arc::new(Event::from_payload(log.next_id, payload)?);
+
+        log.next_id += 1;
+
+        if log.recent.len() == REPLAY_SIZE {
+            log.recent.pop_front();
+        }
+
+        log.recent.push_back(event.clone());
+
+        // Sending fails only when no client is connected
+        let _ = self.sender.send(event);
+
+        Ok(())
+    }
+
+    /// Subscribe to new events, returning the recent events after the last event that a
+    /// reconnecting client received
+    fn subscribe(
+        &self,
+        last_event_id: Option<&str>,
+    ) -> (Vec<Arc<Event>>, broadcast::Receiver<Arc<Event>>) {
+        // Holding the lock makes sure no event is both replayed and received, or neither
+        let log = self.log.lock().unwrap();
+
+        let replay_after = match last_event_id.map(|id| id.rsplit_once('-')) {
+            None => None,
+            Some(Some((instance, id))) if instance == self.instance => {
+                Some(id.parse::<u64>().unwrap_or(0))
+            }
+            Some(_) => Some(0),
+        };
+
+        let replay = match replay_after {
+            None => Vec::new(),
+            Some(after) => log
+                .recent
+                .iter()
+                .filter(|event| event.id > after)
+                .cloned()
+                .collect(),
+        };
+
+        (replay, self.sender.subscribe())
+    }
+}
+
+impl Default for EventHub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Receive events from the database and publish them to the hub, reconnecting when the
+/// connection is lost
+pub async fn listen_for_events(config: Config, tls: MakeRustlsConnect, hub: Arc<EventHub>) {
+    loop {
+        match listen(&config, tls.clone(), &hub).await {
+            Ok(()) => warn!("Database connection for events closed"),
+            Err(e) => error!("Could not listen for database events: {e}"),
+        }
+
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn listen(
+    config: &Config,
+    tls: MakeRustlsConnect,
+    hub: &EventHub,
+) -> Result<(), tokio_postgres::Error> {
+    let (client, mut connection) = config.connect(tls).await?;
+
+    let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+
+    // Polling the connection for messages also drives the client
+    let driver = tokio::spawn(async move {
+        loop {
+            match std::future::poll_fn(|cx| connection.poll_message(cx)).await {
+                Some(Ok(AsyncMessage::Notification(notification))) => {
+                    if sender.send(notification.payload().to_string()).is_err() {
+                        return Ok(());
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e),
+                None => return Ok(()),
+            }
+        }
+    });
+
+    client
+        .batch_execute(&format!("LISTEN {EVENT_CHANNEL}"))
+        .await?;
+
+    info!("Listening for database events on channel '{EVENT_CHANNEL}'");
+
+    while let Some(payload) = receiver.recv().await {
+        if let Err(e) = hub.publish(&payload) {
+            warn!("Ignoring database event: {e}");
+        }
+    }
+
+    match driver.await {
+        Ok(result) => result,
+        Err(e) => {
+            error!("Database event listener stopped: {e}");
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct EventQuery {
+    /// Comma separated event types to receive: notification, change and/or materialization.
+    /// All types when not specified.
+    types: Option<String>,
+    /// Only events with a subject matching this pattern, in which '*' matches any characters.
+    /// The subject is the trigger name for notification events, the change for change events
+    /// and the target trend store part for materialization events.
+    subject: Option<String>,
+}
+
+struct EventFilter {
+    types: Option<Vec<String>>,
+    subject: Option<glob::Pattern>,
+}
+
+impl EventFilter {
+    fn from_query(query: &EventQuery) -> Result<EventFilter, ServiceError> {
+        let bad_request = |message: String| ServiceError {
+            kind: ServiceErrorKind::BadRequest,
+            message,
+        };
+
+        let types = query
+            .types
+            .as_deref()
+            .map(|types| {
+                types
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|event_type| !event_type.is_empty())
+                    .map(|event_type| match EVENT_TYPES.contains(&event_type) {
+                        true => Ok(event_type.to_string()),
+                        false => Err(bad_request(format!(
+                            "Unknown event type '{event_type}', types are: {}",
+                            EVENT_TYPES.join(", ")
+                        ))),
+                    })
+                    .collect::<Result<Vec<String>, ServiceError>>()
+            })
+            .transpose()?;
+
+        let subject = query
+            .subject
+            .as_deref()
+            .map(glob::Pattern::new)
+            .transpose()
+            .map_err(|e| bad_request(format!("Invalid subject pattern: {e}")))?;
+
+        Ok(EventFilter { types, subject })
+    }
+
+    fn matches(&self, event: &Event) -> bool {
+        self.types
+            .as_ref()
+            .map_or(true, |types| types.contains(&event.event_type))
+            && self
+                .subject
+                .as_ref()
+                .map_or(true, |pattern| pattern.matches(&event.subject))
+    }
+}
+
+/// Stream events as server-sent events. Notifications created by triggers, successfully
+/// applied changes and finished materializations are sent as they happen. A reconnecting
+/// client receives the recent events it missed, based on its Last-Event-ID header.
+#[utoipa::path(
+    get,
+    path="/events",
+    params(
+        EventQuery,
+        ("Last-Event-ID" = Option<String>, Header, description = "Id of the last event received before reconnecting"),
+    ),
+    responses(
+    (status = 200, description = "Stream of events", content_type = "text/event-stream", body = String),
+    (status = 400, description = "Unknown event type or invalid subject pattern", body = ProblemDetails),
+    )
+)]
+#[get("/events")]
+pub(super) async fn get_events(
+    _access: ReadAccess,
+    hub: Data<EventHub>,
+    query: Query<EventQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let filter = EventFilter::from_query(&query)?;
+
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok());
+
+    let (replay, receiver) = hub.subscribe(last_event_id);
+
+    let instance = hub.instance.clone();
+
+    // A client that falls behind is disconnected, it receives the missed events when it
+    // reconnects
+    let live = BroadcastStream::new(receiver)
+        .take_while(|event| event.is_ok())
+        .filter_map(|event| event.ok());
+
+    let messages = tokio_stream::iter(replay)
+        .chain(live)
+        .filter(move |event| filter.matches(event))
+        .timeout(KEEPALIVE_INTERVAL)
+        .map(move |event| {
+            Ok::<Bytes, actix_web::Error>(match event {
+                Ok(event) => Bytes::from(event.to_message(&instance)),
+                Err(_) => Bytes::from_static(b": keepalive\n\n"),
+            })
+        });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(messages))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(event_type: &str, subject: &str) -> String {
+        serde_json::json!({"type": event_type, "subject": subject, "count": 2}).to_string()
+    }
+
+    #[test]
+    fn event_message() {
+        let event = Event::from_payload(7, &payload("notification", "node/15m/high")).unwrap();
+
+        assert_eq!(event.event_type, "notification");
+        assert_eq!(event.subject, "node/15m/high");
+        assert_eq!(
+            event.to_message("a1"),
+            format!(
+                "id: a1-7\nevent: notification\ndata: {}\n\n",
+                payload("notification", "node/15m/high")
+            )
+        );
+
+        assert!(Event::from_payload(1, "{\"type\": \"change\"}").is_err());
+        assert!(Event::from_payload(1, "not json").is_err());
+    }
+
+    #[test]
+    fn replay_after_last_event_id() {
+        let hub = EventHub::new();
+
+        for subject in ["a", "b", "c"] {
+            hub.publish(&payload("change", subject)).unwrap();
+        }
+
+        let replayed = |last_event_id: Option<&str>| -> Vec<String> {
+            hub.subscribe(last_event_id)
+                .0
+                .iter()
+                .map(|event| event.subject.clone())
+                .collect()
+        };
+
+        assert!(replayed(None).is_empty());
+        assert_eq!(replayed(Some(&format!("{}-1", hub.instance))), ["b", "c"]);
+        assert!(replayed(Some(&format!("{}-3", hub.instance))).is_empty());
+        // Events of a previous run of the service
+        assert_eq!(replayed(Some("0-2")), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn filter_on_type_and_subject() {
+        let filter = EventFilter::from_query(&EventQuery {
+            types: Some("notification, materialization".to_string()),
+            subject: Some("node/*".to_string()),
+        })
+        .unwrap();
+
+        let event = |event_type, subject| Event::from_payload(1, &payload(event_type, subject));
+
+        assert!(filter.matches(&event("notification", "node/15m/high").unwrap()));
+        assert!(!filter.matches(&event("change", "node/15m/high").unwrap()));
+        assert!(!filter.matches(&event("notification", "cell/15m/high").unwrap()));
+
+        assert!(EventFilter::from_query(&EventQuery {
+            types: Some("incident".to_string()),
+            subject: None,
+        })
+        .is_err());
+    }
+}
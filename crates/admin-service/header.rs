@@ -1,5 +1,6 @@
 use actix_web::{get, web::Path, HttpRequest, HttpResponse};
 
+use super::auth::ReadAccess;
 use super::serviceerror::{ServiceError, ServiceErrorKind};
 
 #[utoipa::path(
@@ -7,12 +8,16 @@ use super::serviceerror::{ServiceError, ServiceErrorKind};
     path="/headers",
     responses(
     (status = 200, description = "Value of the header", body = String),
-    (status = 404, description = "Header does not exist", body = Error),
+    (status = 404, description = "Header does not exist", body = ProblemDetails),
     )
 )]
 
 #[get("/headers/{name}")]
-async fn get_header(req: HttpRequest, name: Path<String>) -> Result<HttpResponse, ServiceError> {
+async fn get_header(
+    _access: ReadAccess,
+    req: HttpRequest,
+    name: Path<String>,
+) -> Result<HttpResponse, ServiceError> {
     let headers = req.headers();
     let headername = name.into_inner();
     match headers.get(headername) {
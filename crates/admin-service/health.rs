@@ -0,0 +1,82 @@
+use deadpool_postgres::Pool;
+
+use actix_web::{get, web::Data, HttpResponse};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use minerva::schema::{get_schema_version, SCHEMA_VERSION};
+
+use super::serviceerror::{ServiceError, ServiceErrorKind};
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct HealthStatus {
+    /// 'ok' when the service is alive or ready
+    status: String,
+    /// Version of the service
+    version: String,
+    /// Version of the Minerva schema in the database, only reported for readiness
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schema_version: Option<String>,
+}
+
+fn unavailable(message: String) -> ServiceError {
+    ServiceError {
+        kind: ServiceErrorKind::Unavailable,
+        message,
+    }
+}
+
+/// Liveness of the service, which does not depend on the database
+#[utoipa::path(
+    get,
+    path="/health/live",
+    responses(
+    (status = 200, description = "Service is alive", body = HealthStatus),
+    ),
+    security(()),
+)]
+#[get("/health/live")]
+pub(super) async fn get_liveness() -> HttpResponse {
+    HttpResponse::Ok().json(HealthStatus {
+        status: "ok".to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        schema_version: None,
+    })
+}
+
+/// Readiness of the service: a connection to the database can be obtained and the database
+/// has a compatible Minerva schema
+#[utoipa::path(
+    get,
+    path="/health/ready",
+    responses(
+    (status = 200, description = "Service is ready to handle requests", body = HealthStatus),
+    (status = 503, description = "Database unreachable or schema version not supported", body = ProblemDetails),
+    ),
+    security(()),
+)]
+#[get("/health/ready")]
+pub(super) async fn get_readiness(pool: Data<Pool>) -> Result<HttpResponse, ServiceError> {
+    let client = pool
+        .get()
+        .await
+        .map_err(|e| unavailable(format!("Could not get database connection: {e}")))?;
+
+    let schema_version = get_schema_version(&**client)
+        .await
+        .map_err(|e| unavailable(e.to_string()))?;
+
+    if !SCHEMA_VERSION.is_compatible_with(&schema_version) {
+        return Err(unavailable(format!(
+            "Schema version {schema_version} is not supported, expected version {}.x",
+            SCHEMA_VERSION.major
+        )));
+    }
+
+    Ok(HttpResponse::Ok().json(HealthStatus {
+        status: "ok".to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        schema_version: Some(schema_version.to_string()),
+    }))
+}
@@ -0,0 +1,254 @@
+use deadpool_postgres::Pool;
+use std::io::{Cursor, Read};
+use std::ops::DerefMut;
+use std::path::{Path, PathBuf};
+
+use actix_web::{post, web, web::Data, web::Payload, HttpResponse};
+use tokio_stream::StreamExt;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use minerva::instance::MinervaInstance;
+
+use super::auth::ReadAccess;
+use super::serviceerror::{ServiceError, ServiceErrorKind};
+
+const MAX_ARCHIVE_SIZE: usize = 64 * 1024 * 1024;
+/// Limits on the extracted archive, because a small archive can expand to a huge size
+const MAX_EXTRACTED_SIZE: u64 = 256 * 1024 * 1024;
+const MAX_ARCHIVE_ENTRIES: usize = 10_000;
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct InstanceChange {
+    /// Description of the change
+    change: String,
+    /// Details of the change
+    payload: Option<serde_json::Value>,
+}
+
+fn bad_request(message: String) -> ServiceError {
+    ServiceError {
+        kind: ServiceErrorKind::BadRequest,
+        message,
+    }
+}
+
+/// Return the instance root in a directory with an extracted archive
+///
+/// Archives of a directory contain a single top level directory that is the instance root.
+fn archive_root(directory: &Path) -> Result<PathBuf, std::io::Error> {
+    let entries = std::fs::read_dir(directory)?.collect::<Result<Vec<_>, _>>()?;
+
+    match entries.as_slice() {
+        [entry] if entry.file_type()?.is_dir() => Ok(entry.path()),
+        _ => Ok(directory.to_path_buf()),
+    }
+}
+
+/// Extract an archive entry by entry, enforcing limits on the number of entries and the total
+/// extracted size
+///
+/// The sizes in the archive can not be trusted, so the number of bytes written is checked too.
+fn extract_archive(
+    zip: &mut zip::ZipArchive<Cursor<Vec<u8>>>,
+    directory: &Path,
+    max_size: u64,
+    max_entries: usize,
+) -> Result<(), String> {
+    if zip.len() > max_entries {
+        return Err(format!(
+            "Instance archive exceeds the maximum of {max_entries} entries"
+        ));
+    }
+
+    let too_large =
+        || format!("Extracted instance archive exceeds the maximum size of {max_size} bytes");
+
+    let mut extracted_size: u64 = 0;
+
+    for index in 0..zip.len() {
+        let mut file = zip
+            .by_index(index)
+            .map_err(|e| format!("Could not read instance archive: {e}"))?;
+
+        let path = file
+            .enclosed_name()
+            .map(|name| directory.join(name))
+            .ok_or_else(|| format!("Invalid file path '{}' in instance archive", file.name()))?;
+
+        if file.is_dir() {
+            std::fs::create_dir_all(&path)
+                .map_err(|e| format!("Could not extract instance archive: {e}"))?;
+
+            continue;
+        }
+
+        if extracted_size + file.size() > max_size {
+            return Err(too_large());
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Could not extract instance archive: {e}"))?;
+        }
+
+        let mut out = std::fs::File::create(&path)
+            .map_err(|e| format!("Could not extract instance archive: {e}"))?;
+
+        extracted_size += std::io::copy(
+            &mut (&mut file).take(max_size - extracted_size + 1),
+            &mut out,
+        )
+        .map_err(|e| format!("Could not extract instance archive: {e}"))?;
+
+        if extracted_size > max_size {
+            return Err(too_large());
+        }
+    }
+
+    Ok(())
+}
+
+fn load_instance_archive(archive: Vec<u8>) -> Result<MinervaInstance, String> {
+    let mut zip = zip::ZipArchive::new(Cursor::new(archive))
+        .map_err(|e| format!("Could not read instance archive: {e}"))?;
+
+    let directory =
+        tempfile::tempdir().map_err(|e| format!("Could not create temporary directory: {e}"))?;
+
+    extract_archive(
+        &mut zip,
+        directory.path(),
+        MAX_EXTRACTED_SIZE,
+        MAX_ARCHIVE_ENTRIES,
+    )?;
+
+    let root = archive_root(directory.path())
+        .map_err(|e| format!("Could not read extracted instance archive: {e}"))?;
+
+    Ok(MinervaInstance::load_from(&root))
+}
+
+/// Compare the database with an instance definition. The instance directory is uploaded as a
+/// zip archive and the changes required to update the database to it are returned.
+#[utoipa::path(
+    post,
+    path="/instance/diff",
+    request_body(content = String, content_type = "application/zip", description = "Zip archive of an instance directory"),
+    responses(
+    (status = 200, description = "Changes to update the database to the instance definition", body = [InstanceChange]),
+    (status = 400, description = "Archive missing, too large or invalid", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
+    )
+)]
+#[post("/instance/diff")]
+pub(super) async fn post_instance_diff(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+    mut payload: Payload,
+) -> Result<HttpResponse, ServiceError> {
+    let mut archive: Vec<u8> = Vec::new();
+
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| bad_request(format!("Could not read archive: {e}")))?;
+
+        if archive.len() + chunk.len() > MAX_ARCHIVE_SIZE {
+            return Err(bad_request(format!(
+                "Archive exceeds the maximum size of {MAX_ARCHIVE_SIZE} bytes"
+            )));
+        }
+
+        archive.extend_from_slice(&chunk);
+    }
+
+    if archive.is_empty() {
+        return Err(bad_request("No instance archive uploaded".to_string()));
+    }
+
+    // Loading is blocking and invalid definitions can make it panic
+    let instance = web::block(move || load_instance_archive(archive))
+        .await
+        .map_err(|_| bad_request("Invalid instance definition in archive".to_string()))?
+        .map_err(bad_request)?;
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let db_instance = MinervaInstance::load_from_db(client).await?;
+
+    let changes: Vec<InstanceChange> = db_instance
+        .diff(&instance)
+        .iter()
+        .map(|change| InstanceChange {
+            change: change.to_string(),
+            payload: change.payload(),
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(changes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn zip_archive(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+
+        for (name, content) in files {
+            writer
+                .start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    const RELATION: &str = "name: node->site\nquery: SELECT 1 AS source_id, 2 AS target_id\n";
+
+    #[test]
+    fn archive_with_top_level_directory() {
+        let archive = zip_archive(&[("instance/relation/node->site.yaml", RELATION)]);
+
+        let instance = load_instance_archive(archive).unwrap();
+
+        assert_eq!(instance.relations.len(), 1);
+        assert_eq!(instance.relations[0].name, "node->site");
+    }
+
+    #[test]
+    fn archive_of_directory_contents() {
+        let archive = zip_archive(&[
+            ("relation/node->site.yaml", RELATION),
+            ("README.md", "Test instance"),
+        ]);
+
+        let instance = load_instance_archive(archive).unwrap();
+
+        assert_eq!(instance.relations.len(), 1);
+    }
+
+    #[test]
+    fn extraction_limits() {
+        let archive = zip_archive(&[("a.yaml", "0123456789"), ("b.yaml", "0123456789")]);
+        let extract = |max_size, max_entries| {
+            let mut zip = zip::ZipArchive::new(Cursor::new(archive.clone())).unwrap();
+            let directory = tempfile::tempdir().unwrap();
+
+            extract_archive(&mut zip, directory.path(), max_size, max_entries)
+        };
+
+        assert!(extract(20, 2).is_ok());
+        assert!(extract(19, 2).is_err());
+        assert!(extract(20, 1).is_err());
+    }
+
+    #[test]
+    fn invalid_archive() {
+        assert!(load_instance_archive(b"not a zip file".to_vec()).is_err());
+    }
+}
@@ -3,17 +3,18 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 use std::ops::DerefMut;
-use std::time::Duration;
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 
 use deadpool_postgres::Pool;
 
-use actix_web::{delete, get, post, put, web::Data, web::Path, HttpResponse};
+use actix_web::{delete, get, post, put, web::Data, web::Path, web::Query, HttpRequest, HttpResponse};
 
 use serde_json::json;
 use tokio_postgres::{types::Type, GenericClient};
 
-use minerva::interval::parse_interval;
+use minerva::audit::{record_change, AuditContext};
+use minerva::error::Error;
+use minerva::granularity::Granularity;
 use minerva::trend_materialization::map_sql_to_plpgsql;
 
 use crate::trendmaterialization::{
@@ -22,52 +23,16 @@ use crate::trendmaterialization::{
 };
 use crate::trendstore::{TrendData, TrendStorePartCompleteData};
 
+use super::auth::{AdminAccess, ReadAccess};
+use super::kpiconfig::{validate_aggregation, KpiConfig, KpiGranularityConfig};
+use super::kpiexpression::{parse, Column, ExpressionError, ValueType};
+use super::listing::{page_response, Listing, PageRequest, SortColumn};
 use super::serviceerror::{ServiceError, ServiceErrorKind};
-use crate::error::{Error, Success};
+use crate::error::Success;
 
 lazy_static! {
-    static ref DATASOURCE: String = "kpi".to_string();
     static ref DESCRIPTION: String = "".to_string();
-    static ref GRANULARITIES: Vec<String> = vec![
-        "15m".to_string(),
-        "1h".to_string(),
-        "1d".to_string(),
-        "1w".to_string(),
-        "1month".to_string()
-    ];
     static ref LANGUAGE: String = "PLPGSQL".to_string();
-    static ref TIME_AGGREGATION: String = "SUM".to_string();
-    static ref ENTITY_AGGREGATION: String = "SUM".to_string();
-    static ref MAPPING_FUNCTION: String = "trend.mapping_id".to_string();
-    static ref DEFAULT_GRANULARITY: String = "1d".to_string();
-    static ref PROCESSING_DELAY: HashMap<String, Duration> = HashMap::from([
-        ("15m".to_string(), parse_interval("10m").unwrap()),
-        ("1h".to_string(), parse_interval("10m").unwrap()),
-        ("1d".to_string(), parse_interval("30m").unwrap()),
-        ("1w".to_string(), parse_interval("30m").unwrap()),
-        ("1month".to_string(), parse_interval("30m").unwrap()),
-    ]);
-    static ref STABILITY_DELAY: HashMap<String, Duration> = HashMap::from([
-        ("15m".to_string(), parse_interval("5m").unwrap()),
-        ("1h".to_string(), parse_interval("5m").unwrap()),
-        ("1d".to_string(), parse_interval("5m").unwrap()),
-        ("1w".to_string(), parse_interval("5m").unwrap()),
-        ("1month".to_string(), parse_interval("5m").unwrap()),
-    ]);
-    static ref REPROCESSING_PERIOD: HashMap<String, Duration> = HashMap::from([
-        ("15m".to_string(), parse_interval("3 days").unwrap()),
-        ("1h".to_string(), parse_interval("3 days").unwrap()),
-        ("1d".to_string(), parse_interval("3 days").unwrap()),
-        ("1w".to_string(), parse_interval("3 days").unwrap()),
-        ("1month".to_string(), parse_interval("3 days").unwrap())
-    ]);
-    static ref PARTITION_SIZE: HashMap<String, Duration> = HashMap::from([
-        ("15m".to_string(), parse_interval("1d").unwrap()),
-        ("1h".to_string(), parse_interval("4d").unwrap()),
-        ("1d".to_string(), parse_interval("3mons").unwrap()),
-        ("1w".to_string(), parse_interval("1y").unwrap()),
-        ("1month".to_string(), parse_interval("5y").unwrap())
-    ]);
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
@@ -89,9 +54,18 @@ pub struct KpiRawData {
     pub entity_type: String,
     pub data_type: String,
     pub enabled: bool,
+    /// Trends to join in addition to the trends referenced by the definition
+    #[serde(default)]
     pub source_trends: Vec<String>,
+    /// KPI expression, like `safe_div(SUM(power_kwh), SUM(samples))`
     pub definition: String,
     pub description: Value,
+    /// Time aggregation of the KPI, like SUM or AVG; the configured default when not specified
+    #[serde(default)]
+    pub time_aggregation: Option<String>,
+    /// Entity aggregation of the KPI; the configured default when not specified
+    #[serde(default)]
+    pub entity_aggregation: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
@@ -102,24 +76,32 @@ pub struct KpiImplementedData {
     pub data_type: String,
     pub enabled: bool,
     pub source_trendstore_parts: Vec<String>,
+    /// SQL of the KPI function
     pub definition: String,
+    /// KPI expression from which the definition was compiled; not set for KPIs that were
+    /// defined in SQL, before definitions were expressions
+    pub expression: Option<String>,
     pub description: Value,
+    pub time_aggregation: String,
+    pub entity_aggregation: String,
 }
 
 #[derive(Eq, PartialOrd, Ord, PartialEq)]
 pub struct Source {
     pub name: String,
     pub relation: Option<String>,
+    pub data_type: String,
 }
 
 async fn get_source<T: GenericClient + Send + Sync>(
     client: &mut T,
     trend_name: &str,
     entity_type: &str,
+    granularity: &str,
 ) -> Result<Source, String> {
     // First go look for a regular table trend
     let query = concat!(
-        "SELECT tsp.name ",
+        "SELECT tsp.name, t.data_type ",
         "FROM trend_directory.table_trend t ",
         "JOIN trend_directory.trend_store_part tsp ON t.trend_store_part_id = tsp.id ",
         "JOIN trend_directory.trend_store ts ON tsp.trend_store_id = ts.id ",
@@ -135,7 +117,7 @@ async fn get_source<T: GenericClient + Send + Sync>(
     let rows = client
         .query(
             &statement,
-            &[&trend_name, &*DEFAULT_GRANULARITY, &entity_type],
+            &[&trend_name, &granularity, &entity_type],
         )
         .await
         .map_err(|e| {
@@ -153,12 +135,13 @@ async fn get_source<T: GenericClient + Send + Sync>(
         return Ok(Source {
             name: tsp,
             relation: None,
+            data_type: row.get(1),
         });
     }
 
     // Otherwise go look for a view based trend
     let query = concat!(
-        "SELECT tvp.name ",
+        "SELECT tvp.name, t.data_type ",
         "FROM trend_directory.view_trend t ",
         "JOIN trend_directory.trend_view_part tvp ON t.trend_view_part_id = tvp.id ",
         "JOIN trend_directory.trend_view tv ON tvp.trend_view_id = tv.id ",
@@ -174,14 +157,14 @@ async fn get_source<T: GenericClient + Send + Sync>(
     let row = client
         .query_one(
             &statement,
-            &[&trend_name, &*DEFAULT_GRANULARITY, &entity_type],
+            &[&trend_name, &granularity, &entity_type],
         )
         .await
         .map_err(|_| {
             format!(
                 "Could not find source trend store part for trend '{}', granularity '{}', entity type '{}'",
                 &trend_name,
-                &*DEFAULT_GRANULARITY,
+                &granularity,
                 &entity_type,
             )
         })?;
@@ -191,25 +174,106 @@ async fn get_source<T: GenericClient + Send + Sync>(
     return Ok(Source {
         name: tsp,
         relation: None,
+        data_type: row.get(1),
     });
 }
 
+/// Maximum length of a KPI definition, which also limits the size of the compiled SQL
+const MAX_DEFINITION_LENGTH: usize = 4096;
+
+fn invalid_expression(e: ExpressionError) -> ServiceError {
+    ServiceError {
+        kind: ServiceErrorKind::BadRequest,
+        message: format!("Invalid KPI definition: {e}"),
+    }
+}
+
 impl KpiRawData {
+    fn validate(&self) -> Result<(), ServiceError> {
+        let bad_request = |message: String| ServiceError {
+            kind: ServiceErrorKind::BadRequest,
+            message,
+        };
+
+        if self.kpi_name.contains('"') {
+            return Err(bad_request(format!("Invalid KPI name '{}'", self.kpi_name)));
+        }
+
+        if ValueType::from_sql_type(&self.data_type).is_none() {
+            return Err(bad_request(format!(
+                "Invalid KPI data type '{}', expected a numeric type",
+                self.data_type
+            )));
+        }
+
+        if self.definition.len() > MAX_DEFINITION_LENGTH {
+            return Err(bad_request(format!(
+                "KPI definition exceeds the maximum length of {MAX_DEFINITION_LENGTH} characters"
+            )));
+        }
+
+        parse(&self.definition).map_err(invalid_expression)?;
+
+        for aggregation in [&self.time_aggregation, &self.entity_aggregation].into_iter().flatten() {
+            validate_aggregation(aggregation).map_err(|e| ServiceError {
+                kind: ServiceErrorKind::BadRequest,
+                message: e,
+            })?;
+        }
+
+        Ok(())
+    }
+
     async fn get_implemented_data<T: GenericClient + Send + Sync>(
         &self,
         client: &mut T,
-    ) -> Result<KpiImplementedData, String> {
-        let mut sources: Vec<String> = vec![];
+        config: &KpiConfig,
+    ) -> Result<KpiImplementedData, ServiceError> {
+        let expression = parse(&self.definition).map_err(invalid_expression)?;
+
+        let expression_trends = expression.trends();
 
-        for source_trend in self.source_trends.iter() {
-            let source = get_source(client, source_trend, &self.entity_type).await?;
+        let mut trend_sources: Vec<(String, Source)> = vec![];
+
+        for trend in expression_trends.iter().chain(self.source_trends.iter()) {
+            if trend_sources.iter().any(|(name, _)| name == trend) {
+                continue;
+            }
 
-            sources.push(source.name);
+            let source = get_source(client, trend, &self.entity_type, &config.default_granularity)
+                .await
+                .map_err(|e| ServiceError {
+                    kind: ServiceErrorKind::BadRequest,
+                    message: e,
+                })?;
+
+            trend_sources.push((trend.clone(), source));
         }
 
+        let mut sources: Vec<String> = trend_sources.iter().map(|(_, source)| source.name.clone()).collect();
+
         sources.sort();
         sources.dedup();
 
+        // The sources are joined as t1, t2, ... in the order of source_trendstore_parts
+        let mut columns: HashMap<String, Column> = HashMap::new();
+
+        for (trend, source) in trend_sources.iter().filter(|(trend, _)| expression_trends.contains(trend)) {
+            let value_type = ValueType::from_sql_type(&source.data_type).ok_or_else(|| ServiceError {
+                kind: ServiceErrorKind::BadRequest,
+                message: format!("Invalid KPI definition: trend '{trend}' has non-numeric data type '{}'", source.data_type),
+            })?;
+
+            let index = sources.iter().position(|name| *name == source.name).unwrap() + 1;
+
+            columns.insert(trend.clone(), Column {
+                sql: format!("t{index}.\"{}\"", trend.replace('"', "\"\"")),
+                value_type,
+            });
+        }
+
+        let (definition_sql, _) = expression.compile(&columns).map_err(invalid_expression)?;
+
         Ok(KpiImplementedData {
             tsp_name: self.tsp_name.clone(),
             kpi_name: self.kpi_name.clone(),
@@ -217,36 +281,66 @@ impl KpiRawData {
             data_type: self.data_type.clone(),
             enabled: self.enabled,
             source_trendstore_parts: sources,
-            definition: self.definition.clone(),
+            definition: format!("({definition_sql})::{}", self.data_type),
+            expression: Some(self.definition.clone()),
             description: self.description.clone(),
+            time_aggregation: self.time_aggregation.clone().unwrap_or_else(|| config.time_aggregation.clone()),
+            entity_aggregation: self.entity_aggregation.clone().unwrap_or_else(|| config.entity_aggregation.clone()),
         })
     }
 
     async fn get_kpi<T: GenericClient + Send + Sync>(
         &self,
-        granularity: String,
+        config: &KpiConfig,
+        granularity: &KpiGranularityConfig,
         client: &mut T,
-    ) -> Result<Kpi, String> {
-        let implementedkpi = self.get_implemented_data(client).await?;
+    ) -> Result<Kpi, ServiceError> {
+        let implementedkpi = self.get_implemented_data(client, config).await?;
 
-        let kpi = implementedkpi.get_kpi(client, granularity).await.map_err(|e| format!("Could not define KPI: {e:?}"))?;
-        
-        kpi.ok_or("So such KPI found".into())
+        let kpi = implementedkpi.get_kpi(client, config, granularity).await?;
+
+        kpi.ok_or_else(|| ServiceError {
+            kind: ServiceErrorKind::NotFound,
+            message: format!("No sources found for KPI '{}' with granularity '{}'", self.kpi_name, granularity.granularity),
+        })
     }
 
     async fn create<T: GenericClient + Send + Sync>(
         &self,
         client: &mut T,
-    ) -> Result<String, Error> {
-        let implementedkpi = self.get_implemented_data(client).await.map_err(|e| Error {
-            code: 404,
-            message: e,
-        })?;
+        context: &AuditContext,
+        config: &KpiConfig,
+    ) -> Result<String, ServiceError> {
+        let implementedkpi = self.get_implemented_data(client, config).await?;
 
-        implementedkpi.create(client).await
+        implementedkpi.create(client, context, config).await
     }
 }
 
+/// Keep the expression of a KPI in the extra data of its trend, which is not stored when
+/// creating the trend
+async fn store_expression<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    trend_store_part: &str,
+    kpi_name: &str,
+    expression: &str,
+) -> Result<(), tokio_postgres::Error> {
+    client
+        .execute(
+            concat!(
+                "UPDATE trend_directory.table_trend t ",
+                "SET extra_data = CASE WHEN jsonb_typeof(t.extra_data) = 'object' THEN t.extra_data ELSE '{}' END ",
+                "|| jsonb_build_object('expression', $3::text) ",
+                "FROM trend_directory.trend_store_part tsp ",
+                "WHERE t.trend_store_part_id = tsp.id AND tsp.name = $1 AND t.name = $2"
+            ),
+            &[&trend_store_part, &kpi_name, &expression],
+        )
+        .await?;
+
+    Ok(())
+}
+
 /// Find the source trend store parts for a trend view
 async fn get_view_sources<T: GenericClient + Send + Sync>(
     client: &mut T,
@@ -306,18 +400,20 @@ async fn source_exists<T: GenericClient + Send + Sync>(
 }
 
 impl KpiImplementedData {
-    fn target_trend_store_part(&self, granularity: String) -> String {
+    fn target_trend_store_part(&self, config: &KpiConfig, granularity: &str) -> String {
         format!(
             "{}-{}_{}_{}",
-            *DATASOURCE, &self.tsp_name, &self.entity_type, granularity
+            config.data_source, &self.tsp_name, &self.entity_type, granularity
         )
     }
 
     async fn get_kpi<T: GenericClient + Send + Sync>(
         &self,
         client: &mut T,
-        granularity: String,
-    ) -> Result<Option<Kpi>, Error> {
+        config: &KpiConfig,
+        granularity_config: &KpiGranularityConfig,
+    ) -> Result<Option<Kpi>, ServiceError> {
+        let granularity = &granularity_config.granularity;
         let mut sources: Vec<TrendMaterializationSourceData> = vec![];
         let mut query_sources: Vec<String> = Vec::new();
         let mut modifieds: Vec<String> = vec![];
@@ -328,30 +424,27 @@ impl KpiImplementedData {
 
         for default_source_name in self.source_trendstore_parts.iter() {
             // Map the source name for the default granularity to the requested granularity
-            let source_name = default_source_name
-                .replace(&DEFAULT_GRANULARITY.to_string(), &granularity.to_string());
+            let source_name = match config.source_for_granularity(default_source_name, granularity) {
+                Some(source_name) => source_name,
+                None => return Ok(None),
+            };
 
             // Check if the source exists
-            if !source_exists(client, &source_name).await.map_err(|e| Error { code: 500, message: format!("Could not check source existence: {e}") } )? {
+            if !source_exists(client, &source_name).await? {
                 return Ok(None);
             }
 
             query_sources.push(source_name.clone());
 
             // Check if the source is a view, and if so, find it's sources
-            let source_trend_store_parts = map_view_sources(client, &source_name)
-                .await
-                .map_err(|e| Error {
-                    code: 500,
-                    message: format!("Could not map source: {e}"),
-                })?;
+            let source_trend_store_parts = map_view_sources(client, &source_name).await?;
 
             let actual_source_name = source_trend_store_parts[0].to_string();
 
             for source_trend_store_part in source_trend_store_parts {
                 sources.push(TrendMaterializationSourceData {
                     trend_store_part: source_trend_store_part.clone(),
-                    mapping_function: MAPPING_FUNCTION.to_string(),
+                    mapping_function: config.mapping_function.clone(),
                 });
             }
 
@@ -396,46 +489,39 @@ impl KpiImplementedData {
 
         let function_src = map_sql_to_plpgsql(src_lines.join(""));
 
-        let granularity_interval = parse_interval(&granularity)
+        let granularity_interval = granularity
+            .parse::<Granularity>()
+            .map(|granularity| granularity.to_duration())
             .map_err(|e| 
-                Error {
-                    code: 500,
+                ServiceError {
+                    kind: ServiceErrorKind::InternalError,
                     message: format!("Could not parse granularity '{granularity}': {e}"),
                 }
             )?;
 
         let kpi = Kpi {
             trend_store_part: TrendStorePartCompleteData {
-                name: self.target_trend_store_part(granularity.clone()),
+                name: self.target_trend_store_part(config, granularity),
                 entity_type: self.entity_type.clone(),
-                data_source: DATASOURCE.to_string(),
+                data_source: config.data_source.clone(),
                 granularity: granularity_interval,
-                partition_size: *PARTITION_SIZE.get(granularity.as_str()).unwrap(),
+                partition_size: granularity_config.partition_size,
                 trends: vec![TrendData {
                     name: self.kpi_name.clone(),
                     data_type: self.data_type.clone(),
-                    time_aggregation: TIME_AGGREGATION.clone(),
-                    entity_aggregation: ENTITY_AGGREGATION.clone(),
-                    extra_data: json!("{}"),
+                    time_aggregation: self.time_aggregation.clone(),
+                    entity_aggregation: self.entity_aggregation.clone(),
+                    extra_data: json!({"expression": self.expression}),
                     description: DESCRIPTION.clone(),
                 }],
                 generated_trends: vec![],
             },
             materialization: TrendFunctionMaterializationData {
                 enabled: self.enabled,
-                target_trend_store_part: self.target_trend_store_part(granularity.clone()),
-                processing_delay: PROCESSING_DELAY
-                    .get(granularity.as_str())
-                    .unwrap()
-                    .to_owned(),
-                stability_delay: STABILITY_DELAY
-                    .get(granularity.as_str())
-                    .unwrap()
-                    .to_owned(),
-                reprocessing_period: REPROCESSING_PERIOD
-                    .get(granularity.as_str())
-                    .unwrap()
-                    .to_owned(),
+                target_trend_store_part: self.target_trend_store_part(config, granularity),
+                processing_delay: granularity_config.processing_delay,
+                stability_delay: granularity_config.stability_delay,
+                reprocessing_period: granularity_config.reprocessing_period,
                 sources,
                 function: TrendMaterializationFunctionData {
                     return_type: format!(
@@ -456,24 +542,22 @@ impl KpiImplementedData {
     async fn create<T: GenericClient + Send + Sync>(
         &self,
         client: &mut T,
-    ) -> Result<String, Error> {
-        for granularity in GRANULARITIES.iter() {
-            if let Some(kpi) = self.get_kpi(client, granularity.to_string()).await? {
+        context: &AuditContext,
+        config: &KpiConfig,
+    ) -> Result<String, ServiceError> {
+        for granularity in config.granularities.iter() {
+            if let Some(kpi) = self.get_kpi(client, config, granularity).await? {
                 kpi.trend_store_part
-                    .create(client)
-                    .await
-                    .map_err(|e| Error {
-                        code: e.code,
-                        message: e.message,
-                    })?;
+                    .create(client, context)
+                    .await?;
+
+                if let Some(expression) = &self.expression {
+                    store_expression(client, &kpi.trend_store_part.name, &self.kpi_name, expression).await?;
+                }
 
                 kpi.materialization
-                    .create(client)
-                    .await
-                    .map_err(|e| Error {
-                        code: e.code,
-                        message: e.message,
-                    })?;
+                    .create(client, context)
+                    .await?;
             }
         }
 
@@ -481,99 +565,144 @@ impl KpiImplementedData {
     }
 }
 
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct KpiListQuery {
+    /// Only KPIs of this entity type
+    entity_type: Option<String>,
+    /// Only KPIs with a name matching this pattern, in which '*' matches any characters
+    name: Option<String>,
+    /// Only enabled or only disabled KPIs
+    enabled: Option<bool>,
+    /// Field to sort on: name (default), entity_type or trend_store_part, prefixed with '-'
+    /// for descending order
+    sort: Option<String>,
+    /// Cursor from the Link header of the previous page
+    cursor: Option<String>,
+    /// Maximum number of KPIs to return, all when not specified
+    limit: Option<i64>,
+}
+
+const KPI_SORT_COLUMNS: [SortColumn; 3] = [
+    SortColumn { name: "name", expression: "item.name", sql_type: "text" },
+    SortColumn { name: "entity_type", expression: "item.entity_type", sql_type: "text" },
+    SortColumn { name: "trend_store_part", expression: "item.trend_store_part", sql_type: "text" },
+];
+
+const KPI_KEY: SortColumn = SortColumn { name: "id", expression: "item.id", sql_type: "integer" };
+
 #[utoipa::path(
     get,
     path="/kpis",
+    params(KpiListQuery),
     responses(
-    (status = 200, description = "List of existing KPIs", body = [KpiImplementedData]),
-    (status = 500, description = "Database unreachable", body = Error),
+    (status = 200, description = "List of existing KPIs", body = [KpiImplementedData],
+        headers(("Link" = String, description = "Link to the next page when there are more KPIs"))),
+    (status = 400, description = "Invalid filter, sort order, cursor or limit", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
     )
 )]
 #[get("/kpis")]
-pub(super) async fn get_kpis(pool: Data<Pool>) -> Result<HttpResponse, ServiceError> {
-    let client = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+pub(super) async fn get_kpis(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+    config: Data<KpiConfig>,
+    query: Query<KpiListQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let client = pool.get().await?;
+
+    let mut listing = Listing::new(
+        concat!(
+            "SELECT t.id, t.name::text AS name, tsp.name::text AS trend_store_part, ",
+            "et.name::text AS entity_type, t.data_type, m.enabled, m.id AS materialization_id, ",
+            "prosrc AS definition, m.description, ds.name::text AS data_source, ts.granularity, ",
+            "t.time_aggregation, t.entity_aggregation, t.extra_data->>'expression' AS expression ",
+            "FROM trend_directory.table_trend t ",
+            "JOIN trend_directory.trend_store_part tsp ON t.trend_store_part_id = tsp.id ",
+            "JOIN trend_directory.trend_store ts ON tsp.trend_store_id = ts.id ",
+            "JOIN directory.data_source ds ON ts.data_source_id = ds.id ",
+            "JOIN directory.entity_type et ON ts.entity_type_id = et.id ",
+            "JOIN trend_directory.materialization m ON tsp.id = m.dst_trend_store_part_id ",
+            "JOIN trend_directory.function_materialization fm ON m.id = fm.materialization_id ",
+            "JOIN pg_proc ON pg_proc.oid = format('%s(timestamptz)', fm.src_function)::regprocedure::oid"
+        ),
+        &KPI_SORT_COLUMNS,
+        &KPI_KEY,
+    );
+
+    listing
+        .filter("item.data_source = $?", Some(config.data_source.as_str()))
+        .filter("item.granularity = $?::text::interval", Some(config.default_granularity.as_str()))
+        .filter("item.entity_type = $?", query.entity_type.as_deref())
+        .filter("item.enabled = $?::text::bool", query.enabled)
+        .filter_pattern("item.name", query.name.as_deref());
+
+    let page = listing
+        .fetch(client.client(), &PageRequest {
+            sort: query.sort.as_deref(),
+            cursor: query.cursor.as_deref(),
+            limit: query.limit,
+        })
+        .await?;
+
+    let materialization_ids: Vec<i32> = page.rows
+        .iter()
+        .map(|row| row.get("materialization_id"))
+        .collect();
 
     let sources: Vec<TrendMaterializationSourceIdentifier> = client
         .query(
             concat!(
                 "SELECT materialization_id, tsp.name, timestamp_mapping_func::text ",
                 "FROM trend_directory.materialization_trend_store_link ",
-                "JOIN trend_directory.trend_store_part tsp ON trend_store_part_id = tsp.id"
+                "JOIN trend_directory.trend_store_part tsp ON trend_store_part_id = tsp.id ",
+                "WHERE materialization_id = ANY($1)"
             ),
-            &[],
+            &[&materialization_ids],
         )
-        .await
-        .map_err(|e| Error {
-            code: 500,
-            message: e.to_string(),
+        .await?
+        .iter()
+        .map(|row| TrendMaterializationSourceIdentifier {
+            materialization: row.get(0),
+            source: TrendMaterializationSourceData {
+                trend_store_part: row.get(1),
+                mapping_function: row.get(2),
+            },
         })
-        .map(|rows| {
-            rows.iter()
-                .map(|row| TrendMaterializationSourceIdentifier {
-                    materialization: row.get(0),
-                    source: TrendMaterializationSourceData {
-                        trend_store_part: row.get(1),
-                        mapping_function: row.get(2),
-                    },
-                })
-                .collect()
-        })?;
-
-    let result: Vec<KpiImplementedData> = client
-        .query(
-            concat!(
-                "SELECT t.name, tsp.name, et.name, t.data_type, m.enabled, m.id, prosrc, m.description ",
-                "FROM trend_directory.table_trend t ",
-                "JOIN trend_directory.trend_store_part tsp ON t.trend_store_part_id = tsp.id ",
-                "JOIN trend_directory.trend_store ts ON tsp.trend_store_id = ts.id ",
-                "JOIN directory.data_source ds ON ts.data_source_id = ds.id ",
-                "JOIN directory.entity_type et ON ts.entity_type_id = et.id ",
-                "JOIN trend_directory.materialization m ON tsp.id = m.dst_trend_store_part_id ",
-                "JOIN trend_directory.function_materialization fm ON m.id = fm.materialization_id ",
-                "JOIN pg_proc ON pg_proc.oid = format('%s(timestamptz)', fm.src_function)::regprocedure::oid ",
-                "WHERE ds.name = $1 AND ts.granularity = $2::text::interval ",
-                "ORDER BY t.name"
-            ),
-            &[&*DATASOURCE, &*DEFAULT_GRANULARITY]
-        )
-        .await
-        .map_err(|e| Error {
-            code: 500,
-            message: e.to_string(),
+        .collect();
+
+    let result: Vec<KpiImplementedData> = page.rows
+        .iter()
+        .map(|row| {
+            let mat_id: i32 = row.get("materialization_id");
+            let this_sources: Vec<String> = sources
+                .iter()
+                .filter(|source| source.materialization == mat_id)
+                .map(|source| source.source.trend_store_part.clone())
+                .collect();
+
+            let full_tsp_name: String = row.get("trend_store_part");
+            let tsp_name = ((full_tsp_name
+                .splitn(2, '-').collect::<Vec<&str>>())[1]
+                .rsplitn(3, '_').collect::<Vec<&str>>())[2];
+
+            KpiImplementedData {
+                kpi_name: row.get("name"),
+                tsp_name: tsp_name.to_string(),
+                entity_type: row.get("entity_type"),
+                data_type: row.get("data_type"),
+                enabled: row.get("enabled"),
+                source_trendstore_parts: this_sources,
+                definition: row.get("definition"),
+                expression: row.get("expression"),
+                description: row.get("description"),
+                time_aggregation: row.get("time_aggregation"),
+                entity_aggregation: row.get("entity_aggregation"),
+            }
         })
-        .map(|rows| rows
-            .iter()
-            .map(|row| {
-                let mat_id: i32 = row.get(5);
-                let this_sources: Vec<String> = sources
-                    .iter()
-                    .filter(|source| source.materialization == mat_id)
-                    .map(|source| source.source.trend_store_part.clone())
-                    .collect();
-
-                let full_tsp_name: String = row.get(1);
-                let tsp_name = ((full_tsp_name
-                    .splitn(2, '-').collect::<Vec<&str>>())[1]
-                    .rsplitn(3, '_').collect::<Vec<&str>>())[2];
-
-                KpiImplementedData {
-                    kpi_name: row.get(0),
-                    tsp_name: tsp_name.to_string(),
-                    entity_type: row.get(2),
-                    data_type: row.get(3),
-                    enabled: row.get(4),
-                    source_trendstore_parts: this_sources,
-                    definition: row.get(6),
-                    description: row.get(7),
-                }
-            })
-            .collect()
-        )?;
+        .collect();
 
-    Ok(HttpResponse::Ok().json(result))
+    Ok(page_response(&req, &result, page.next_cursor))
 }
 
 #[utoipa::path(
@@ -581,25 +710,24 @@ pub(super) async fn get_kpis(pool: Data<Pool>) -> Result<HttpResponse, ServiceEr
     path="/kpis/{name}",
     responses(
     (status = 200, description = "Content of KPI", body = [KpiImplementedData]),
-    (status = 404, description = "KPI does not exist", body = Error),
-    (status = 500, description = "Database unreachable", body = Error),
+    (status = 404, description = "KPI does not exist", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
     )
 )]
 #[get("/kpis/{name}")]
 pub(super) async fn get_kpi(
+    _access: ReadAccess,
     pool: Data<Pool>,
+    config: Data<KpiConfig>,
     name: Path<String>,
 ) -> Result<HttpResponse, ServiceError> {
     let kpiname = name.into_inner().replace('_', " ");
 
-    let client = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+    let client = pool.get().await?;
     let kpi = client
         .query_one(
             concat!(
-                "SELECT t.name, tsp.name, et.name, t.data_type, m.enabled, m.id, routine_definition, m.description ",
+                "SELECT t.name, tsp.name, et.name, t.data_type, m.enabled, m.id, routine_definition, m.description, t.time_aggregation, t.entity_aggregation, t.extra_data->>'expression' ",
                 "FROM trend_directory.table_trend t ",
                 "JOIN trend_directory.trend_store_part tsp ON t.trend_store_part_id = tsp.id ",
                 "JOIN trend_directory.trend_store ts ON tsp.trend_store_id = ts.id ",
@@ -608,13 +736,13 @@ pub(super) async fn get_kpi(
                 "JOIN trend_directory.materialization m ON tsp.id = m.dst_trend_store_part_id ",
                 "JOIN trend_directory.function_materialization fm ON m.id = fm.materialization_id ",
                 "JOIN information_schema.routines ON FORMAT('%s.\"%s\"', routine_schema, routine_name) = fm.src_function ",
-                "WHERE ds.name = $1 AND ts.granularity = $2 AND t.name = $3"
+                "WHERE ds.name = $1 AND ts.granularity = $2::text::interval AND t.name = $3"
             ),
-            &[&*DATASOURCE, &*DEFAULT_GRANULARITY, &kpiname]
+            &[&config.data_source, &config.default_granularity, &kpiname]
         )
         .await
-        .map_err(|_| Error {
-            code: 404,
+        .map_err(|_| ServiceError {
+            kind: ServiceErrorKind::NotFound,
             message: format!("KPI {} not found", &kpiname),
         })?;
 
@@ -630,10 +758,6 @@ pub(super) async fn get_kpi(
             &[&materialization_id],
         )
         .await
-        .map_err(|e| Error {
-            code: 500,
-            message: e.to_string(),
-        })
         .map(|rows| rows.iter().map(|row| row.get(1)).collect())?;
 
     let full_tsp_name: String = kpi.get(1);
@@ -649,51 +773,53 @@ pub(super) async fn get_kpi(
         enabled: kpi.get(4),
         source_trendstore_parts: sources,
         definition: kpi.get(6),
+        expression: kpi.get(10),
         description: kpi.get(7),
+        time_aggregation: kpi.get(8),
+        entity_aggregation: kpi.get(9),
     };
 
     Ok(HttpResponse::Ok().json(result))
 }
 
-// curl -H "Content-Type: application/json" -X POST -d '{"name":"average-output","entity_type":"Cell","data_type":"numeric","enabled":true,"source_trends":["L.Thrp.bits.UL.NsaDc","L.DL.CRS.RateAvg"],"definition":"public.safe_division(SUM(\"L.Thrp.bits.UL.NsaDc\"),SUM(\"L.DL.CRS.RateAvg\") * 1000)","description":{"type": "ratio", "numerator": [{"type": "trend", "value": "L.Thrp.bits.UL.NsaDC"}], "denominator": [{"type": "constant", "value": "1000"}, {"type": "operator", "value": "*"}, {"type": "trend", "value": "L.DL.CRS.RateAvg"}]}}' localhost:8000/kpis
+// curl -H "Content-Type: application/json" -X POST -d '{"name":"average-output","entity_type":"Cell","data_type":"numeric","enabled":true,"source_trends":["L.Thrp.bits.UL.NsaDc","L.DL.CRS.RateAvg"],"definition":"safe_div(SUM(L.Thrp.bits.UL.NsaDc), SUM(L.DL.CRS.RateAvg) * 1000)","description":{"type": "ratio", "numerator": [{"type": "trend", "value": "L.Thrp.bits.UL.NsaDC"}], "denominator": [{"type": "constant", "value": "1000"}, {"type": "operator", "value": "*"}, {"type": "trend", "value": "L.DL.CRS.RateAvg"}]}}' localhost:8000/kpis
 
 #[utoipa::path(
     post,
     path="/kpis",
     responses(
     (status = 200, description = "Create a new KPI", body = Success),
-    (status = 400, description = "Incorrect data format", body = Error),
-    (status = 409, description = "KPI creation failed", body = Error),
-    (status = 500, description = "Database unreachable", body = Error),
+    (status = 400, description = "Incorrect data format or invalid KPI definition", body = ProblemDetails),
+    (status = 409, description = "KPI creation failed", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
     )
 )]
 #[post("/kpis")]
-pub(super) async fn post_kpi(pool: Data<Pool>, post: String) -> Result<HttpResponse, ServiceError> {
-    let data: KpiRawData = serde_json::from_str(&post).map_err(|e| Error {
-        code: 400,
+pub(super) async fn post_kpi(
+    access: AdminAccess,
+    pool: Data<Pool>,
+    config: Data<KpiConfig>,
+    post: String,
+) -> Result<HttpResponse, ServiceError> {
+    let data: KpiRawData = serde_json::from_str(&post).map_err(|e| ServiceError {
+        kind: ServiceErrorKind::BadRequest,
         message: format!("Unable to parse input JSON data: {e}"),
     })?;
 
-    let mut manager = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+    data.validate()?;
+
+    let mut manager = pool.get().await?;
 
     let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
 
-    let mut transaction = client.transaction().await.map_err(|e| Error {
-        code: 500,
-        message: e.to_string(),
-    })?;
+    let mut transaction = client.transaction().await?;
 
     transaction.execute("SET LOCAL citus.multi_shard_modify_mode TO 'sequential';", &[]).await?;
 
-    data.create(&mut transaction).await?;
+    data.create(&mut transaction, &access.audit_context(), &config)
+        .await?;
 
-    transaction.commit().await.map_err(|e| Error {
-        code: 500,
-        message: e.to_string(),
-    })?;
+    transaction.commit().await?;
 
     Ok(HttpResponse::Ok().json(Success {
         code: 200,
@@ -701,56 +827,73 @@ pub(super) async fn post_kpi(pool: Data<Pool>, post: String) -> Result<HttpRespo
     }))
 }
 
-// curl -H "Content-Type: application/json" -X PUT -d '{"name":"average-output","entity_type":"Cell","data_type":"numeric","enabled":true,"source_trends":["L.Thrp.bits.UL.NsaDc"],"definition":"public.safe_division(SUM(\"L.Thrp.bits.UL.NsaDc\"),1000::numeric)","description":{"type": "ratio", "numerator": [{"type": "trend", "value": "L.Thrp.bits.UL.NsaDC"}], "denominator": [{"type": "constant", "value": "1000"}]}}' localhost:8000/kpis
+// curl -H "Content-Type: application/json" -X PUT -d '{"name":"average-output","entity_type":"Cell","data_type":"numeric","enabled":true,"source_trends":["L.Thrp.bits.UL.NsaDc"],"definition":"SUM(L.Thrp.bits.UL.NsaDc) / 1000","description":{"type": "ratio", "numerator": [{"type": "trend", "value": "L.Thrp.bits.UL.NsaDC"}], "denominator": [{"type": "constant", "value": "1000"}]}}' localhost:8000/kpis
+/// Update a KPI with a new definition. KPIs that were defined in SQL, before definitions were
+/// expressions, are updated like any other KPI, so their definition has to be rewritten as an
+/// expression; a SQL definition is rejected as invalid.
 #[utoipa::path(
     put,
     path="/kpis",
     responses(
     (status = 200, description = "Updated KPI", body = Success),
-    (status = 400, description = "Input format incorrect", body = Error),
-    (status = 404, description = "KPI not found", body = Error),
-    (status = 409, description = "Update failed", body = Error),
-    (status = 500, description = "General error", body = Error)
+    (status = 400, description = "Input format incorrect or invalid KPI definition", body = ProblemDetails),
+    (status = 404, description = "KPI not found", body = ProblemDetails),
+    (status = 409, description = "Update failed", body = ProblemDetails),
+    (status = 500, description = "General error", body = ProblemDetails)
     )
 )]
 #[put("/kpis")]
 pub(super) async fn update_kpi(
+    access: AdminAccess,
     pool: Data<Pool>,
+    config: Data<KpiConfig>,
     post: String,
 ) -> Result<HttpResponse, ServiceError> {
-    let data: KpiRawData = serde_json::from_str(&post).map_err(|e| Error {
-        code: 400,
+    let data: KpiRawData = serde_json::from_str(&post).map_err(|e| ServiceError {
+        kind: ServiceErrorKind::BadRequest,
         message: e.to_string(),
     })?;
 
-    let mut manager = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+    data.validate()?;
+
+    let mut manager = pool.get().await?;
 
     let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
 
-    let mut transaction = client.transaction().await.map_err(|e| Error {
-        code: 500,
-        message: e.to_string(),
-    })?;
+    let mut transaction = client.transaction().await?;
 
-    for granularity in GRANULARITIES.iter() {
+    for granularity in config.granularities.iter() {
         let kpi = data
-            .get_kpi(granularity.to_string(), &mut transaction)
-            .await
-            .map_err(|e| Error {
-                code: 404,
-                message: e,
-            })?;
+            .get_kpi(&config, granularity, &mut transaction)
+            .await?;
+
+        kpi.materialization
+            .update(&mut transaction, &access.audit_context())
+            .await?;
+
+        // Aggregations that are not specified are left unchanged
+        transaction
+            .execute(
+                concat!(
+                    "UPDATE trend_directory.table_trend t ",
+                    "SET time_aggregation = coalesce($1, t.time_aggregation), ",
+                    "entity_aggregation = coalesce($2, t.entity_aggregation) ",
+                    "FROM trend_directory.trend_store_part tsp ",
+                    "WHERE t.trend_store_part_id = tsp.id AND tsp.name = $3 AND t.name = $4"
+                ),
+                &[
+                    &data.time_aggregation,
+                    &data.entity_aggregation,
+                    &kpi.trend_store_part.name,
+                    &data.kpi_name,
+                ],
+            )
+            .await?;
 
-        kpi.materialization.update(&mut transaction).await?;
+        store_expression(&mut transaction, &kpi.trend_store_part.name, &data.kpi_name, &data.definition).await?;
     }
 
-    transaction.commit().await.map_err(|e| Error {
-        code: 500,
-        message: e.to_string(),
-    })?;
+    transaction.commit().await?;
 
     Ok(HttpResponse::Ok().json(Success {
         code: 200,
@@ -763,35 +906,31 @@ pub(super) async fn update_kpi(
     path="/kpis/{et}/{name}",
     responses(
     (status = 200, description = "Updated KPI", body = Success),
-    (status = 400, description = "Input format incorrect", body = Error),
-    (status = 404, description = "KPI not found", body = Error),
-    (status = 409, description = "Update failed", body = Error),
-    (status = 500, description = "General error", body = Error)
+    (status = 400, description = "Input format incorrect", body = ProblemDetails),
+    (status = 404, description = "KPI not found", body = ProblemDetails),
+    (status = 409, description = "Update failed", body = ProblemDetails),
+    (status = 500, description = "General error", body = ProblemDetails)
     )
 )]
 #[delete("/kpis/{et}/{name}")]
 pub(super) async fn delete_kpi(
+    access: AdminAccess,
     pool: Data<Pool>,
+    config: Data<KpiConfig>,
     args: Path<(String, String)>,
 ) -> Result<HttpResponse, ServiceError> {
     let kpiname = &args.1;
     let entitytype = &args.0;
-    let mut manager = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+    let mut manager = pool.get().await?;
 
     let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
 
-    let mut transaction = client.transaction().await.map_err(|e| Error {
-        code: 500,
-        message: e.to_string(),
-    })?;
+    let mut transaction = client.transaction().await?;
 
     let kpi = transaction
         .query_one(
         concat!(
-                "SELECT t.name, tsp.name, et.name, t.data_type, m.enabled, m.id, routine_definition, m.description ",
+                "SELECT t.name, tsp.name, et.name, t.data_type, m.enabled, m.id, routine_definition, m.description, t.time_aggregation, t.entity_aggregation, t.extra_data->>'expression' ",
                 "FROM trend_directory.table_trend t ",
                 "JOIN trend_directory.trend_store_part tsp ON t.trend_store_part_id = tsp.id ",
                 "JOIN trend_directory.trend_store ts ON tsp.trend_store_id = ts.id ",
@@ -802,7 +941,7 @@ pub(super) async fn delete_kpi(
                 "JOIN information_schema.routines ON FORMAT('%s.\"%s\"', routine_schema, routine_name) = fm.src_function ",
                 "WHERE ds.name = $1 AND et.name = $2 AND ts.granularity = $3::text::interval AND t.name = $4",
             ),
-            &[&*DATASOURCE, &entitytype, &*DEFAULT_GRANULARITY, &kpiname]
+            &[&config.data_source, &entitytype, &config.default_granularity, &kpiname]
         )
         .await
         .map_err(|_| ServiceError {
@@ -823,10 +962,6 @@ pub(super) async fn delete_kpi(
             &[&materialization_id],
         )
         .await
-        .map_err(|e| Error {
-            code: 500,
-            message: e.to_string(),
-        })
         .map(|rows| rows.iter().map(|row| row.get(1)).collect())?;
 
     let full_tsp_name: String = kpi.get(1);
@@ -842,55 +977,74 @@ pub(super) async fn delete_kpi(
         enabled: kpi.get(4),
         source_trendstore_parts: sources,
         definition: kpi.get(6),
+        expression: kpi.get(10),
         description: kpi.get(7),
+        time_aggregation: kpi.get(8),
+        entity_aggregation: kpi.get(9),
     };
 
-    for granularity in GRANULARITIES.iter() {
+    let mut target_trend_store_parts: Vec<(Option<Kpi>, String)> = Vec::new();
+
+    for granularity in config.granularities.iter() {
         let kpi_result = kpidata
-            .get_kpi(&mut transaction, granularity.to_string())
+            .get_kpi(&mut transaction, &config, granularity)
             .await?;
 
-        if let Some(kpi) = kpi_result {
-            kpi.materialization
-                .as_minerva()
-                .delete(&mut transaction)
-                .await
-                .map_err(|e| Error {
-                    code: 409,
-                    message: e.to_string(),
-                })?;
+        target_trend_store_parts.push((
+            kpi_result,
+            kpidata.target_trend_store_part(&config, &granularity.granularity),
+        ));
+    }
+
+    let result = delete_kpi_trend_store_parts(&mut transaction, &target_trend_store_parts)
+        .await
+        .map(|_| "KPI deleted".to_string());
+
+    let recorded = record_change(
+        &mut transaction,
+        &access.audit_context(),
+        &format!("DeleteKpi({entitytype}, {kpiname})"),
+        None,
+        &result,
+    )
+    .await;
+
+    let message = result?;
+
+    recorded?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(Success {
+        code: 200,
+        message,
+    }))
+}
+
+/// Delete the materializations and trend store parts of a KPI for every granularity
+async fn delete_kpi_trend_store_parts<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    target_trend_store_parts: &[(Option<Kpi>, String)],
+) -> Result<(), Error> {
+    for (kpi, target_trend_store_part) in target_trend_store_parts {
+        if let Some(kpi) = kpi {
+            kpi.materialization.as_minerva().delete(client).await?;
         };
 
-        transaction
+        client
             .query(
                 "DELETE FROM trend_directory.trend_store_part WHERE NAME = $1",
-                &[&("kpi-".to_string() + tsp_name + "_" + entitytype + "_" + granularity)],
+                &[&target_trend_store_part],
             )
-            .await
-            .map_err(|e| Error {
-                code: 500,
-                message: e.to_string(),
-            })?;
+            .await?;
 
-        transaction
+        client
             .query(
-                &format!("DROP TABLE trend.\"kpi-{tsp_name}_{entitytype}_{granularity}_staging\""),
+                &format!("DROP TABLE trend.\"{target_trend_store_part}_staging\""),
                 &[],
             )
-            .await
-            .map_err(|e| Error {
-                code: 500,
-                message: e.to_string(),
-            })?;
+            .await?;
     }
 
-    transaction.commit().await.map_err(|e| Error {
-        code: 500,
-        message: e.to_string(),
-    })?;
-
-    Ok(HttpResponse::Ok().json(Success {
-        code: 200,
-        message: "KPI deleted".to_string(),
-    }))
+    Ok(())
 }
@@ -0,0 +1,247 @@
+use std::env;
+use std::path::Path;
+use std::time::Duration;
+
+use regex::Regex;
+use serde::Deserialize;
+
+use minerva::granularity::Granularity;
+
+static ENV_KPI_CONFIG_FILE: &str = "SERVICE_KPI_CONFIG_FILE";
+
+/// Settings of the KPI trend store parts and materializations for one granularity
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KpiGranularityConfig {
+    pub granularity: String,
+    #[serde(with = "humantime_serde")]
+    pub processing_delay: Duration,
+    #[serde(with = "humantime_serde")]
+    pub stability_delay: Duration,
+    #[serde(with = "humantime_serde")]
+    pub reprocessing_period: Duration,
+    #[serde(with = "humantime_serde")]
+    pub partition_size: Duration,
+}
+
+/// Deployment specific settings for the KPIs managed through the service
+///
+/// KPIs are defined on source trend store parts of the default granularity and are
+/// materialized for every configured granularity.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KpiConfig {
+    /// Data source of the KPI trend store parts
+    pub data_source: String,
+    /// Granularity of the source trend store parts that KPI definitions refer to
+    pub default_granularity: String,
+    /// Timestamp mapping function for the KPI materialization sources
+    pub mapping_function: String,
+    /// Time aggregation of KPIs that do not specify one
+    pub time_aggregation: String,
+    /// Entity aggregation of KPIs that do not specify one
+    pub entity_aggregation: String,
+    pub granularities: Vec<KpiGranularityConfig>,
+}
+
+impl Default for KpiConfig {
+    fn default() -> Self {
+        let minutes = |m: u64| Duration::from_secs(m * 60);
+        let days = |d: u64| Duration::from_secs(d * 86400);
+
+        let granularity =
+            |granularity: &str, processing_delay, partition_size| KpiGranularityConfig {
+                granularity: granularity.to_string(),
+                processing_delay,
+                stability_delay: minutes(5),
+                reprocessing_period: days(3),
+                partition_size,
+            };
+
+        KpiConfig {
+            data_source: "kpi".to_string(),
+            default_granularity: "1d".to_string(),
+            mapping_function: "trend.mapping_id".to_string(),
+            time_aggregation: "SUM".to_string(),
+            entity_aggregation: "SUM".to_string(),
+            granularities: vec![
+                granularity("15m", minutes(10), days(1)),
+                granularity("1h", minutes(10), days(4)),
+                granularity(
+                    "1d",
+                    minutes(30),
+                    humantime::parse_duration("3months").unwrap(),
+                ),
+                granularity("1w", minutes(30), humantime::parse_duration("1y").unwrap()),
+                granularity(
+                    "1month",
+                    minutes(30),
+                    humantime::parse_duration("5y").unwrap(),
+                ),
+            ],
+        }
+    }
+}
+
+impl KpiConfig {
+    /// Load the configuration from the file set by SERVICE_KPI_CONFIG_FILE, or use the
+    /// defaults when it is not set
+    pub fn from_env() -> Result<KpiConfig, String> {
+        match env::var(ENV_KPI_CONFIG_FILE) {
+            Ok(path) => KpiConfig::from_file(Path::new(&path)),
+            Err(_) => Ok(KpiConfig::default()),
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<KpiConfig, String> {
+        let file = std::fs::File::open(path).map_err(|e| {
+            format!(
+                "Could not open KPI configuration file '{}': {e}",
+                path.display()
+            )
+        })?;
+
+        let config: KpiConfig = serde_yaml::from_reader(file).map_err(|e| {
+            format!(
+                "Could not parse KPI configuration file '{}': {e}",
+                path.display()
+            )
+        })?;
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        // The data source is separated from the rest of the trend store part name by a '-'
+        if self.data_source.contains('-') {
+            return Err(format!(
+                "KPI data source '{}' may not contain a '-'",
+                self.data_source
+            ));
+        }
+
+        for granularity in &self.granularities {
+            granularity
+                .granularity
+                .parse::<Granularity>()
+                .map_err(|e| {
+                    format!("Invalid KPI granularity '{}': {e}", granularity.granularity)
+                })?;
+        }
+
+        if self.granularity(&self.default_granularity).is_none() {
+            return Err(format!(
+                "Default KPI granularity '{}' is not one of the configured granularities",
+                self.default_granularity
+            ));
+        }
+
+        validate_aggregation(&self.time_aggregation)?;
+        validate_aggregation(&self.entity_aggregation)?;
+
+        Ok(())
+    }
+
+    pub fn granularity(&self, granularity: &str) -> Option<&KpiGranularityConfig> {
+        self.granularities
+            .iter()
+            .find(|config| config.granularity == granularity)
+    }
+
+    /// Name of the source for `granularity` corresponding to a source of the default
+    /// granularity, which is expected to end with the default granularity, like
+    /// 'hub_node_main_1d'
+    pub fn source_for_granularity(&self, source: &str, granularity: &str) -> Option<String> {
+        if granularity == self.default_granularity {
+            return Some(source.to_string());
+        }
+
+        source
+            .strip_suffix(&format!("_{}", self.default_granularity))
+            .map(|base| format!("{base}_{granularity}"))
+    }
+}
+
+/// Check that an aggregation is the name of an aggregate function, like 'SUM' or 'AVG',
+/// optionally qualified with a schema
+pub fn validate_aggregation(aggregation: &str) -> Result<(), String> {
+    let aggregation_re =
+        Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*(\.[A-Za-z_][A-Za-z0-9_]*)?$").unwrap();
+
+    match aggregation_re.is_match(aggregation) {
+        true => Ok(()),
+        false => Err(format!(
+            "Invalid aggregation '{aggregation}', expected the name of an aggregate function"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        let config: KpiConfig = serde_yaml::from_str(concat!(
+            "data_source: derived_kpi\n",
+            "default_granularity: 1h\n",
+            "mapping_function: trend.mapping_id\n",
+            "time_aggregation: SUM\n",
+            "entity_aggregation: AVG\n",
+            "granularities:\n",
+            "- granularity: 1h\n",
+            "  processing_delay: 10m\n",
+            "  stability_delay: 5m\n",
+            "  reprocessing_period: 3days\n",
+            "  partition_size: 4days\n",
+        ))
+        .unwrap();
+
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config.granularity("1h").unwrap().partition_size,
+            Duration::from_secs(4 * 86400)
+        );
+
+        let mut config = config;
+        config.default_granularity = "1d".to_string();
+
+        assert!(config.validate().is_err());
+
+        config.default_granularity = "1h".to_string();
+        config.data_source = "kpi-derived".to_string();
+
+        assert!(config.validate().is_err());
+        assert!(KpiConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn source_names() {
+        let config = KpiConfig::default();
+
+        assert_eq!(
+            config.source_for_granularity("hub_node_main_1d", "1h"),
+            Some("hub_node_main_1h".to_string())
+        );
+        // Only the granularity suffix is replaced
+        assert_eq!(
+            config.source_for_granularity("hub_1d_node_1d", "15m"),
+            Some("hub_1d_node_15m".to_string())
+        );
+        assert_eq!(config.source_for_granularity("hub_node_main", "1h"), None);
+        assert_eq!(
+            config.source_for_granularity("hub_node_main", "1d"),
+            Some("hub_node_main".to_string())
+        );
+    }
+
+    #[test]
+    fn aggregations() {
+        assert!(validate_aggregation("SUM").is_ok());
+        assert!(validate_aggregation("public.weighted_avg").is_ok());
+        assert!(validate_aggregation("SUM); DROP TABLE x; --").is_err());
+        assert!(validate_aggregation("").is_err());
+    }
+}
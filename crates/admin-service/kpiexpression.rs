@@ -0,0 +1,767 @@
+//! Expression language for KPI definitions
+//!
+//! KPIs are defined as expressions over the trends of the source trend store parts, like
+//! `safe_div(SUM(power_kwh), SUM(samples)) * 1000`. Expressions are type checked against the
+//! data types of the trends and compiled to SQL, so that KPI definitions never contain SQL
+//! written by users.
+//!
+//! - Trends are referenced by name, quoted with double quotes when the name contains other
+//!   characters than letters, digits, '_' and '.', like `"L.Thrp.bits.UL"`
+//! - Constants are integer or decimal numbers
+//! - Arithmetic with `+`, `-`, `*` and `/`, and parentheses
+//! - `safe_div(numerator, denominator)` results in NULL when the denominator is 0, and
+//!   `safe_div(numerator, denominator, value)` results in `value`
+//! - Aggregates `SUM`, `AVG`, `MIN`, `MAX` and `COUNT` over the values of an entity; every
+//!   trend must be referenced within an aggregate
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl Operator {
+    fn sql(&self) -> &'static str {
+        match self {
+            Operator::Add => "+",
+            Operator::Subtract => "-",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    Sum,
+    Avg,
+    Min,
+    Max,
+    Count,
+    SafeDiv,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Function> {
+        match name.to_lowercase().as_str() {
+            "sum" => Some(Function::Sum),
+            "avg" => Some(Function::Avg),
+            "min" => Some(Function::Min),
+            "max" => Some(Function::Max),
+            "count" => Some(Function::Count),
+            "safe_div" => Some(Function::SafeDiv),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Function::Sum => "SUM",
+            Function::Avg => "AVG",
+            Function::Min => "MIN",
+            Function::Max => "MAX",
+            Function::Count => "COUNT",
+            Function::SafeDiv => "safe_div",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Trend(String),
+    /// Numeric literal as written in the expression
+    Constant(String),
+    Negate(Box<Expression>),
+    Binary(Box<Expression>, Operator, Box<Expression>),
+    Function(Function, Vec<Expression>),
+}
+
+/// Numeric types that KPI expressions operate on, ordered by precedence in arithmetic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ValueType {
+    Integer,
+    Numeric,
+    Float,
+}
+
+impl ValueType {
+    /// The value type of a PostgreSQL data type, or None for non-numeric types
+    pub fn from_sql_type(data_type: &str) -> Option<ValueType> {
+        let data_type = data_type.trim().to_lowercase();
+
+        // Strip the precision and scale, as in 'numeric(10,2)'
+        let base_type = match data_type.split_once('(') {
+            Some((base_type, modifiers))
+                if modifiers.ends_with(')')
+                    && modifiers[..modifiers.len() - 1]
+                        .chars()
+                        .all(|c| c.is_ascii_digit() || c == ',' || c == ' ') =>
+            {
+                base_type.trim()
+            }
+            Some(_) => return None,
+            None => data_type.as_str(),
+        };
+
+        match base_type {
+            "smallint" | "int2" | "integer" | "int" | "int4" | "bigint" | "int8" => {
+                Some(ValueType::Integer)
+            }
+            "numeric" | "decimal" => Some(ValueType::Numeric),
+            "real" | "float4" | "double precision" | "float8" => Some(ValueType::Float),
+            _ => None,
+        }
+    }
+
+    fn sql_type(&self) -> &'static str {
+        match self {
+            ValueType::Integer => "bigint",
+            ValueType::Numeric => "numeric",
+            ValueType::Float => "double precision",
+        }
+    }
+}
+
+/// A trend as it can be referenced from the compiled SQL
+pub struct Column {
+    pub sql: String,
+    pub value_type: ValueType,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpressionError {
+    pub message: String,
+    /// Character position in the expression, starting at 1
+    pub position: Option<usize>,
+}
+
+impl ExpressionError {
+    fn at(position: usize, message: String) -> ExpressionError {
+        ExpressionError {
+            message,
+            position: Some(position),
+        }
+    }
+
+    fn new(message: String) -> ExpressionError {
+        ExpressionError {
+            message,
+            position: None,
+        }
+    }
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some(position) => write!(f, "{} at position {position}", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(String),
+    Identifier(String),
+    QuotedName(String),
+    Symbol(char),
+}
+
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, ExpressionError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        let c = chars[index];
+        let position = index + 1;
+
+        if c.is_whitespace() {
+            index += 1;
+        } else if c.is_ascii_digit() {
+            let start = index;
+
+            while index < chars.len() && (chars[index].is_ascii_digit() || chars[index] == '.') {
+                index += 1;
+            }
+
+            let number: String = chars[start..index].iter().collect();
+
+            if number.ends_with('.') || number.matches('.').count() > 1 {
+                return Err(ExpressionError::at(
+                    position,
+                    format!("Invalid number '{number}'"),
+                ));
+            }
+
+            tokens.push((position, Token::Number(number)));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = index;
+
+            while index < chars.len()
+                && (chars[index].is_alphanumeric() || chars[index] == '_' || chars[index] == '.')
+            {
+                index += 1;
+            }
+
+            tokens.push((
+                position,
+                Token::Identifier(chars[start..index].iter().collect()),
+            ));
+        } else if c == '"' {
+            let mut name = String::new();
+
+            index += 1;
+
+            loop {
+                match chars.get(index) {
+                    None => {
+                        return Err(ExpressionError::at(
+                            position,
+                            "Unterminated quoted name".to_string(),
+                        ))
+                    }
+                    Some('"') if chars.get(index + 1) == Some(&'"') => {
+                        name.push('"');
+                        index += 2;
+                    }
+                    Some('"') => {
+                        index += 1;
+                        break;
+                    }
+                    Some(c) => {
+                        name.push(*c);
+                        index += 1;
+                    }
+                }
+            }
+
+            if name.is_empty() {
+                return Err(ExpressionError::at(
+                    position,
+                    "Empty quoted name".to_string(),
+                ));
+            }
+
+            tokens.push((position, Token::QuotedName(name)));
+        } else if "+-*/(),".contains(c) {
+            tokens.push((position, Token::Symbol(c)));
+            index += 1;
+        } else {
+            return Err(ExpressionError::at(
+                position,
+                format!("Unexpected character '{c}'"),
+            ));
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Maximum depth of an expression, to keep the recursive parsing and compilation within the
+/// stack. Parentheses, function calls, negations and every operator in a chain like `a + b + c`
+/// add a level.
+const MAX_DEPTH: usize = 128;
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    index: usize,
+    /// Position just after the expression, for errors at the end
+    end: usize,
+    /// Current nesting depth
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.index)
+            .map(|(position, _)| *position)
+            .unwrap_or(self.end)
+    }
+
+    fn next_is(&self, symbol: char) -> bool {
+        self.peek() == Some(&Token::Symbol(symbol))
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), ExpressionError> {
+        if self.next_is(symbol) {
+            self.index += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("'{symbol}'")))
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> ExpressionError {
+        let found = match self.peek() {
+            None => "end of expression".to_string(),
+            Some(Token::Number(number)) => format!("'{number}'"),
+            Some(Token::Identifier(name)) => format!("'{name}'"),
+            Some(Token::QuotedName(name)) => format!("'\"{name}\"'"),
+            Some(Token::Symbol(symbol)) => format!("'{symbol}'"),
+        };
+
+        ExpressionError::at(
+            self.position(),
+            format!("Expected {expected}, found {found}"),
+        )
+    }
+
+    fn enter(&mut self) -> Result<(), ExpressionError> {
+        self.depth += 1;
+
+        if self.depth > MAX_DEPTH {
+            return Err(ExpressionError::at(
+                self.position(),
+                format!("Expression is more than {MAX_DEPTH} levels deep"),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn expression(&mut self) -> Result<Expression, ExpressionError> {
+        self.enter()?;
+
+        let expression = self.sum();
+
+        self.depth -= 1;
+
+        expression
+    }
+
+    fn sum(&mut self) -> Result<Expression, ExpressionError> {
+        let mut expression = self.term()?;
+        let mut operators = 0;
+
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Symbol('+')) => Operator::Add,
+                Some(Token::Symbol('-')) => Operator::Subtract,
+                _ => {
+                    self.depth -= operators;
+                    return Ok(expression);
+                }
+            };
+
+            self.index += 1;
+
+            self.enter()?;
+            operators += 1;
+
+            expression = Expression::Binary(Box::new(expression), operator, Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<Expression, ExpressionError> {
+        let mut expression = self.unary()?;
+        let mut operators = 0;
+
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Symbol('*')) => Operator::Multiply,
+                Some(Token::Symbol('/')) => Operator::Divide,
+                _ => {
+                    self.depth -= operators;
+                    return Ok(expression);
+                }
+            };
+
+            self.index += 1;
+
+            self.enter()?;
+            operators += 1;
+
+            expression =
+                Expression::Binary(Box::new(expression), operator, Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expression, ExpressionError> {
+        if self.next_is('-') {
+            self.index += 1;
+
+            self.enter()?;
+
+            let expression = self.unary()?;
+
+            self.depth -= 1;
+
+            return Ok(Expression::Negate(Box::new(expression)));
+        }
+
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expression, ExpressionError> {
+        let position = self.position();
+
+        match self.peek().cloned() {
+            Some(Token::Number(number)) => {
+                self.index += 1;
+                Ok(Expression::Constant(number))
+            }
+            Some(Token::QuotedName(name)) => {
+                self.index += 1;
+                Ok(Expression::Trend(name))
+            }
+            Some(Token::Identifier(name)) => {
+                self.index += 1;
+
+                if !self.next_is('(') {
+                    return Ok(Expression::Trend(name));
+                }
+
+                let function = Function::from_name(&name).ok_or_else(|| {
+                    ExpressionError::at(position, format!("Unknown function '{name}'"))
+                })?;
+
+                self.index += 1;
+
+                let mut arguments = vec![self.expression()?];
+
+                while self.next_is(',') {
+                    self.index += 1;
+                    arguments.push(self.expression()?);
+                }
+
+                self.expect(')')?;
+
+                let valid_count = match function {
+                    Function::SafeDiv => arguments.len() == 2 || arguments.len() == 3,
+                    _ => arguments.len() == 1,
+                };
+
+                if !valid_count {
+                    return Err(ExpressionError::at(
+                        position,
+                        format!(
+                            "Wrong number of arguments for {}: {}",
+                            function.name(),
+                            arguments.len()
+                        ),
+                    ));
+                }
+
+                Ok(Expression::Function(function, arguments))
+            }
+            Some(Token::Symbol('(')) => {
+                self.index += 1;
+
+                let expression = self.expression()?;
+
+                self.expect(')')?;
+
+                Ok(expression)
+            }
+            _ => Err(self.unexpected("a trend, number, function or '('")),
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<Expression, ExpressionError> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        index: 0,
+        end: text.chars().count() + 1,
+        depth: 0,
+    };
+
+    let expression = parser.expression()?;
+
+    if parser.peek().is_some() {
+        return Err(parser.unexpected("an operator"));
+    }
+
+    if expression.trends().is_empty() {
+        return Err(ExpressionError::new(
+            "Expression does not refer to any trend".to_string(),
+        ));
+    }
+
+    Ok(expression)
+}
+
+impl Expression {
+    /// Names of the referenced trends, in order of first reference
+    pub fn trends(&self) -> Vec<String> {
+        let mut trends = Vec::new();
+
+        self.collect_trends(&mut trends);
+
+        trends
+    }
+
+    fn collect_trends(&self, trends: &mut Vec<String>) {
+        match self {
+            Expression::Trend(name) => {
+                if !trends.contains(name) {
+                    trends.push(name.clone());
+                }
+            }
+            Expression::Constant(_) => {}
+            Expression::Negate(expression) => expression.collect_trends(trends),
+            Expression::Binary(left, _, right) => {
+                left.collect_trends(trends);
+                right.collect_trends(trends);
+            }
+            Expression::Function(_, arguments) => {
+                for argument in arguments {
+                    argument.collect_trends(trends);
+                }
+            }
+        }
+    }
+
+    /// Type check the expression and compile it to SQL, the columns being the referenced
+    /// trends by name
+    pub fn compile(
+        &self,
+        columns: &HashMap<String, Column>,
+    ) -> Result<(String, ValueType), ExpressionError> {
+        self.compile_in(columns, None)
+    }
+
+    fn compile_in(
+        &self,
+        columns: &HashMap<String, Column>,
+        aggregate: Option<Function>,
+    ) -> Result<(String, ValueType), ExpressionError> {
+        match self {
+            Expression::Trend(name) => {
+                let column = columns
+                    .get(name)
+                    .ok_or_else(|| ExpressionError::new(format!("Unknown trend '{name}'")))?;
+
+                if aggregate.is_none() {
+                    return Err(ExpressionError::new(format!(
+                        "Trend '{name}' must be used within an aggregate like SUM"
+                    )));
+                }
+
+                Ok((column.sql.clone(), column.value_type))
+            }
+            Expression::Constant(number) => match number.contains('.') {
+                true => Ok((number.clone(), ValueType::Numeric)),
+                false => Ok((number.clone(), ValueType::Integer)),
+            },
+            Expression::Negate(expression) => {
+                let (sql, value_type) = expression.compile_in(columns, aggregate)?;
+
+                Ok((format!("(-{sql})"), value_type))
+            }
+            Expression::Binary(left, operator, right) => {
+                let (left_sql, left_type) = left.compile_in(columns, aggregate)?;
+                let (right_sql, right_type) = right.compile_in(columns, aggregate)?;
+
+                if *operator == Operator::Divide {
+                    if let Expression::Constant(number) = right.as_ref() {
+                        if number.chars().all(|c| c == '0' || c == '.') {
+                            return Err(ExpressionError::new(
+                                "Division by zero, use safe_div for denominators that may be 0"
+                                    .to_string(),
+                            ));
+                        }
+                    }
+
+                    // Avoid integer division
+                    let value_type = left_type.max(right_type).max(ValueType::Numeric);
+
+                    return Ok((
+                        format!("({left_sql}::{} / {right_sql})", value_type.sql_type()),
+                        value_type,
+                    ));
+                }
+
+                Ok((
+                    format!("({left_sql} {} {right_sql})", operator.sql()),
+                    left_type.max(right_type),
+                ))
+            }
+            Expression::Function(Function::SafeDiv, arguments) => {
+                let compiled = arguments
+                    .iter()
+                    .map(|argument| argument.compile_in(columns, aggregate))
+                    .collect::<Result<Vec<(String, ValueType)>, ExpressionError>>()?;
+
+                let value_type = compiled
+                    .iter()
+                    .map(|(_, value_type)| *value_type)
+                    .fold(ValueType::Numeric, ValueType::max);
+
+                // Both arguments of public.safe_division must have the same type
+                let arguments: Vec<String> = compiled
+                    .iter()
+                    .map(|(sql, _)| format!("{sql}::{}", value_type.sql_type()))
+                    .collect();
+
+                Ok((
+                    format!("public.safe_division({})", arguments.join(", ")),
+                    value_type,
+                ))
+            }
+            Expression::Function(function, arguments) => {
+                if let Some(outer) = aggregate {
+                    return Err(ExpressionError::new(format!(
+                        "Aggregate {} cannot be used within aggregate {}",
+                        function.name(),
+                        outer.name()
+                    )));
+                }
+
+                let (sql, value_type) = arguments[0].compile_in(columns, Some(*function))?;
+
+                let value_type = match function {
+                    Function::Count => ValueType::Integer,
+                    Function::Avg => value_type.max(ValueType::Numeric),
+                    _ => value_type,
+                };
+
+                Ok((format!("{}({sql})", function.name()), value_type))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns() -> HashMap<String, Column> {
+        HashMap::from([
+            (
+                "samples".to_string(),
+                Column {
+                    sql: "t1.\"samples\"".to_string(),
+                    value_type: ValueType::Integer,
+                },
+            ),
+            (
+                "L.power".to_string(),
+                Column {
+                    sql: "t2.\"L.power\"".to_string(),
+                    value_type: ValueType::Float,
+                },
+            ),
+        ])
+    }
+
+    fn compile(text: &str) -> Result<String, String> {
+        parse(text)
+            .and_then(|expression| expression.compile(&columns()))
+            .map(|(sql, _)| sql)
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn parse_precedence() {
+        let expression = parse("-SUM(a) + 2 * (\"b c\" - 1.5)").unwrap();
+
+        assert_eq!(
+            expression,
+            Expression::Binary(
+                Box::new(Expression::Negate(Box::new(Expression::Function(
+                    Function::Sum,
+                    vec![Expression::Trend("a".to_string())]
+                )))),
+                Operator::Add,
+                Box::new(Expression::Binary(
+                    Box::new(Expression::Constant("2".to_string())),
+                    Operator::Multiply,
+                    Box::new(Expression::Binary(
+                        Box::new(Expression::Trend("b c".to_string())),
+                        Operator::Subtract,
+                        Box::new(Expression::Constant("1.5".to_string())),
+                    )),
+                )),
+            )
+        );
+
+        assert_eq!(expression.trends(), ["a", "b c"]);
+    }
+
+    #[test]
+    fn compile_to_sql() {
+        assert_eq!(
+            compile("SUM(samples) / COUNT(samples)").unwrap(),
+            "(SUM(t1.\"samples\")::numeric / COUNT(t1.\"samples\"))"
+        );
+        assert_eq!(
+            compile("safe_div(sum(L.power), SUM(samples), 0) * 100").unwrap(),
+            concat!(
+                "(public.safe_division(SUM(t2.\"L.power\")::double precision, ",
+                "SUM(t1.\"samples\")::double precision, 0::double precision) * 100)"
+            )
+        );
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(
+            compile("SUM(samples) +").unwrap_err(),
+            "Expected a trend, number, function or '(', found end of expression at position 15"
+        );
+        assert_eq!(
+            compile("SUM(samples) 2").unwrap_err(),
+            "Expected an operator, found '2' at position 14"
+        );
+        assert_eq!(
+            compile("median(samples)").unwrap_err(),
+            "Unknown function 'median' at position 1"
+        );
+        assert_eq!(
+            compile("SUM(samples); DROP TABLE x").unwrap_err(),
+            "Unexpected character ';' at position 13"
+        );
+        assert!(compile("SUM(samples, 1)").is_err());
+        assert!(compile("\"samples").is_err());
+        assert!(compile("1 + 2").is_err());
+    }
+
+    #[test]
+    fn nesting_depth() {
+        let nested =
+            |depth: usize| format!("{}SUM(samples){}", "(".repeat(depth), ")".repeat(depth));
+
+        assert!(compile(&nested(MAX_DEPTH - 2)).is_ok());
+        assert_eq!(
+            compile(&nested(MAX_DEPTH)).unwrap_err(),
+            "Expression is more than 128 levels deep at position 129"
+        );
+        assert!(compile(&format!("SUM(samples){}", "+1".repeat(MAX_DEPTH - 2))).is_ok());
+        assert!(compile(&format!("SUM(samples){}", "+1".repeat(2000))).is_err());
+        assert!(compile(&format!("{}SUM(samples)", "-".repeat(100_000))).is_err());
+        assert!(compile(&format!("{}SUM(samples)", "(".repeat(100_000))).is_err());
+    }
+
+    #[test]
+    fn type_errors() {
+        assert_eq!(compile("SUM(other)").unwrap_err(), "Unknown trend 'other'");
+        assert_eq!(
+            compile("samples * 2").unwrap_err(),
+            "Trend 'samples' must be used within an aggregate like SUM"
+        );
+        assert_eq!(
+            compile("SUM(MAX(samples))").unwrap_err(),
+            "Aggregate MAX cannot be used within aggregate SUM"
+        );
+        assert!(compile("SUM(samples) / 0").is_err());
+
+        assert_eq!(
+            ValueType::from_sql_type("numeric(10, 2)"),
+            Some(ValueType::Numeric)
+        );
+        assert_eq!(ValueType::from_sql_type("text"), None);
+        assert_eq!(ValueType::from_sql_type("integer); --"), None);
+    }
+}
@@ -0,0 +1,443 @@
+use actix_web::{HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{GenericClient, Row};
+
+use super::serviceerror::{ServiceError, ServiceErrorKind};
+
+/// Maximum number of items that can be requested in one page
+pub const MAX_LIMIT: i64 = 10000;
+
+/// Value that an item list can be sorted on
+pub struct SortColumn {
+    /// Name of the value in the sort parameter
+    pub name: &'static str,
+    /// SQL expression for the value on the `item` row of the listing query
+    pub expression: &'static str,
+    /// SQL type of the value, to restore it from the text in a cursor
+    pub sql_type: &'static str,
+}
+
+/// Position in a sorted list, encoded in the cursor of the next page
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Cursor {
+    sort: String,
+    after: Vec<String>,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        serde_json::to_vec(self)
+            .unwrap()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+
+    fn decode(value: &str) -> Option<Cursor> {
+        if value.len() % 2 != 0 {
+            return None;
+        }
+
+        let bytes = (0..value.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(value.get(index..index + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+
+        serde_json::from_slice(&bytes).ok()
+    }
+}
+
+fn bad_request(message: String) -> ServiceError {
+    ServiceError {
+        kind: ServiceErrorKind::BadRequest,
+        message,
+    }
+}
+
+/// Page of items as requested by the sort, cursor and limit query parameters
+pub struct PageRequest<'a> {
+    /// Name of the value to sort on, prefixed with '-' for descending order
+    pub sort: Option<&'a str>,
+    /// Cursor from the link to the next page of a previous request
+    pub cursor: Option<&'a str>,
+    /// Maximum number of items, all remaining items when not specified
+    pub limit: Option<i64>,
+}
+
+/// Rows of one page and the cursor for the next page when there are more rows
+pub struct Page {
+    pub rows: Vec<Row>,
+    pub next_cursor: Option<String>,
+}
+
+/// SQL and text parameters of the query for one page
+struct PageQuery {
+    sql: String,
+    params: Vec<String>,
+    /// Sort order to record in the cursor
+    sort: String,
+    /// Number of sort order values appended to each row
+    order_columns: usize,
+}
+
+/// Query for a filtered, sorted and paginated list of items
+///
+/// The source query returns one row per item and is wrapped in a query that applies the
+/// filters and selects one page of rows with keyset pagination: the cursor holds the sort
+/// value and key of the last item of the previous page, so pages do not skip or repeat items
+/// when items are added or removed between requests.
+pub struct Listing<'a> {
+    source: &'a str,
+    columns: &'a [SortColumn],
+    key: &'a SortColumn,
+    conditions: Vec<String>,
+    params: Vec<String>,
+}
+
+impl<'a> Listing<'a> {
+    /// Create a listing of the rows of `source`, that can be sorted on `columns` and in
+    /// which `key` uniquely identifies an item. The first column is the default sort order.
+    pub fn new(source: &'a str, columns: &'a [SortColumn], key: &'a SortColumn) -> Self {
+        Listing {
+            source,
+            columns,
+            key,
+            conditions: Vec::new(),
+            params: Vec::new(),
+        }
+    }
+
+    /// Only items that match a condition when a value is specified. The `$?` placeholders in
+    /// the condition are replaced by the text parameter holding the value.
+    pub fn filter<V: ToString>(&mut self, condition: &str, value: Option<V>) -> &mut Self {
+        if let Some(value) = value {
+            self.params.push(value.to_string());
+
+            self.conditions
+                .push(condition.replace("$?", &format!("${}", self.params.len())));
+        }
+
+        self
+    }
+
+    /// Only items for which the expression matches a name pattern, in which '*' matches any
+    /// sequence of characters
+    pub fn filter_pattern(&mut self, expression: &str, pattern: Option<&str>) -> &mut Self {
+        self.filter(&format!("{expression} LIKE $?"), pattern.map(like_pattern))
+    }
+
+    fn sort_order(&self, sort: Option<&str>) -> Result<(&SortColumn, bool), ServiceError> {
+        let Some(sort) = sort else {
+            return Ok((&self.columns[0], false));
+        };
+
+        let (name, descending) = match sort.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (sort, false),
+        };
+
+        self.columns
+            .iter()
+            .find(|column| column.name == name)
+            .map(|column| (column, descending))
+            .ok_or_else(|| {
+                bad_request(format!(
+                    "Cannot sort on '{name}', sortable fields are: {}",
+                    self.columns
+                        .iter()
+                        .map(|column| column.name)
+                        .collect::<Vec<&str>>()
+                        .join(", ")
+                ))
+            })
+    }
+
+    /// Build the query for a page
+    fn page_query(&self, page: &PageRequest) -> Result<PageQuery, ServiceError> {
+        let (column, descending) = self.sort_order(page.sort)?;
+
+        let sort = format!("{}{}", if descending { "-" } else { "" }, column.name);
+
+        let mut order_columns = vec![column];
+
+        if column.name != self.key.name {
+            order_columns.push(self.key);
+        }
+
+        let mut conditions = self.conditions.clone();
+        let mut params = self.params.clone();
+
+        if let Some(cursor) = page.cursor {
+            let cursor = Cursor::decode(cursor)
+                .ok_or_else(|| bad_request(format!("Invalid cursor '{cursor}'")))?;
+
+            if cursor.sort != sort || cursor.after.len() != order_columns.len() {
+                return Err(bad_request(
+                    "Cursor does not match the requested sort order".to_string(),
+                ));
+            }
+
+            let values = order_columns
+                .iter()
+                .zip(cursor.after)
+                .map(|(column, value)| {
+                    params.push(value);
+                    format!("${}::text::{}", params.len(), column.sql_type)
+                })
+                .collect::<Vec<String>>();
+
+            conditions.push(format!(
+                "({}) {} ({})",
+                order_columns
+                    .iter()
+                    .map(|column| column.expression)
+                    .collect::<Vec<&str>>()
+                    .join(", "),
+                if descending { "<" } else { ">" },
+                values.join(", ")
+            ));
+        }
+
+        let mut sql = format!(
+            "SELECT item.*, {} FROM ({}) item",
+            order_columns
+                .iter()
+                .map(|column| format!("({})::text", column.expression))
+                .collect::<Vec<String>>()
+                .join(", "),
+            self.source
+        );
+
+        if !conditions.is_empty() {
+            sql.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+        }
+
+        sql.push_str(&format!(
+            " ORDER BY {}",
+            order_columns
+                .iter()
+                .map(|column| format!(
+                    "{} {}",
+                    column.expression,
+                    if descending { "DESC" } else { "ASC" }
+                ))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ));
+
+        if let Some(limit) = page.limit {
+            if !(1..=MAX_LIMIT).contains(&limit) {
+                return Err(bad_request(format!(
+                    "Limit must be between 1 and {MAX_LIMIT}"
+                )));
+            }
+
+            // One extra row shows whether there is a next page
+            sql.push_str(&format!(" LIMIT {}", limit + 1));
+        }
+
+        Ok(PageQuery {
+            sql,
+            params,
+            sort,
+            order_columns: order_columns.len(),
+        })
+    }
+
+    /// Load the rows of a page. The values of the sort order are appended to each row as text
+    /// columns.
+    pub async fn fetch<T: GenericClient>(
+        &self,
+        client: &T,
+        page: &PageRequest<'_>,
+    ) -> Result<Page, ServiceError> {
+        let query = self.page_query(page)?;
+
+        let params: Vec<&(dyn ToSql + Sync)> = query
+            .params
+            .iter()
+            .map(|param| param as &(dyn ToSql + Sync))
+            .collect();
+
+        let mut rows = client.query(&query.sql, &params).await?;
+
+        let next_cursor = match page.limit {
+            Some(limit) if rows.len() as i64 > limit => {
+                rows.truncate(limit as usize);
+
+                rows.last().map(|row| {
+                    Cursor {
+                        sort: query.sort,
+                        after: (row.len() - query.order_columns..row.len())
+                            .map(|index| row.get(index))
+                            .collect(),
+                    }
+                    .encode()
+                })
+            }
+            _ => None,
+        };
+
+        Ok(Page { rows, next_cursor })
+    }
+}
+
+/// Convert a name pattern with '*' wildcards into a LIKE pattern
+fn like_pattern(pattern: &str) -> String {
+    pattern
+        .chars()
+        .map(|c| match c {
+            '*' => "%".to_string(),
+            '%' | '_' | '\\' => format!("\\{c}"),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// Link to the next page: the request with its cursor parameter replaced
+fn next_link(path: &str, query_string: &str, cursor: &str) -> String {
+    let mut params: Vec<&str> = query_string
+        .split('&')
+        .filter(|param| !param.is_empty() && !param.starts_with("cursor="))
+        .collect();
+
+    let cursor_param = format!("cursor={cursor}");
+
+    params.push(&cursor_param);
+
+    format!("<{path}?{}>; rel=\"next\"", params.join("&"))
+}
+
+/// Respond with a page of items, linking to the next page in the Link header when there are
+/// more items
+pub fn page_response<T: Serialize>(
+    req: &HttpRequest,
+    items: &[T],
+    next_cursor: Option<String>,
+) -> HttpResponse {
+    let mut response = HttpResponse::Ok();
+
+    if let Some(cursor) = next_cursor {
+        response.insert_header(("Link", next_link(req.path(), req.query_string(), &cursor)));
+    }
+
+    response.json(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLUMNS: [SortColumn; 2] = [
+        SortColumn {
+            name: "name",
+            expression: "item.name",
+            sql_type: "text",
+        },
+        SortColumn {
+            name: "id",
+            expression: "item.id",
+            sql_type: "integer",
+        },
+    ];
+
+    #[test]
+    fn cursor_round_trip() {
+        let cursor = Cursor {
+            sort: "-name".to_string(),
+            after: vec!["Cell \"1\"".to_string(), "12".to_string()],
+        };
+
+        let encoded = cursor.encode();
+
+        assert!(encoded.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(Cursor::decode(&encoded), Some(cursor));
+        assert_eq!(Cursor::decode("7b7"), None);
+        assert_eq!(Cursor::decode("zz"), None);
+    }
+
+    #[test]
+    fn page_query_with_filters_and_cursor() {
+        let mut listing = Listing::new("SELECT id, name FROM t", &COLUMNS, &COLUMNS[1]);
+
+        listing
+            .filter("item.owner = $?", Some("admin"))
+            .filter("item.enabled = $?::text::bool", None::<bool>)
+            .filter_pattern("item.name", Some("kpi_*"));
+
+        let cursor = Cursor {
+            sort: "-name".to_string(),
+            after: vec!["kpi_b".to_string(), "3".to_string()],
+        }
+        .encode();
+
+        let query = listing
+            .page_query(&PageRequest {
+                sort: Some("-name"),
+                cursor: Some(&cursor),
+                limit: Some(10),
+            })
+            .unwrap();
+
+        assert_eq!(
+            query.sql,
+            concat!(
+                "SELECT item.*, (item.name)::text, (item.id)::text FROM (SELECT id, name FROM t) item ",
+                "WHERE item.owner = $1 AND item.name LIKE $2 ",
+                "AND (item.name, item.id) < ($3::text::text, $4::text::integer) ",
+                "ORDER BY item.name DESC, item.id DESC LIMIT 11"
+            )
+        );
+        assert_eq!(query.params, vec!["admin", "kpi\\_%", "kpi_b", "3"]);
+        assert_eq!(query.sort, "-name");
+        assert_eq!(query.order_columns, 2);
+    }
+
+    #[test]
+    fn page_query_errors() {
+        let listing = Listing::new("SELECT id, name FROM t", &COLUMNS, &COLUMNS[1]);
+
+        let request = |sort, cursor, limit| PageRequest {
+            sort,
+            cursor,
+            limit,
+        };
+
+        let query = listing
+            .page_query(&request(Some("id"), None, None))
+            .unwrap();
+        assert!(query.sql.ends_with("ORDER BY item.id ASC"));
+        assert_eq!(query.order_columns, 1);
+
+        assert!(listing
+            .page_query(&request(Some("owner"), None, None))
+            .is_err());
+        assert!(listing.page_query(&request(None, None, Some(0))).is_err());
+        assert!(listing
+            .page_query(&request(None, Some("not-a-cursor"), None))
+            .is_err());
+
+        let cursor = Cursor {
+            sort: "name".to_string(),
+            after: vec!["a".to_string(), "1".to_string()],
+        }
+        .encode();
+
+        assert!(listing
+            .page_query(&request(Some("-name"), Some(&cursor), None))
+            .is_err());
+    }
+
+    #[test]
+    fn next_page_link() {
+        assert_eq!(
+            next_link("/trends", "data_source=pm&cursor=00&limit=2", "7b7d"),
+            "</trends?data_source=pm&limit=2&cursor=7b7d>; rel=\"next\""
+        );
+        assert_eq!(
+            next_link("/kpis", "", "7b7d"),
+            "</kpis?cursor=7b7d>; rel=\"next\""
+        );
+    }
+}
@@ -1,16 +1,17 @@
-use std::{env, process::exit};
+use std::{env, process::exit, sync::Arc, time::Duration, time::Instant};
 
-use log::info;
+use log::{info, warn};
 
 use actix_cors::Cors;
-use actix_web::{middleware::Logger, web, App, HttpServer};
+use actix_web::{dev::Service, middleware::Logger, web, App, HttpServer};
 
 use deadpool_postgres::{Manager, ManagerConfig, Pool, RecyclingMethod};
 use rustls::ClientConfig as RustlsClientConfig;
 use tokio_postgres::{config::SslMode, Config};
 use tokio_postgres_rustls::MakeRustlsConnect;
 
-use utoipa::OpenApi;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
 use utoipa_swagger_ui::SwaggerUi;
 
 use minerva::error::{ConfigurationError, DatabaseError, Error};
@@ -18,12 +19,16 @@ use minerva::error::{ConfigurationError, DatabaseError, Error};
 mod trendmaterialization;
 use trendmaterialization::{
     delete_trend_function_materialization, delete_trend_view_materialization,
+    disable_trend_materialization, enable_expired_materializations_task,
+    enable_trend_materialization,
     get_trend_function_materialization, get_trend_function_materializations,
-    get_trend_materializations, get_trend_view_materialization, get_trend_view_materializations,
+    get_trend_materialization_metrics, get_trend_materializations, get_trend_view_materialization,
+    get_trend_view_materializations,
     post_trend_function_materialization, post_trend_view_materialization,
     update_trend_function_materialization, update_trend_view_materialization,
     TrendFunctionMaterializationData, TrendFunctionMaterializationFull, TrendMaterializationDef,
-    TrendMaterializationSourceData, TrendViewMaterializationData, TrendViewMaterializationFull,
+    TrendMaterializationDisableData, TrendMaterializationMetrics, TrendMaterializationSourceData,
+    TrendViewMaterializationData, TrendViewMaterializationFull,
 };
 
 mod trendstore;
@@ -33,6 +38,9 @@ use trendstore::{
     GeneratedTrendFull, TrendFull, TrendStoreFull, TrendStorePartFull,
 };
 
+mod trenddata;
+use trenddata::{get_trend_data, TrendDataPageFull, TrendDataRowFull};
+
 mod datasource;
 use datasource::{get_data_source, get_data_sources, DataSource};
 
@@ -40,10 +48,48 @@ mod entitytype;
 use entitytype::{get_entity_type, get_entity_types, EntityType};
 
 mod kpi;
+mod kpiconfig;
+use kpiconfig::KpiConfig;
+mod kpiexpression;
 use kpi::{delete_kpi, get_kpi, get_kpis, post_kpi, update_kpi, KpiImplementedData, KpiRawData};
 
 mod trigger;
-use trigger::{change_thresholds, get_triggers, TriggerBasicData, TriggerData};
+use trigger::{
+    change_thresholds, delete_trigger, delete_trigger_exceptions, get_trigger,
+    get_trigger_exceptions, get_triggers, post_trigger, post_trigger_exception, update_trigger,
+    TriggerBasicData, TriggerData, TriggerExceptionData, TriggerExceptionFull,
+    TriggerNotificationCount,
+};
+
+mod attributestore;
+use attributestore::{
+    delete_attribute_store, get_attribute_store, get_attribute_stores, post_attribute_store,
+    update_attribute_store, AttributeData, AttributeStoreData,
+};
+
+mod notificationstore;
+use notificationstore::{
+    delete_notification_store, get_notification_store, get_notification_stores,
+    post_notification_store, update_notification_store, NotificationAttributeData,
+    NotificationStoreData,
+};
+
+mod relation;
+use relation::{delete_relation, get_relation, get_relations, post_relation, RelationData};
+
+mod virtualentity;
+use virtualentity::{
+    delete_virtual_entity, get_virtual_entities, post_virtual_entity, VirtualEntityData,
+};
+
+mod instance;
+use instance::{post_instance_diff, InstanceChange};
+
+mod notification;
+use notification::{
+    acknowledge_incident, assign_incident, close_incident, get_incident, get_incidents,
+    IncidentFull, IncidentUpdateData,
+};
 
 mod entityset;
 use entityset::{get_entity_sets, change_entity_set, create_entity_set, EntitySetData};
@@ -51,12 +97,50 @@ use entityset::{get_entity_sets, change_entity_set, create_entity_set, EntitySet
 mod header;
 use header::get_header;
 
+mod auth;
+use auth::Authenticator;
+
+mod audit;
+use audit::{get_audit_record, get_audit_records, AuditRecordFull};
+
 mod error;
+use error::{ProblemDetails, Success};
+
 mod serviceerror;
+use serviceerror::{
+    path_error_handler, query_error_handler, unknown_resource, ProblemResponses,
+};
+
+mod listing;
+
+mod events;
+use events::{get_events, listen_for_events, EventHub};
+
+mod health;
+use health::{get_liveness, get_readiness, HealthStatus};
+
+mod metrics;
+use metrics::{get_metrics, RequestMetrics};
 
 static ENV_DB_CONN: &str = "MINERVA_DB_CONN";
 static ENV_PORT: &str = "SERVICE_PORT";
 static ENV_ADDRESS: &str = "SERVICE_ADDRESS";
+static ENV_CORS_ALLOWED_ORIGINS: &str = "SERVICE_CORS_ALLOWED_ORIGINS";
+
+const MAX_CONNECT_DELAY: Duration = Duration::from_secs(30);
+
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "bearer_token",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+        }
+    }
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -70,6 +154,9 @@ async fn main() -> std::io::Result<()> {
             trendmaterialization::get_trend_function_materializations,
             trendmaterialization::get_trend_function_materialization,
             trendmaterialization::get_trend_materializations,
+            trendmaterialization::get_trend_materialization_metrics,
+            trendmaterialization::enable_trend_materialization,
+            trendmaterialization::disable_trend_materialization,
             trendmaterialization::post_trend_view_materialization,
             trendmaterialization::post_trend_function_materialization,
             trendmaterialization::delete_trend_view_materialization,
@@ -84,6 +171,7 @@ async fn main() -> std::io::Result<()> {
             trendstore::post_trend_store_part,
             trendstore::get_trends,
             trendstore::get_trends_by_entity_type,
+            trenddata::get_trend_data,
             datasource::get_data_sources,
             datasource::get_data_source,
             entitytype::get_entity_types,
@@ -95,9 +183,45 @@ async fn main() -> std::io::Result<()> {
             kpi::delete_kpi,
             trigger::get_triggers,
             trigger::change_thresholds,
+            trigger::get_trigger_exceptions,
+            trigger::post_trigger_exception,
+            trigger::delete_trigger_exceptions,
+            trigger::get_trigger,
+            trigger::post_trigger,
+            trigger::update_trigger,
+            trigger::delete_trigger,
+            attributestore::get_attribute_stores,
+            attributestore::get_attribute_store,
+            attributestore::post_attribute_store,
+            attributestore::update_attribute_store,
+            attributestore::delete_attribute_store,
+            notificationstore::get_notification_stores,
+            notificationstore::get_notification_store,
+            notificationstore::post_notification_store,
+            notificationstore::update_notification_store,
+            notificationstore::delete_notification_store,
+            relation::get_relations,
+            relation::get_relation,
+            relation::post_relation,
+            relation::delete_relation,
+            virtualentity::get_virtual_entities,
+            virtualentity::post_virtual_entity,
+            virtualentity::delete_virtual_entity,
+            instance::post_instance_diff,
+            notification::get_incidents,
+            notification::get_incident,
+            notification::acknowledge_incident,
+            notification::assign_incident,
+            notification::close_incident,
             entityset::get_entity_sets,
             entityset::change_entity_set,
             entityset::create_entity_set,
+            audit::get_audit_records,
+            audit::get_audit_record,
+            events::get_events,
+            health::get_liveness,
+            health::get_readiness,
+            metrics::get_metrics,
             header::get_header
         ),
         components(
@@ -105,14 +229,27 @@ async fn main() -> std::io::Result<()> {
                 TrendMaterializationSourceData, TrendMaterializationDef,
                 TrendViewMaterializationFull, TrendFunctionMaterializationFull,
                 TrendViewMaterializationData, TrendFunctionMaterializationData,
+                TrendMaterializationMetrics, TrendMaterializationDisableData,
                 TrendFull, GeneratedTrendFull, TrendStorePartFull, TrendStoreFull,
+                TrendDataPageFull, TrendDataRowFull,
                 DataSource, EntityType, KpiRawData, KpiImplementedData,
-                TriggerData, TriggerBasicData, EntitySetData,
+                TriggerData, TriggerBasicData, TriggerExceptionData, TriggerExceptionFull,
+                TriggerNotificationCount,
+                AttributeStoreData, AttributeData,
+                NotificationStoreData, NotificationAttributeData,
+                RelationData, VirtualEntityData, InstanceChange,
+                IncidentFull, IncidentUpdateData,
+                EntitySetData,
+                AuditRecordFull,
+                HealthStatus,
+                ProblemDetails, Success,
             )
         ),
         tags(
             (name = "Trend Materialization", description = "Trend materialization management endpoints.")
         ),
+        modifiers(&SecurityAddon, &ProblemResponses),
+        security(("bearer_token" = [])),
     )]
     struct ApiDoc;
 
@@ -126,20 +263,100 @@ async fn main() -> std::io::Result<()> {
         Ok(value) => value,
     };
 
-    let pool = connect_db().await.unwrap();
+    let authenticator = match Authenticator::from_env() {
+        Err(e) => {
+            println!("Could not configure authentication: {e}");
+            exit(-1);
+        }
+        Ok(authenticator) => web::Data::new(authenticator),
+    };
+
+    let cors_allowed_origins = match cors_allowed_origins() {
+        Err(e) => {
+            println!("Could not configure CORS: {e}");
+            exit(-1);
+        }
+        Ok(origins) => origins,
+    };
+
+    let kpi_config = match KpiConfig::from_env() {
+        Err(e) => {
+            println!("Could not configure KPIs: {e}");
+            exit(-1);
+        }
+        Ok(config) => web::Data::new(config),
+    };
+
+    if !authenticator.is_enabled() {
+        warn!("Authentication is disabled, all requests are executed as an anonymous admin");
+    }
+
+    let pool = match connect_db().await {
+        Err(e) => {
+            println!("Could not configure database connection: {e}");
+            exit(-1);
+        }
+        Ok(pool) => pool,
+    };
+
+    tokio::spawn(wait_for_db(pool.clone()));
+
+    let request_metrics = Arc::new(RequestMetrics::new());
+
+    let event_hub = Arc::new(EventHub::new());
+
+    tokio::spawn(listen_for_events(
+        get_db_config().unwrap(),
+        make_tls_connector(),
+        event_hub.clone(),
+    ));
+
+    tokio::spawn(enable_expired_materializations_task(
+        pool.clone(),
+        Duration::from_secs(60),
+    ));
 
     let openapi = ApiDoc::openapi();
 
     info!("Listening on {service_address}:{service_port}");
 
     HttpServer::new(move || {
-        let cors = Cors::permissive()
-            .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
+        let cors = cors_allowed_origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+            .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"])
+            .allowed_headers(vec!["Authorization", "Content-Type", "Accept", "Last-Event-ID"])
             .max_age(3600);
+        let metrics = request_metrics.clone();
         App::new()
             .wrap(cors)
             .wrap(Logger::default())
+            .wrap_fn(move |req, srv| {
+                let metrics = metrics.clone();
+                let method = req.method().to_string();
+                let start = Instant::now();
+                let response = srv.call(req);
+
+                async move {
+                    let response = response.await?;
+
+                    metrics.record(
+                        &method,
+                        response.request().match_pattern().as_deref(),
+                        response.status().as_u16(),
+                        start.elapsed(),
+                    );
+
+                    Ok(response)
+                }
+            })
             .app_data(web::Data::new(pool.clone()))
+            .app_data(authenticator.clone())
+            .app_data(kpi_config.clone())
+            .app_data(web::Data::from(event_hub.clone()))
+            .app_data(web::Data::from(request_metrics.clone()))
+            .app_data(web::QueryConfig::default().error_handler(query_error_handler))
+            .app_data(web::PathConfig::default().error_handler(path_error_handler))
             .service(
                 SwaggerUi::new("/swagger-ui/{_:.*}").url("/api-doc/openapi.json", openapi.clone()),
             )
@@ -148,6 +365,9 @@ async fn main() -> std::io::Result<()> {
             .service(get_trend_function_materializations)
             .service(get_trend_function_materialization)
             .service(get_trend_materializations)
+            .service(get_trend_materialization_metrics)
+            .service(enable_trend_materialization)
+            .service(disable_trend_materialization)
             .service(post_trend_view_materialization)
             .service(post_trend_function_materialization)
             .service(delete_trend_view_materialization)
@@ -162,6 +382,7 @@ async fn main() -> std::io::Result<()> {
             .service(get_trend_store)
             .service(get_trends)
             .service(get_trends_by_entity_type)
+            .service(get_trend_data)
             .service(get_data_sources)
             .service(get_data_source)
             .service(get_entity_types)
@@ -173,16 +394,72 @@ async fn main() -> std::io::Result<()> {
             .service(delete_kpi)
             .service(get_triggers)
             .service(change_thresholds)
+            .service(get_trigger_exceptions)
+            .service(post_trigger_exception)
+            .service(delete_trigger_exceptions)
+            .service(get_trigger)
+            .service(post_trigger)
+            .service(update_trigger)
+            .service(delete_trigger)
+            .service(get_attribute_stores)
+            .service(get_attribute_store)
+            .service(post_attribute_store)
+            .service(update_attribute_store)
+            .service(delete_attribute_store)
+            .service(get_notification_stores)
+            .service(get_notification_store)
+            .service(post_notification_store)
+            .service(update_notification_store)
+            .service(delete_notification_store)
+            .service(get_relations)
+            .service(get_relation)
+            .service(post_relation)
+            .service(delete_relation)
+            .service(get_virtual_entities)
+            .service(post_virtual_entity)
+            .service(delete_virtual_entity)
+            .service(post_instance_diff)
+            .service(get_incidents)
+            .service(get_incident)
+            .service(acknowledge_incident)
+            .service(assign_incident)
+            .service(close_incident)
             .service(get_entity_sets)
             .service(change_entity_set)
             .service(create_entity_set)
+            .service(get_audit_records)
+            .service(get_audit_record)
+            .service(get_events)
+            .service(get_liveness)
+            .service(get_readiness)
+            .service(get_metrics)
             .service(get_header)
+            .default_service(web::to(unknown_resource))
     })
     .bind((service_address, service_port))?
     .run()
     .await
 }
 
+/// Origins that are allowed to make cross-origin requests, as a comma separated list in
+/// SERVICE_CORS_ALLOWED_ORIGINS. No cross-origin requests are allowed when it is not set.
+fn cors_allowed_origins() -> Result<Vec<String>, String> {
+    let value = match env::var(ENV_CORS_ALLOWED_ORIGINS) {
+        Ok(value) => value,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .map(|origin| match origin.parse::<actix_web::http::Uri>() {
+            Ok(uri) if uri.scheme().is_some() && uri.host().is_some() => Ok(origin.to_string()),
+            _ => Err(format!("Invalid origin '{origin}' in {ENV_CORS_ALLOWED_ORIGINS}")),
+        })
+        .collect()
+}
+
 fn get_db_config() -> Result<Config, Error> {
     let config = match env::var(ENV_DB_CONN) {
         Ok(value) => Config::new().options(&value).clone(),
@@ -264,6 +541,31 @@ async fn connect_db() -> Result<Pool, Error> {
     make_db_pool(&config).await
 }
 
+/// Report on the database connection until one can be made. The service is started without
+/// waiting for the database and reports itself not ready until then.
+async fn wait_for_db(pool: Pool) {
+    let mut delay = Duration::from_secs(1);
+
+    loop {
+        match pool.get().await {
+            Ok(_) => {
+                info!("Connected to database");
+                return;
+            }
+            Err(e) => {
+                warn!(
+                    "Could not connect to database, retrying in {}s: {e}",
+                    delay.as_secs()
+                );
+            }
+        }
+
+        tokio::time::sleep(delay).await;
+
+        delay = (delay * 2).min(MAX_CONNECT_DELAY);
+    }
+}
+
 //async fn connect_to_db(
 //    config: &Config,
 //) -> Result<bb8::Pool<PostgresConnectionManager<MakeRustlsConnect>>, Error> {
@@ -286,7 +588,7 @@ async fn connect_db() -> Result<Pool, Error> {
 //    Ok(pool)
 //}
 
-async fn make_db_pool(config: &Config) -> Result<Pool, Error> {
+fn make_tls_connector() -> MakeRustlsConnect {
     let mut roots = rustls::RootCertStore::empty();
 
     for cert in rustls_native_certs::load_native_certs().expect("could not load platform certs") {
@@ -297,7 +599,12 @@ async fn make_db_pool(config: &Config) -> Result<Pool, Error> {
         .with_safe_defaults()
         .with_root_certificates(roots)
         .with_no_client_auth();
-    let tls = MakeRustlsConnect::new(tls_config);
+
+    MakeRustlsConnect::new(tls_config)
+}
+
+async fn make_db_pool(config: &Config) -> Result<Pool, Error> {
+    let tls = make_tls_connector();
     let mgr_config = ManagerConfig {
         recycling_method: RecyclingMethod::Fast,
     };
@@ -306,5 +613,9 @@ async fn make_db_pool(config: &Config) -> Result<Pool, Error> {
     Pool::builder(mgr)
         .max_size(16)
         .build()
-        .map_err(|_| Error::Database(DatabaseError::from_msg("test".to_string())))
+        .map_err(|e| {
+            Error::Database(DatabaseError::from_msg(format!(
+                "Could not create database connection pool: {e}"
+            )))
+        })
 }
@@ -0,0 +1,349 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use deadpool_postgres::{Pool, Status};
+use log::warn;
+use tokio_postgres::GenericClient;
+
+use actix_web::{get, web::Data, HttpResponse};
+
+/// Upper bounds in seconds of the request duration histogram buckets
+const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Route label for requests that match no endpoint, to keep the number of series bounded
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+#[derive(Default)]
+struct RouteStats {
+    statuses: BTreeMap<u16, u64>,
+    /// Number of requests per duration bucket, not cumulative
+    buckets: [u64; DURATION_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+/// Request counts and latencies per route, recorded by the request middleware
+#[derive(Default)]
+pub struct RequestMetrics {
+    routes: Mutex<BTreeMap<(String, String), RouteStats>>,
+}
+
+impl RequestMetrics {
+    pub fn new() -> RequestMetrics {
+        RequestMetrics::default()
+    }
+
+    /// Record a handled request, `route` being the matched route pattern
+    pub fn record(&self, method: &str, route: Option<&str>, status: u16, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let route = route.unwrap_or(UNMATCHED_ROUTE);
+
+        let mut routes = self.routes.lock().unwrap();
+
+        let stats = routes
+            .entry((method.to_string(), route.to_string()))
+            .or_default();
+
+        *stats.statuses.entry(status).or_default() += 1;
+
+        if let Some(index) = DURATION_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            stats.buckets[index] += 1;
+        }
+
+        stats.sum += seconds;
+        stats.count += 1;
+    }
+
+    fn write(&self, out: &mut MetricWriter) {
+        let routes = self.routes.lock().unwrap();
+
+        out.header(
+            "minerva_service_http_requests_total",
+            "counter",
+            "Number of handled HTTP requests",
+        );
+
+        for ((method, route), stats) in routes.iter() {
+            for (status, count) in &stats.statuses {
+                out.sample(
+                    "minerva_service_http_requests_total",
+                    &[
+                        ("method", method),
+                        ("route", route),
+                        ("status", &status.to_string()),
+                    ],
+                    count,
+                );
+            }
+        }
+
+        out.header(
+            "minerva_service_http_request_duration_seconds",
+            "histogram",
+            "Time taken to handle HTTP requests",
+        );
+
+        for ((method, route), stats) in routes.iter() {
+            let mut cumulative = 0;
+
+            for (bound, count) in DURATION_BUCKETS.iter().zip(stats.buckets) {
+                cumulative += count;
+
+                out.sample(
+                    "minerva_service_http_request_duration_seconds_bucket",
+                    &[
+                        ("method", method),
+                        ("route", route),
+                        ("le", &bound.to_string()),
+                    ],
+                    cumulative,
+                );
+            }
+
+            let labels = [("method", method.as_str()), ("route", route.as_str())];
+
+            out.sample(
+                "minerva_service_http_request_duration_seconds_bucket",
+                &[labels[0], labels[1], ("le", "+Inf")],
+                stats.count,
+            );
+            out.sample(
+                "minerva_service_http_request_duration_seconds_sum",
+                &labels,
+                stats.sum,
+            );
+            out.sample(
+                "minerva_service_http_request_duration_seconds_count",
+                &labels,
+                stats.count,
+            );
+        }
+    }
+}
+
+/// Writer of metrics in the Prometheus text exposition format
+struct MetricWriter {
+    text: String,
+}
+
+impl MetricWriter {
+    fn header(&mut self, name: &str, metric_type: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {name} {help}");
+        let _ = writeln!(self.text, "# TYPE {name} {metric_type}");
+    }
+
+    fn sample<V: Display>(&mut self, name: &str, labels: &[(&str, &str)], value: V) {
+        let labels = labels
+            .iter()
+            .map(|(label, value)| format!("{label}=\"{}\"", escape_label_value(value)))
+            .collect::<Vec<String>>()
+            .join(",");
+
+        if labels.is_empty() {
+            let _ = writeln!(self.text, "{name} {value}");
+        } else {
+            let _ = writeln!(self.text, "{name}{{{labels}}} {value}");
+        }
+    }
+
+    fn gauge<V: Display>(&mut self, name: &str, help: &str, value: V) {
+        self.header(name, "gauge", help);
+        self.sample(name, &[], value);
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn write_pool_status(status: &Status, out: &mut MetricWriter) {
+    out.gauge(
+        "minerva_service_db_pool_max_size",
+        "Maximum number of database connections in the pool",
+        status.max_size,
+    );
+    out.gauge(
+        "minerva_service_db_pool_size",
+        "Number of open database connections in the pool",
+        status.size,
+    );
+    out.gauge(
+        "minerva_service_db_pool_available",
+        "Number of idle database connections in the pool",
+        status.available,
+    );
+    out.gauge(
+        "minerva_service_db_pool_waiting",
+        "Number of requests waiting for a database connection",
+        status.waiting,
+    );
+}
+
+async fn write_minerva_gauges<T: GenericClient>(
+    client: &T,
+    out: &mut MetricWriter,
+) -> Result<(), tokio_postgres::Error> {
+    let row = client
+        .query_one(
+            concat!(
+                "SELECT (SELECT count(*) FROM trend_directory.trend_store), ",
+                "(SELECT count(*) FROM trend_directory.trend_store_part), ",
+                "(SELECT count(*) FROM trend_directory.partition)"
+            ),
+            &[],
+        )
+        .await?;
+
+    // Changed source data that is past the processing delay and has been stable for the
+    // stability delay
+    let pending_rows = client
+        .query(
+            concat!(
+                "SELECT m::text, count(ms.timestamp) ",
+                "FROM trend_directory.materialization m ",
+                "LEFT JOIN trend_directory.materialization_state ms ON ms.materialization_id = m.id ",
+                "AND ms.source_fingerprint IS DISTINCT FROM ms.processed_fingerprint ",
+                "AND ms.timestamp < now() - m.processing_delay ",
+                "AND ms.max_modified < now() - m.stability_delay ",
+                "WHERE m.enabled ",
+                "GROUP BY m.id ORDER BY m::text"
+            ),
+            &[],
+        )
+        .await?;
+
+    out.gauge(
+        "minerva_trend_stores",
+        "Number of trend stores",
+        row.get::<_, i64>(0),
+    );
+    out.gauge(
+        "minerva_trend_store_parts",
+        "Number of trend store parts",
+        row.get::<_, i64>(1),
+    );
+    out.gauge(
+        "minerva_trend_partitions",
+        "Number of trend store part partitions",
+        row.get::<_, i64>(2),
+    );
+
+    out.header(
+        "minerva_materialization_pending_timestamps",
+        "gauge",
+        "Number of timestamps that are due for materialization, per enabled materialization",
+    );
+
+    for row in pending_rows {
+        let materialization: String = row.get(0);
+        let count: i64 = row.get(1);
+
+        out.sample(
+            "minerva_materialization_pending_timestamps",
+            &[("materialization", &materialization)],
+            count,
+        );
+    }
+
+    Ok(())
+}
+
+/// Metrics in the Prometheus text format: request counts and latencies per route, the
+/// database connection pool and the size of the Minerva instance. Like the health endpoints,
+/// this endpoint does not require authentication.
+#[utoipa::path(
+    get,
+    path="/metrics",
+    responses(
+    (status = 200, description = "Metrics in the Prometheus text format", content_type = "text/plain", body = String),
+    ),
+    security(()),
+)]
+#[get("/metrics")]
+pub(super) async fn get_metrics(pool: Data<Pool>, metrics: Data<RequestMetrics>) -> HttpResponse {
+    let mut out = MetricWriter {
+        text: String::new(),
+    };
+
+    metrics.write(&mut out);
+
+    write_pool_status(&pool.status(), &mut out);
+
+    let database_up = match pool.get().await {
+        Ok(client) => match write_minerva_gauges(&**client, &mut out).await {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Could not load Minerva metrics: {e}");
+                false
+            }
+        },
+        Err(e) => {
+            warn!("Could not get database connection for metrics: {e}");
+            false
+        }
+    };
+
+    out.gauge(
+        "minerva_service_database_up",
+        "Whether the Minerva metrics could be loaded from the database",
+        u8::from(database_up),
+    );
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(out.text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_histogram() {
+        let metrics = RequestMetrics::new();
+
+        metrics.record("GET", Some("/triggers"), 200, Duration::from_millis(3));
+        metrics.record("GET", Some("/triggers"), 200, Duration::from_millis(40));
+        metrics.record("GET", Some("/triggers"), 500, Duration::from_secs(20));
+        metrics.record("GET", None, 404, Duration::from_millis(1));
+
+        let mut out = MetricWriter {
+            text: String::new(),
+        };
+
+        metrics.write(&mut out);
+
+        let lines: Vec<&str> = out.text.lines().collect();
+
+        for expected in [
+            "minerva_service_http_requests_total{method=\"GET\",route=\"/triggers\",status=\"200\"} 2",
+            "minerva_service_http_requests_total{method=\"GET\",route=\"/triggers\",status=\"500\"} 1",
+            "minerva_service_http_requests_total{method=\"GET\",route=\"unmatched\",status=\"404\"} 1",
+            "minerva_service_http_request_duration_seconds_bucket{method=\"GET\",route=\"/triggers\",le=\"0.005\"} 1",
+            "minerva_service_http_request_duration_seconds_bucket{method=\"GET\",route=\"/triggers\",le=\"0.05\"} 2",
+            "minerva_service_http_request_duration_seconds_bucket{method=\"GET\",route=\"/triggers\",le=\"10\"} 2",
+            "minerva_service_http_request_duration_seconds_bucket{method=\"GET\",route=\"/triggers\",le=\"+Inf\"} 3",
+            "minerva_service_http_request_duration_seconds_count{method=\"GET\",route=\"/triggers\"} 3",
+        ] {
+            assert!(lines.contains(&expected), "missing: {expected}");
+        }
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        let mut out = MetricWriter {
+            text: String::new(),
+        };
+
+        out.sample("metric", &[("name", "a \"b\"\\c\n")], 1);
+
+        assert_eq!(out.text, "metric{name=\"a \\\"b\\\"\\\\c\\n\"} 1\n");
+    }
+}
@@ -0,0 +1,260 @@
+use deadpool_postgres::Pool;
+use std::ops::DerefMut;
+
+use actix_web::{get, post, web::Data, web::Path, web::Query, HttpResponse};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use minerva::audit::generic_apply_audited;
+use minerva::notification_incident::{
+    load_incident, load_incidents, AcknowledgeIncident, AssignIncident, CloseIncident, Incident,
+    IncidentFilter, IncidentStatus,
+};
+
+use super::auth::{AdminAccess, ReadAccess};
+use super::serviceerror::{ServiceError, ServiceErrorKind};
+use crate::error::Success;
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct IncidentFull {
+    id: i32,
+    trigger: String,
+    entity_id: i32,
+    entity: Option<String>,
+    first_timestamp: DateTime<Utc>,
+    last_timestamp: DateTime<Utc>,
+    notification_count: i32,
+    /// One of 'open', 'acknowledged' or 'closed'
+    status: String,
+    assignee: Option<String>,
+    comment: Option<String>,
+    created: DateTime<Utc>,
+    modified: DateTime<Utc>,
+    closed: Option<DateTime<Utc>>,
+}
+
+impl From<Incident> for IncidentFull {
+    fn from(incident: Incident) -> Self {
+        IncidentFull {
+            id: incident.id,
+            trigger: incident.trigger,
+            entity_id: incident.entity_id,
+            entity: incident.entity,
+            first_timestamp: incident.first_timestamp,
+            last_timestamp: incident.last_timestamp,
+            notification_count: incident.notification_count,
+            status: incident.status.to_string(),
+            assignee: incident.assignee,
+            comment: incident.comment,
+            created: incident.created,
+            modified: incident.modified,
+            closed: incident.closed,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct IncidentUpdateData {
+    assignee: Option<String>,
+    comment: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct IncidentQuery {
+    /// Name of the trigger
+    trigger: Option<String>,
+    /// Name of the entity
+    entity: Option<String>,
+    /// Status of the incident: 'open', 'acknowledged' or 'closed'
+    status: Option<String>,
+}
+
+fn parse_update_data(post: &str) -> Result<IncidentUpdateData, ServiceError> {
+    if post.trim().is_empty() {
+        return Ok(IncidentUpdateData {
+            assignee: None,
+            comment: None,
+        });
+    }
+
+    serde_json::from_str(post).map_err(|e| ServiceError {
+        kind: ServiceErrorKind::BadRequest,
+        message: e.to_string(),
+    })
+}
+
+#[utoipa::path(
+    get,
+    path="/incidents",
+    params(IncidentQuery),
+    responses(
+    (status = 200, description = "Notification incidents", body = [IncidentFull]),
+    (status = 400, description = "Unknown incident status", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
+    )
+)]
+#[get("/incidents")]
+pub(super) async fn get_incidents(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+    query: Query<IncidentQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let status = query
+        .status
+        .as_deref()
+        .map(str::parse::<IncidentStatus>)
+        .transpose()?;
+
+    let filter = IncidentFilter {
+        trigger: query.trigger.clone(),
+        entity: query.entity.clone(),
+        status,
+    };
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let incidents: Vec<IncidentFull> = load_incidents(client, &filter)
+        .await?
+        .into_iter()
+        .map(IncidentFull::from)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(incidents))
+}
+
+#[utoipa::path(
+    get,
+    path="/incidents/{id}",
+    responses(
+    (status = 200, description = "Notification incident", body = IncidentFull),
+    (status = 404, description = "Incident not found", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
+    )
+)]
+#[get("/incidents/{id}")]
+pub(super) async fn get_incident(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+    id: Path<i32>,
+) -> Result<HttpResponse, ServiceError> {
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let incident = load_incident(client, id.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(IncidentFull::from(incident)))
+}
+
+#[utoipa::path(
+    post,
+    path="/incidents/{id}/acknowledge",
+    request_body = IncidentUpdateData,
+    responses(
+    (status = 200, description = "Acknowledged incident", body = Success),
+    (status = 400, description = "Input format incorrect or incident closed", body = ProblemDetails),
+    (status = 404, description = "Incident not found", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
+    )
+)]
+#[post("/incidents/{id}/acknowledge")]
+pub(super) async fn acknowledge_incident(
+    access: AdminAccess,
+    pool: Data<Pool>,
+    id: Path<i32>,
+    post: String,
+) -> Result<HttpResponse, ServiceError> {
+    let data = parse_update_data(&post)?;
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let change = AcknowledgeIncident {
+        id: id.into_inner(),
+        assignee: data.assignee,
+        comment: data.comment,
+    };
+
+    let message = generic_apply_audited(&change, client, &access.audit_context()).await?;
+
+    Ok(HttpResponse::Ok().json(Success { code: 200, message }))
+}
+
+#[utoipa::path(
+    post,
+    path="/incidents/{id}/assign",
+    request_body = IncidentUpdateData,
+    responses(
+    (status = 200, description = "Assigned incident", body = Success),
+    (status = 400, description = "Input format incorrect, no assignee or incident closed", body = ProblemDetails),
+    (status = 404, description = "Incident not found", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
+    )
+)]
+#[post("/incidents/{id}/assign")]
+pub(super) async fn assign_incident(
+    access: AdminAccess,
+    pool: Data<Pool>,
+    id: Path<i32>,
+    post: String,
+) -> Result<HttpResponse, ServiceError> {
+    let data = parse_update_data(&post)?;
+
+    let assignee = data.assignee.ok_or_else(|| ServiceError {
+        kind: ServiceErrorKind::BadRequest,
+        message: "No assignee specified".to_string(),
+    })?;
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let change = AssignIncident {
+        id: id.into_inner(),
+        assignee,
+        comment: data.comment,
+    };
+
+    let message = generic_apply_audited(&change, client, &access.audit_context()).await?;
+
+    Ok(HttpResponse::Ok().json(Success { code: 200, message }))
+}
+
+#[utoipa::path(
+    post,
+    path="/incidents/{id}/close",
+    request_body = IncidentUpdateData,
+    responses(
+    (status = 200, description = "Closed incident", body = Success),
+    (status = 400, description = "Input format incorrect or incident already closed", body = ProblemDetails),
+    (status = 404, description = "Incident not found", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
+    )
+)]
+#[post("/incidents/{id}/close")]
+pub(super) async fn close_incident(
+    access: AdminAccess,
+    pool: Data<Pool>,
+    id: Path<i32>,
+    post: String,
+) -> Result<HttpResponse, ServiceError> {
+    let data = parse_update_data(&post)?;
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let change = CloseIncident {
+        id: id.into_inner(),
+        comment: data.comment,
+    };
+
+    let message = generic_apply_audited(&change, client, &access.audit_context()).await?;
+
+    Ok(HttpResponse::Ok().json(Success { code: 200, message }))
+}
@@ -0,0 +1,221 @@
+use deadpool_postgres::Pool;
+use std::ops::DerefMut;
+
+use actix_web::{delete, get, post, put, web::Data, web::Path, HttpResponse};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use minerva::audit::apply_audited;
+use minerva::notification_store::{
+    load_notification_store, load_notification_stores, AddNotificationStore, Attribute,
+    DeleteNotificationStore, NotificationStore,
+};
+
+use super::auth::{AdminAccess, ReadAccess};
+use super::serviceerror::{ServiceError, ServiceErrorKind};
+use crate::error::Success;
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct NotificationAttributeData {
+    name: String,
+    data_type: String,
+    #[serde(default)]
+    description: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct NotificationStoreData {
+    data_source: String,
+    attributes: Vec<NotificationAttributeData>,
+}
+
+impl From<NotificationStore> for NotificationStoreData {
+    fn from(notification_store: NotificationStore) -> Self {
+        NotificationStoreData {
+            data_source: notification_store.data_source,
+            attributes: notification_store
+                .attributes
+                .into_iter()
+                .map(|attribute| NotificationAttributeData {
+                    name: attribute.name,
+                    data_type: attribute.data_type,
+                    description: attribute.description,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl NotificationStoreData {
+    fn as_minerva(&self) -> NotificationStore {
+        NotificationStore {
+            title: None,
+            data_source: self.data_source.clone(),
+            attributes: self
+                .attributes
+                .iter()
+                .map(|attribute| Attribute {
+                    name: attribute.name.clone(),
+                    data_type: attribute.data_type.clone(),
+                    description: attribute.description.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+fn parse_notification_store_data(post: &str) -> Result<NotificationStoreData, ServiceError> {
+    serde_json::from_str(post).map_err(|e| ServiceError {
+        kind: ServiceErrorKind::BadRequest,
+        message: e.to_string(),
+    })
+}
+
+#[utoipa::path(
+    get,
+    path="/notification-stores",
+    responses(
+    (status = 200, description = "List of existing notification stores", body = [NotificationStoreData]),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
+    )
+)]
+#[get("/notification-stores")]
+pub(super) async fn get_notification_stores(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let notification_stores: Vec<NotificationStoreData> = load_notification_stores(client)
+        .await?
+        .into_iter()
+        .map(NotificationStoreData::from)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(notification_stores))
+}
+
+#[utoipa::path(
+    get,
+    path="/notification-stores/{data_source}",
+    responses(
+    (status = 200, description = "Notification store", body = NotificationStoreData),
+    (status = 404, description = "Notification store not found", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
+    )
+)]
+#[get("/notification-stores/{data_source}")]
+pub(super) async fn get_notification_store(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+    data_source: Path<String>,
+) -> Result<HttpResponse, ServiceError> {
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let notification_store = load_notification_store(client, &data_source).await?;
+
+    Ok(HttpResponse::Ok().json(NotificationStoreData::from(notification_store)))
+}
+
+#[utoipa::path(
+    post,
+    path="/notification-stores",
+    request_body = NotificationStoreData,
+    responses(
+    (status = 200, description = "Created notification store", body = Success),
+    (status = 400, description = "Input format incorrect", body = ProblemDetails),
+    (status = 500, description = "Notification store could not be created", body = ProblemDetails),
+    )
+)]
+#[post("/notification-stores")]
+pub(super) async fn post_notification_store(
+    access: AdminAccess,
+    pool: Data<Pool>,
+    post: String,
+) -> Result<HttpResponse, ServiceError> {
+    let data = parse_notification_store_data(&post)?;
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let change = AddNotificationStore {
+        notification_store: data.as_minerva(),
+    };
+
+    let message = apply_audited(&change, client, &access.audit_context()).await?;
+
+    Ok(HttpResponse::Ok().json(Success { code: 200, message }))
+}
+
+/// Update a notification store by adding new attributes. Existing attributes are kept as
+/// they are.
+#[utoipa::path(
+    put,
+    path="/notification-stores",
+    request_body = NotificationStoreData,
+    responses(
+    (status = 200, description = "Updated notification store", body = [Success]),
+    (status = 400, description = "Input format incorrect", body = ProblemDetails),
+    (status = 404, description = "Notification store not found", body = ProblemDetails),
+    (status = 500, description = "Notification store could not be updated", body = ProblemDetails),
+    )
+)]
+#[put("/notification-stores")]
+pub(super) async fn update_notification_store(
+    access: AdminAccess,
+    pool: Data<Pool>,
+    post: String,
+) -> Result<HttpResponse, ServiceError> {
+    let data = parse_notification_store_data(&post)?;
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let current = load_notification_store(client, &data.data_source).await?;
+
+    let context = access.audit_context();
+    let mut results: Vec<Success> = Vec::new();
+
+    for change in current.diff(&data.as_minerva()) {
+        let message = apply_audited(change.as_ref(), client, &context).await?;
+
+        results.push(Success { code: 200, message });
+    }
+
+    Ok(HttpResponse::Ok().json(results))
+}
+
+#[utoipa::path(
+    delete,
+    path="/notification-stores/{data_source}",
+    responses(
+    (status = 200, description = "Deleted notification store", body = Success),
+    (status = 404, description = "Notification store not found", body = ProblemDetails),
+    (status = 500, description = "Notification store could not be deleted", body = ProblemDetails),
+    )
+)]
+#[delete("/notification-stores/{data_source}")]
+pub(super) async fn delete_notification_store(
+    access: AdminAccess,
+    pool: Data<Pool>,
+    data_source: Path<String>,
+) -> Result<HttpResponse, ServiceError> {
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let change = DeleteNotificationStore {
+        data_source: data_source.into_inner(),
+    };
+
+    let message = apply_audited(&change, client, &access.audit_context()).await?;
+
+    Ok(HttpResponse::Ok().json(Success { code: 200, message }))
+}
@@ -0,0 +1,145 @@
+use deadpool_postgres::Pool;
+use std::ops::DerefMut;
+
+use actix_web::{delete, get, post, web::Data, web::Path, HttpResponse};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use minerva::audit::apply_audited;
+use minerva::relation::{load_relation, load_relations, AddRelation, DeleteRelation, Relation};
+
+use super::auth::{AdminAccess, ReadAccess};
+use super::serviceerror::{ServiceError, ServiceErrorKind};
+use crate::error::Success;
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct RelationData {
+    name: String,
+    /// Query returning the source_id and target_id columns of the relation
+    query: String,
+}
+
+impl From<Relation> for RelationData {
+    fn from(relation: Relation) -> Self {
+        RelationData {
+            name: relation.name,
+            query: relation.query,
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path="/relations",
+    responses(
+    (status = 200, description = "List of existing relations", body = [RelationData]),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
+    )
+)]
+#[get("/relations")]
+pub(super) async fn get_relations(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let relations: Vec<RelationData> = load_relations(client)
+        .await?
+        .into_iter()
+        .map(RelationData::from)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(relations))
+}
+
+#[utoipa::path(
+    get,
+    path="/relations/{name}",
+    responses(
+    (status = 200, description = "Relation", body = RelationData),
+    (status = 404, description = "Relation not found", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
+    )
+)]
+#[get("/relations/{name}")]
+pub(super) async fn get_relation(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+    name: Path<String>,
+) -> Result<HttpResponse, ServiceError> {
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let relation = load_relation(client, &name).await?;
+
+    Ok(HttpResponse::Ok().json(RelationData::from(relation)))
+}
+
+#[utoipa::path(
+    post,
+    path="/relations",
+    request_body = RelationData,
+    responses(
+    (status = 200, description = "Created relation", body = Success),
+    (status = 400, description = "Input format incorrect", body = ProblemDetails),
+    (status = 500, description = "Relation could not be created", body = ProblemDetails),
+    )
+)]
+#[post("/relations")]
+pub(super) async fn post_relation(
+    access: AdminAccess,
+    pool: Data<Pool>,
+    post: String,
+) -> Result<HttpResponse, ServiceError> {
+    let data: RelationData = serde_json::from_str(&post).map_err(|e| ServiceError {
+        kind: ServiceErrorKind::BadRequest,
+        message: e.to_string(),
+    })?;
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let change = AddRelation {
+        relation: Relation {
+            name: data.name,
+            query: data.query,
+        },
+    };
+
+    let message = apply_audited(&change, client, &access.audit_context()).await?;
+
+    Ok(HttpResponse::Ok().json(Success { code: 200, message }))
+}
+
+#[utoipa::path(
+    delete,
+    path="/relations/{name}",
+    responses(
+    (status = 200, description = "Deleted relation", body = Success),
+    (status = 404, description = "Relation not found", body = ProblemDetails),
+    (status = 500, description = "Relation could not be deleted", body = ProblemDetails),
+    )
+)]
+#[delete("/relations/{name}")]
+pub(super) async fn delete_relation(
+    access: AdminAccess,
+    pool: Data<Pool>,
+    name: Path<String>,
+) -> Result<HttpResponse, ServiceError> {
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let change = DeleteRelation {
+        name: name.into_inner(),
+    };
+
+    let message = apply_audited(&change, client, &access.audit_context()).await?;
+
+    Ok(HttpResponse::Ok().json(Success { code: 200, message }))
+}
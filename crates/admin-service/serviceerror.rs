@@ -1,10 +1,18 @@
-use actix_web::error::ResponseError;
-use actix_web::{http::StatusCode, HttpResponse};
+use actix_web::error::{PathError, QueryPayloadError, ResponseError};
+use actix_web::{http::StatusCode, HttpRequest, HttpResponse};
 use core::fmt::Display;
 use derive_more::{Display, From};
 use log::error;
+use utoipa::openapi::path::Operation;
+use utoipa::openapi::security::SecurityRequirement;
+use utoipa::openapi::{ContentBuilder, Ref, RefOr, Response, ResponseBuilder};
+use utoipa::Modify;
 
-use super::error::Error;
+use minerva::error::DatabaseErrorKind;
+
+use super::error::ProblemDetails;
+
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
 
 #[derive(Display, From, Debug)]
 pub enum ServiceErrorKind {
@@ -12,7 +20,11 @@ pub enum ServiceErrorKind {
     PoolError,
     DbError,
     BadRequest,
+    Unauthorized,
+    Forbidden,
+    Conflict,
     InternalError,
+    Unavailable,
 }
 
 #[derive(From, Debug)]
@@ -36,38 +48,279 @@ impl ResponseError for ServiceError {
             ServiceErrorKind::PoolError => StatusCode::INTERNAL_SERVER_ERROR,
             ServiceErrorKind::DbError => StatusCode::INTERNAL_SERVER_ERROR,
             ServiceErrorKind::BadRequest => StatusCode::BAD_REQUEST,
+            ServiceErrorKind::Unauthorized => StatusCode::UNAUTHORIZED,
+            ServiceErrorKind::Forbidden => StatusCode::FORBIDDEN,
+            ServiceErrorKind::Conflict => StatusCode::CONFLICT,
             ServiceErrorKind::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            ServiceErrorKind::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
         }
     }
 
     fn error_response(&self) -> HttpResponse {
         let status_code = self.status_code();
 
-        HttpResponse::build(status_code).json(Error {
-            code: i32::from(status_code.as_u16()),
-            message: self.to_string(),
-        })
+        let mut response = HttpResponse::build(status_code);
+
+        if let ServiceErrorKind::Unauthorized = self.kind {
+            response.insert_header(("WWW-Authenticate", "Bearer"));
+        }
+
+        response
+            .content_type(PROBLEM_CONTENT_TYPE)
+            .json(ProblemDetails {
+                problem_type: "about:blank".to_string(),
+                title: status_code
+                    .canonical_reason()
+                    .unwrap_or_default()
+                    .to_string(),
+                status: status_code.as_u16(),
+                detail: (!self.message.is_empty()).then(|| self.message.clone()),
+            })
     }
 }
 
 impl From<tokio_postgres::error::Error> for ServiceError {
     fn from(value: tokio_postgres::error::Error) -> ServiceError {
-        error!("{value:?}");
+        ServiceError::from(minerva::error::Error::Database(
+            minerva::error::DatabaseError::from(value),
+        ))
+    }
+}
 
-        ServiceError {
-            kind: ServiceErrorKind::DbError,
-            message: format!("{value}"),
+/// Map the error of loading or changing a Minerva object
+///
+/// Not found errors are raised for objects that do not exist and configuration errors for
+/// invalid definitions. Database errors are mapped on the kind of error that the database
+/// reported and any other runtime error is an internal error.
+impl From<minerva::error::Error> for ServiceError {
+    fn from(value: minerva::error::Error) -> ServiceError {
+        match value {
+            minerva::error::Error::NotFound(e) => ServiceError {
+                kind: ServiceErrorKind::NotFound,
+                message: e.msg,
+            },
+            minerva::error::Error::Runtime(e) => {
+                error!("{}", e.msg);
+
+                ServiceError {
+                    kind: ServiceErrorKind::InternalError,
+                    message: e.msg,
+                }
+            }
+            minerva::error::Error::Configuration(e) => ServiceError {
+                kind: ServiceErrorKind::BadRequest,
+                message: e.msg,
+            },
+            minerva::error::Error::Database(e) => match e.kind {
+                DatabaseErrorKind::UniqueViolation | DatabaseErrorKind::ForeignKeyViolation => {
+                    ServiceError {
+                        kind: ServiceErrorKind::Conflict,
+                        message: e.msg,
+                    }
+                }
+                DatabaseErrorKind::UndefinedObject => ServiceError {
+                    kind: ServiceErrorKind::NotFound,
+                    message: e.msg,
+                },
+                DatabaseErrorKind::Default => {
+                    error!("{}", e.msg);
+
+                    ServiceError {
+                        kind: ServiceErrorKind::DbError,
+                        message: e.msg,
+                    }
+                }
+            },
         }
     }
 }
 
-impl From<Error> for ServiceError {
-    fn from(value: Error) -> ServiceError {
-        error!("{value:?}");
+impl From<deadpool_postgres::PoolError> for ServiceError {
+    fn from(value: deadpool_postgres::PoolError) -> ServiceError {
+        error!("Could not get database connection: {value}");
 
         ServiceError {
-            kind: ServiceErrorKind::InternalError,
-            message: format!("{value:?}"),
+            kind: ServiceErrorKind::PoolError,
+            message: format!("Could not get database connection: {value}"),
         }
     }
 }
+
+/// Error handler for invalid query strings, to report them as problem details
+pub fn query_error_handler(err: QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    ServiceError {
+        kind: ServiceErrorKind::BadRequest,
+        message: err.to_string(),
+    }
+    .into()
+}
+
+/// Error handler for path parameters that cannot be parsed, to report them as problem details
+pub fn path_error_handler(err: PathError, _req: &HttpRequest) -> actix_web::Error {
+    ServiceError {
+        kind: ServiceErrorKind::NotFound,
+        message: err.to_string(),
+    }
+    .into()
+}
+
+/// Default service for requests that match none of the endpoints
+pub async fn unknown_resource(req: HttpRequest) -> Result<HttpResponse, ServiceError> {
+    Err(ServiceError {
+        kind: ServiceErrorKind::NotFound,
+        message: format!("No resource found for {} {}", req.method(), req.path()),
+    })
+}
+
+fn problem_response(description: &str) -> Response {
+    ResponseBuilder::new()
+        .description(description)
+        .content(
+            PROBLEM_CONTENT_TYPE,
+            ContentBuilder::new()
+                .schema(Ref::from_schema_name("ProblemDetails"))
+                .build(),
+        )
+        .build()
+}
+
+/// Documents the problem details content type for the error responses of all endpoints
+///
+/// Error responses that can occur for any endpoint, like failing authentication, are added
+/// when they are not documented by the endpoint itself.
+pub struct ProblemResponses;
+
+impl ProblemResponses {
+    fn modify_operation(operation: &mut Operation) {
+        let responses = &mut operation.responses.responses;
+
+        for (status, response) in responses.iter_mut() {
+            if status.starts_with('2') {
+                continue;
+            }
+
+            if let RefOr::T(response) = response {
+                if let Some(content) = response.content.shift_remove("application/json") {
+                    response
+                        .content
+                        .insert(PROBLEM_CONTENT_TYPE.to_string(), content);
+                }
+            }
+        }
+
+        // Endpoints such as the health checks declare an empty security requirement
+        let public = operation.security.as_ref().is_some_and(|requirements| {
+            requirements
+                .iter()
+                .all(|requirement| *requirement == SecurityRequirement::default())
+        });
+
+        for (status, description) in [
+            ("401", "Missing or invalid credentials"),
+            ("403", "Role of the user does not permit the request"),
+            ("500", "Database unreachable or unexpected problem"),
+        ] {
+            if public && status != "500" {
+                continue;
+            }
+
+            operation
+                .responses
+                .responses
+                .entry(status.to_string())
+                .or_insert_with(|| problem_response(description).into());
+        }
+    }
+}
+
+impl Modify for ProblemResponses {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        for path_item in openapi.paths.paths.values_mut() {
+            for operation in path_item.operations.values_mut() {
+                ProblemResponses::modify_operation(operation);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use minerva::error::{ConfigurationError, DatabaseError, Error, NotFoundError, RuntimeError};
+
+    fn database_error(kind: DatabaseErrorKind) -> Error {
+        Error::Database(DatabaseError {
+            msg: "error".to_string(),
+            kind,
+        })
+    }
+
+    #[test]
+    fn minerva_error_status() {
+        let cases = [
+            (
+                Error::NotFound(NotFoundError::from_msg("error".to_string())),
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                Error::Runtime(RuntimeError::from_msg("error".to_string())),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                Error::Configuration(ConfigurationError::from_msg("error".to_string())),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                database_error(DatabaseErrorKind::UniqueViolation),
+                StatusCode::CONFLICT,
+            ),
+            (
+                database_error(DatabaseErrorKind::ForeignKeyViolation),
+                StatusCode::CONFLICT,
+            ),
+            (
+                database_error(DatabaseErrorKind::UndefinedObject),
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                database_error(DatabaseErrorKind::Default),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        ];
+
+        for (error, status) in cases {
+            assert_eq!(ServiceError::from(error).status_code(), status);
+        }
+    }
+
+    #[actix_web::test]
+    async fn problem_details_response() {
+        let error = ServiceError {
+            kind: ServiceErrorKind::Conflict,
+            message: "Trigger 'x' already exists".to_string(),
+        };
+
+        let response = error.error_response();
+
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            response.headers().get("Content-Type").unwrap(),
+            PROBLEM_CONTENT_TYPE
+        );
+
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(
+            problem,
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Conflict",
+                "status": 409,
+                "detail": "Trigger 'x' already exists",
+            })
+        );
+    }
+}
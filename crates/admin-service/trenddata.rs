@@ -0,0 +1,213 @@
+use deadpool_postgres::Pool;
+use std::collections::BTreeMap;
+use std::ops::DerefMut;
+
+use actix_web::{get, web::Data, web::Query, HttpResponse};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use minerva::entity_set::load_entity_set;
+use minerva::trend_data::{query_trend_data, TrendDataPage, TrendDataQuery, TrendDataRow};
+
+use super::auth::ReadAccess;
+use super::serviceerror::{ServiceError, ServiceErrorKind};
+
+const DEFAULT_ROW_LIMIT: i64 = 1000;
+const MAX_ROW_LIMIT: i64 = 10000;
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct TrendDataRowFull {
+    entity_id: i32,
+    entity: Option<String>,
+    timestamp: DateTime<Utc>,
+    /// Value per trend, null when there is no value
+    values: BTreeMap<String, serde_json::Value>,
+}
+
+impl From<TrendDataRow> for TrendDataRowFull {
+    fn from(row: TrendDataRow) -> Self {
+        TrendDataRowFull {
+            entity_id: row.entity_id,
+            entity: row.entity,
+            timestamp: row.timestamp,
+            values: row.values,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct TrendDataPageFull {
+    entity_type: String,
+    granularity: String,
+    trend_store_parts: Vec<String>,
+    trends: Vec<String>,
+    rows: Vec<TrendDataRowFull>,
+    offset: i64,
+    limit: i64,
+    /// Offset of the next page, if there are more rows
+    next_offset: Option<i64>,
+}
+
+impl From<TrendDataPage> for TrendDataPageFull {
+    fn from(page: TrendDataPage) -> Self {
+        TrendDataPageFull {
+            entity_type: page.entity_type,
+            granularity: page.granularity,
+            trend_store_parts: page.trend_store_parts,
+            trends: page.trends,
+            rows: page.rows.into_iter().map(TrendDataRowFull::from).collect(),
+            offset: page.offset,
+            limit: page.limit,
+            next_offset: page.next_offset,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct TrendDataParams {
+    /// Name of the trend store part, all its trends are returned when no trends are specified
+    trend_store_part: Option<String>,
+    /// Comma separated trend names
+    trends: Option<String>,
+    /// Entity type, to choose between trends with the same name
+    entity_type: Option<String>,
+    /// Granularity, e.g. 15m or 1d, to choose between trends with the same name
+    granularity: Option<String>,
+    /// Comma separated entity Ids
+    entity_ids: Option<String>,
+    /// Comma separated entity names
+    entities: Option<String>,
+    /// Entity set as owner:name
+    entity_set: Option<String>,
+    /// Start of the time range (inclusive)
+    start: DateTime<Utc>,
+    /// End of the time range (exclusive)
+    end: DateTime<Utc>,
+    /// Response format: json (default) or csv
+    format: Option<String>,
+    /// Number of rows to skip
+    offset: Option<i64>,
+    /// Maximum number of rows to return, at most 10000
+    limit: Option<i64>,
+}
+
+fn split_list(value: &Option<String>) -> Vec<String> {
+    value
+        .as_deref()
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn bad_request(message: String) -> ServiceError {
+    ServiceError {
+        kind: ServiceErrorKind::BadRequest,
+        message,
+    }
+}
+
+#[utoipa::path(
+    get,
+    path="/trend-data",
+    params(TrendDataParams),
+    responses(
+    (status = 200, description = "Trend data rows, ordered by timestamp and entity Id", body = TrendDataPageFull),
+    (status = 400, description = "Invalid or ambiguous selection", body = ProblemDetails),
+    (status = 404, description = "Trend, trend store part, entity or entity set not found", body = ProblemDetails),
+    (status = 500, description = "Problem interacting with database", body = ProblemDetails),
+    )
+)]
+#[get("/trend-data")]
+pub(super) async fn get_trend_data(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+    params: Query<TrendDataParams>,
+) -> Result<HttpResponse, ServiceError> {
+    let csv = match params.format.as_deref() {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(format) => {
+            return Err(bad_request(format!(
+                "Unsupported format '{format}', expected json or csv"
+            )))
+        }
+    };
+
+    let limit = params.limit.unwrap_or(DEFAULT_ROW_LIMIT);
+
+    if limit > MAX_ROW_LIMIT {
+        return Err(bad_request(format!(
+            "Limit {limit} exceeds the maximum of {MAX_ROW_LIMIT} rows"
+        )));
+    }
+
+    let entity_ids = split_list(&params.entity_ids)
+        .iter()
+        .map(|id| {
+            id.parse::<i32>()
+                .map_err(|e| bad_request(format!("Invalid entity Id '{id}': {e}")))
+        })
+        .collect::<Result<Vec<i32>, ServiceError>>()?;
+
+    let mut entity_names = split_list(&params.entities);
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    if let Some(entity_set) = &params.entity_set {
+        let (owner, name) = entity_set.split_once(':').ok_or_else(|| {
+            bad_request(format!(
+                "Invalid entity set '{entity_set}', expected owner:name"
+            ))
+        })?;
+
+        let entity_set = load_entity_set(client, owner, name)
+            .await
+            .map_err(|e| ServiceError {
+                kind: ServiceErrorKind::NotFound,
+                message: e,
+            })?;
+
+        entity_names.extend(entity_set.entities);
+    }
+
+    let query = TrendDataQuery {
+        trend_store_part: params.trend_store_part.clone(),
+        trends: split_list(&params.trends),
+        entity_type: params.entity_type.clone(),
+        granularity: params.granularity.clone(),
+        entity_ids,
+        entity_names,
+        start: params.start,
+        end: params.end,
+        offset: params.offset.unwrap_or(0),
+        limit,
+    };
+
+    let page = query_trend_data(client, &query).await?;
+
+    if csv {
+        let mut response = HttpResponse::Ok();
+
+        response.content_type("text/csv");
+
+        if let Some(next_offset) = page.next_offset {
+            response.insert_header(("X-Next-Offset", next_offset.to_string()));
+        }
+
+        let body = page.to_csv()?;
+
+        Ok(response.body(body))
+    } else {
+        Ok(HttpResponse::Ok().json(TrendDataPageFull::from(page)))
+    }
+}
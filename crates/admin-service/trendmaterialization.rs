@@ -1,27 +1,35 @@
 use serde_json::Value;
+use std::collections::HashMap;
 use std::ops::DerefMut;
 use std::time::Duration;
 
 use deadpool_postgres::Pool;
 
-use actix_web::{delete, get, post, put, web::Data, web::Path, HttpResponse, Responder};
+use actix_web::{
+    delete, get, patch, post, put, web::Data, web::Path, web::Query, HttpRequest, HttpResponse,
+};
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 
-use minerva::change::GenericChange;
+use minerva::audit::{generic_apply_audited, record_change, AuditContext};
 use minerva::interval::parse_interval;
 use minerva::trend_materialization::{
-    AddTrendMaterialization, TrendFunctionMaterialization, TrendMaterialization,
+    enable_expired_materializations, load_materialization_metrics, AddTrendMaterialization,
+    DisableTrendMaterialization, EnableTrendMaterialization, MaterializationMetrics,
+    TrendFunctionMaterialization, TrendMaterialization,
     TrendMaterializationFunction, TrendMaterializationSource, TrendViewMaterialization,
     UpdateTrendMaterialization,
 };
 use tokio_postgres::GenericClient;
 
-use log::error;
+use log::{error, info};
 
+use super::auth::{AdminAccess, ReadAccess};
+use super::listing::{page_response, Listing, PageRequest, SortColumn};
 use super::serviceerror::ServiceError;
-use crate::error::{Error, Success};
+use crate::error::Success;
 use crate::serviceerror::ServiceErrorKind;
 
 #[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
@@ -170,14 +178,12 @@ impl TrendViewMaterializationData {
     pub async fn create<T: GenericClient + Send + Sync>(
         &self,
         client: &mut T,
-    ) -> Result<TrendViewMaterializationFull, Error> {
+        context: &AuditContext,
+    ) -> Result<TrendViewMaterializationFull, ServiceError> {
         let action = AddTrendMaterialization {
             trend_materialization: self.as_minerva(),
         };
-        action.generic_apply(client).await.map_err(|e| Error {
-            code: 409,
-            message: e.to_string(),
-        })?;
+        generic_apply_audited(&action, client, context).await?;
 
         let row = client
             .query_one(
@@ -193,8 +199,8 @@ impl TrendViewMaterializationData {
                 &[&self.target_trend_store_part]
             )
             .await
-            .map_err(|e| Error {
-                code: 404,
+            .map_err(|e| ServiceError {
+                kind: ServiceErrorKind::NotFound,
                 message: format!("Creation reported as succeeded, but could not find created view materialization afterward: {}", &e)
             })?;
 
@@ -211,8 +217,8 @@ impl TrendViewMaterializationData {
                 &[&id]
             )
             .await
-            .map_err(|e| Error {
-                code: 404,
+            .map_err(|e| ServiceError {
+                kind: ServiceErrorKind::NotFound,
                 message: format!("Creation reported as succeeded, but could not find created view materialization afterward: {}", &e)
             })
             .map(|rows| rows
@@ -261,15 +267,13 @@ impl TrendFunctionMaterializationData {
     pub async fn create<T: GenericClient + Send + Sync>(
         &self,
         client: &mut T,
-    ) -> Result<TrendFunctionMaterializationFull, Error> {
+        context: &AuditContext,
+    ) -> Result<TrendFunctionMaterializationFull, ServiceError> {
         let action = AddTrendMaterialization {
             trend_materialization: self.as_minerva(),
         };
 
-        action.generic_apply(client).await.map_err(|e| Error {
-            code: 409,
-            message: e.to_string(),
-        })?;
+        generic_apply_audited(&action, client, context).await?;
 
         let query = concat!(
             "SELECT fm.id, m.id, src_function, tsp.name, processing_delay::text, stability_delay::text, reprocessing_period::text, enabled, pg_proc.prosrc, data_type, routine_definition, external_language, m.description ",
@@ -290,8 +294,8 @@ impl TrendFunctionMaterializationData {
             .map_err(|e| {
                 error!("Creation reported as succeeded, but could not find created function materialization afterward: {query}");
 
-                Error {
-                    code: 404,
+                ServiceError {
+                    kind: ServiceErrorKind::NotFound,
                     message: format!("Creation reported as succeeded, but could not find created function materialization afterward: {}", &e)
                 }
             })?;
@@ -309,7 +313,6 @@ impl TrendFunctionMaterializationData {
                 &[&id],
             )
             .await
-            .map_err(|e| Error { code: 500, message: e.to_string() })
             .map(|rows| rows
                 .iter().map(|inner_row|
                     TrendMaterializationSourceData {
@@ -344,7 +347,8 @@ impl TrendFunctionMaterializationData {
     pub async fn update<T: GenericClient + Send + Sync>(
         &self,
         client: &mut T,
-    ) -> Result<Success, Error> {
+        context: &AuditContext,
+    ) -> Result<Success, ServiceError> {
         client
             .query_one(
                 concat!(
@@ -357,8 +361,8 @@ impl TrendFunctionMaterializationData {
                 &[&self.target_trend_store_part],
             )
             .await
-            .map_err(|e| Error {
-                code: 404,
+            .map_err(|e| ServiceError {
+                kind: ServiceErrorKind::NotFound,
                 message: format!(
                     "No function materialization targetting '{}' found: {}",
                     self.target_trend_store_part,
@@ -370,13 +374,8 @@ impl TrendFunctionMaterializationData {
             trend_materialization: self.as_minerva(),
         };
 
-        action
-            .generic_apply(client)
+        generic_apply_audited(&action, client, context)
             .await
-            .map_err(|e| Error {
-                code: 500,
-                message: format!("Update of materialization failed: {e}"),
-            })
             .map(|_| {
                 Ok(Success {
                     code: 200,
@@ -388,8 +387,9 @@ impl TrendFunctionMaterializationData {
     pub async fn client_update<T: GenericClient + Send + Sync>(
         &self,
         client: &mut T,
-    ) -> Result<Success, Error> {
-        self.update(client).await
+        context: &AuditContext,
+    ) -> Result<Success, ServiceError> {
+        self.update(client, context).await
     }
 }
 
@@ -406,17 +406,15 @@ impl TrendFunctionMaterializationData {
     path="/trend-view-materializations",
     responses(
         (status = 200, description = "List current trend view materialization items", body = [TrendViewMaterializationFull]),
-    (status = 500, description = "Problem interacting with database", body = Error)
+    (status = 500, description = "Problem interacting with database", body = ProblemDetails)
     )
 )]
 #[get("/trend-view-materializations")]
 pub(super) async fn get_trend_view_materializations(
+    _access: ReadAccess,
     pool: Data<Pool>,
 ) -> Result<HttpResponse, ServiceError> {
-    let client = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+    let client = pool.get().await?;
 
     let sources: Vec<TrendMaterializationSourceIdentifier> = client
         .query(
@@ -428,10 +426,6 @@ pub(super) async fn get_trend_view_materializations(
             &[],
         )
         .await
-        .map_err(|e| Error {
-            code: 500,
-            message: format!("Unable to list sources: {e}"),
-        })
         .map(|rows| {
             rows.iter()
                 .map(|row| TrendMaterializationSourceIdentifier {
@@ -457,10 +451,6 @@ pub(super) async fn get_trend_view_materializations(
             &[],
         )
         .await
-        .map_err(|e| Error {
-            code: 500,
-            message: format!("Unable to list materializations: {e}"),
-        })
         .map(|rows| rows
             .iter()
             .map(|row| {
@@ -496,21 +486,19 @@ pub(super) async fn get_trend_view_materializations(
     path="/trend-view-materializations/{id}",
     responses(
     (status = 200, description = "Get a specific view materialization", body = TrendViewMaterializationFull),
-    (status = 404, description = "View materialization not found", body = Error),
-    (status = 500, description = "Unable to interact with database", body = Error)
+    (status = 404, description = "View materialization not found", body = ProblemDetails),
+    (status = 500, description = "Unable to interact with database", body = ProblemDetails)
     )
 )]
 #[get("/trend-view-materializations/{id}")]
 pub(super) async fn get_trend_view_materialization(
+    _access: ReadAccess,
     pool: Data<Pool>,
     id: Path<i32>,
 ) -> Result<HttpResponse, ServiceError> {
     let vm_id = id.into_inner();
 
-    let client = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+    let client = pool.get().await?;
 
     let sources: Vec<TrendMaterializationSourceData> = client
         .query(
@@ -524,10 +512,6 @@ pub(super) async fn get_trend_view_materialization(
             &[&vm_id],
         )
         .await
-        .map_err(|e| Error {
-            code: 500,
-            message: e.to_string(),
-        })
         .map(|rows| rows
             .iter()
             .map(|row| TrendMaterializationSourceData {
@@ -551,8 +535,8 @@ pub(super) async fn get_trend_view_materialization(
             &[&vm_id],
         )
         .await
-        .map_err(|_| Error {
-            code: 404,
+        .map_err(|_| ServiceError {
+            kind: ServiceErrorKind::NotFound,
             message: format!("Trend view materialization with id {} not found", &vm_id),
         })
         .map(|row| TrendViewMaterializationFull {
@@ -580,79 +564,60 @@ pub(super) async fn get_trend_view_materialization(
     )
 )]
 #[get("/trend-function-materializations")]
-pub(super) async fn get_trend_function_materializations(pool: Data<Pool>) -> impl Responder {
-    let mut m: Vec<TrendFunctionMaterializationFull> = vec![];
-    let client_query = pool.get().await;
-    match client_query {
-        Err(e) => HttpResponse::InternalServerError().json(Error {
-            code: 500,
-            message: e.to_string(),
-        }),
-        Ok(client) => {
-            let mut sources: Vec<TrendMaterializationSourceIdentifier> = vec![];
-            let query = client.query("SELECT materialization_id, tsp.name, timestamp_mapping_func::text FROM trend_directory.materialization_trend_store_link JOIN trend_directory.trend_store_part tsp ON trend_store_part_id = tsp.id", &[],).await;
-            match query {
-                Err(e) => HttpResponse::InternalServerError().json(Error {
-                    code: 500,
-                    message: e.to_string(),
-                }),
-                Ok(query_result) => {
-                    for row in query_result {
-                        let source = TrendMaterializationSourceIdentifier {
-                            materialization: row.get(0),
-                            source: TrendMaterializationSourceData {
-                                trend_store_part: row.get(1),
-                                mapping_function: row.get(2),
-                            },
-                        };
-                        sources.push(source)
-                    }
+pub(super) async fn get_trend_function_materializations(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client = pool.get().await?;
 
-                    let query = client.query("SELECT fm.id, m.id, src_function, tsp.name, processing_delay::text, stability_delay::text, reprocessing_period::text, enabled, pg_proc.prosrc, data_type, routine_definition, external_language, m.description FROM trend_directory.function_materialization fm JOIN trend_directory.materialization m ON fm.materialization_id = m.id JOIN information_schema.routines ON FORMAT('%s.\"%s\"', routine_schema, routine_name) = src_function  JOIN trend_directory.trend_store_part tsp ON dst_trend_store_part_id = tsp.id LEFT JOIN pg_proc ON trend_directory.fingerprint_function_name(m) = proname", &[],).await;
-                    match query {
-                        Err(e) => HttpResponse::InternalServerError().json(Error {
-                            code: 500,
-                            message: e.to_string(),
-                        }),
-                        Ok(query_result) => {
-                            for row in query_result {
-                                let mat_id: i32 = row.get(1);
-
-                                let mut this_sources: Vec<TrendMaterializationSourceData> = vec![];
-                                for source in &sources {
-                                    if source.materialization == mat_id {
-                                        this_sources.push(source.source.clone())
-                                    }
-                                }
-
-                                let materialization = TrendFunctionMaterializationFull {
-                                    id: row.get(0),
-                                    materialization_id: row.get(1),
-                                    target_trend_store_part: row.get(3),
-                                    enabled: row.get(7),
-                                    processing_delay: parse_interval(row.get(4)).unwrap(),
-                                    stability_delay: parse_interval(row.get(5)).unwrap(),
-                                    reprocessing_period: parse_interval(row.get(6)).unwrap(),
-                                    sources: this_sources,
-                                    function: TrendMaterializationFunctionFull {
-                                        name: row.get(2),
-                                        return_type: row.get(9),
-                                        src: row.get(10),
-                                        language: row.get(11),
-                                    },
-                                    description: row.get(12),
-                                    fingerprint_function: row.get(8),
-                                };
-
-                                m.push(materialization)
-                            }
-                            HttpResponse::Ok().json(m)
-                        }
-                    }
-                }
+    let sources: Vec<TrendMaterializationSourceIdentifier> = client
+        .query("SELECT materialization_id, tsp.name, timestamp_mapping_func::text FROM trend_directory.materialization_trend_store_link JOIN trend_directory.trend_store_part tsp ON trend_store_part_id = tsp.id", &[],)
+        .await?
+        .iter()
+        .map(|row| TrendMaterializationSourceIdentifier {
+            materialization: row.get(0),
+            source: TrendMaterializationSourceData {
+                trend_store_part: row.get(1),
+                mapping_function: row.get(2),
+            },
+        })
+        .collect();
+
+    let materializations: Vec<TrendFunctionMaterializationFull> = client
+        .query("SELECT fm.id, m.id, src_function, tsp.name, processing_delay::text, stability_delay::text, reprocessing_period::text, enabled, pg_proc.prosrc, data_type, routine_definition, external_language, m.description FROM trend_directory.function_materialization fm JOIN trend_directory.materialization m ON fm.materialization_id = m.id JOIN information_schema.routines ON FORMAT('%s.\"%s\"', routine_schema, routine_name) = src_function  JOIN trend_directory.trend_store_part tsp ON dst_trend_store_part_id = tsp.id LEFT JOIN pg_proc ON trend_directory.fingerprint_function_name(m) = proname", &[],)
+        .await?
+        .iter()
+        .map(|row| {
+            let mat_id: i32 = row.get(1);
+
+            let this_sources: Vec<TrendMaterializationSourceData> = sources
+                .iter()
+                .filter(|source| source.materialization == mat_id)
+                .map(|source| source.source.clone())
+                .collect();
+
+            TrendFunctionMaterializationFull {
+                id: row.get(0),
+                materialization_id: row.get(1),
+                target_trend_store_part: row.get(3),
+                enabled: row.get(7),
+                processing_delay: parse_interval(row.get(4)).unwrap(),
+                stability_delay: parse_interval(row.get(5)).unwrap(),
+                reprocessing_period: parse_interval(row.get(6)).unwrap(),
+                sources: this_sources,
+                function: TrendMaterializationFunctionFull {
+                    name: row.get(2),
+                    return_type: row.get(9),
+                    src: row.get(10),
+                    language: row.get(11),
+                },
+                description: row.get(12),
+                fingerprint_function: row.get(8),
             }
-        }
-    }
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(materializations))
 }
 
 #[utoipa::path(
@@ -660,222 +625,235 @@ pub(super) async fn get_trend_function_materializations(pool: Data<Pool>) -> imp
     path="/trend-function-materializations/{id}",
     responses(
     (status = 200, description = "Get a specific function materialization", body = TrendFunctionMaterializationFull),
-    (status = 404, description = "View function not found", body = Error),
-    (status = 500, description = "Unable to interact with database", body = Error)
+    (status = 404, description = "View function not found", body = ProblemDetails),
+    (status = 500, description = "Unable to interact with database", body = ProblemDetails)
     )
 )]
 #[get("/trend-function-materializations/{id}")]
 pub(super) async fn get_trend_function_materialization(
+    _access: ReadAccess,
     pool: Data<Pool>,
     id: Path<i32>,
-) -> impl Responder {
+) -> Result<HttpResponse, ServiceError> {
     let fm_id = id.into_inner();
 
-    let result = pool.get().await;
-    match result {
-        Err(e) => HttpResponse::InternalServerError().json(Error {
-            code: 500,
-            message: e.to_string(),
-        }),
-        Ok(client) => {
-            let query_result = client.query_one("SELECT fm.id, m.id, src_function, tsp.name, processing_delay::text, stability_delay::text, reprocessing_period::text, enabled, pg_proc.prosrc, data_type, routine_definition, external_language, m.description FROM trend_directory.function_materialization fm JOIN trend_directory.materialization m ON fm.materialization_id = m.id JOIN trend_directory.trend_store_part tsp ON dst_trend_store_part_id = tsp.id JOIN information_schema.routines ON FORMAT('%s.\"%s\"', routine_schema, routine_name) = src_function LEFT JOIN pg_proc ON routine_name = proname WHERE fm.id = $1", &[&fm_id],).await;
-
-            match query_result {
-                Err(e) => HttpResponse::NotFound().json(Error {
-                    code: 404,
-                    message: e.to_string(),
-                }),
-                Ok(row) => {
-                    let mut sources: Vec<TrendMaterializationSourceData> = vec![];
-                    let query = client.query("SELECT tsp.name, timestamp_mapping_func::text FROM trend_directory.materialization_trend_store_link tsl JOIN trend_directory.trend_store_part tsp ON trend_store_part_id = tsp.id JOIN trend_directory.view_materialization vm ON tsl.materialization_id = vm.materialization_id WHERE vm.id = $1", &[&fm_id],).await;
-                    match query {
-                        Err(e) => HttpResponse::InternalServerError().json(Error {
-                            code: 500,
-                            message: e.to_string(),
-                        }),
-                        Ok(query_result) => {
-                            for inner_row in query_result {
-                                let source = TrendMaterializationSourceData {
-                                    trend_store_part: inner_row.get(0),
-                                    mapping_function: inner_row.get(1),
-                                };
-                                sources.push(source)
-                            }
-
-                            let materialization = TrendFunctionMaterializationFull {
-                                id: row.get(0),
-                                materialization_id: row.get(1),
-                                target_trend_store_part: row.get(3),
-                                enabled: row.get(7),
-                                processing_delay: parse_interval(row.get(4)).unwrap(),
-                                stability_delay: parse_interval(row.get(5)).unwrap(),
-                                reprocessing_period: parse_interval(row.get(6)).unwrap(),
-                                sources,
-                                function: TrendMaterializationFunctionFull {
-                                    name: row.get(2),
-                                    return_type: row.get(9),
-                                    src: row.get(10),
-                                    language: row.get(11),
-                                },
-                                description: row.get(12),
-                                fingerprint_function: row.get(8),
-                            };
-
-                            HttpResponse::Ok().json(materialization)
-                        }
-                    }
-                }
-            }
-        }
-    }
+    let client = pool.get().await?;
+
+    let row = client
+        .query_opt("SELECT fm.id, m.id, src_function, tsp.name, processing_delay::text, stability_delay::text, reprocessing_period::text, enabled, pg_proc.prosrc, data_type, routine_definition, external_language, m.description FROM trend_directory.function_materialization fm JOIN trend_directory.materialization m ON fm.materialization_id = m.id JOIN trend_directory.trend_store_part tsp ON dst_trend_store_part_id = tsp.id JOIN information_schema.routines ON FORMAT('%s.\"%s\"', routine_schema, routine_name) = src_function LEFT JOIN pg_proc ON routine_name = proname WHERE fm.id = $1", &[&fm_id],)
+        .await?
+        .ok_or_else(|| ServiceError {
+            kind: ServiceErrorKind::NotFound,
+            message: format!("Trend function materialization with id {} not found", &fm_id),
+        })?;
+
+    let sources: Vec<TrendMaterializationSourceData> = client
+        .query("SELECT tsp.name, timestamp_mapping_func::text FROM trend_directory.materialization_trend_store_link tsl JOIN trend_directory.trend_store_part tsp ON trend_store_part_id = tsp.id JOIN trend_directory.view_materialization vm ON tsl.materialization_id = vm.materialization_id WHERE vm.id = $1", &[&fm_id],)
+        .await?
+        .iter()
+        .map(|inner_row| TrendMaterializationSourceData {
+            trend_store_part: inner_row.get(0),
+            mapping_function: inner_row.get(1),
+        })
+        .collect();
+
+    let materialization = TrendFunctionMaterializationFull {
+        id: row.get(0),
+        materialization_id: row.get(1),
+        target_trend_store_part: row.get(3),
+        enabled: row.get(7),
+        processing_delay: parse_interval(row.get(4)).unwrap(),
+        stability_delay: parse_interval(row.get(5)).unwrap(),
+        reprocessing_period: parse_interval(row.get(6)).unwrap(),
+        sources,
+        function: TrendMaterializationFunctionFull {
+            name: row.get(2),
+            return_type: row.get(9),
+            src: row.get(10),
+            language: row.get(11),
+        },
+        description: row.get(12),
+        fingerprint_function: row.get(8),
+    };
+
+    Ok(HttpResponse::Ok().json(materialization))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct TrendMaterializationListQuery {
+    /// Only materializations into a trend store part of this data source
+    data_source: Option<String>,
+    /// Only materializations into a trend store part of this entity type
+    entity_type: Option<String>,
+    /// Only materializations into a trend store part of this granularity, e.g. 15m or 1 day
+    granularity: Option<String>,
+    /// Only materializations with a target trend store part name matching this pattern, in
+    /// which '*' matches any characters
+    name: Option<String>,
+    /// Only enabled or only disabled materializations
+    enabled: Option<bool>,
+    /// Field to sort on: name (default), id, data_source, entity_type or granularity, prefixed
+    /// with '-' for descending order
+    sort: Option<String>,
+    /// Cursor from the Link header of the previous page
+    cursor: Option<String>,
+    /// Maximum number of materializations to return, all when not specified
+    limit: Option<i64>,
 }
 
+const TREND_MATERIALIZATION_SORT_COLUMNS: [SortColumn; 5] = [
+    SortColumn { name: "name", expression: "item.name", sql_type: "text" },
+    SortColumn { name: "id", expression: "item.id", sql_type: "integer" },
+    SortColumn { name: "data_source", expression: "item.data_source", sql_type: "text" },
+    SortColumn { name: "entity_type", expression: "item.entity_type", sql_type: "text" },
+    SortColumn { name: "granularity", expression: "item.granularity", sql_type: "interval" },
+];
+
 #[utoipa::path(
     get,
     path="/trend-materializations",
+    params(TrendMaterializationListQuery),
     responses(
-        (status = 200, description = "List current trend materializations", body = [TrendFunctionMaterializationFull]),
-    (status = 500, description = "Unable to correctly interact with database", body = Error)
+        (status = 200, description = "List current trend materializations", body = [TrendFunctionMaterializationFull],
+            headers(("Link" = String, description = "Link to the next page when there are more materializations"))),
+    (status = 400, description = "Invalid filter, sort order, cursor or limit", body = ProblemDetails),
+    (status = 500, description = "Unable to correctly interact with database", body = ProblemDetails)
     )
 )]
 #[get("/trend-materializations")]
-pub(super) async fn get_trend_materializations(pool: Data<Pool>) -> impl Responder {
-    let mut m: Vec<TrendMaterializationDef> = vec![];
-    let result = pool.get().await;
-    match result {
-        Err(e) => HttpResponse::InternalServerError().json(Error {
-            code: 500,
-            message: e.to_string(),
-        }),
-        Ok(client) => {
-            let mut sources: Vec<TrendMaterializationSourceIdentifier> = vec![];
-            let query_result = client
-                .query(
-                    concat!(
-                        "SELECT materialization_id, tsp.name, timestamp_mapping_func::text ",
-                        "FROM trend_directory.materialization_trend_store_link ",
-                        "JOIN trend_directory.trend_store_part tsp ON trend_store_part_id = tsp.id"
-                    ),
-                    &[],
-                )
-                .await;
-
-            match query_result {
-                Err(e) => HttpResponse::InternalServerError().json(Error {
-                    code: 500,
-                    message: e.to_string(),
-                }),
-                Ok(rows) => {
-                    for row in rows {
-                        let source = TrendMaterializationSourceIdentifier {
-                            materialization: row.get(0),
-                            source: TrendMaterializationSourceData {
-                                trend_store_part: row.get(1),
-                                mapping_function: row.get(2),
-                            },
-                        };
-                        sources.push(source)
-                    }
+pub(super) async fn get_trend_materializations(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+    query: Query<TrendMaterializationListQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let client = pool.get().await?;
+
+    let mut listing = Listing::new(
+        concat!(
+            "SELECT m.id, tsp.name::text AS name, m.enabled, ",
+            "ds.name::text AS data_source, et.name::text AS entity_type, ts.granularity ",
+            "FROM trend_directory.materialization m ",
+            "JOIN trend_directory.trend_store_part tsp ON tsp.id = m.dst_trend_store_part_id ",
+            "JOIN trend_directory.trend_store ts ON ts.id = tsp.trend_store_id ",
+            "JOIN directory.data_source ds ON ds.id = ts.data_source_id ",
+            "JOIN directory.entity_type et ON et.id = ts.entity_type_id"
+        ),
+        &TREND_MATERIALIZATION_SORT_COLUMNS,
+        &TREND_MATERIALIZATION_SORT_COLUMNS[1],
+    );
+
+    listing
+        .filter("item.data_source = $?", query.data_source.as_deref())
+        .filter("item.entity_type = $?", query.entity_type.as_deref())
+        .filter("item.granularity = $?::text::interval", query.granularity.as_deref())
+        .filter("item.enabled = $?::text::bool", query.enabled)
+        .filter_pattern("item.name", query.name.as_deref());
+
+    let page = listing
+        .fetch(client.client(), &PageRequest {
+            sort: query.sort.as_deref(),
+            cursor: query.cursor.as_deref(),
+            limit: query.limit,
+        })
+        .await?;
 
-                    let query_result = client.query(
-                        concat!(
-                            "SELECT vm.id, m.id, pg_views.definition, tsp.name, processing_delay::text, stability_delay::text, reprocessing_period::text, enabled, pg_proc.prosrc, m.description ",
-                            "FROM trend_directory.view_materialization vm ",
-                            "JOIN trend_directory.materialization m ON vm.materialization_id = m.id ",
-                            "JOIN trend_directory.trend_store_part tsp ON dst_trend_store_part_id = tsp.id ",
-                            "JOIN pg_proc ON trend_directory.fingerprint_function_name(m) = proname ",
-                            "JOIN pg_views ON FORMAT('%s.\"%s\"', schemaname, viewname) = src_view"
-                        ),
-                        &[]
-                    ).await;
-
-                    match query_result {
-                        Err(e) => HttpResponse::InternalServerError().json(Error {
-                            code: 500,
-                            message: e.to_string(),
-                        }),
-                        Ok(rows) => {
-                            for row in rows {
-                                let mat_id: i32 = row.get(1);
-
-                                let mut this_sources: Vec<TrendMaterializationSourceData> = vec![];
-                                for source in &sources {
-                                    if source.materialization == mat_id {
-                                        this_sources.push(source.source.clone())
-                                    }
-                                }
-
-                                let materialization =
-                                    TrendMaterializationDef::View(TrendViewMaterializationFull {
-                                        id: row.get(0),
-                                        materialization_id: row.get(1),
-                                        target_trend_store_part: row.get(3),
-                                        enabled: row.get(7),
-                                        processing_delay: parse_interval(row.get(4)).unwrap(),
-                                        stability_delay: parse_interval(row.get(5)).unwrap(),
-                                        reprocessing_period: parse_interval(row.get(6)).unwrap(),
-                                        sources: this_sources,
-                                        view: row.get(2),
-                                        description: row.get(9),
-                                        fingerprint_function: row.get(8),
-                                    });
-
-                                m.push(materialization);
-                            }
-
-                            let query_result = client.query("SELECT fm.id, m.id, src_function, tsp.name, processing_delay::text, stability_delay::text, reprocessing_period::text, enabled, pg_proc.prosrc, data_type, routine_definition, external_language, m.description FROM trend_directory.function_materialization fm JOIN trend_directory.materialization m ON fm.materialization_id = m.id JOIN trend_directory.trend_store_part tsp ON dst_trend_store_part_id = tsp.id JOIN information_schema.routines ON FORMAT('%s.\"%s\"', routine_schema, routine_name) = src_function LEFT JOIN pg_proc ON trend_directory.fingerprint_function_name(m) = proname", &[],).await;
-                            match query_result {
-                                Err(e) => HttpResponse::InternalServerError().json(Error {
-                                    code: 500,
-                                    message: e.to_string(),
-                                }),
-                                Ok(rows) => {
-                                    for row in rows {
-                                        let mat_id: i32 = row.get(1);
-                                        let mut this_sources: Vec<TrendMaterializationSourceData> =
-                                            vec![];
-                                        for source in &sources {
-                                            if source.materialization == mat_id {
-                                                this_sources.push(source.source.clone())
-                                            }
-                                        }
-
-                                        let materialization = TrendMaterializationDef::Function(
-                                            TrendFunctionMaterializationFull {
-                                                id: row.get(0),
-                                                materialization_id: row.get(1),
-                                                target_trend_store_part: row.get(3),
-                                                enabled: row.get(7),
-                                                processing_delay: parse_interval(row.get(4))
-                                                    .unwrap(),
-                                                stability_delay: parse_interval(row.get(5))
-                                                    .unwrap(),
-                                                reprocessing_period: parse_interval(row.get(6))
-                                                    .unwrap(),
-                                                sources: this_sources,
-                                                function: TrendMaterializationFunctionFull {
-                                                    name: row.get(2),
-                                                    return_type: row.get(9),
-                                                    src: row.get(10),
-                                                    language: row.get(11),
-                                                },
-                                                description: row.get(12),
-                                                fingerprint_function: row.get(8),
-                                            },
-                                        );
-
-                                        m.push(materialization)
-                                    }
-
-                                    HttpResponse::Ok().json(m)
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
+    let ids: Vec<i32> = page.rows.iter().map(|row| row.get("id")).collect();
+
+    let sources: Vec<TrendMaterializationSourceIdentifier> = client
+        .query(
+            concat!(
+                "SELECT materialization_id, tsp.name, timestamp_mapping_func::text ",
+                "FROM trend_directory.materialization_trend_store_link ",
+                "JOIN trend_directory.trend_store_part tsp ON trend_store_part_id = tsp.id ",
+                "WHERE materialization_id = ANY($1)"
+            ),
+            &[&ids],
+        )
+        .await?
+        .iter()
+        .map(|row| TrendMaterializationSourceIdentifier {
+            materialization: row.get(0),
+            source: TrendMaterializationSourceData {
+                trend_store_part: row.get(1),
+                mapping_function: row.get(2),
+            },
+        })
+        .collect();
+
+    let materialization_sources = |mat_id: i32| -> Vec<TrendMaterializationSourceData> {
+        sources
+            .iter()
+            .filter(|source| source.materialization == mat_id)
+            .map(|source| source.source.clone())
+            .collect()
+    };
+
+    let mut materializations: HashMap<i32, TrendMaterializationDef> = client
+        .query(
+            concat!(
+                "SELECT vm.id, m.id, pg_views.definition, tsp.name, processing_delay::text, stability_delay::text, reprocessing_period::text, enabled, pg_proc.prosrc, m.description ",
+                "FROM trend_directory.view_materialization vm ",
+                "JOIN trend_directory.materialization m ON vm.materialization_id = m.id ",
+                "JOIN trend_directory.trend_store_part tsp ON dst_trend_store_part_id = tsp.id ",
+                "JOIN pg_proc ON trend_directory.fingerprint_function_name(m) = proname ",
+                "JOIN pg_views ON FORMAT('%s.\"%s\"', schemaname, viewname) = src_view ",
+                "WHERE m.id = ANY($1)"
+            ),
+            &[&ids]
+        )
+        .await?
+        .iter()
+        .map(|row| {
+            (row.get(1), TrendMaterializationDef::View(TrendViewMaterializationFull {
+                id: row.get(0),
+                materialization_id: row.get(1),
+                target_trend_store_part: row.get(3),
+                enabled: row.get(7),
+                processing_delay: parse_interval(row.get(4)).unwrap(),
+                stability_delay: parse_interval(row.get(5)).unwrap(),
+                reprocessing_period: parse_interval(row.get(6)).unwrap(),
+                sources: materialization_sources(row.get(1)),
+                view: row.get(2),
+                description: row.get(9),
+                fingerprint_function: row.get(8),
+            }))
+        })
+        .collect();
+
+    let function_materializations = client
+        .query("SELECT fm.id, m.id, src_function, tsp.name, processing_delay::text, stability_delay::text, reprocessing_period::text, enabled, pg_proc.prosrc, data_type, routine_definition, external_language, m.description FROM trend_directory.function_materialization fm JOIN trend_directory.materialization m ON fm.materialization_id = m.id JOIN trend_directory.trend_store_part tsp ON dst_trend_store_part_id = tsp.id JOIN information_schema.routines ON FORMAT('%s.\"%s\"', routine_schema, routine_name) = src_function LEFT JOIN pg_proc ON trend_directory.fingerprint_function_name(m) = proname WHERE m.id = ANY($1)", &[&ids],)
+        .await?;
+
+    for row in function_materializations {
+        materializations.insert(row.get(1), TrendMaterializationDef::Function(
+            TrendFunctionMaterializationFull {
+                id: row.get(0),
+                materialization_id: row.get(1),
+                target_trend_store_part: row.get(3),
+                enabled: row.get(7),
+                processing_delay: parse_interval(row.get(4)).unwrap(),
+                stability_delay: parse_interval(row.get(5)).unwrap(),
+                reprocessing_period: parse_interval(row.get(6)).unwrap(),
+                sources: materialization_sources(row.get(1)),
+                function: TrendMaterializationFunctionFull {
+                    name: row.get(2),
+                    return_type: row.get(9),
+                    src: row.get(10),
+                    language: row.get(11),
+                },
+                description: row.get(12),
+                fingerprint_function: row.get(8),
+            },
+        ));
     }
+
+    let m: Vec<TrendMaterializationDef> = ids
+        .iter()
+        .filter_map(|id| materializations.remove(id))
+        .collect();
+
+    Ok(page_response(&req, &m, page.next_cursor))
 }
 
 // To call this with curl: -
@@ -888,35 +866,30 @@ pub(super) async fn get_trend_materializations(pool: Data<Pool>) -> impl Respond
     path="/trend-view-materializations",
     responses(
     (status = 200, description = "Create a new view materialization", body = TrendViewMaterializationFull),
-    (status = 400, description = "Incorrect data format", body = Error),
-    (status = 404, description = "Materialization cannot be found after creation", body = Error),
-    (status = 409, description = "View materialization cannot be created with these data", body = Error),
-    (status = 500, description = "Unable to interact with database", body = Error),
+    (status = 400, description = "Incorrect data format", body = ProblemDetails),
+    (status = 404, description = "Materialization cannot be found after creation", body = ProblemDetails),
+    (status = 409, description = "View materialization cannot be created with these data", body = ProblemDetails),
+    (status = 500, description = "Unable to interact with database", body = ProblemDetails),
     )
 )]
 #[post("/trend-view-materializations")]
 pub(super) async fn post_trend_view_materialization(
+    access: AdminAccess,
     pool: Data<Pool>,
     post: String,
 ) -> Result<HttpResponse, ServiceError> {
-    let data: TrendViewMaterializationData = serde_json::from_str(&post).map_err(|e| Error {
-        code: 400,
+    let data: TrendViewMaterializationData = serde_json::from_str(&post).map_err(|e| ServiceError {
+        kind: ServiceErrorKind::BadRequest,
         message: e.to_string(),
     })?;
 
-    let mut manager = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+    let mut manager = pool.get().await?;
 
     let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
 
-    let mut transaction = client.transaction().await.map_err(|e| Error {
-        code: 500,
-        message: e.to_string(),
-    })?;
+    let mut transaction = client.transaction().await?;
 
-    data.create(&mut transaction)
+    data.create(&mut transaction, &access.audit_context())
         .await
         .map(|materialization| Ok(HttpResponse::Ok().json(materialization)))?
 }
@@ -930,39 +903,58 @@ pub(super) async fn post_trend_view_materialization(
     path="/trend-function-materializations",
     responses(
     (status = 200, description = "Create a new view materialization", body = TrendViewMaterializationFull),
-    (status = 400, description = "Incorrect data format", body = Error),
-    (status = 404, description = "Materialization cannot be found after creation", body = Error),
-    (status = 409, description = "View materialization cannot be created with these data", body = Error),
+    (status = 400, description = "Incorrect data format", body = ProblemDetails),
+    (status = 404, description = "Materialization cannot be found after creation", body = ProblemDetails),
+    (status = 409, description = "View materialization cannot be created with these data", body = ProblemDetails),
     )
 )]
 #[post("/trend-function-materializations")]
 pub(super) async fn post_trend_function_materialization(
+    access: AdminAccess,
     pool: Data<Pool>,
     post: String,
 ) -> Result<HttpResponse, ServiceError> {
     let data: TrendFunctionMaterializationData =
-        serde_json::from_str(&post).map_err(|e| Error {
-            code: 400,
+        serde_json::from_str(&post).map_err(|e| ServiceError {
+            kind: ServiceErrorKind::BadRequest,
             message: e.to_string(),
         })?;
 
-    let mut manager = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+    let mut manager = pool.get().await?;
 
     let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
 
-    let mut transaction = client.transaction().await.map_err(|e| Error {
-        code: 500,
-        message: e.to_string(),
-    })?;
+    let mut transaction = client.transaction().await?;
 
-    data.create(&mut transaction)
+    data.create(&mut transaction, &access.audit_context())
         .await
         .map(|materialization| Ok(HttpResponse::Ok().json(materialization)))?
 }
 
+/// Delete a materialization together with its view or function materialization
+async fn delete_materialization<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    table: &str,
+    id: i32,
+    materialization_id: i32,
+) -> Result<(), minerva::error::Error> {
+    client
+        .execute(
+            &format!("DELETE FROM trend_directory.{table} WHERE id = $1"),
+            &[&id],
+        )
+        .await?;
+
+    client
+        .execute(
+            "DELETE FROM trend_directory.materialization WHERE id = $1",
+            &[&materialization_id],
+        )
+        .await?;
+
+    Ok(())
+}
+
 // curl -X DELETE localhost:8000/trend-view-materializations/1
 
 #[utoipa::path(
@@ -970,69 +962,60 @@ pub(super) async fn post_trend_function_materialization(
     path="/trend-view-materializations/{id}",
     responses(
     (status = 200, description = "Deleted function materialization", body = Success),
-    (status = 404, description = "Function materialization not found", body = Error),
-    (status = 500, description = "Deletion failed fully or partially", body = Error)
+    (status = 404, description = "Function materialization not found", body = ProblemDetails),
+    (status = 500, description = "Deletion failed fully or partially", body = ProblemDetails)
     )
 )]
 #[delete("/trend-view-materializations/{id}")]
 pub(super) async fn delete_trend_view_materialization(
+    access: AdminAccess,
     pool: Data<Pool>,
     id: Path<i32>,
-) -> impl Responder {
+) -> Result<HttpResponse, ServiceError> {
     let vm_id = id.into_inner();
-    let query_result = pool.get().await;
-    match query_result {
-        Err(e) => HttpResponse::InternalServerError().json(Error {
-            code: 500,
-            message: e.to_string(),
-        }),
-        Ok(client) => {
-            let query_result = client.query_one(
-        "SELECT materialization_id FROM trend_directory.view_materialization WHERE id = $1",
-        &[&vm_id],
-        ).await;
-            match query_result {
-                Err(e) => HttpResponse::NotFound().json(Error {
-                    code: 404,
-                    message: "Trend view materialization not found: ".to_owned() + &e.to_string(),
-                }),
-                Ok(row) => {
-                    let m_id: i32 = row.get(0);
-                    let result = client
-                        .execute(
-                            "DELETE FROM trend_directory.view_materialization WHERE id = $1",
-                            &[&vm_id],
-                        )
-                        .await;
-                    match result {
-                        Err(e) => HttpResponse::InternalServerError().json(Error {
-                            code: 500,
-                            message: "Deletion failed: ".to_owned() + &e.to_string(),
-                        }),
-                        Ok(_) => {
-                            let result = client
-                                .execute(
-                                    "DELETE FROM trend_directory.materialization WHERE id = $1",
-                                    &[&m_id],
-                                )
-                                .await;
-                            match result {
-                                Err(e) => HttpResponse::InternalServerError().json(Error {
-                                    code: 500,
-                                    message: "Deletion partially failed: ".to_owned()
-                                        + &e.to_string(),
-                                }),
-                                Ok(_) => HttpResponse::Ok().json(Success {
-                                    code: 200,
-                                    message: "materialization deleted".to_string(),
-                                }),
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let mut transaction = client.transaction().await?;
+
+    let row = transaction
+        .query_opt(
+            "SELECT materialization_id FROM trend_directory.view_materialization WHERE id = $1",
+            &[&vm_id],
+        )
+        .await?
+        .ok_or_else(|| ServiceError {
+            kind: ServiceErrorKind::NotFound,
+            message: format!("Trend view materialization with id {} not found", &vm_id),
+        })?;
+
+    let m_id: i32 = row.get(0);
+
+    let result = delete_materialization(&mut transaction, "view_materialization", vm_id, m_id)
+        .await
+        .map(|_| "materialization deleted".to_string());
+
+    let recorded = record_change(
+        &mut transaction,
+        &access.audit_context(),
+        &format!("DeleteTrendViewMaterialization({vm_id})"),
+        None,
+        &result,
+    )
+    .await;
+
+    let message = result?;
+
+    recorded?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(Success {
+        code: 200,
+        message,
+    }))
 }
 
 #[utoipa::path(
@@ -1040,72 +1023,60 @@ pub(super) async fn delete_trend_view_materialization(
     path="/trend-function-materializations/{id}",
     responses(
     (status = 200, description = "Deleted function materialization", body = Success),
-    (status = 404, description = "Function materialization not found", body = Error),
-    (status = 500, description = "Deletion failed fully or partially", body = Error)
+    (status = 404, description = "Function materialization not found", body = ProblemDetails),
+    (status = 500, description = "Deletion failed fully or partially", body = ProblemDetails)
     )
 )]
 #[delete("/trend-function-materializations/{id}")]
 pub(super) async fn delete_trend_function_materialization(
+    access: AdminAccess,
     pool: Data<Pool>,
     id: Path<i32>,
-) -> impl Responder {
+) -> Result<HttpResponse, ServiceError> {
     let fm_id = id.into_inner();
-    let result = pool.get().await;
-    match result {
-        Err(e) => HttpResponse::InternalServerError().json(Error {
-            code: 500,
-            message: e.to_string(),
-        }),
-        Ok(client) => {
-            let query_result = client
-        .query_one(
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let mut transaction = client.transaction().await?;
+
+    let row = transaction
+        .query_opt(
             "SELECT materialization_id FROM trend_directory.function_materialization WHERE id = $1",
             &[&fm_id],
         )
-        .await;
-            match query_result {
-                Err(e) => HttpResponse::NotFound().json(Error {
-                    code: 404,
-                    message: "Trend function materialization not found: ".to_owned()
-                        + &e.to_string(),
-                }),
-                Ok(row) => {
-                    let m_id: i32 = row.get(0);
-                    let result = client
-                        .execute(
-                            "DELETE FROM trend_directory.function_materialization WHERE id = $1",
-                            &[&fm_id],
-                        )
-                        .await;
-                    match result {
-                        Err(e) => HttpResponse::InternalServerError().json(Error {
-                            code: 500,
-                            message: "Deletion failed: ".to_owned() + &e.to_string(),
-                        }),
-                        Ok(_) => {
-                            let result = client
-                                .execute(
-                                    "DELETE FROM trend_directory.materialization WHERE id = $1",
-                                    &[&m_id],
-                                )
-                                .await;
-                            match result {
-                                Err(e) => HttpResponse::InternalServerError().json(Error {
-                                    code: 500,
-                                    message: "Deletion partially failed: ".to_owned()
-                                        + &e.to_string(),
-                                }),
-                                Ok(_) => HttpResponse::Ok().json(Success {
-                                    code: 200,
-                                    message: "Function materialization deleted.".to_string(),
-                                }),
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
+        .await?
+        .ok_or_else(|| ServiceError {
+            kind: ServiceErrorKind::NotFound,
+            message: format!("Trend function materialization with id {} not found", &fm_id),
+        })?;
+
+    let m_id: i32 = row.get(0);
+
+    let result = delete_materialization(&mut transaction, "function_materialization", fm_id, m_id)
+        .await
+        .map(|_| "Function materialization deleted.".to_string());
+
+    let recorded = record_change(
+        &mut transaction,
+        &access.audit_context(),
+        &format!("DeleteTrendFunctionMaterialization({fm_id})"),
+        None,
+        &result,
+    )
+    .await;
+
+    let message = result?;
+
+    recorded?;
+
+    transaction.commit().await?;
+
+    Ok(HttpResponse::Ok().json(Success {
+        code: 200,
+        message,
+    }))
 }
 
 #[utoipa::path(
@@ -1113,35 +1084,30 @@ pub(super) async fn delete_trend_function_materialization(
     path="/trend-view-materializations",
     responses(
     (status = 200, description = "Updated view materialization", body = Success),
-    (status = 400, description = "Input format incorrect", body = Error),
-    (status = 404, description = "Function materialization not found", body = Error),
-    (status = 500, description = "Deletion failed fully or partially", body = Error)
+    (status = 400, description = "Input format incorrect", body = ProblemDetails),
+    (status = 404, description = "Function materialization not found", body = ProblemDetails),
+    (status = 500, description = "Deletion failed fully or partially", body = ProblemDetails)
     )
 )]
 #[put("/trend-view-materializations")]
 pub(super) async fn update_trend_view_materialization(
+    access: AdminAccess,
     pool: Data<Pool>,
     post: String,
 ) -> Result<HttpResponse, ServiceError> {
     let data: TrendFunctionMaterializationData =
-        serde_json::from_str(&post).map_err(|e| Error {
-            code: 400,
+        serde_json::from_str(&post).map_err(|e| ServiceError {
+            kind: ServiceErrorKind::BadRequest,
             message: e.to_string(),
         })?;
 
-    let mut manager = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+    let mut manager = pool.get().await?;
 
     let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
 
-    let mut transaction = client.transaction().await.map_err(|e| Error {
-        code: 500,
-        message: e.to_string(),
-    })?;
+    let mut transaction = client.transaction().await?;
 
-    data.client_update(&mut transaction)
+    data.client_update(&mut transaction, &access.audit_context())
         .await
         .map(|success| Ok(HttpResponse::Ok().json(success)))?
 }
@@ -1151,35 +1117,237 @@ pub(super) async fn update_trend_view_materialization(
     path="/trend-function-materializations",
     responses(
     (status = 200, description = "Updated function materialization", body = TrendFunctionMaterializationFull),
-    (status = 400, description = "Input format incorrect", body = Error),
-    (status = 404, description = "Function materialization not found", body = Error),
-    (status = 500, description = "Deletion failed fully or partially", body = Error)
+    (status = 400, description = "Input format incorrect", body = ProblemDetails),
+    (status = 404, description = "Function materialization not found", body = ProblemDetails),
+    (status = 500, description = "Deletion failed fully or partially", body = ProblemDetails)
     )
 )]
 #[put("/trend-function-materializations")]
 pub(super) async fn update_trend_function_materialization(
+    access: AdminAccess,
     pool: Data<Pool>,
     post: String,
 ) -> Result<HttpResponse, ServiceError> {
     let data: TrendFunctionMaterializationData =
-        serde_json::from_str(&post).map_err(|e| Error {
-            code: 400,
+        serde_json::from_str(&post).map_err(|e| ServiceError {
+            kind: ServiceErrorKind::BadRequest,
             message: e.to_string(),
         })?;
 
-    let mut manager = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+    let mut manager = pool.get().await?;
 
     let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
 
-    let mut transaction = client.transaction().await.map_err(|e| Error {
-        code: 500,
-        message: e.to_string(),
-    })?;
+    let mut transaction = client.transaction().await?;
 
-    data.client_update(&mut transaction)
+    data.client_update(&mut transaction, &access.audit_context())
         .await
         .map(|success| Ok(HttpResponse::Ok().json(success)))?
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct TrendMaterializationMetrics {
+    pub materialization: String,
+    pub enabled: bool,
+    pub total_execution_count: i32,
+    #[serde(with = "humantime_serde")]
+    pub total_duration: Duration,
+    pub execution_count: i64,
+    #[serde(with = "humantime_serde")]
+    pub duration: Duration,
+    #[serde(with = "humantime_serde")]
+    pub average_duration: Duration,
+    #[serde(with = "humantime_serde")]
+    pub max_duration: Duration,
+    pub rows: i64,
+    pub failures: i64,
+}
+
+impl From<MaterializationMetrics> for TrendMaterializationMetrics {
+    fn from(metrics: MaterializationMetrics) -> Self {
+        TrendMaterializationMetrics {
+            materialization: metrics.materialization,
+            enabled: metrics.enabled,
+            total_execution_count: metrics.total_execution_count,
+            total_duration: metrics.total_duration,
+            execution_count: metrics.execution_count,
+            duration: metrics.duration,
+            average_duration: metrics.average_duration,
+            max_duration: metrics.max_duration,
+            rows: metrics.rows,
+            failures: metrics.failures,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+pub struct MetricsQuery {
+    /// Size of the time window ending now, e.g. '1d' or '6h'. Ignored when 'from' is specified
+    pub window: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[utoipa::path(
+    get,
+    path="/trend-materializations/metrics",
+    params(MetricsQuery),
+    responses(
+    (status = 200, description = "Execution metrics of trend materializations", body = [TrendMaterializationMetrics]),
+    (status = 400, description = "Invalid time window", body = ProblemDetails),
+    (status = 500, description = "Unable to interact with database", body = ProblemDetails),
+    )
+)]
+#[get("/trend-materializations/metrics")]
+pub(super) async fn get_trend_materialization_metrics(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+    query: Query<MetricsQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let to = query.to.unwrap_or_else(Utc::now);
+
+    let from = match query.from {
+        Some(from) => from,
+        None => {
+            let window_str = query.window.clone().unwrap_or("1d".to_string());
+
+            let window = parse_interval(&window_str)
+                .ok()
+                .and_then(|window| chrono::Duration::from_std(window).ok())
+                .ok_or_else(|| ServiceError {
+                    kind: ServiceErrorKind::BadRequest,
+                    message: format!("Invalid window '{window_str}'"),
+                })?;
+
+            to - window
+        }
+    };
+
+    if from >= to {
+        return Err(ServiceError {
+            kind: ServiceErrorKind::BadRequest,
+            message: "Start of window must be before the end".to_string(),
+        });
+    }
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let metrics: Vec<TrendMaterializationMetrics> =
+        load_materialization_metrics(client, &from, &to)
+            .await?
+            .into_iter()
+            .map(TrendMaterializationMetrics::from)
+            .collect();
+
+    Ok(HttpResponse::Ok().json(metrics))
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, ToSchema)]
+pub struct TrendMaterializationDisableData {
+    /// Why the materialization is disabled
+    pub reason: Option<String>,
+    /// Moment after which the materialization is automatically enabled again
+    pub expires: Option<DateTime<Utc>>,
+}
+
+#[utoipa::path(
+    patch,
+    path="/trend-materializations/{name}/enable",
+    responses(
+    (status = 200, description = "Enabled materialization", body = Success),
+    (status = 404, description = "Materialization not found", body = ProblemDetails),
+    (status = 500, description = "Unable to interact with database", body = ProblemDetails),
+    )
+)]
+#[patch("/trend-materializations/{name}/enable")]
+pub(super) async fn enable_trend_materialization(
+    access: AdminAccess,
+    pool: Data<Pool>,
+    name: Path<String>,
+) -> Result<HttpResponse, ServiceError> {
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let change = EnableTrendMaterialization {
+        name: name.into_inner(),
+    };
+
+    let message = generic_apply_audited(&change, client, &access.audit_context())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(Success { code: 200, message }))
+}
+
+#[utoipa::path(
+    patch,
+    path="/trend-materializations/{name}/disable",
+    request_body(content = Option<TrendMaterializationDisableData>),
+    responses(
+    (status = 200, description = "Disabled materialization", body = Success),
+    (status = 400, description = "Input format incorrect", body = ProblemDetails),
+    (status = 404, description = "Materialization not found", body = ProblemDetails),
+    (status = 500, description = "Unable to interact with database", body = ProblemDetails),
+    )
+)]
+#[patch("/trend-materializations/{name}/disable")]
+pub(super) async fn disable_trend_materialization(
+    access: AdminAccess,
+    pool: Data<Pool>,
+    name: Path<String>,
+    post: String,
+) -> Result<HttpResponse, ServiceError> {
+    let data: TrendMaterializationDisableData = if post.trim().is_empty() {
+        TrendMaterializationDisableData::default()
+    } else {
+        serde_json::from_str(&post).map_err(|e| ServiceError {
+            kind: ServiceErrorKind::BadRequest,
+            message: e.to_string(),
+        })?
+    };
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let change = DisableTrendMaterialization {
+        name: name.into_inner(),
+        reason: data.reason,
+        expires: data.expires,
+    };
+
+    let message = generic_apply_audited(&change, client, &access.audit_context())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(Success { code: 200, message }))
+}
+
+/// Periodically enable the materializations of which the disable period has expired
+pub async fn enable_expired_materializations_task(pool: Pool, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        interval.tick().await;
+
+        let mut manager = match pool.get().await {
+            Ok(manager) => manager,
+            Err(e) => {
+                error!("Could not get connection for enabling expired materializations: {e}");
+                continue;
+            }
+        };
+
+        let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+        match enable_expired_materializations(client).await {
+            Ok(names) => {
+                for name in names {
+                    info!("Enabled materialization '{name}' after its disable period expired");
+                }
+            }
+            Err(e) => error!("{e}"),
+        }
+    }
+}
@@ -6,38 +6,24 @@ use std::time::Duration;
 use deadpool_postgres::Pool;
 use tokio_postgres::GenericClient;
 
-use actix_web::{get, post, web::Data, web::Path, web::Query, HttpResponse};
+use actix_web::{get, post, web::Data, web::Path, web::Query, HttpRequest, HttpResponse};
 
 use serde::{Deserialize, Serialize};
 use utoipa::{IntoParams, ToSchema};
 
-use minerva::change::GenericChange;
+use minerva::audit::{generic_apply_audited, AuditContext};
 use minerva::changes::trend_store::{AddTrendStore, AddTrendStorePart, AddTrends};
+use minerva::granularity::Granularity;
 use minerva::interval::parse_interval;
 use minerva::trend_store::{load_trend_store, GeneratedTrend, Trend, TrendStore, TrendStorePart};
 
 use minerva::meas_value::DataType;
 
-use super::error::Error;
+use super::auth::{AdminAccess, ReadAccess};
+use super::listing::{page_response, Listing, PageRequest, SortColumn};
 use super::serviceerror::{ServiceError, ServiceErrorKind};
 
 lazy_static! {
-    static ref PARTITION_SIZE: HashMap<Duration, Duration> = HashMap::from([
-        (
-            parse_interval("15m").unwrap(),
-            parse_interval("1d").unwrap()
-        ),
-        (parse_interval("1h").unwrap(), parse_interval("4d").unwrap()),
-        (
-            parse_interval("1d").unwrap(),
-            parse_interval("3mons").unwrap()
-        ),
-        (parse_interval("1w").unwrap(), parse_interval("1y").unwrap()),
-        (
-            parse_interval("1mon").unwrap(),
-            parse_interval("5y").unwrap()
-        ),
-    ]);
     static ref DEFAULT_GRANULARITY: String = "1 day".to_string();
 }
 
@@ -186,6 +172,7 @@ impl TrendStoreBasicData {
     async fn as_minerva<T: GenericClient + Send + Sync>(
         &self,
         client: &mut T,
+        context: &AuditContext,
     ) -> Result<TrendStore, String> {
         let result = load_trend_store(
             client,
@@ -198,18 +185,20 @@ impl TrendStoreBasicData {
         match result {
             Ok(trendstore) => Ok(trendstore),
             Err(_) => {
+                let granularity = Granularity::try_from(self.granularity)
+                    .map_err(|e| format!("Unable to create trend store: {e}"))?;
+
                 let new_trend_store = TrendStore {
                     data_source: self.data_source.clone(),
                     entity_type: self.entity_type.clone(),
                     granularity: self.granularity,
-                    partition_size: *PARTITION_SIZE.get(&self.granularity.clone()).unwrap(),
+                    partition_size: granularity.default_partition_size(),
                     parts: vec![],
                 };
-                let result = AddTrendStore {
+                let change = AddTrendStore {
                     trend_store: new_trend_store.clone(),
-                }
-                .generic_apply(client)
-                .await;
+                };
+                let result = generic_apply_audited(&change, client, context).await;
                 match result {
                     Ok(_) => Ok(new_trend_store),
                     Err(e) => Err(format!("Unable to find or create trend store: {e}")),
@@ -252,7 +241,8 @@ impl TrendStorePartCompleteData {
     pub async fn create<T: GenericClient + Send + Sync>(
         &self,
         client: &mut T,
-    ) -> Result<TrendStorePartFull, Error> {
+        context: &AuditContext,
+    ) -> Result<TrendStorePartFull, ServiceError> {
         // first ensure the data source exists
         _ = client.execute(
             "SELECT directory.name_to_data_source($1)",
@@ -261,10 +251,10 @@ impl TrendStorePartCompleteData {
 
         let trendstore = self
             .trend_store()
-            .as_minerva(client)
+            .as_minerva(client, context)
             .await
-            .map_err(|e| Error {
-                code: 409,
+            .map_err(|e| ServiceError {
+                kind: ServiceErrorKind::Conflict,
                 message: e,
             })?;
 
@@ -273,22 +263,26 @@ impl TrendStorePartCompleteData {
             trend_store_part: self.trend_store_part().as_minerva(),
         };
 
-        action.generic_apply(client).await.map_err(|e| Error {
-            code: 409,
-            message: format!("Creation of trendstorepart failed: {e}"),
-        })?;
+        generic_apply_audited(&action, client, context)
+            .await
+            .map_err(|e| ServiceError {
+                kind: ServiceErrorKind::Conflict,
+                message: format!("Creation of trendstorepart failed: {e}"),
+            })?;
 
         let action = AddTrends {
             trend_store_part: self.trend_store_part().as_minerva(),
             trends: self.trend_store_part().as_minerva().trends,
         };
 
-        action.generic_apply(client).await.map_err(|e| Error {
-            code: 409,
-            message: format!(
-                "Creation of trendstorepart succeeded, but inserting trends failed: {e}"
-            ),
-        })?;
+        generic_apply_audited(&action, client, context)
+            .await
+            .map_err(|e| ServiceError {
+                kind: ServiceErrorKind::Conflict,
+                message: format!(
+                    "Creation of trendstorepart succeeded, but inserting trends failed: {e}"
+                ),
+            })?;
 
         let (trend_store_part_id, trend_store_id): (i32, i32) = client
             .query_one(
@@ -296,8 +290,8 @@ impl TrendStorePartCompleteData {
                 &[&self.name],
             )
             .await
-            .map_err(|_| Error {
-                code: 404,
+            .map_err(|_| ServiceError {
+                kind: ServiceErrorKind::NotFound,
                 message: "Trend store part created, but could not be found after creation"
                     .to_string(),
             })
@@ -312,7 +306,6 @@ impl TrendStorePartCompleteData {
                 ),
                 &[&trend_store_part_id]
             ).await
-            .map_err(|e| Error { code: 500, message: e.to_string() })
             .map(|rows| rows
                 .iter()
                 .map(|row| TrendFull {
@@ -337,7 +330,6 @@ impl TrendStorePartCompleteData {
                 ),
                 &[&trend_store_part_id]
             ).await
-            .map_err(|e| Error { code: 500, message: e.to_string() } )
             .map(|rows| rows
                 .iter()
                 .map(|row| GeneratedTrendFull {
@@ -364,115 +356,156 @@ impl TrendStorePartCompleteData {
     }
 }
 
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct TrendStorePartListQuery {
+    /// Only trend store parts of this data source
+    data_source: Option<String>,
+    /// Only trend store parts of this entity type
+    entity_type: Option<String>,
+    /// Only trend store parts of this granularity, e.g. 15m or 1 day
+    granularity: Option<String>,
+    /// Only trend store parts with a name matching this pattern, in which '*' matches any characters
+    name: Option<String>,
+    /// Field to sort on: name (default), id, data_source, entity_type or granularity, prefixed
+    /// with '-' for descending order
+    sort: Option<String>,
+    /// Cursor from the Link header of the previous page
+    cursor: Option<String>,
+    /// Maximum number of trend store parts to return, all when not specified
+    limit: Option<i64>,
+}
+
+const TREND_STORE_PART_SORT_COLUMNS: [SortColumn; 5] = [
+    SortColumn { name: "name", expression: "item.name", sql_type: "text" },
+    SortColumn { name: "id", expression: "item.id", sql_type: "integer" },
+    SortColumn { name: "data_source", expression: "item.data_source", sql_type: "text" },
+    SortColumn { name: "entity_type", expression: "item.entity_type", sql_type: "text" },
+    SortColumn { name: "granularity", expression: "item.granularity", sql_type: "interval" },
+];
+
 #[utoipa::path(
     get,
     path="/trend-store-parts",
+    params(TrendStorePartListQuery),
     responses(
-    (status = 200, description = "List all trend store parts", body = [TrendStorePartFull]),
-    (status = 500, description = "Problem interacting with database", body = Error),
+    (status = 200, description = "List all trend store parts", body = [TrendStorePartFull],
+        headers(("Link" = String, description = "Link to the next page when there are more trend store parts"))),
+    (status = 400, description = "Invalid filter, sort order, cursor or limit", body = ProblemDetails),
+    (status = 500, description = "Problem interacting with database", body = ProblemDetails),
     )
 )]
 #[get("/trend-store-parts")]
-pub(super) async fn get_trend_store_parts(pool: Data<Pool>) -> Result<HttpResponse, ServiceError> {
-    let client = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+pub(super) async fn get_trend_store_parts(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+    query: Query<TrendStorePartListQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let client = pool.get().await?;
+
+    let mut listing = Listing::new(
+        concat!(
+            "SELECT tsp.id, tsp.name::text AS name, tsp.trend_store_id, ",
+            "ds.name::text AS data_source, et.name::text AS entity_type, ts.granularity ",
+            "FROM trend_directory.trend_store_part tsp ",
+            "JOIN trend_directory.trend_store ts ON ts.id = tsp.trend_store_id ",
+            "JOIN directory.data_source ds ON ds.id = ts.data_source_id ",
+            "JOIN directory.entity_type et ON et.id = ts.entity_type_id"
+        ),
+        &TREND_STORE_PART_SORT_COLUMNS,
+        &TREND_STORE_PART_SORT_COLUMNS[1],
+    );
+
+    listing
+        .filter("item.data_source = $?", query.data_source.as_deref())
+        .filter("item.entity_type = $?", query.entity_type.as_deref())
+        .filter("item.granularity = $?::text::interval", query.granularity.as_deref())
+        .filter_pattern("item.name", query.name.as_deref());
+
+    let page = listing
+        .fetch(client.client(), &PageRequest {
+            sort: query.sort.as_deref(),
+            cursor: query.cursor.as_deref(),
+            limit: query.limit,
+        })
+        .await?;
 
-    let trends: Vec<TrendFull> = client
+    let ids: Vec<i32> = page.rows.iter().map(|row| row.get("id")).collect();
+
+    let mut trends: HashMap<i32, Vec<TrendFull>> = HashMap::new();
+
+    let trend_rows = client
         .query(
             concat!(
                 "SELECT id, trend_store_part_id, name, data_type, time_aggregation, entity_aggregation, extra_data, description ",
-                "FROM trend_directory.table_trend"
+                "FROM trend_directory.table_trend ",
+                "WHERE trend_store_part_id = ANY($1)"
             ),
-            &[]
+            &[&ids]
         )
-        .await
-        .map_err(|e| Error {
-            code: 500,
-            message: e.to_string(),
-        })
-        .map(|rows| rows
-            .iter()
-            .map(|row| TrendFull {
-                id: row.get(0),
-                trend_store_part: row.get(1),
-                name: row.get(2),
-                data_type: row.get(3),
-                time_aggregation: row.get(4),
-                entity_aggregation: row.get(5),
-                extra_data: row.get(6),
-                description: row.get(7),
-            })
-            .collect()
-        )?;
+        .await?;
+
+    for row in trend_rows {
+        let trend = TrendFull {
+            id: row.get(0),
+            trend_store_part: row.get(1),
+            name: row.get(2),
+            data_type: row.get(3),
+            time_aggregation: row.get(4),
+            entity_aggregation: row.get(5),
+            extra_data: row.get(6),
+            description: row.get(7),
+        };
 
-    let generated_trends: Vec<GeneratedTrendFull> = client
+        trends.entry(trend.trend_store_part).or_default().push(trend);
+    }
+
+    let mut generated_trends: HashMap<i32, Vec<GeneratedTrendFull>> = HashMap::new();
+
+    let generated_trend_rows = client
         .query(
             concat!(
                 "SELECT id, trend_store_part_id, name, data_type, expression, extra_data, description ",
-                "FROM trend_directory.generated_table_trend"
+                "FROM trend_directory.generated_table_trend ",
+                "WHERE trend_store_part_id = ANY($1)"
             ),
-            &[],
-        )
-        .await
-        .map_err(|e| Error {
-            code: 500,
-            message: e.to_string(),
-        })
-        .map(|rows| rows
-            .iter()
-            .map(|row| GeneratedTrendFull {
-                id: row.get(0),
-                trend_store_part: row.get(1),
-                name: row.get(2),
-                data_type: row.get(3),
-                expression: row.get(4),
-                extra_data: row.get(5),
-                description: row.get(6),
-            })
-            .collect()
-        )?;
-
-    let trend_store_parts: Vec<TrendStorePartFull> = client
-        .query(
-            "SELECT id, name, trend_store_id FROM trend_directory.trend_store_part",
-            &[],
+            &[&ids],
         )
-        .await
-        .map_err(|e| Error {
-            code: 500,
-            message: e.to_string(),
-        })
-        .map(|rows| {
-            rows.iter()
-                .map(|row| {
-                    let tspid: i32 = row.get(0);
-
-                    let my_trends: Vec<TrendFull> = trends
-                        .iter()
-                        .filter(|trend| trend.trend_store_part == tspid)
-                        .cloned()
-                        .collect();
+        .await?;
+
+    for row in generated_trend_rows {
+        let generated_trend = GeneratedTrendFull {
+            id: row.get(0),
+            trend_store_part: row.get(1),
+            name: row.get(2),
+            data_type: row.get(3),
+            expression: row.get(4),
+            extra_data: row.get(5),
+            description: row.get(6),
+        };
 
-                    let my_generated_trends: Vec<GeneratedTrendFull> = generated_trends
-                        .iter()
-                        .filter(|generated_trend| generated_trend.trend_store_part == tspid)
-                        .cloned()
-                        .collect();
+        generated_trends
+            .entry(generated_trend.trend_store_part)
+            .or_default()
+            .push(generated_trend);
+    }
 
-                    TrendStorePartFull {
-                        id: tspid,
-                        name: row.get(1),
-                        trend_store: row.get(2),
-                        trends: my_trends,
-                        generated_trends: my_generated_trends,
-                    }
-                })
-                .collect()
-        })?;
+    let trend_store_parts: Vec<TrendStorePartFull> = page.rows
+        .iter()
+        .map(|row| {
+            let tspid: i32 = row.get("id");
+
+            TrendStorePartFull {
+                id: tspid,
+                name: row.get("name"),
+                trend_store: row.get("trend_store_id"),
+                trends: trends.remove(&tspid).unwrap_or_default(),
+                generated_trends: generated_trends.remove(&tspid).unwrap_or_default(),
+            }
+        })
+        .collect();
 
-    Ok(HttpResponse::Ok().json(trend_store_parts))
+    Ok(page_response(&req, &trend_store_parts, page.next_cursor))
 }
 
 #[utoipa::path(
@@ -480,21 +513,19 @@ pub(super) async fn get_trend_store_parts(pool: Data<Pool>) -> Result<HttpRespon
     path="/trend-store-part/{id}",
     responses(
     (status = 200, description = "Get a specific trend store part", body = TrendStorePartFull),
-    (status = 404, description = "Trend store part not found", body = Error),
-    (status = 500, description = "Failure to interact with database", body = Error)
+    (status = 404, description = "Trend store part not found", body = ProblemDetails),
+    (status = 500, description = "Failure to interact with database", body = ProblemDetails)
     )
 )]
 #[get("/trend-store-part/{id}")]
 pub(super) async fn get_trend_store_part(
+    _access: ReadAccess,
     pool: Data<Pool>,
     id: Path<i32>,
 ) -> Result<HttpResponse, ServiceError> {
     let tsp_id = id.into_inner();
 
-    let client = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+    let client = pool.get().await?;
 
     let trends: Vec<TrendFull> = client
         .query(
@@ -505,10 +536,6 @@ pub(super) async fn get_trend_store_part(
             ),
             &[&tsp_id]
         ).await
-        .map_err(|e| Error {
-            code: 500,
-            message: e.to_string(),
-        })
         .map(|rows|rows
             .iter()
             .map(|row| TrendFull {
@@ -534,10 +561,6 @@ pub(super) async fn get_trend_store_part(
             &[&tsp_id],
         )
         .await
-        .map_err(|e| Error {
-            code: 500,
-            message: e.to_string(),
-        })
         .map(|rows| {
             rows.iter()
                 .map(|row| GeneratedTrendFull {
@@ -558,8 +581,8 @@ pub(super) async fn get_trend_store_part(
             &[&tsp_id],
         )
         .await
-        .map_err(|_| Error {
-            code: 404,
+        .map_err(|_| ServiceError {
+            kind: ServiceErrorKind::NotFound,
             message: format!("Trend store part with id {} not found", &tsp_id),
         })
         .map(|row| TrendStorePartFull {
@@ -578,21 +601,19 @@ pub(super) async fn get_trend_store_part(
     path="/trend-store-parts/find",
     responses(
     (status = 200, description = "Get a specific trend store part", body = TrendStorePartFull),
-    (status = 404, description = "Trend store part not found", body = Error),
-    (status = 500, description = "Unable to interact with database", body = Error),
+    (status = 404, description = "Trend store part not found", body = ProblemDetails),
+    (status = 500, description = "Unable to interact with database", body = ProblemDetails),
     )
 )]
 #[get("/trend-store-parts/find")]
 pub(super) async fn find_trend_store_part(
+    _access: ReadAccess,
     pool: Data<Pool>,
     info: Query<QueryData>,
 ) -> Result<HttpResponse, ServiceError> {
     let name = &info.name;
 
-    let client = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+    let client = pool.get().await?;
 
     let (trend_store_part_id, trend_store_id): (i32, i32) = client
         .query_one(
@@ -600,8 +621,8 @@ pub(super) async fn find_trend_store_part(
             &[&name],
         )
         .await
-        .map_err(|_| Error {
-            code: 404,
+        .map_err(|_| ServiceError {
+            kind: ServiceErrorKind::NotFound,
             message: format!("Trend store part with name {} not found", &name),
         })
         .map(|row| (row.get(0), row.get(1)))?;
@@ -615,10 +636,6 @@ pub(super) async fn find_trend_store_part(
             ),
             &[&trend_store_part_id]
         ).await
-        .map_err(|e| Error {
-            code: 500,
-            message: e.to_string(),
-        })
         .map(|rows| rows
             .iter()
             .map(|row| TrendFull {
@@ -644,10 +661,6 @@ pub(super) async fn find_trend_store_part(
             &[&trend_store_part_id],
         )
         .await
-        .map_err(|e| Error {
-            code: 500,
-            message: e.to_string(),
-        })
         .map(|rows| rows
             .iter()
             .map(|row| GeneratedTrendFull {
@@ -678,15 +691,15 @@ pub(super) async fn find_trend_store_part(
     path="/trend-stores",
     responses(
     (status = 200, description = "List all trend store parts", body = [TrendStorePartFull]),
-    (status = 500, description = "Problems interacting with database", body = Error),
+    (status = 500, description = "Problems interacting with database", body = ProblemDetails),
     )
 )]
 #[get("/trend-stores")]
-pub(super) async fn get_trend_stores(pool: Data<Pool>) -> Result<HttpResponse, ServiceError> {
-    let client = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+pub(super) async fn get_trend_stores(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client = pool.get().await?;
 
     let trends: Vec<TrendFull> = client
         .query(
@@ -695,10 +708,7 @@ pub(super) async fn get_trend_stores(pool: Data<Pool>) -> Result<HttpResponse, S
                 "FROM trend_directory.table_trend"
             ),
             &[]
-        ).await.map_err(|e| Error {
-            code: 500,
-            message: e.to_string(),
-        }).map(|rows| rows
+        ).await.map(|rows| rows
             .iter()
             .map(|row| TrendFull {
                 id: row.get(0),
@@ -722,10 +732,6 @@ pub(super) async fn get_trend_stores(pool: Data<Pool>) -> Result<HttpResponse, S
             &[],
         )
         .await
-        .map_err(|e| Error {
-            code: 500,
-            message: e.to_string(),
-        })
         .map(|rows| rows
             .iter()
             .map(|row| GeneratedTrendFull {
@@ -746,10 +752,6 @@ pub(super) async fn get_trend_stores(pool: Data<Pool>) -> Result<HttpResponse, S
             &[],
         )
         .await
-        .map_err(|e| Error {
-            code: 500,
-            message: e.to_string(),
-        })
         .map(|rows| {
             rows.iter()
                 .map(|row| {
@@ -787,10 +789,6 @@ pub(super) async fn get_trend_stores(pool: Data<Pool>) -> Result<HttpResponse, S
             ),
             &[]
         ).await
-        .map_err(|e| Error {
-            code: 500,
-            message: e.to_string(),
-        })
         .map(|rows| rows
             .iter()
             .map(|row| {
@@ -822,21 +820,19 @@ pub(super) async fn get_trend_stores(pool: Data<Pool>) -> Result<HttpResponse, S
     path="/trend-stores/{id}",
     responses(
     (status = 200, description = "Get a specific trend store", body = TrendStorePartFull),
-    (status = 404, description = "Trend store not found", body = Error),
-    (status = 500, description = "Unable to interact with database", body = Error),
+    (status = 404, description = "Trend store not found", body = ProblemDetails),
+    (status = 500, description = "Unable to interact with database", body = ProblemDetails),
     )
 )]
 #[get("/trend-stores/{id}")]
 pub(super) async fn get_trend_store(
+    _access: ReadAccess,
     pool: Data<Pool>,
     id: Path<i32>,
 ) -> Result<HttpResponse, ServiceError> {
     let tsid = id.into_inner();
 
-    let client = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+    let client = pool.get().await?;
 
     let trends: Vec<TrendFull> = client
         .query(
@@ -848,10 +844,6 @@ pub(super) async fn get_trend_store(
             ),
             &[&tsid]
         ).await
-        .map_err(|e| Error {
-            code: 500,
-            message: e.to_string(),
-        })
         .map(|rows| rows
             .iter()
             .map(|row| TrendFull {
@@ -877,10 +869,6 @@ pub(super) async fn get_trend_store(
             ),
             &[&tsid]
         ).await
-        .map_err(|e| Error {
-            code: 500,
-            message: e.to_string(),
-        })
         .map(|rows| rows
             .iter()
             .map(|row| GeneratedTrendFull {
@@ -905,10 +893,6 @@ pub(super) async fn get_trend_store(
             &[&tsid],
         )
         .await
-        .map_err(|e| Error {
-            code: 500,
-            message: e.to_string(),
-        })
         .map(|rows| {
             rows.iter()
                 .map(|row| {
@@ -946,8 +930,8 @@ pub(super) async fn get_trend_store(
                 "WHERE ts.id = $1",
             ),
             &[&tsid],
-        ).await.map_err(|_| Error {
-            code: 404,
+        ).await.map_err(|_| ServiceError {
+            kind: ServiceErrorKind::NotFound,
             message: format!("Trend store with id {} not found", &tsid),
         }).map(|row| TrendStoreFull {
             id: tsid,
@@ -970,14 +954,15 @@ pub(super) async fn get_trend_store(
     path="/trend-store-parts/new",
     responses(
     (status = 200, description = "Create a new trend store part", body = TrendStorePartData),
-    (status = 400, description = "Incorrect data format", body = Error),
-    (status = 404, description = "Trend store part cannot be found after creation", body = Error),
-    (status = 409, description = "Trend store part cannot be created with these data", body = Error),
-    (status = 500, description = "Problems interacting with database", body = Error),
+    (status = 400, description = "Incorrect data format", body = ProblemDetails),
+    (status = 404, description = "Trend store part cannot be found after creation", body = ProblemDetails),
+    (status = 409, description = "Trend store part cannot be created with these data", body = ProblemDetails),
+    (status = 500, description = "Problems interacting with database", body = ProblemDetails),
     )
 )]
 #[post("/trend-store-parts/new")]
 pub(super) async fn post_trend_store_part(
+    access: AdminAccess,
     pool: Data<Pool>,
     post: String,
 ) -> Result<HttpResponse, ServiceError> {
@@ -987,97 +972,127 @@ pub(super) async fn post_trend_store_part(
             message: format!("{e}"),
         })?;
 
-    let mut manager = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+    let mut manager = pool.get().await?;
 
     let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
 
     let mut transaction = client.transaction().await?;
 
-    let tsp = data.create(&mut transaction).await?;
+    let tsp = data
+        .create(&mut transaction, &access.audit_context())
+        .await?;
 
     transaction.commit().await?;
 
     Ok(HttpResponse::Ok().json(tsp))
 }
 
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct TrendListQuery {
+    /// Only trends of this data source
+    data_source: Option<String>,
+    /// Only trends of this entity type
+    entity_type: Option<String>,
+    /// Only trends of this granularity, e.g. 15m or 1 day
+    granularity: Option<String>,
+    /// Only trends of this trend store part
+    trend_store_part: Option<String>,
+    /// Only trends with a name matching this pattern, in which '*' matches any characters
+    name: Option<String>,
+    /// Field to sort on: name (default), trend_store_part, data_source, entity_type or
+    /// granularity, prefixed with '-' for descending order
+    sort: Option<String>,
+    /// Cursor from the Link header of the previous page
+    cursor: Option<String>,
+    /// Maximum number of trends to return, all when not specified
+    limit: Option<i64>,
+}
+
+const TREND_SORT_COLUMNS: [SortColumn; 5] = [
+    SortColumn { name: "name", expression: "item.name", sql_type: "text" },
+    SortColumn { name: "trend_store_part", expression: "item.trend_store_part", sql_type: "text" },
+    SortColumn { name: "data_source", expression: "item.data_source", sql_type: "text" },
+    SortColumn { name: "entity_type", expression: "item.entity_type", sql_type: "text" },
+    SortColumn { name: "granularity", expression: "item.granularity::interval", sql_type: "interval" },
+];
+
+/// Table and generated trends have separate Id sequences, so the key combines the kind and Id
+const TREND_KEY: SortColumn = SortColumn { name: "key", expression: "item.key", sql_type: "text" };
+
 #[utoipa::path(
     get,
     path="/trends",
+    params(TrendListQuery),
     responses(
-    (status = 200, description = "List all trend store parts", body = [TrendDataWithTrendStorePart]),
-    (status = 500, description = "Problem interacting with database", body = Error),
+    (status = 200, description = "List all trends", body = [TrendDataWithTrendStorePart],
+        headers(("Link" = String, description = "Link to the next page when there are more trends"))),
+    (status = 400, description = "Invalid filter, sort order, cursor or limit", body = ProblemDetails),
+    (status = 500, description = "Problem interacting with database", body = ProblemDetails),
     )
 )]
 #[get("/trends")]
-pub(super) async fn get_trends(pool: Data<Pool>) -> Result<HttpResponse, ServiceError> {
-    let client = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
-
-    let table_trends: Vec<TrendDataWithTrendStorePart> = client
-        .query(
-            concat!(
-                "SELECT t.id, t.name, tsp.name, et.name, ds.name, ts.granularity::text, t.data_type ",
-                "FROM trend_directory.table_trend t ",
-                "JOIN trend_directory.trend_store_part tsp ON t.trend_store_part_id = tsp.id ",
-                "JOIN trend_directory.trend_store ts ON tsp.trend_store_id = ts.id ",
-                "JOIN directory.entity_type et ON ts.entity_type_id = et.id ",
-                "JOIN directory.data_source ds ON ts.data_source_id = ds.id"
-            ),
-            &[],
-        )
-        .await
-        .map(|rows| rows
-            .iter()
-            .map(|row| TrendDataWithTrendStorePart {
-                id: row.get(0),
-                is_generated: false,
-                name: row.get(1),
-                trend_store_part: row.get(2),
-                entity_type: row.get(3),
-                data_source: row.get(4),
-                granularity: parse_interval(row.get(5)).unwrap(),
-                data_type: row.get(6),
-            })
-            .collect()
-        )?;
-
-    let generated_table_trends: Vec<TrendDataWithTrendStorePart> = client
-        .query(
-            concat!(
-                "SELECT t.id, t.name, tsp.name, et.name, ds.name, ts.granularity::text, t.data_type ",
-                "FROM trend_directory.generated_table_trend t ",
-                "JOIN trend_directory.trend_store_part tsp ON t.trend_store_part_id = tsp.id ",
-                "JOIN trend_directory.trend_store ts ON tsp.trend_store_id = ts.id ",
-                "JOIN directory.entity_type et ON ts.entity_type_id = et.id ",
-                "JOIN directory.data_source ds ON ts.data_source_id = ds.id"
-            ),
-            &[],
-        )
-        .await
-        .map(|rows| rows
-            .iter()
-            .map(|row| TrendDataWithTrendStorePart {
-                id: row.get(0),
-                is_generated: true,
-                name: row.get(1),
-                trend_store_part: row.get(2),
-                entity_type: row.get(3),
-                data_source: row.get(4),
-                granularity: parse_interval(row.get(5)).unwrap(),
-                data_type: row.get(6),
-            })
-            .collect()
-        )?;
-
-    let trends: Vec<&TrendDataWithTrendStorePart> =
-        table_trends.iter().chain(&generated_table_trends).collect();
+pub(super) async fn get_trends(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+    query: Query<TrendListQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let client = pool.get().await?;
+
+    let mut listing = Listing::new(
+        concat!(
+            "SELECT t.id, false AS is_generated, 't' || t.id AS key, t.name::text AS name, ",
+            "tsp.name::text AS trend_store_part, et.name::text AS entity_type, ",
+            "ds.name::text AS data_source, ts.granularity::text AS granularity, t.data_type ",
+            "FROM trend_directory.table_trend t ",
+            "JOIN trend_directory.trend_store_part tsp ON t.trend_store_part_id = tsp.id ",
+            "JOIN trend_directory.trend_store ts ON tsp.trend_store_id = ts.id ",
+            "JOIN directory.entity_type et ON ts.entity_type_id = et.id ",
+            "JOIN directory.data_source ds ON ts.data_source_id = ds.id ",
+            "UNION ALL ",
+            "SELECT t.id, true AS is_generated, 'g' || t.id AS key, t.name::text AS name, ",
+            "tsp.name::text AS trend_store_part, et.name::text AS entity_type, ",
+            "ds.name::text AS data_source, ts.granularity::text AS granularity, t.data_type ",
+            "FROM trend_directory.generated_table_trend t ",
+            "JOIN trend_directory.trend_store_part tsp ON t.trend_store_part_id = tsp.id ",
+            "JOIN trend_directory.trend_store ts ON tsp.trend_store_id = ts.id ",
+            "JOIN directory.entity_type et ON ts.entity_type_id = et.id ",
+            "JOIN directory.data_source ds ON ts.data_source_id = ds.id"
+        ),
+        &TREND_SORT_COLUMNS,
+        &TREND_KEY,
+    );
+
+    listing
+        .filter("item.data_source = $?", query.data_source.as_deref())
+        .filter("item.entity_type = $?", query.entity_type.as_deref())
+        .filter("item.granularity::interval = $?::text::interval", query.granularity.as_deref())
+        .filter("item.trend_store_part = $?", query.trend_store_part.as_deref())
+        .filter_pattern("item.name", query.name.as_deref());
+
+    let page = listing
+        .fetch(client.client(), &PageRequest {
+            sort: query.sort.as_deref(),
+            cursor: query.cursor.as_deref(),
+            limit: query.limit,
+        })
+        .await?;
+
+    let trends: Vec<TrendDataWithTrendStorePart> = page.rows
+        .iter()
+        .map(|row| TrendDataWithTrendStorePart {
+            id: row.get("id"),
+            is_generated: row.get("is_generated"),
+            name: row.get("name"),
+            trend_store_part: row.get("trend_store_part"),
+            entity_type: row.get("entity_type"),
+            data_source: row.get("data_source"),
+            granularity: parse_interval(row.get("granularity")).unwrap(),
+            data_type: row.get("data_type"),
+        })
+        .collect();
 
-    Ok(HttpResponse::Ok().json(trends))
+    Ok(page_response(&req, &trends, page.next_cursor))
 }
 
 #[utoipa::path(
@@ -1085,20 +1100,18 @@ pub(super) async fn get_trends(pool: Data<Pool>) -> Result<HttpResponse, Service
     path="/trendsentitytype/{et}",
     responses(
     (status = 200, description = "List the name of trend store parts for an entity type", body = [String]),
-    (status = 500, description = "Problem interacting with database", body = Error),
+    (status = 500, description = "Problem interacting with database", body = ProblemDetails),
     )
 )]
 #[get("/trendsentitytype/{et}")]
 pub(super) async fn get_trends_by_entity_type(
+    _access: ReadAccess,
     pool: Data<Pool>,
     et: Path<String>,
 ) -> Result<HttpResponse, ServiceError> {
     let entity_type = et.into_inner();
 
-    let mut manager = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+    let mut manager = pool.get().await?;
 
     let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
 
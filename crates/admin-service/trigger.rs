@@ -1,17 +1,33 @@
 use deadpool_postgres::Pool;
 use std::ops::DerefMut;
 
-use actix_web::{get, put, web::Data, HttpResponse};
+use std::collections::{BTreeMap, HashMap};
 
+use actix_web::{
+    delete, get, post, put, web::Data, web::Path, web::Query, HttpRequest, HttpResponse,
+};
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 
+use minerva::audit::{generic_apply_audited, record_change};
 use minerva::trigger::{
-    list_triggers, load_thresholds_with_client, load_trigger, set_thresholds, Threshold,
+    load_thresholds_for_triggers, load_trigger, set_thresholds, AddTrigger, DeleteTrigger,
+    Threshold, Trigger, UpdateTrigger,
+};
+use minerva::trigger_exception::{
+    load_threshold_exceptions, AddThresholdException, RemoveThresholdExceptions,
+    ThresholdException, ThresholdExceptionRecord,
+};
+use minerva::trigger_status::{
+    load_trigger_status, TriggerStatus, TriggerStatusFilter, MAX_PERIODS,
 };
 
+use super::auth::{AdminAccess, ReadAccess};
+use super::listing::{page_response, Listing, PageRequest, SortColumn};
 use super::serviceerror::{ServiceError, ServiceErrorKind};
-use crate::error::{Error, Success};
+use crate::error::Success;
 
 #[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct TriggerData {
@@ -19,8 +35,63 @@ pub struct TriggerData {
     enabled: bool,
     description: String,
     thresholds: Vec<Threshold>,
+    granularity: Option<String>,
+    default_interval: Option<String>,
+    notification_store: Option<String>,
+    tags: Vec<String>,
+    trend_store_parts: Vec<String>,
+    /// Most recent timestamp processed by the notification runner
+    last_evaluated: Option<DateTime<Utc>>,
+    /// Time at which the most recent timestamp was processed
+    last_processed: Option<DateTime<Utc>>,
+    /// Timestamp of the most recent notification
+    last_notification: Option<DateTime<Utc>>,
+    /// Notifications per period for the most recent periods, oldest first
+    notification_counts: Vec<TriggerNotificationCount>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct TriggerNotificationCount {
+    timestamp: DateTime<Utc>,
+    count: i64,
+}
+
+fn default_periods() -> u32 {
+    4
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct TriggerListQuery {
+    /// Only triggers with this tag
+    tag: Option<String>,
+    /// Only enabled or only disabled triggers
+    enabled: Option<bool>,
+    /// Only triggers with a name matching this pattern, in which '*' matches any characters
+    name: Option<String>,
+    /// Number of recent periods to count notifications for, at most 1000
+    #[serde(default = "default_periods")]
+    periods: u32,
+    /// Field to sort on: name (default) or id, prefixed with '-' for descending order
+    sort: Option<String>,
+    /// Cursor from the Link header of the previous page
+    cursor: Option<String>,
+    /// Maximum number of triggers to return, all when not specified
+    limit: Option<i64>,
+}
+
+const TRIGGER_SORT_COLUMNS: [SortColumn; 2] = [
+    SortColumn {
+        name: "name",
+        expression: "item.name",
+        sql_type: "text",
+    },
+    SortColumn {
+        name: "id",
+        expression: "item.id",
+        sql_type: "integer",
+    },
+];
+
 #[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct TriggerBasicData {
     name: String,
@@ -30,43 +101,110 @@ pub struct TriggerBasicData {
 #[utoipa::path(
     get,
     path="/triggers",
+    params(TriggerListQuery),
     responses(
-    (status = 200, description = "List of existing triggers", body = [TriggerData]),
-    (status = 500, description = "Database unreachable", body = Error),
+    (status = 200, description = "List of existing triggers", body = [TriggerData],
+        headers(("Link" = String, description = "Link to the next page when there are more triggers"))),
+    (status = 400, description = "Invalid filter, sort order, cursor, limit or number of periods", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
     )
 )]
 #[get("/triggers")]
-pub(super) async fn get_triggers(pool: Data<Pool>) -> Result<HttpResponse, ServiceError> {
-    let mut manager = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+pub(super) async fn get_triggers(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+    query: Query<TriggerListQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    if query.periods > MAX_PERIODS {
+        return Err(ServiceError {
+            kind: ServiceErrorKind::BadRequest,
+            message: format!("Number of periods may not exceed {MAX_PERIODS}"),
+        });
+    }
 
-    let mut client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+    let mut manager = pool.get().await?;
 
-    let triggerdata = list_triggers(&mut client).await.map_err(|e| Error {
-        code: 500,
-        message: e.to_string(),
-    })?;
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let mut listing = Listing::new(
+        "SELECT r.id, r.name::text AS name, r.enabled FROM trigger.rule r",
+        &TRIGGER_SORT_COLUMNS,
+        &TRIGGER_SORT_COLUMNS[1],
+    );
+
+    listing
+        .filter("item.enabled = $?::text::bool", query.enabled)
+        .filter(
+            concat!(
+                "EXISTS (SELECT 1 FROM trigger.rule_tag_link tl ",
+                "JOIN directory.tag t ON t.id = tl.tag_id ",
+                "WHERE tl.rule_id = item.id AND t.name = $?)"
+            ),
+            query.tag.as_deref(),
+        )
+        .filter_pattern("item.name", query.name.as_deref());
 
-    let mut result: Vec<TriggerData> = [].to_vec();
-
-    for trigger in triggerdata.iter() {
-        let thresholds = load_thresholds_with_client(&mut client, &trigger.0)
-            .await
-            .map_err(|e| Error {
-                code: 500,
-                message: e.to_string(),
-            })?;
-        result.push(TriggerData {
-            name: trigger.0.clone(),
-            enabled: trigger.5,
-            description: trigger.4.clone(),
-            thresholds: thresholds,
+    let page = listing
+        .fetch(
+            client,
+            &PageRequest {
+                sort: query.sort.as_deref(),
+                cursor: query.cursor.as_deref(),
+                limit: query.limit,
+            },
+        )
+        .await?;
+
+    let ids: Vec<i32> = page.rows.iter().map(|row| row.get("id")).collect();
+
+    let filter = TriggerStatusFilter {
+        ids: Some(ids.clone()),
+        ..TriggerStatusFilter::default()
+    };
+
+    let mut triggers: HashMap<i32, TriggerStatus> =
+        load_trigger_status(client, &filter, &Utc::now(), query.periods)
+            .await?
+            .into_iter()
+            .map(|trigger| (trigger.id, trigger))
+            .collect();
+
+    let names: Vec<String> = triggers
+        .values()
+        .map(|trigger| trigger.name.clone())
+        .collect();
+
+    let mut thresholds = load_thresholds_for_triggers(client, &names).await?;
+
+    let result: Vec<TriggerData> = ids
+        .iter()
+        .filter_map(|id| triggers.remove(id))
+        .map(|trigger| TriggerData {
+            thresholds: thresholds.remove(&trigger.name).unwrap_or_default(),
+            name: trigger.name,
+            enabled: trigger.enabled,
+            description: trigger.description.unwrap_or_default(),
+            granularity: trigger.granularity,
+            default_interval: trigger.default_interval,
+            notification_store: trigger.notification_store,
+            tags: trigger.tags,
+            trend_store_parts: trigger.trend_store_parts,
+            last_evaluated: trigger.last_evaluated,
+            last_processed: trigger.last_processed,
+            last_notification: trigger.last_notification,
+            notification_counts: trigger
+                .notification_counts
+                .into_iter()
+                .map(|count| TriggerNotificationCount {
+                    timestamp: count.timestamp,
+                    count: count.count,
+                })
+                .collect(),
         })
-    }
+        .collect();
 
-    Ok(HttpResponse::Ok().json(result))
+    Ok(page_response(&req, &result, page.next_cursor))
 }
 
 // curl -H "Content-Type: application/json" -X PUT -d '{"name":"average-output","entity_type":"Cell","data_type":"numeric","enabled":true,"source_trends":["L.Thrp.bits.UL.NsaDc"],"definition":"public.safe_division(SUM(\"L.Thrp.bits.UL.NsaDc\"),1000::numeric)","description":{"type": "ratio", "numerator": [{"type": "trend", "value": "L.Thrp.bits.UL.NsaDC"}], "denominator": [{"type": "constant", "value": "1000"}]}}' localhost:8000/kpis
@@ -75,57 +213,371 @@ pub(super) async fn get_triggers(pool: Data<Pool>) -> Result<HttpResponse, Servi
     path="/triggers",
     responses(
     (status = 200, description = "Updated trigger", body = Success),
-    (status = 400, description = "Input format incorrect", body = Error),
-    (status = 404, description = "Trigger not found", body = Error),
-    (status = 409, description = "Update failed", body = Error),
-    (status = 500, description = "General error", body = Error)
+    (status = 400, description = "Input format incorrect", body = ProblemDetails),
+    (status = 404, description = "Trigger not found", body = ProblemDetails),
+    (status = 409, description = "Update failed", body = ProblemDetails),
+    (status = 500, description = "General error", body = ProblemDetails)
     )
 )]
 #[put("/triggers")]
 pub(super) async fn change_thresholds(
+    access: AdminAccess,
     pool: Data<Pool>,
     post: String,
 ) -> Result<HttpResponse, ServiceError> {
-    let data: TriggerBasicData = serde_json::from_str(&post).map_err(|e| Error {
-        code: 400,
+    let data: TriggerBasicData = serde_json::from_str(&post).map_err(|e| ServiceError {
+        kind: ServiceErrorKind::BadRequest,
         message: e.to_string(),
     })?;
 
-    let mut manager = pool.get().await.map_err(|_| ServiceError {
-        kind: ServiceErrorKind::PoolError,
-        message: "".to_string(),
-    })?;
+    let mut manager = pool.get().await?;
 
     let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
 
-    let mut transaction = client.transaction().await.map_err(|e| Error {
-        code: 500,
-        message: e.to_string(),
-    })?;
+    let mut transaction = client.transaction().await?;
 
-    let mut trigger = load_trigger(&mut transaction, &data.name)
-        .await
-        .map_err(|e| Error {
-            code: 404,
-            message: e.to_string(),
-        })?;
+    let mut trigger = load_trigger(&mut transaction, &data.name).await?;
 
     trigger.thresholds = data.thresholds;
 
-    set_thresholds(&trigger, &mut transaction)
-        .await
-        .map_err(|e| Error {
-            code: 409,
-            message: e.to_string(),
-        })?;
+    let result = set_thresholds(&trigger, &mut transaction).await;
 
-    transaction.commit().await.map_err(|e| Error {
-        code: 409,
-        message: e.to_string(),
-    })?;
+    let recorded = record_change(
+        &mut transaction,
+        &access.audit_context(),
+        &format!("SetThresholds({})", &trigger.name),
+        serde_json::to_value(&trigger.thresholds).ok(),
+        &result,
+    )
+    .await;
+
+    result?;
+
+    recorded?;
+
+    transaction.commit().await?;
 
     Ok(HttpResponse::Ok().json(Success {
         code: 200,
         message: "thresholds updated".to_string(),
     }))
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct TriggerExceptionData {
+    trigger: String,
+    entity: String,
+    thresholds: BTreeMap<String, String>,
+    start: Option<DateTime<Utc>>,
+    expires: Option<DateTime<Utc>>,
+    remark: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct TriggerExceptionFull {
+    id: i32,
+    created: Option<DateTime<Utc>>,
+    entity: String,
+    thresholds: BTreeMap<String, String>,
+    start: Option<DateTime<Utc>>,
+    expires: Option<DateTime<Utc>>,
+    remark: Option<String>,
+}
+
+impl From<ThresholdExceptionRecord> for TriggerExceptionFull {
+    fn from(record: ThresholdExceptionRecord) -> Self {
+        TriggerExceptionFull {
+            id: record.id,
+            created: record.created,
+            entity: record.exception.entity,
+            thresholds: record.exception.thresholds,
+            start: record.exception.start,
+            expires: record.exception.expires,
+            remark: record.exception.remark,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct TriggerExceptionQuery {
+    /// Name of the trigger
+    trigger: String,
+    /// Name of the entity, required when removing exceptions
+    entity: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path="/trigger-exceptions",
+    params(TriggerExceptionQuery),
+    responses(
+    (status = 200, description = "Threshold exceptions of the trigger", body = [TriggerExceptionFull]),
+    (status = 404, description = "Trigger not found", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
+    )
+)]
+#[get("/trigger-exceptions")]
+pub(super) async fn get_trigger_exceptions(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+    query: Query<TriggerExceptionQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let exceptions: Vec<TriggerExceptionFull> = load_threshold_exceptions(client, &query.trigger)
+        .await?
+        .into_iter()
+        .map(TriggerExceptionFull::from)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(exceptions))
+}
+
+#[utoipa::path(
+    post,
+    path="/trigger-exceptions",
+    request_body = TriggerExceptionData,
+    responses(
+    (status = 200, description = "Added threshold exception", body = Success),
+    (status = 400, description = "Input format incorrect or unknown threshold", body = ProblemDetails),
+    (status = 404, description = "Trigger or entity not found", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
+    )
+)]
+#[post("/trigger-exceptions")]
+pub(super) async fn post_trigger_exception(
+    access: AdminAccess,
+    pool: Data<Pool>,
+    post: String,
+) -> Result<HttpResponse, ServiceError> {
+    let data: TriggerExceptionData = serde_json::from_str(&post).map_err(|e| ServiceError {
+        kind: ServiceErrorKind::BadRequest,
+        message: e.to_string(),
+    })?;
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let change = AddThresholdException {
+        trigger_name: data.trigger,
+        exception: ThresholdException {
+            entity: data.entity,
+            thresholds: data.thresholds,
+            start: data.start,
+            expires: data.expires,
+            remark: data.remark,
+        },
+    };
+
+    let message = generic_apply_audited(&change, client, &access.audit_context()).await?;
+
+    Ok(HttpResponse::Ok().json(Success { code: 200, message }))
+}
+
+#[utoipa::path(
+    delete,
+    path="/trigger-exceptions",
+    params(TriggerExceptionQuery),
+    responses(
+    (status = 200, description = "Removed threshold exceptions of the entity", body = Success),
+    (status = 400, description = "No entity specified", body = ProblemDetails),
+    (status = 404, description = "Trigger or exceptions not found", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
+    )
+)]
+#[delete("/trigger-exceptions")]
+pub(super) async fn delete_trigger_exceptions(
+    access: AdminAccess,
+    pool: Data<Pool>,
+    query: Query<TriggerExceptionQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let entity = query.entity.clone().ok_or_else(|| ServiceError {
+        kind: ServiceErrorKind::BadRequest,
+        message: "No entity specified".to_string(),
+    })?;
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let change = RemoveThresholdExceptions {
+        trigger_name: query.trigger.clone(),
+        entity,
+    };
+
+    let message = generic_apply_audited(&change, client, &access.audit_context()).await?;
+
+    Ok(HttpResponse::Ok().json(Success { code: 200, message }))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct TriggerChangeQuery {
+    /// Run the checks of the trigger after creating or updating it
+    #[serde(default)]
+    verify: bool,
+    /// Enable the trigger after creating it
+    #[serde(default)]
+    enable: bool,
+}
+
+fn parse_trigger_definition(post: &str) -> Result<Trigger, ServiceError> {
+    serde_json::from_str(post).map_err(|e| ServiceError {
+        kind: ServiceErrorKind::BadRequest,
+        message: e.to_string(),
+    })
+}
+
+/// Get the full definition of a trigger, in the same format as the trigger definition files.
+///
+/// Trigger names can contain slashes, which are matched as part of the name.
+#[utoipa::path(
+    get,
+    path="/triggers/{name}",
+    responses(
+    (status = 200, description = "Trigger definition", body = Object),
+    (status = 404, description = "Trigger not found", body = ProblemDetails),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
+    )
+)]
+#[get("/triggers/{name:.*}")]
+pub(super) async fn get_trigger(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+    name: Path<String>,
+) -> Result<HttpResponse, ServiceError> {
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let exists = client
+        .query_opt(
+            "SELECT 1 FROM trigger.rule WHERE name = $1",
+            &[&name.as_str()],
+        )
+        .await?
+        .is_some();
+
+    if !exists {
+        return Err(ServiceError {
+            kind: ServiceErrorKind::NotFound,
+            message: format!("No trigger found matching name '{name}'"),
+        });
+    }
+
+    let trigger = load_trigger(client, &name).await?;
+
+    Ok(HttpResponse::Ok().json(trigger))
+}
+
+/// Create a trigger from a definition in the same format as the trigger definition files.
+#[utoipa::path(
+    post,
+    path="/triggers",
+    params(TriggerChangeQuery),
+    request_body(content = Object, description = "Trigger definition"),
+    responses(
+    (status = 200, description = "Created trigger", body = Success),
+    (status = 400, description = "Input format incorrect", body = ProblemDetails),
+    (status = 500, description = "Trigger could not be created", body = ProblemDetails),
+    )
+)]
+#[post("/triggers")]
+pub(super) async fn post_trigger(
+    access: AdminAccess,
+    pool: Data<Pool>,
+    query: Query<TriggerChangeQuery>,
+    post: String,
+) -> Result<HttpResponse, ServiceError> {
+    let trigger = parse_trigger_definition(&post)?;
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let change = AddTrigger {
+        trigger,
+        verify: query.verify,
+        enable: query.enable,
+    };
+
+    let message = generic_apply_audited(&change, client, &access.audit_context()).await?;
+
+    Ok(HttpResponse::Ok().json(Success { code: 200, message }))
+}
+
+/// Replace the definition of a trigger. Threshold exceptions are kept.
+#[utoipa::path(
+    put,
+    path="/triggers/{name}",
+    params(TriggerChangeQuery),
+    request_body(content = Object, description = "Trigger definition"),
+    responses(
+    (status = 200, description = "Updated trigger", body = Success),
+    (status = 400, description = "Input format incorrect or name mismatch", body = ProblemDetails),
+    (status = 500, description = "Trigger could not be updated", body = ProblemDetails),
+    )
+)]
+#[put("/triggers/{name:.*}")]
+pub(super) async fn update_trigger(
+    access: AdminAccess,
+    pool: Data<Pool>,
+    name: Path<String>,
+    query: Query<TriggerChangeQuery>,
+    post: String,
+) -> Result<HttpResponse, ServiceError> {
+    let trigger = parse_trigger_definition(&post)?;
+
+    if trigger.name != *name {
+        return Err(ServiceError {
+            kind: ServiceErrorKind::BadRequest,
+            message: format!(
+                "Trigger name '{}' does not match '{}' in the path",
+                trigger.name, name
+            ),
+        });
+    }
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let change = UpdateTrigger {
+        trigger,
+        verify: query.verify,
+    };
+
+    let message = generic_apply_audited(&change, client, &access.audit_context()).await?;
+
+    Ok(HttpResponse::Ok().json(Success { code: 200, message }))
+}
+
+#[utoipa::path(
+    delete,
+    path="/triggers/{name}",
+    responses(
+    (status = 200, description = "Deleted trigger", body = Success),
+    (status = 404, description = "Trigger not found", body = ProblemDetails),
+    (status = 500, description = "Trigger could not be deleted", body = ProblemDetails),
+    )
+)]
+#[delete("/triggers/{name:.*}")]
+pub(super) async fn delete_trigger(
+    access: AdminAccess,
+    pool: Data<Pool>,
+    name: Path<String>,
+) -> Result<HttpResponse, ServiceError> {
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let change = DeleteTrigger {
+        trigger_name: name.into_inner(),
+    };
+
+    let message = generic_apply_audited(&change, client, &access.audit_context()).await?;
+
+    Ok(HttpResponse::Ok().json(Success { code: 200, message }))
+}
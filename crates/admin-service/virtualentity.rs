@@ -0,0 +1,126 @@
+use deadpool_postgres::Pool;
+use std::ops::DerefMut;
+
+use actix_web::{delete, get, post, web::Data, web::Path, HttpResponse};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use minerva::audit::apply_audited;
+use minerva::virtual_entity::{
+    load_virtual_entities, AddVirtualEntity, DeleteVirtualEntity, VirtualEntity,
+};
+
+use super::auth::{AdminAccess, ReadAccess};
+use super::serviceerror::{ServiceError, ServiceErrorKind};
+use crate::error::Success;
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct VirtualEntityData {
+    name: String,
+    /// SQL that defines the virtual_entity view and populates the entity type
+    sql: String,
+}
+
+impl From<VirtualEntity> for VirtualEntityData {
+    fn from(virtual_entity: VirtualEntity) -> Self {
+        VirtualEntityData {
+            name: virtual_entity.name,
+            sql: virtual_entity.sql,
+        }
+    }
+}
+
+/// Get the virtual entities. Only the view definition of each virtual entity is available.
+#[utoipa::path(
+    get,
+    path="/virtual-entities",
+    responses(
+    (status = 200, description = "List of existing virtual entities", body = [VirtualEntityData]),
+    (status = 500, description = "Database unreachable", body = ProblemDetails),
+    )
+)]
+#[get("/virtual-entities")]
+pub(super) async fn get_virtual_entities(
+    _access: ReadAccess,
+    pool: Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let virtual_entities: Vec<VirtualEntityData> = load_virtual_entities(client)
+        .await?
+        .into_iter()
+        .map(VirtualEntityData::from)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(virtual_entities))
+}
+
+/// Create or update a virtual entity by executing its SQL.
+#[utoipa::path(
+    post,
+    path="/virtual-entities",
+    request_body = VirtualEntityData,
+    responses(
+    (status = 200, description = "Created virtual entity", body = Success),
+    (status = 400, description = "Input format incorrect", body = ProblemDetails),
+    (status = 500, description = "Virtual entity could not be created", body = ProblemDetails),
+    )
+)]
+#[post("/virtual-entities")]
+pub(super) async fn post_virtual_entity(
+    access: AdminAccess,
+    pool: Data<Pool>,
+    post: String,
+) -> Result<HttpResponse, ServiceError> {
+    let data: VirtualEntityData = serde_json::from_str(&post).map_err(|e| ServiceError {
+        kind: ServiceErrorKind::BadRequest,
+        message: e.to_string(),
+    })?;
+
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let change = AddVirtualEntity {
+        virtual_entity: VirtualEntity {
+            name: data.name,
+            sql: data.sql,
+        },
+    };
+
+    let message = apply_audited(&change, client, &access.audit_context()).await?;
+
+    Ok(HttpResponse::Ok().json(Success { code: 200, message }))
+}
+
+/// Delete the view of a virtual entity. The entity type and its entities are kept.
+#[utoipa::path(
+    delete,
+    path="/virtual-entities/{name}",
+    responses(
+    (status = 200, description = "Deleted virtual entity", body = Success),
+    (status = 404, description = "Virtual entity not found", body = ProblemDetails),
+    (status = 500, description = "Virtual entity could not be deleted", body = ProblemDetails),
+    )
+)]
+#[delete("/virtual-entities/{name}")]
+pub(super) async fn delete_virtual_entity(
+    access: AdminAccess,
+    pool: Data<Pool>,
+    name: Path<String>,
+) -> Result<HttpResponse, ServiceError> {
+    let mut manager = pool.get().await?;
+
+    let client: &mut tokio_postgres::Client = manager.deref_mut().deref_mut();
+
+    let change = DeleteVirtualEntity {
+        name: name.into_inner(),
+    };
+
+    let message = apply_audited(&change, client, &access.audit_context()).await?;
+
+    Ok(HttpResponse::Ok().json(Success { code: 200, message }))
+}
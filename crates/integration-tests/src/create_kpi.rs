@@ -93,7 +93,8 @@ mod tests {
         let mut cmd = Command::cargo_bin("minerva-service")?;
         cmd.env("PGDATABASE", &database_name)
             .env("SERVICE_ADDRESS", service_address.to_string())
-            .env("SERVICE_PORT", service_port.to_string());
+            .env("SERVICE_PORT", service_port.to_string())
+            .env("SERVICE_AUTH_DISABLED", "true");
 
         let mut proc_handle = cmd.spawn().expect("Process started");
 
@@ -124,7 +125,7 @@ mod tests {
   "data_type": "numeric",
   "enabled": true,
   "source_trends": ["inside_temp"],
-  "definition": "inside_temp - outside_temp",
+  "definition": "SUM(inside_temp) - SUM(outside_temp)",
   "description": {
       "factors": [
           [
@@ -173,7 +174,7 @@ mod tests {
             "SELECT\n",
             "  t1.entity_id,\n",
             "  $1 AS timestamp,\n",
-            "  inside_temp - outside_temp AS \"test-kpi-name\"\n",
+            "  ((SUM(t1.\"inside_temp\") - SUM(t1.\"outside_temp\")))::numeric AS \"test-kpi-name\"\n",
             "FROM trend.\"hub_node_main_15m\" t1\n",
             "WHERE t1.timestamp = $1\n",
             "GROUP BY t1.entity_id\n",
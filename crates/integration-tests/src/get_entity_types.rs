@@ -74,7 +74,8 @@ mod tests {
         let mut cmd = Command::cargo_bin("minerva-service")?;
         cmd.env("PGDATABASE", &database_name)
             .env("SERVICE_ADDRESS", service_address.to_string())
-            .env("SERVICE_PORT", service_port.to_string());
+            .env("SERVICE_PORT", service_port.to_string())
+            .env("SERVICE_AUTH_DISABLED", "true");
 
         let mut proc_handle = cmd.spawn().expect("Process started");
 
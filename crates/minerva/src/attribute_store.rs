@@ -10,7 +10,7 @@ use async_trait::async_trait;
 type PostgresName = String;
 
 use super::change::{Change, ChangeResult};
-use super::error::{ConfigurationError, DatabaseError, Error, RuntimeError};
+use super::error::{ConfigurationError, DatabaseError, Error, NotFoundError, RuntimeError};
 use crate::meas_value::DataType;
 
 #[derive(Debug, Serialize, Deserialize, Clone, ToSql)]
@@ -32,6 +32,7 @@ fn default_empty_string() -> String {
     String::new()
 }
 
+#[derive(Serialize)]
 pub struct AddAttributes {
     pub attribute_store: AttributeStore,
     pub attributes: Vec<Attribute>,
@@ -90,6 +91,7 @@ impl Change for AddAttributes {
     }
 }
 
+#[derive(Serialize)]
 pub struct ChangeAttribute {
     pub attribute_store: AttributeStore,
     pub attribute: Attribute,
@@ -202,6 +204,7 @@ impl fmt::Display for AttributeStore {
     }
 }
 
+#[derive(Serialize)]
 pub struct AddAttributeStore {
     pub attribute_store: AttributeStore,
 }
@@ -241,6 +244,52 @@ impl Change for AddAttributeStore {
     }
 }
 
+#[derive(Serialize)]
+pub struct DeleteAttributeStore {
+    pub data_source: String,
+    pub entity_type: String,
+}
+
+impl fmt::Display for DeleteAttributeStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "DeleteAttributeStore({}, {})",
+            &self.data_source, &self.entity_type
+        )
+    }
+}
+
+#[async_trait]
+impl Change for DeleteAttributeStore {
+    async fn apply(&self, client: &mut Client) -> ChangeResult {
+        let query = concat!(
+            "SELECT attribute_directory.delete_attribute_store(attribute_store) ",
+            "FROM attribute_directory.attribute_store ",
+            "JOIN directory.data_source ON data_source.id = attribute_store.data_source_id ",
+            "JOIN directory.entity_type ON entity_type.id = attribute_store.entity_type_id ",
+            "WHERE data_source.name = $1 AND entity_type.name = $2"
+        );
+
+        let rows = client
+            .query(query, &[&self.data_source, &self.entity_type])
+            .await
+            .map_err(|e| DatabaseError::from_msg(format!("Error deleting attribute store: {e}")))?;
+
+        if rows.is_empty() {
+            return Err(Error::NotFound(NotFoundError::from_msg(format!(
+                "No attribute store found for data source '{}' and entity type '{}'",
+                &self.data_source, &self.entity_type
+            ))));
+        }
+
+        Ok(format!(
+            "Deleted attribute store '{}_{}'",
+            &self.data_source, &self.entity_type
+        ))
+    }
+}
+
 pub async fn load_attribute_stores(conn: &mut Client) -> Result<Vec<AttributeStore>, Error> {
     let mut attribute_stores: Vec<AttributeStore> = Vec::new();
 
@@ -287,9 +336,14 @@ pub async fn load_attribute_store(
     );
 
     let result = conn
-        .query_one(query, &[&data_source, &entity_type])
+        .query_opt(query, &[&data_source, &entity_type])
         .await
-        .map_err(|e| DatabaseError::from_msg(format!("Could not load attribute stores: {e}")))?;
+        .map_err(|e| DatabaseError::from_msg(format!("Could not load attribute stores: {e}")))?
+        .ok_or_else(|| {
+            NotFoundError::from_msg(format!(
+                "No attribute store found for data source '{data_source}' and entity type '{entity_type}'"
+            ))
+        })?;
 
     let attributes = load_attributes(conn, result.get::<usize, i32>(0)).await;
 
@@ -0,0 +1,371 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio_postgres::{Client, GenericClient, Row};
+
+use super::change::{Change, ChangeResult, GenericChange};
+use super::database::connect_db;
+use super::error::{ConfigurationError, DatabaseError, Error};
+
+/// Tool through which a change was applied
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditSource {
+    Cli,
+    Service,
+}
+
+impl fmt::Display for AuditSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditSource::Cli => write!(f, "cli"),
+            AuditSource::Service => write!(f, "service"),
+        }
+    }
+}
+
+impl FromStr for AuditSource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cli" => Ok(AuditSource::Cli),
+            "service" => Ok(AuditSource::Service),
+            _ => Err(Error::Configuration(ConfigurationError::from_msg(format!(
+                "Invalid audit source '{s}', expected cli or service"
+            )))),
+        }
+    }
+}
+
+/// Records failed changes on a connection other than the one the change was applied on
+///
+/// A failed change usually aborts the transaction it was applied in, and the caller then rolls
+/// back that transaction, including any audit record written in it.
+#[async_trait]
+pub trait FailureLog: Send + Sync {
+    async fn record_failure(
+        &self,
+        context: &AuditContext,
+        change: &str,
+        payload: Option<serde_json::Value>,
+        message: &str,
+    ) -> Result<(), Error>;
+}
+
+/// Failure log that opens a new connection configured from the environment, like the
+/// connection of the command line tool itself
+pub struct ConnectingFailureLog;
+
+#[async_trait]
+impl FailureLog for ConnectingFailureLog {
+    async fn record_failure(
+        &self,
+        context: &AuditContext,
+        change: &str,
+        payload: Option<serde_json::Value>,
+        message: &str,
+    ) -> Result<(), Error> {
+        let mut client = connect_db().await?;
+
+        record_failure(&mut client, context, change, payload, message).await
+    }
+}
+
+/// Who applies changes and through which tool
+#[derive(Clone)]
+pub struct AuditContext {
+    pub actor: String,
+    pub source: AuditSource,
+    /// Where failed changes are recorded, on the connection of the change when not set
+    pub failure_log: Option<Arc<dyn FailureLog>>,
+}
+
+impl fmt::Debug for AuditContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditContext")
+            .field("actor", &self.actor)
+            .field("source", &self.source)
+            .field("failure_log", &self.failure_log.is_some())
+            .finish()
+    }
+}
+
+impl AuditContext {
+    /// Context for changes applied by the command line tool on behalf of the OS user
+    pub fn cli() -> AuditContext {
+        let actor = ["USER", "USERNAME", "LOGNAME"]
+            .iter()
+            .find_map(|name| std::env::var(name).ok())
+            .unwrap_or_else(|| "unknown".to_string());
+
+        AuditContext {
+            actor,
+            source: AuditSource::Cli,
+            failure_log: Some(Arc::new(ConnectingFailureLog)),
+        }
+    }
+
+    /// Context for changes applied by the service on behalf of an authenticated user
+    pub fn service(actor: &str) -> AuditContext {
+        AuditContext {
+            actor: actor.to_string(),
+            source: AuditSource::Service,
+            failure_log: None,
+        }
+    }
+
+    pub fn with_failure_log(self, failure_log: Arc<dyn FailureLog>) -> AuditContext {
+        AuditContext {
+            failure_log: Some(failure_log),
+            ..self
+        }
+    }
+}
+
+async fn insert_record<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    context: &AuditContext,
+    change: &str,
+    payload: Option<serde_json::Value>,
+    success: bool,
+    message: &str,
+) -> Result<(), Error> {
+    let query = concat!(
+        "INSERT INTO logging.audit(actor, source, change, payload, success, message) ",
+        "VALUES ($1, $2, $3, $4, $5, $6)"
+    );
+
+    client
+        .execute(
+            query,
+            &[
+                &context.actor,
+                &context.source.to_string(),
+                &change,
+                &payload,
+                &success,
+                &message,
+            ],
+        )
+        .await
+        .map_err(|e| {
+            DatabaseError::from_msg(format!("Error recording change in audit log: {e}"))
+        })?;
+
+    Ok(())
+}
+
+/// Record a failed change in the audit log on `client`, for implementations of `FailureLog`
+pub async fn record_failure<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    context: &AuditContext,
+    change: &str,
+    payload: Option<serde_json::Value>,
+    message: &str,
+) -> Result<(), Error> {
+    insert_record(client, context, change, payload, false, message).await
+}
+
+/// Record the outcome of a change in the audit log
+///
+/// Failures are recorded through the failure log of the context when it has one, so that the
+/// record survives a rollback of the transaction on `client`.
+pub async fn record_change<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    context: &AuditContext,
+    change: &str,
+    payload: Option<serde_json::Value>,
+    result: &ChangeResult,
+) -> Result<(), Error> {
+    match (result, &context.failure_log) {
+        (Ok(message), _) => insert_record(client, context, change, payload, true, message).await,
+        (Err(e), Some(failure_log)) => {
+            failure_log
+                .record_failure(context, change, payload, &e.to_string())
+                .await
+        }
+        (Err(e), None) => record_failure(client, context, change, payload, &e.to_string()).await,
+    }
+}
+
+/// Combine the result of a change with the result of recording it
+///
+/// A failed change is reported as is, because that error is more relevant than a failure to
+/// record it.
+fn audited_result(result: ChangeResult, recorded: Result<(), Error>) -> ChangeResult {
+    match (result, recorded) {
+        (Ok(_), Err(e)) => Err(Error::Database(DatabaseError::from_msg(format!(
+            "Change was applied, but could not be recorded: {e}"
+        )))),
+        (result, _) => result,
+    }
+}
+
+/// Apply a change and record the outcome in the audit log
+pub async fn apply_audited<C: Change + ?Sized>(
+    change: &C,
+    client: &mut Client,
+    context: &AuditContext,
+) -> ChangeResult {
+    let result = change.apply(client).await;
+
+    let recorded = record_change(
+        client,
+        context,
+        &change.to_string(),
+        change.payload(),
+        &result,
+    )
+    .await;
+
+    audited_result(result, recorded)
+}
+
+/// Apply a generic change and record the outcome in the audit log
+pub async fn generic_apply_audited<C: GenericChange, T: GenericClient + Send + Sync>(
+    change: &C,
+    client: &mut T,
+    context: &AuditContext,
+) -> ChangeResult {
+    let result = change.generic_apply(client).await;
+
+    let recorded = record_change(
+        client,
+        context,
+        &change.to_string(),
+        change.payload(),
+        &result,
+    )
+    .await;
+
+    audited_result(result, recorded)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditRecord {
+    pub id: i64,
+    pub timestamp: DateTime<Utc>,
+    pub actor: String,
+    pub db_user: String,
+    pub source: String,
+    pub change: String,
+    pub payload: Option<serde_json::Value>,
+    pub success: bool,
+    pub message: Option<String>,
+}
+
+/// Selection of audit records, all criteria are optional
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub source: Option<AuditSource>,
+    /// Only records of which the change description contains this text
+    pub change: Option<String>,
+    pub success: Option<bool>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+}
+
+fn audit_record_from_row(row: &Row) -> Result<AuditRecord, Error> {
+    Ok(AuditRecord {
+        id: row.try_get(0)?,
+        timestamp: row.try_get(1)?,
+        actor: row.try_get(2)?,
+        db_user: row.try_get(3)?,
+        source: row.try_get(4)?,
+        change: row.try_get(5)?,
+        payload: row.try_get(6)?,
+        success: row.try_get(7)?,
+        message: row.try_get(8)?,
+    })
+}
+
+/// Load the audit records matching the filter, most recent first
+pub async fn load_audit_records<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    filter: &AuditFilter,
+) -> Result<Vec<AuditRecord>, Error> {
+    let query = concat!(
+        "SELECT id, timestamp, actor, db_user, source, change, payload, success, message ",
+        "FROM logging.audit ",
+        "WHERE ($1::text IS NULL OR actor = $1) ",
+        "AND ($2::text IS NULL OR source = $2) ",
+        "AND ($3::text IS NULL OR strpos(change, $3) > 0) ",
+        "AND ($4::bool IS NULL OR success = $4) ",
+        "AND ($5::timestamptz IS NULL OR timestamp >= $5) ",
+        "AND ($6::timestamptz IS NULL OR timestamp < $6) ",
+        "ORDER BY timestamp DESC, id DESC ",
+        "LIMIT $7"
+    );
+
+    let source = filter.source.map(|source| source.to_string());
+
+    let rows = client
+        .query(
+            query,
+            &[
+                &filter.actor,
+                &source,
+                &filter.change,
+                &filter.success,
+                &filter.since,
+                &filter.until,
+                &filter.limit,
+            ],
+        )
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error loading audit records: {e}")))?;
+
+    rows.iter().map(audit_record_from_row).collect()
+}
+
+/// Load one audit record by its Id
+pub async fn load_audit_record<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    id: i64,
+) -> Result<Option<AuditRecord>, Error> {
+    let query = concat!(
+        "SELECT id, timestamp, actor, db_user, source, change, payload, success, message ",
+        "FROM logging.audit WHERE id = $1"
+    );
+
+    let row = client
+        .query_opt(query, &[&id])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error loading audit record: {e}")))?;
+
+    row.as_ref().map(audit_record_from_row).transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::RuntimeError;
+
+    #[test]
+    fn recording_failure_only_fails_successful_changes() {
+        let recording_error = || {
+            Err(Error::Database(DatabaseError::from_msg(
+                "no audit table".to_string(),
+            )))
+        };
+
+        assert!(audited_result(Ok("done".to_string()), Ok(())).is_ok());
+        assert!(audited_result(Ok("done".to_string()), recording_error()).is_err());
+
+        let result = audited_result(
+            Err(Error::Runtime(RuntimeError::from_msg(
+                "change failed".to_string(),
+            ))),
+            recording_error(),
+        );
+
+        assert!(matches!(result, Err(Error::Runtime(_))));
+    }
+}
@@ -2,17 +2,29 @@ use std::fmt;
 
 use super::error::Error;
 use async_trait::async_trait;
+use serde::Serialize;
 use std::marker::{Send, Sync};
 use tokio_postgres::{Client, GenericClient};
 
 pub type ChangeResult = Result<String, Error>;
 
+/// JSON representation of a change, as recorded in the audit log
+pub trait ChangePayload {
+    fn payload(&self) -> Option<serde_json::Value>;
+}
+
+impl<C: Serialize> ChangePayload for C {
+    fn payload(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self).ok()
+    }
+}
+
 #[async_trait]
-pub trait Change: fmt::Display + Send + Sync {
+pub trait Change: fmt::Display + Send + Sync + ChangePayload {
     async fn apply(&self, client: &mut Client) -> ChangeResult;
 }
 
 #[async_trait]
-pub trait GenericChange: fmt::Display + Send + Sync {
+pub trait GenericChange: fmt::Display + Send + Sync + ChangePayload {
     async fn generic_apply<T: GenericClient + Send + Sync>(&self, client: &mut T) -> ChangeResult;
 }
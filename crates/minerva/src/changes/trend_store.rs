@@ -1,4 +1,5 @@
 use std::fmt;
+use serde::Serialize;
 use serde_json::Value;
 use tokio_postgres::{Client, GenericClient};
 
@@ -9,6 +10,7 @@ use crate::error::DatabaseError;
 use crate::meas_value::DataType;
 use crate::trend_store::{Trend, TrendStore, TrendStorePart};
 
+#[derive(Serialize)]
 pub struct RemoveTrends {
     pub trend_store_part: TrendStorePart,
     pub trends: Vec<String>,
@@ -82,6 +84,7 @@ impl Change for RemoveTrends {
 // AddTrends
 ////////////
 
+#[derive(Serialize)]
 pub struct AddTrends {
     pub trend_store_part: TrendStorePart,
     pub trends: Vec<Trend>,
@@ -144,6 +147,7 @@ impl Change for AddTrends {
     }
 }
 
+#[derive(Serialize)]
 pub struct ModifyTrendDataType {
     pub trend_name: String,
     pub from_type: DataType,
@@ -165,6 +169,7 @@ impl fmt::Display for ModifyTrendDataType {
 ///
 /// The change of data types for multiple trends in a trend store part is
 /// grouped into one operation for efficiency purposes.
+#[derive(Serialize)]
 pub struct ModifyTrendDataTypes {
     pub trend_store_part: TrendStorePart,
     pub modifications: Vec<ModifyTrendDataType>,
@@ -301,6 +306,7 @@ impl Change for ModifyTrendDataTypes {
     }
 }
 
+#[derive(Serialize)]
 pub struct ModifyTrendExtraData {
     pub trend_name: String,
     pub trend_store_part_name: String,
@@ -360,6 +366,7 @@ impl Change for ModifyTrendExtraData {
     }
 }
 
+#[derive(Serialize)]
 pub struct AddTrendStorePart {
     pub trend_store: TrendStore,
     pub trend_store_part: TrendStorePart,
@@ -423,6 +430,7 @@ impl fmt::Display for AddTrendStorePart {
     }
 }
 
+#[derive(Serialize)]
 pub struct AddTrendStore {
     pub trend_store: TrendStore,
 }
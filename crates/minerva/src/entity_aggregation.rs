@@ -0,0 +1,554 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use glob::glob;
+use postgres_protocol::escape::{escape_identifier, escape_literal};
+use serde::{Deserialize, Serialize};
+
+use super::error::{ConfigurationError, Error, RuntimeError};
+use super::meas_value::DataType;
+use super::trend_materialization::{
+    map_sql_to_plpgsql, TrendFunctionMaterialization, TrendMaterialization,
+    TrendMaterializationFunction, TrendMaterializationSource, TrendViewMaterialization,
+};
+use super::trend_store::{Trend, TrendStore, TrendStorePart};
+
+/// Name of the trend that holds the number of source records that were aggregated
+pub const SAMPLES_TREND_NAME: &str = "samples";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum EntityAggregationType {
+    #[serde(rename = "VIEW")]
+    #[default]
+    View,
+    #[serde(rename = "FUNCTION")]
+    Function,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EntityAggregationPart {
+    pub name: String,
+    pub source: String,
+}
+
+/// Definition of an aggregation of trend data over a relation, from the source entity type of
+/// the relation to the target entity type (e.g. node -> v-network).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EntityAggregation {
+    pub name: String,
+    pub source: String,
+    pub data_source: String,
+    pub entity_type: String,
+    pub relation: String,
+    #[serde(default)]
+    pub aggregation_type: EntityAggregationType,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(with = "humantime_serde", default = "default_processing_delay")]
+    pub processing_delay: Duration,
+    #[serde(with = "humantime_serde", default = "default_stability_delay")]
+    pub stability_delay: Duration,
+    #[serde(with = "humantime_serde", default = "default_reprocessing_period")]
+    pub reprocessing_period: Duration,
+    pub parts: Vec<EntityAggregationPart>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_processing_delay() -> Duration {
+    Duration::from_secs(30 * 60)
+}
+
+fn default_stability_delay() -> Duration {
+    Duration::from_secs(5 * 60)
+}
+
+fn default_reprocessing_period() -> Duration {
+    Duration::from_secs(3 * 24 * 60 * 60)
+}
+
+/// Wrapper matching the layout of the aggregation definition files, where the entity
+/// aggregation is stored under an 'entity_aggregation' key.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct EntityAggregationDefinition {
+    entity_aggregation: EntityAggregation,
+}
+
+impl fmt::Display for EntityAggregation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "EntityAggregation({}, {})",
+            &self.name, &self.relation
+        )
+    }
+}
+
+/// Returns the data type that results from applying the entity aggregation function to values
+/// of the specified data type.
+pub fn aggregate_data_type(aggregation: &str, data_type: DataType) -> Result<DataType, Error> {
+    match aggregation.to_uppercase().as_str() {
+        "SUM" => match data_type {
+            DataType::Int2 | DataType::Integer => Ok(DataType::Int8),
+            DataType::Int8 | DataType::Numeric => Ok(DataType::Numeric),
+            DataType::Real => Ok(DataType::Real),
+            DataType::Double => Ok(DataType::Double),
+            _ => Err(ConfigurationError::from_msg(format!(
+                "Entity aggregation SUM is not supported for data type '{data_type}'"
+            ))
+            .into()),
+        },
+        "AVG" => match data_type {
+            DataType::Int2 | DataType::Integer | DataType::Int8 | DataType::Numeric => {
+                Ok(DataType::Numeric)
+            }
+            DataType::Real | DataType::Double => Ok(DataType::Double),
+            _ => Err(ConfigurationError::from_msg(format!(
+                "Entity aggregation AVG is not supported for data type '{data_type}'"
+            ))
+            .into()),
+        },
+        "MIN" | "MAX" => Ok(data_type),
+        _ => Err(ConfigurationError::from_msg(format!(
+            "Unsupported entity aggregation '{aggregation}'"
+        ))
+        .into()),
+    }
+}
+
+impl EntityAggregation {
+    fn find_source_trend_store<'a>(
+        &self,
+        trend_stores: &'a [TrendStore],
+    ) -> Result<&'a TrendStore, Error> {
+        let first_part = self.parts.first().ok_or_else(|| {
+            ConfigurationError::from_msg(format!(
+                "Entity aggregation '{}' has no parts",
+                &self.name
+            ))
+        })?;
+
+        trend_stores
+            .iter()
+            .find(|trend_store| {
+                trend_store
+                    .parts
+                    .iter()
+                    .any(|part| part.name == first_part.source)
+            })
+            .ok_or_else(|| {
+                ConfigurationError::from_msg(format!(
+                    "Could not find source trend store part '{}' for entity aggregation '{}'",
+                    &first_part.source, &self.name
+                ))
+                .into()
+            })
+    }
+
+    fn target_part(&self, source_part: &TrendStorePart, name: &str) -> Result<TrendStorePart, Error> {
+        let mut trends: Vec<Trend> = source_part
+            .trends
+            .iter()
+            .map(|trend| {
+                Ok(Trend {
+                    name: trend.name.clone(),
+                    data_type: aggregate_data_type(&trend.entity_aggregation, trend.data_type)?,
+                    description: trend.description.clone(),
+                    time_aggregation: trend.time_aggregation.clone(),
+                    entity_aggregation: trend.entity_aggregation.clone(),
+                    extra_data: trend.extra_data.clone(),
+                })
+            })
+            .collect::<Result<Vec<Trend>, Error>>()?;
+
+        if !source_part.trends.iter().any(|trend| trend.name == SAMPLES_TREND_NAME) {
+            trends.push(Trend {
+                name: SAMPLES_TREND_NAME.to_string(),
+                data_type: DataType::Int8,
+                description: "Number of source records".to_string(),
+                time_aggregation: "SUM".to_string(),
+                entity_aggregation: "SUM".to_string(),
+                extra_data: serde_json::json!({}),
+            });
+        }
+
+        Ok(TrendStorePart {
+            name: name.to_string(),
+            trends,
+            generated_trends: Vec::new(),
+        })
+    }
+
+    /// Generate the query that aggregates the data of the source part over the relation
+    pub fn aggregation_query(&self, source_part: &TrendStorePart) -> Result<String, Error> {
+        let mut columns: Vec<String> = vec![
+            "  r.target_id AS entity_id".to_string(),
+            match self.aggregation_type {
+                EntityAggregationType::View => "  t.timestamp".to_string(),
+                EntityAggregationType::Function => "  $1 AS timestamp".to_string(),
+            },
+        ];
+
+        if !source_part.trends.iter().any(|trend| trend.name == SAMPLES_TREND_NAME) {
+            columns.push(format!(
+                "  count(*)::bigint AS {}",
+                escape_identifier(SAMPLES_TREND_NAME)
+            ));
+        }
+
+        for trend in &source_part.trends {
+            let data_type = aggregate_data_type(&trend.entity_aggregation, trend.data_type)?;
+
+            columns.push(format!(
+                "  {}(t.{})::{} AS {}",
+                trend.entity_aggregation.to_uppercase(),
+                escape_identifier(&trend.name),
+                data_type,
+                escape_identifier(&trend.name)
+            ));
+        }
+
+        let mut lines: Vec<String> = vec![
+            "SELECT".to_string(),
+            columns.join(",\n"),
+            format!("FROM trend.{} t", escape_identifier(&source_part.name)),
+            format!(
+                "JOIN relation.{} r ON t.entity_id = r.source_id",
+                escape_identifier(&self.relation)
+            ),
+        ];
+
+        match self.aggregation_type {
+            EntityAggregationType::View => {
+                lines.push("GROUP BY t.timestamp, r.target_id".to_string());
+            }
+            EntityAggregationType::Function => {
+                lines.push("WHERE t.timestamp = $1".to_string());
+                lines.push("GROUP BY r.target_id".to_string());
+            }
+        }
+
+        Ok(format!("{}\n", lines.join("\n")))
+    }
+
+    fn fingerprint_function(source_part_name: &str) -> String {
+        format!(
+            concat!(
+                "SELECT modified.last, format('{{%s: \"%s\"}}', {}, modified.last)::jsonb\n",
+                "FROM trend_directory.modified\n",
+                "JOIN trend_directory.trend_store_part ttsp ON ttsp.id = modified.trend_store_part_id\n",
+                "WHERE ttsp.name = {} AND modified.timestamp = $1;\n"
+            ),
+            escape_literal(&format!("\"{source_part_name}\"")),
+            escape_literal(source_part_name),
+        )
+    }
+
+    fn materialization(
+        &self,
+        source_part: &TrendStorePart,
+        target_part: &TrendStorePart,
+    ) -> Result<TrendMaterialization, Error> {
+        let sources = vec![TrendMaterializationSource {
+            trend_store_part: source_part.name.clone(),
+            mapping_function: "trend.mapping_id".to_string(),
+        }];
+
+        let query = self.aggregation_query(source_part)?;
+        let fingerprint_function = EntityAggregation::fingerprint_function(&source_part.name);
+        let description = Some(serde_json::json!({
+            "entity_aggregation": &self.name,
+            "relation": &self.relation,
+        }));
+
+        let materialization = match self.aggregation_type {
+            EntityAggregationType::View => TrendMaterialization::View(TrendViewMaterialization {
+                target_trend_store_part: target_part.name.clone(),
+                enabled: self.enabled,
+                processing_delay: self.processing_delay,
+                stability_delay: self.stability_delay,
+                reprocessing_period: self.reprocessing_period,
+                sources,
+                view: query,
+                fingerprint_function,
+                description,
+            }),
+            EntityAggregationType::Function => {
+                let mut columns: Vec<String> = vec![
+                    "  \"entity_id\" integer".to_string(),
+                    "  \"timestamp\" timestamp with time zone".to_string(),
+                ];
+
+                let samples = target_part
+                    .trends
+                    .iter()
+                    .find(|trend| trend.name == SAMPLES_TREND_NAME);
+
+                // The samples column comes first in the query when it is generated
+                if let Some(trend) = samples {
+                    if !source_part.trends.iter().any(|t| t.name == SAMPLES_TREND_NAME) {
+                        columns.push(format!(
+                            "  {} {}",
+                            escape_identifier(&trend.name),
+                            trend.data_type
+                        ));
+                    }
+                }
+
+                for trend in &source_part.trends {
+                    columns.push(format!(
+                        "  {} {}",
+                        escape_identifier(&trend.name),
+                        aggregate_data_type(&trend.entity_aggregation, trend.data_type)?
+                    ));
+                }
+
+                TrendMaterialization::Function(TrendFunctionMaterialization {
+                    target_trend_store_part: target_part.name.clone(),
+                    enabled: self.enabled,
+                    processing_delay: self.processing_delay,
+                    stability_delay: self.stability_delay,
+                    reprocessing_period: self.reprocessing_period,
+                    sources,
+                    function: TrendMaterializationFunction {
+                        return_type: format!("TABLE (\n{}\n)\n", columns.join(",\n")),
+                        src: map_sql_to_plpgsql(query),
+                        language: "plpgsql".to_string(),
+                    },
+                    fingerprint_function,
+                    description,
+                })
+            }
+        };
+
+        Ok(materialization)
+    }
+
+    /// Generate the target trend store and the materializations that implement the entity
+    /// aggregation, based on the source trend store definitions.
+    pub fn generate(
+        &self,
+        trend_stores: &[TrendStore],
+    ) -> Result<(TrendStore, Vec<TrendMaterialization>), Error> {
+        let source_trend_store = self.find_source_trend_store(trend_stores)?;
+
+        let mut parts: Vec<TrendStorePart> = Vec::new();
+        let mut materializations: Vec<TrendMaterialization> = Vec::new();
+
+        for aggregation_part in &self.parts {
+            let source_part = source_trend_store
+                .parts
+                .iter()
+                .find(|part| part.name == aggregation_part.source)
+                .ok_or_else(|| {
+                    ConfigurationError::from_msg(format!(
+                        "Source part '{}' of entity aggregation '{}' is not part of trend store {}",
+                        &aggregation_part.source, &self.name, source_trend_store
+                    ))
+                })?;
+
+            let target_part = self.target_part(source_part, &aggregation_part.name)?;
+
+            materializations.push(self.materialization(source_part, &target_part)?);
+            parts.push(target_part);
+        }
+
+        let trend_store = TrendStore {
+            data_source: self.data_source.clone(),
+            entity_type: self.entity_type.clone(),
+            granularity: source_trend_store.granularity,
+            partition_size: source_trend_store.partition_size,
+            parts,
+        };
+
+        Ok((trend_store, materializations))
+    }
+}
+
+pub fn load_entity_aggregation_from_file(path: &PathBuf) -> Result<EntityAggregation, Error> {
+    let f = std::fs::File::open(path).map_err(|e| {
+        ConfigurationError::from_msg(format!(
+            "Could not open entity aggregation definition file '{}': {}",
+            path.display(),
+            e
+        ))
+    })?;
+
+    if path.extension() == Some(std::ffi::OsStr::new("yaml")) {
+        let definition: EntityAggregationDefinition =
+            serde_yaml::from_reader(f).map_err(|e| {
+                RuntimeError::from_msg(format!(
+                    "Could not read entity aggregation definition from file '{}': {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+
+        Ok(definition.entity_aggregation)
+    } else if path.extension() == Some(std::ffi::OsStr::new("json")) {
+        let definition: EntityAggregationDefinition =
+            serde_json::from_reader(f).map_err(|e| {
+                RuntimeError::from_msg(format!(
+                    "Could not read entity aggregation definition from file '{}': {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+
+        Ok(definition.entity_aggregation)
+    } else {
+        Err(ConfigurationError::from_msg(format!(
+            "Unsupported entity aggregation definition format '{}'",
+            path.extension().unwrap_or_default().to_string_lossy()
+        ))
+        .into())
+    }
+}
+
+/// Load all entity aggregation definitions from the 'aggregation' directory of an instance.
+/// Other aggregation definitions (such as time aggregations) in the same directory are skipped.
+pub fn load_entity_aggregations_from(
+    minerva_instance_root: &Path,
+) -> impl Iterator<Item = EntityAggregation> {
+    let glob_path = format!(
+        "{}/aggregation/*.yaml",
+        minerva_instance_root.to_string_lossy()
+    );
+
+    glob(&glob_path)
+        .expect("Failed to read glob pattern")
+        .filter_map(|entry| match entry {
+            Ok(path) => {
+                let is_entity_aggregation = std::fs::read_to_string(&path)
+                    .map(|content| {
+                        content
+                            .lines()
+                            .any(|line| line.starts_with("entity_aggregation:"))
+                    })
+                    .unwrap_or(false);
+
+                if !is_entity_aggregation {
+                    return None;
+                }
+
+                match load_entity_aggregation_from_file(&path) {
+                    Ok(entity_aggregation) => Some(entity_aggregation),
+                    Err(e) => {
+                        println!(
+                            "Error loading entity aggregation '{}': {}",
+                            &path.display(),
+                            e
+                        );
+                        None
+                    }
+                }
+            }
+            Err(_) => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source_trend_store() -> TrendStore {
+        serde_yaml::from_str(concat!(
+            "data_source: hub\n",
+            "entity_type: node\n",
+            "granularity: 15m\n",
+            "partition_size: 1d\n",
+            "parts:\n",
+            "  - name: hub_node_main_15m\n",
+            "    trends:\n",
+            "      - name: power_kwh\n",
+            "        data_type: numeric\n",
+            "      - name: inside_temp\n",
+            "        data_type: integer\n",
+            "        entity_aggregation: AVG\n",
+        ))
+        .unwrap()
+    }
+
+    fn entity_aggregation() -> EntityAggregation {
+        let definition: EntityAggregationDefinition = serde_yaml::from_str(concat!(
+            "entity_aggregation:\n",
+            "  source: hub_node_15m\n",
+            "  name: hub_v-network_15m\n",
+            "  basename: hub_v-network_15m\n",
+            "  data_source: hub\n",
+            "  entity_type: v-network\n",
+            "  relation: node->v-network\n",
+            "  aggregation_type: VIEW\n",
+            "  parts:\n",
+            "  - name: hub_v-network_main_15m\n",
+            "    source: hub_node_main_15m\n",
+        ))
+        .unwrap();
+
+        definition.entity_aggregation
+    }
+
+    #[test]
+    fn generate_target_trend_store() {
+        let (trend_store, materializations) = entity_aggregation()
+            .generate(&[source_trend_store()])
+            .unwrap();
+
+        assert_eq!(trend_store.entity_type, "v-network");
+        assert_eq!(trend_store.granularity, Duration::from_secs(900));
+
+        let trends: Vec<(String, DataType)> = trend_store.parts[0]
+            .trends
+            .iter()
+            .map(|trend| (trend.name.clone(), trend.data_type))
+            .collect();
+
+        assert_eq!(
+            trends,
+            vec![
+                ("power_kwh".to_string(), DataType::Numeric),
+                ("inside_temp".to_string(), DataType::Numeric),
+                ("samples".to_string(), DataType::Int8),
+            ]
+        );
+
+        assert_eq!(materializations.len(), 1);
+        assert_eq!(materializations[0].name(), "hub_v-network_main_15m");
+    }
+
+    #[test]
+    fn aggregation_query_uses_entity_aggregation() {
+        let source = source_trend_store();
+        let query = entity_aggregation()
+            .aggregation_query(&source.parts[0])
+            .unwrap();
+
+        assert_eq!(
+            query,
+            concat!(
+                "SELECT\n",
+                "  r.target_id AS entity_id,\n",
+                "  t.timestamp,\n",
+                "  count(*)::bigint AS \"samples\",\n",
+                "  SUM(t.\"power_kwh\")::numeric AS \"power_kwh\",\n",
+                "  AVG(t.\"inside_temp\")::numeric AS \"inside_temp\"\n",
+                "FROM trend.\"hub_node_main_15m\" t\n",
+                "JOIN relation.\"node->v-network\" r ON t.entity_id = r.source_id\n",
+                "GROUP BY t.timestamp, r.target_id\n",
+            )
+        );
+    }
+
+    #[test]
+    fn unsupported_aggregation() {
+        assert!(aggregate_data_type("SUM", DataType::Text).is_err());
+        assert!(aggregate_data_type("MEDIAN", DataType::Numeric).is_err());
+        assert_eq!(
+            aggregate_data_type("max", DataType::Integer).unwrap(),
+            DataType::Integer
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fmt;
 
 use serde::{Deserialize, Serialize};
@@ -30,13 +31,22 @@ impl fmt::Display for EntitySet {
     }
 }
 
-async fn get_entity_set_members(
-    conn: &mut Client,
-    id: i32,
-) -> Result<Vec<String>, String> {
-    let query = "SELECT relation_directory.get_entity_set_members($1)";
-    let row = conn.query_one(query, &[&id]).await.map_err(|e| format!("{e}"))?;
-    Ok(row.get(0))
+/// Load the members of multiple entity sets with one query, by entity set Id
+pub async fn load_entity_set_members<T: GenericClient + Send + Sync>(
+    conn: &T,
+    ids: &[i32],
+) -> Result<HashMap<i32, Vec<String>>, String> {
+    let query = concat!(
+        "SELECT id, relation_directory.get_entity_set_members(id) ",
+        "FROM attribute.minerva_entity_set WHERE id = ANY($1)"
+    );
+
+    let rows = conn
+        .query(query, &[&ids])
+        .await
+        .map_err(|e| format!("Error loading entity set content: {e}"))?;
+
+    Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
 }
 
 pub async fn load_entity_sets(
@@ -44,7 +54,7 @@ pub async fn load_entity_sets(
 ) -> Result<Vec<EntitySet>, String> {
     let query = concat!(
         "SELECT name, \"group\", source_entity_type, owner, description, ",
-        "id, first_appearance, modified ",
+        "relation_directory.get_entity_set_members(es.id), first_appearance, modified ",
         "FROM attribute.minerva_entity_set es"
     );
 
@@ -52,27 +62,20 @@ pub async fn load_entity_sets(
         .await
         .map_err(|e| format!("Error loading entity sets: {e}"))?;
 
-    let mut entity_sets: Vec<EntitySet> = vec!();
-
-    for row in rows {
-        let entities = get_entity_set_members(conn, row.get(5))
-            .await
-            .map_err(|e| format!("Error loading entity set content: {e}"))?;
+    let entity_sets = rows
+        .iter()
+        .map(|row| EntitySet {
+            name: row.get(0),
+            group: row.get(1),
+            entity_type: row.get(2),
+            owner: row.get(3),
+            description: row.try_get(4).unwrap_or("".into()),
+            entities: row.get(5),
+            created: row.get(6),
+            modified: row.get(7),
+        })
+        .collect();
 
-        entity_sets.push(
-            EntitySet {
-                name: row.get(0),
-                group: row.get(1),
-                entity_type: row.get(2),
-                owner: row.get(3),
-                description: row.try_get(4).unwrap_or("".into()),
-                entities: entities,
-                created: row.get(6),
-                modified: row.get(7),
-            }
-        )
-    };
-     
     Ok(entity_sets)
 }
 
@@ -83,7 +86,7 @@ pub async fn load_entity_set(
 ) -> Result<EntitySet, String> {
     let query = concat!(
         "SELECT name, \"group\", source_entity_type, owner, description, ",
-        "entity_set.get_entity_set_members(es.id), first_appearance, modified ",
+        "relation_directory.get_entity_set_members(es.id), first_appearance, modified ",
         "FROM attribute.minerva_entity_set es ",
         "WHERE es.owner = $1 AND es.name = $2"
     );
@@ -106,6 +109,7 @@ pub async fn load_entity_set(
     Ok(entity_set)
 }
 
+#[derive(Serialize)]
 pub struct ChangeEntitySet {
     pub entity_set: EntitySet,
     pub entities: Vec<String>
@@ -171,6 +175,7 @@ impl GenericChange for ChangeEntitySet {
     }
 }
 
+#[derive(Serialize)]
 pub struct CreateEntitySet {
     pub entity_set: EntitySet
 }
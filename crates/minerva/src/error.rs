@@ -6,6 +6,9 @@ use tokio_postgres::{self, error::SqlState};
 pub enum DatabaseErrorKind {
     Default,
     UniqueViolation,
+    ForeignKeyViolation,
+    /// A table, function or other object referenced by the statement does not exist
+    UndefinedObject,
 }
 
 #[derive(Debug)]
@@ -17,6 +20,12 @@ pub struct DatabaseError {
 fn map_error_kind(sql_state: &SqlState) -> DatabaseErrorKind {
     match sql_state {
         &SqlState::UNIQUE_VIOLATION => DatabaseErrorKind::UniqueViolation,
+        &SqlState::FOREIGN_KEY_VIOLATION => DatabaseErrorKind::ForeignKeyViolation,
+        &SqlState::UNDEFINED_TABLE
+        | &SqlState::UNDEFINED_FUNCTION
+        | &SqlState::UNDEFINED_OBJECT
+        | &SqlState::UNDEFINED_SCHEMA
+        | &SqlState::NO_DATA_FOUND => DatabaseErrorKind::UndefinedObject,
         _ => DatabaseErrorKind::Default,
     }
 }
@@ -69,11 +78,24 @@ impl From<String> for RuntimeError {
     }
 }
 
+/// A named object, like a trigger or trend store, that does not exist
+#[derive(Debug)]
+pub struct NotFoundError {
+    pub msg: String,
+}
+
+impl NotFoundError {
+    pub fn from_msg(msg: String) -> NotFoundError {
+        NotFoundError { msg }
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
     Database(DatabaseError),
     Configuration(ConfigurationError),
     Runtime(RuntimeError),
+    NotFound(NotFoundError),
 }
 
 impl std::error::Error for Error {
@@ -86,6 +108,7 @@ impl std::error::Error for Error {
             Error::Database(_) => "Database error",
             Error::Configuration(_) => "Configuration error",
             Error::Runtime(_) => "Runtime error",
+            Error::NotFound(_) => "Not found error",
         }
     }
 
@@ -100,6 +123,7 @@ impl fmt::Display for Error {
             Error::Database(e) => write!(f, "{}", &e.msg),
             Error::Configuration(e) => write!(f, "{}", &e.msg),
             Error::Runtime(e) => write!(f, "{}", &e.msg),
+            Error::NotFound(e) => write!(f, "{}", &e.msg),
         }
     }
 }
@@ -122,6 +146,12 @@ impl From<RuntimeError> for Error {
     }
 }
 
+impl From<NotFoundError> for Error {
+    fn from(err: NotFoundError) -> Error {
+        Error::NotFound(err)
+    }
+}
+
 impl From<tokio_postgres::Error> for Error {
     fn from(err: tokio_postgres::Error) -> Error {
         Error::Database(DatabaseError::from(err))
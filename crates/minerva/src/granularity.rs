@@ -0,0 +1,357 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Months, NaiveDate, TimeZone, Timelike};
+
+use super::error::{Error, RuntimeError};
+use super::interval::parse_interval;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+const SECONDS_PER_WEEK: u64 = 7 * SECONDS_PER_DAY;
+/// Length of a month as used by humantime
+const SECONDS_PER_MONTH: u64 = 2_630_016;
+/// Length of a month as used by PostgreSQL when converting intervals to seconds
+const SECONDS_PER_PG_MONTH: u64 = 30 * SECONDS_PER_DAY;
+/// Length of a year as used by humantime
+const SECONDS_PER_YEAR: u64 = 31_557_600;
+
+/// Size of the periods into which timestamps of trend data and notifications are bucketed
+///
+/// Periods are aligned in the timezone of the timestamps they are applied to, so a day starts
+/// at local midnight, a week on Monday (ISO week) and a month on the first day of the month.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Granularity {
+    /// A period shorter than a day that divides a day evenly, e.g. 5m, 15m or 1h
+    Fixed(Duration),
+    Day,
+    Week,
+    /// A number of calendar months that divides a year evenly, aligned to the start of the year
+    Months(u32),
+}
+
+impl Granularity {
+    /// Nominal length of one period
+    pub fn to_duration(&self) -> Duration {
+        match self {
+            Granularity::Fixed(duration) => *duration,
+            Granularity::Day => Duration::from_secs(SECONDS_PER_DAY),
+            Granularity::Week => Duration::from_secs(SECONDS_PER_WEEK),
+            Granularity::Months(12) => Duration::from_secs(SECONDS_PER_YEAR),
+            Granularity::Months(months) => Duration::from_secs(*months as u64 * SECONDS_PER_MONTH),
+        }
+    }
+
+    /// Default size of the partitions of trend stores with this granularity
+    ///
+    /// Partitions of granularities shorter than a day hold 96 periods, e.g. 1 day of 15m data
+    /// or 4 days of 1h data.
+    pub fn default_partition_size(&self) -> Duration {
+        match self {
+            Granularity::Fixed(duration) => *duration * 96,
+            Granularity::Day => Duration::from_secs(3 * SECONDS_PER_MONTH),
+            Granularity::Week => Duration::from_secs(SECONDS_PER_YEAR),
+            Granularity::Months(_) => Duration::from_secs(5 * SECONDS_PER_YEAR),
+        }
+    }
+
+    /// Return the start of the period that contains the timestamp
+    pub fn truncate<Tz: TimeZone>(&self, timestamp: &DateTime<Tz>) -> Result<DateTime<Tz>, Error> {
+        match self {
+            Granularity::Fixed(duration) => {
+                let step = duration.as_secs();
+                let remainder = timestamp.time().num_seconds_from_midnight() as u64 % step;
+
+                Ok(timestamp.clone()
+                    - chrono::Duration::seconds(remainder as i64)
+                    - chrono::Duration::nanoseconds(timestamp.nanosecond() as i64))
+            }
+            Granularity::Day => local_midnight(&timestamp.timezone(), timestamp.date_naive()),
+            Granularity::Week => {
+                let date = timestamp.date_naive();
+                let days_from_monday = date.weekday().num_days_from_monday();
+
+                local_midnight(
+                    &timestamp.timezone(),
+                    date - chrono::Duration::days(days_from_monday as i64),
+                )
+            }
+            Granularity::Months(months) => {
+                let date = timestamp.date_naive();
+                let month0 = date.month0() - date.month0() % months;
+
+                local_midnight(
+                    &timestamp.timezone(),
+                    NaiveDate::from_ymd_opt(date.year(), month0 + 1, 1).unwrap(),
+                )
+            }
+        }
+    }
+
+    /// Return the start of the period `count` periods after (or before, when negative) the period
+    /// that contains the timestamp
+    pub fn offset<Tz: TimeZone>(
+        &self,
+        timestamp: &DateTime<Tz>,
+        count: i32,
+    ) -> Result<DateTime<Tz>, Error> {
+        let start = self.truncate(timestamp)?;
+
+        match self {
+            Granularity::Fixed(duration) => {
+                Ok(start + chrono::Duration::seconds(duration.as_secs() as i64 * count as i64))
+            }
+            Granularity::Day => local_midnight(
+                &start.timezone(),
+                start.date_naive() + chrono::Duration::days(count as i64),
+            ),
+            Granularity::Week => local_midnight(
+                &start.timezone(),
+                start.date_naive() + chrono::Duration::weeks(count as i64),
+            ),
+            Granularity::Months(months) => {
+                let delta = Months::new(months * count.unsigned_abs());
+
+                let date = if count < 0 {
+                    start.date_naive().checked_sub_months(delta)
+                } else {
+                    start.date_naive().checked_add_months(delta)
+                };
+
+                match date {
+                    Some(date) => local_midnight(&start.timezone(), date),
+                    None => Err(Error::Runtime(RuntimeError::from_msg(format!(
+                        "Timestamp out of range when adding {count} periods of {self}"
+                    )))),
+                }
+            }
+        }
+    }
+
+    /// Return the start of the period following the one that contains the timestamp
+    pub fn succ<Tz: TimeZone>(&self, timestamp: &DateTime<Tz>) -> Result<DateTime<Tz>, Error> {
+        self.offset(timestamp, 1)
+    }
+
+    /// Return the start of the period preceding the one that contains the timestamp
+    pub fn pred<Tz: TimeZone>(&self, timestamp: &DateTime<Tz>) -> Result<DateTime<Tz>, Error> {
+        self.offset(timestamp, -1)
+    }
+
+    /// Return true if the timestamp is the start of a period
+    pub fn is_aligned<Tz: TimeZone>(&self, timestamp: &DateTime<Tz>) -> Result<bool, Error> {
+        Ok(self.truncate(timestamp)? == *timestamp)
+    }
+
+    /// Return the starts of all periods from `from` up to and including `to`
+    pub fn range<Tz: TimeZone>(
+        &self,
+        from: &DateTime<Tz>,
+        to: &DateTime<Tz>,
+    ) -> Result<Vec<DateTime<Tz>>, Error> {
+        let mut timestamp = self.truncate(from)?;
+
+        if timestamp < *from {
+            timestamp = self.succ(&timestamp)?;
+        }
+
+        let mut timestamps = Vec::new();
+
+        while timestamp <= *to {
+            timestamps.push(timestamp.clone());
+            timestamp = self.succ(&timestamp)?;
+        }
+
+        Ok(timestamps)
+    }
+}
+
+fn local_midnight<Tz: TimeZone>(timezone: &Tz, date: NaiveDate) -> Result<DateTime<Tz>, Error> {
+    timezone
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+        .earliest()
+        .ok_or_else(|| {
+            Error::Runtime(RuntimeError::from_msg(format!(
+                "Midnight of {date} does not exist in the timezone"
+            )))
+        })
+}
+
+impl TryFrom<Duration> for Granularity {
+    type Error = Error;
+
+    fn try_from(duration: Duration) -> Result<Self, Self::Error> {
+        let seconds = duration.as_secs();
+
+        let granularity = if duration.subsec_nanos() != 0 || seconds == 0 {
+            None
+        } else if seconds < SECONDS_PER_DAY && SECONDS_PER_DAY % seconds == 0 {
+            Some(Granularity::Fixed(duration))
+        } else if seconds == SECONDS_PER_DAY {
+            Some(Granularity::Day)
+        } else if seconds == SECONDS_PER_WEEK {
+            Some(Granularity::Week)
+        } else if seconds == SECONDS_PER_YEAR {
+            Some(Granularity::Months(12))
+        } else if seconds % SECONDS_PER_MONTH == 0 {
+            Some(Granularity::Months((seconds / SECONDS_PER_MONTH) as u32))
+        } else if seconds % SECONDS_PER_PG_MONTH == 0 {
+            Some(Granularity::Months((seconds / SECONDS_PER_PG_MONTH) as u32))
+        } else {
+            None
+        };
+
+        match granularity {
+            Some(Granularity::Months(months)) if 12 % months != 0 => None,
+            granularity => granularity,
+        }
+        .ok_or_else(|| {
+            Error::Runtime(RuntimeError::from_msg(format!(
+                "Unsupported granularity: {}",
+                humantime::format_duration(duration)
+            )))
+        })
+    }
+}
+
+impl FromStr for Granularity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Granularity::try_from(parse_interval(s)?)
+    }
+}
+
+impl fmt::Display for Granularity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Granularity::Fixed(duration) => write!(f, "{}", humantime::format_duration(*duration)),
+            Granularity::Day => write!(f, "1d"),
+            Granularity::Week => write!(f, "1w"),
+            Granularity::Months(1) => write!(f, "1month"),
+            Granularity::Months(months) => write!(f, "{months}months"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, Utc};
+
+    use super::*;
+
+    #[test]
+    fn parse_granularities() {
+        assert_eq!(
+            "5m".parse::<Granularity>().unwrap(),
+            Granularity::Fixed(Duration::from_secs(300))
+        );
+        assert_eq!(
+            "00:30:00".parse::<Granularity>().unwrap(),
+            Granularity::Fixed(Duration::from_secs(1800))
+        );
+        assert_eq!("1 day".parse::<Granularity>().unwrap(), Granularity::Day);
+        assert_eq!("7 days".parse::<Granularity>().unwrap(), Granularity::Week);
+        assert_eq!("1w".parse::<Granularity>().unwrap(), Granularity::Week);
+        assert_eq!(
+            "1 mon".parse::<Granularity>().unwrap(),
+            Granularity::Months(1)
+        );
+        assert_eq!(
+            "3months".parse::<Granularity>().unwrap(),
+            Granularity::Months(3)
+        );
+        assert!("7m".parse::<Granularity>().is_err());
+        assert!("2 days".parse::<Granularity>().is_err());
+    }
+
+    #[test]
+    fn default_partition_sizes() {
+        let partition_size = |granularity: &str| {
+            granularity
+                .parse::<Granularity>()
+                .unwrap()
+                .default_partition_size()
+        };
+
+        assert_eq!(partition_size("15m"), parse_interval("1d").unwrap());
+        assert_eq!(partition_size("1h"), parse_interval("4d").unwrap());
+        assert_eq!(partition_size("1 day"), parse_interval("3mons").unwrap());
+        assert_eq!(partition_size("7 days"), parse_interval("1y").unwrap());
+        assert_eq!(partition_size("1mon"), parse_interval("5y").unwrap());
+    }
+
+    #[test]
+    fn truncate_fixed() {
+        let granularity: Granularity = "5m".parse().unwrap();
+        let timestamp = Utc.with_ymd_and_hms(2023, 3, 25, 14, 7, 12).unwrap();
+
+        assert_eq!(
+            granularity.truncate(&timestamp).unwrap(),
+            Utc.with_ymd_and_hms(2023, 3, 25, 14, 5, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn truncate_week_is_iso_week() {
+        let timestamp = Utc.with_ymd_and_hms(2023, 3, 25, 14, 7, 12).unwrap();
+
+        assert_eq!(
+            Granularity::Week.truncate(&timestamp).unwrap(),
+            Utc.with_ymd_and_hms(2023, 3, 20, 0, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn truncate_day_in_timezone() {
+        let timezone = FixedOffset::east_opt(2 * 3600).unwrap();
+        let timestamp = timezone.with_ymd_and_hms(2023, 3, 25, 1, 30, 0).unwrap();
+
+        let truncated = Granularity::Day.truncate(&timestamp).unwrap();
+
+        assert_eq!(
+            truncated,
+            timezone.with_ymd_and_hms(2023, 3, 25, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            truncated.with_timezone(&Utc),
+            Utc.with_ymd_and_hms(2023, 3, 24, 22, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn months() {
+        let granularity = Granularity::Months(3);
+        let timestamp = Utc.with_ymd_and_hms(2023, 5, 25, 14, 7, 12).unwrap();
+
+        assert_eq!(
+            granularity.truncate(&timestamp).unwrap(),
+            Utc.with_ymd_and_hms(2023, 4, 1, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            granularity.succ(&timestamp).unwrap(),
+            Utc.with_ymd_and_hms(2023, 7, 1, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            Granularity::Months(1)
+                .pred(&Utc.with_ymd_and_hms(2023, 1, 31, 0, 0, 0).unwrap())
+                .unwrap(),
+            Utc.with_ymd_and_hms(2022, 12, 1, 0, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn range() {
+        let granularity: Granularity = "1h".parse().unwrap();
+        let from = Utc.with_ymd_and_hms(2023, 3, 25, 12, 30, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(2023, 3, 25, 14, 0, 0).unwrap();
+
+        assert_eq!(
+            granularity.range(&from, &to).unwrap(),
+            vec![
+                Utc.with_ymd_and_hms(2023, 3, 25, 13, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2023, 3, 25, 14, 0, 0).unwrap(),
+            ]
+        );
+    }
+}
@@ -8,8 +8,10 @@ use glob::glob;
 use tokio_postgres::Client;
 
 use super::attribute_store::{load_attribute_stores, AddAttributeStore, AttributeStore};
+use super::audit::{apply_audited, AuditContext};
 use super::change::Change;
 use super::changes::trend_store::AddTrendStore;
+use super::entity_aggregation::{load_entity_aggregations_from, EntityAggregation};
 use super::error::Error;
 use super::notification_store::{
     load_notification_stores, AddNotificationStore, NotificationStore,
@@ -17,10 +19,11 @@ use super::notification_store::{
 use super::relation::{load_relation_from_file, AddRelation, Relation};
 use super::trend_materialization::{
     load_materializations, load_materializations_from, AddTrendMaterialization,
-    TrendMaterialization,
+    TrendMaterialization, UpdateTrendMaterialization,
 };
 use super::trend_store::{load_trend_store_from_file, load_trend_stores, TrendStore};
 use super::trigger::{load_trigger_from_file, load_triggers, AddTrigger, Trigger};
+use super::trigger_template::load_triggers_from_template_file;
 use super::virtual_entity::{load_virtual_entity_from_file, AddVirtualEntity, VirtualEntity};
 use super::entity_set::{load_entity_sets, EntitySet};
 
@@ -34,6 +37,7 @@ pub struct MinervaInstance {
     pub trend_materializations: Vec<TrendMaterialization>,
     pub triggers: Vec<Trigger>,
     pub entity_sets: Vec<EntitySet>,
+    pub entity_aggregations: Vec<EntityAggregation>,
 }
 
 impl MinervaInstance {
@@ -68,18 +72,39 @@ impl MinervaInstance {
             trend_materializations,
             triggers,
             entity_sets,
+            entity_aggregations: Vec::new(),
         })
     }
 
     pub fn load_from(minerva_instance_root: &Path) -> MinervaInstance {
-        let trend_stores = load_trend_stores_from(minerva_instance_root).collect();
+        let mut trend_stores: Vec<TrendStore> =
+            load_trend_stores_from(minerva_instance_root).collect();
         let notification_stores = load_notification_stores_from(minerva_instance_root).collect();
         let attribute_stores = load_attribute_stores_from(minerva_instance_root).collect();
         let virtual_entities = load_virtual_entities_from(minerva_instance_root).collect();
         let relations = load_relations_from(minerva_instance_root).collect();
-        let trend_materializations = load_materializations_from(minerva_instance_root).collect();
-        let triggers = load_triggers_from(minerva_instance_root).collect();
+        let mut trend_materializations: Vec<TrendMaterialization> =
+            load_materializations_from(minerva_instance_root).collect();
+        let mut triggers: Vec<Trigger> = load_triggers_from(minerva_instance_root).collect();
         let entity_sets: Vec<EntitySet> = vec!();
+        let entity_aggregations: Vec<EntityAggregation> =
+            load_entity_aggregations_from(minerva_instance_root).collect();
+
+        // Entity aggregations are expanded into regular trend stores and materializations
+        for entity_aggregation in &entity_aggregations {
+            match entity_aggregation.generate(&trend_stores) {
+                Ok((trend_store, mut materializations)) => {
+                    trend_stores.push(trend_store);
+                    trend_materializations.append(&mut materializations);
+                }
+                Err(e) => {
+                    println!("Error generating {entity_aggregation}: {e}");
+                }
+            }
+        }
+
+        // Trigger templates are expanded into regular triggers
+        triggers.extend(load_trigger_templates_from(minerva_instance_root));
 
         MinervaInstance {
             instance_root: Some(PathBuf::from(minerva_instance_root)),
@@ -91,10 +116,11 @@ impl MinervaInstance {
             trend_materializations,
             triggers,
             entity_sets,
+            entity_aggregations,
         }
     }
 
-    pub async fn initialize(&self, client: &mut Client) {
+    pub async fn initialize(&self, client: &mut Client, context: &AuditContext) {
         if let Some(instance_root) = &self.instance_root {
             initialize_custom(
                 client,
@@ -103,15 +129,15 @@ impl MinervaInstance {
             .await
         }
 
-        initialize_attribute_stores(client, &self.attribute_stores).await;
+        initialize_attribute_stores(client, context, &self.attribute_stores).await;
 
-        initialize_trend_stores(client, &self.trend_stores).await;
+        initialize_trend_stores(client, context, &self.trend_stores).await;
 
-        initialize_notification_stores(client, &self.notification_stores).await;
+        initialize_notification_stores(client, context, &self.notification_stores).await;
 
-        initialize_virtual_entities(client, &self.virtual_entities).await;
+        initialize_virtual_entities(client, context, &self.virtual_entities).await;
 
-        initialize_relations(client, &self.relations).await;
+        initialize_relations(client, context, &self.relations).await;
 
         if let Some(instance_root) = &self.instance_root {
             initialize_custom(
@@ -124,7 +150,7 @@ impl MinervaInstance {
             .await
         }
 
-        initialize_trend_materializations(client, &self.trend_materializations).await;
+        initialize_trend_materializations(client, context, &self.trend_materializations).await;
 
         if let Some(instance_root) = &self.instance_root {
             initialize_custom(
@@ -137,7 +163,7 @@ impl MinervaInstance {
             .await
         }
 
-        initialize_triggers(client, &self.triggers).await;
+        initialize_triggers(client, context, &self.triggers).await;
 
         if let Some(instance_root) = &self.instance_root {
             initialize_custom(
@@ -222,7 +248,12 @@ impl MinervaInstance {
         changes
     }
 
-    pub async fn update(&self, client: &mut Client, other: &MinervaInstance) -> Result<(), Error> {
+    pub async fn update(
+        &self,
+        client: &mut Client,
+        other: &MinervaInstance,
+        context: &AuditContext,
+    ) -> Result<(), Error> {
         let changes = self.diff(other);
 
         println!("Applying changes:");
@@ -230,7 +261,7 @@ impl MinervaInstance {
         for change in changes {
             println!("* {change}");
 
-            match change.apply(client).await {
+            match apply_audited(change.as_ref(), client, context).await {
                 Ok(message) => println!("> {}", &message),
                 Err(err) => println!("! Error applying change: {}", &err),
             }
@@ -238,7 +269,11 @@ impl MinervaInstance {
 
         // Materializations have no diff mechanism yet, so just update
         for materialization in &other.trend_materializations {
-            let result = materialization.update(client).await;
+            let change = UpdateTrendMaterialization {
+                trend_materialization: materialization.clone(),
+            };
+
+            let result = apply_audited(&change, client, context).await;
 
             if let Err(e) = result {
                 println!("Erro updating trend materialization: {e}");
@@ -288,13 +323,17 @@ fn load_attribute_stores_from(
         })
 }
 
-async fn initialize_attribute_stores(client: &mut Client, attribute_stores: &Vec<AttributeStore>) {
+async fn initialize_attribute_stores(
+    client: &mut Client,
+    context: &AuditContext,
+    attribute_stores: &Vec<AttributeStore>,
+) {
     for attribute_store in attribute_stores {
         let change = AddAttributeStore {
             attribute_store: attribute_store.clone(),
         };
 
-        let result = change.apply(client).await;
+        let result = apply_audited(&change, client, context).await;
 
         match result {
             Ok(message) => {
@@ -330,6 +369,7 @@ fn load_notification_stores_from(
 
 async fn initialize_notification_stores(
     client: &mut Client,
+    context: &AuditContext,
     notification_stores: &Vec<NotificationStore>,
 ) {
     for notification_store in notification_stores {
@@ -337,7 +377,7 @@ async fn initialize_notification_stores(
             notification_store: notification_store.clone(),
         };
 
-        match change.apply(client).await {
+        match apply_audited(&change, client, context).await {
             Ok(message) => {
                 println!("{message}");
             }
@@ -375,13 +415,17 @@ fn load_trend_stores_from(minerva_instance_root: &Path) -> impl Iterator<Item =
         })
 }
 
-async fn initialize_trend_stores(client: &mut Client, trend_stores: &Vec<TrendStore>) {
+async fn initialize_trend_stores(
+    client: &mut Client,
+    context: &AuditContext,
+    trend_stores: &Vec<TrendStore>,
+) {
     for trend_store in trend_stores {
         let change = AddTrendStore {
             trend_store: trend_store.clone(),
         };
 
-        match change.apply(client).await {
+        match apply_audited(&change, client, context).await {
             Ok(message) => {
                 println!("{change}: {message}");
             }
@@ -419,6 +463,25 @@ fn load_triggers_from(minerva_instance_root: &Path) -> impl Iterator<Item = Trig
         })
 }
 
+fn load_trigger_templates_from(minerva_instance_root: &Path) -> impl Iterator<Item = Trigger> {
+    let yaml_paths = glob(&format!(
+        "{}/trigger-template/*.yaml",
+        minerva_instance_root.to_string_lossy()
+    ))
+    .expect("Failed to read glob pattern");
+
+    yaml_paths.flat_map(|entry| match entry {
+        Ok(path) => match load_triggers_from_template_file(&path) {
+            Ok(triggers) => triggers,
+            Err(e) => {
+                println!("Error loading trigger template: {e}");
+                Vec::new()
+            }
+        },
+        Err(_) => Vec::new(),
+    })
+}
+
 fn load_virtual_entities_from(minerva_instance_root: &Path) -> impl Iterator<Item = VirtualEntity> {
     let sql_paths = glob(&format!(
         "{}/virtual-entity/*.sql",
@@ -465,22 +528,30 @@ fn load_relations_from(minerva_instance_root: &Path) -> impl Iterator<Item = Rel
         })
 }
 
-async fn initialize_virtual_entities(client: &mut Client, virtual_entities: &Vec<VirtualEntity>) {
+async fn initialize_virtual_entities(
+    client: &mut Client,
+    context: &AuditContext,
+    virtual_entities: &Vec<VirtualEntity>,
+) {
     for virtual_entity in virtual_entities {
         let change: AddVirtualEntity = AddVirtualEntity::from(virtual_entity.clone());
 
-        match change.apply(client).await {
+        match apply_audited(&change, client, context).await {
             Ok(message) => println!("{message}"),
             Err(e) => print!("Error creating virtual entity: {e}"),
         }
     }
 }
 
-async fn initialize_relations(client: &mut Client, relations: &Vec<Relation>) {
+async fn initialize_relations(
+    client: &mut Client,
+    context: &AuditContext,
+    relations: &Vec<Relation>,
+) {
     for relation in relations {
         let change: AddRelation = AddRelation::from(relation.clone());
 
-        match change.apply(client).await {
+        match apply_audited(&change, client, context).await {
             Ok(message) => println!("{message}"),
             Err(e) => print!("Error creating relation: {e}"),
         }
@@ -489,19 +560,24 @@ async fn initialize_relations(client: &mut Client, relations: &Vec<Relation>) {
 
 async fn initialize_trend_materializations(
     client: &mut Client,
+    context: &AuditContext,
     trend_materializations: &Vec<TrendMaterialization>,
 ) {
     for materialization in trend_materializations {
         let change = AddTrendMaterialization::from(materialization.clone());
 
-        match change.apply(client).await {
+        match apply_audited(&change, client, context).await {
             Ok(message) => println!("{message}"),
             Err(e) => println!("Error creating trend materialization: {e}"),
         }
     }
 }
 
-async fn initialize_triggers(client: &mut Client, triggers: &Vec<Trigger>) {
+async fn initialize_triggers(
+    client: &mut Client,
+    context: &AuditContext,
+    triggers: &Vec<Trigger>,
+) {
     for trigger in triggers {
         let change = AddTrigger {
             trigger: trigger.clone(),
@@ -509,7 +585,7 @@ async fn initialize_triggers(client: &mut Client, triggers: &Vec<Trigger>) {
             enable: true,
         };
 
-        match change.apply(client).await {
+        match apply_audited(&change, client, context).await {
             Ok(message) => println!("{message}"),
             Err(e) => println!("Error creating trigger '{}': {}", trigger.name, e),
         }
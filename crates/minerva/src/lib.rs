@@ -1,18 +1,32 @@
 pub mod attribute_store;
+pub mod audit;
 pub mod change;
 pub mod changes;
 pub mod database;
+pub mod entity_aggregation;
 pub mod error;
+pub mod granularity;
 pub mod instance;
 pub mod interval;
 pub mod job;
 pub mod loading;
+pub mod materialization_test;
 pub mod meas_value;
+pub mod notification_forward;
+pub mod notification_incident;
 pub mod notification_store;
 pub mod relation;
 pub mod schema;
+pub mod sql_check;
+pub mod trend_data;
 pub mod trend_materialization;
 pub mod trend_store;
 pub mod trigger;
+pub mod trigger_backtest;
+pub mod trigger_exception;
+pub mod trigger_runner;
+pub mod trigger_status;
+pub mod trigger_template;
+pub mod trigger_test;
 pub mod virtual_entity;
 pub mod entity_set;
@@ -1,3 +1,4 @@
+use std::collections::BTreeSet;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
@@ -8,12 +9,13 @@ use serde_json::{json, Value};
 use tokio_postgres::Client;
 
 use crate::error::{Error, RuntimeError};
+use crate::granularity::Granularity;
 use crate::interval::parse_interval;
 use crate::job::{end_job, start_job};
 use crate::trend_store::get_trend_store_id;
 use crate::trend_store::{
-    create_partitions_for_trend_store_and_timestamp, load_trend_store, RawMeasurementStore,
-    TrendStore,
+    create_partitions_for_trend_store_and_timestamp, load_trend_store, partition_start,
+    RawMeasurementStore, TrendStore,
 };
 
 #[derive(Serialize, Deserialize)]
@@ -92,6 +94,14 @@ pub async fn load_data<P: AsRef<Path>>(
         }
     };
 
+    // Any interval is accepted here, not only granularities supported by Granularity, because
+    // the trend store is looked up by its granularity as stored in the database
+    let granularity = parse_interval(&parser_config.granularity)?;
+
+    // Timestamps are bucketed into the periods of the granularity in their own timezone, when
+    // the granularity is one that Granularity can express
+    let periods = Granularity::try_from(granularity).ok();
+
     let job_id = start_job(client, &description).await?;
 
     let raw_data_package: Vec<(String, DateTime<chrono::Utc>, Vec<String>)> = csv_reader
@@ -102,19 +112,21 @@ pub async fn load_data<P: AsRef<Path>>(
             let entity: String = String::from(record.get(entity_column_index).unwrap());
             let timestamp_txt: &str = record.get(timestamp_column_index).unwrap();
 
-            let timestamp: DateTime<chrono::Utc> = DateTime::parse_from_rfc3339(timestamp_txt)
-                .unwrap()
-                .with_timezone(&chrono::offset::Utc);
+            let timestamp = DateTime::parse_from_rfc3339(timestamp_txt).unwrap();
+
+            let timestamp: DateTime<chrono::Utc> = match &periods {
+                Some(periods) => periods.truncate(&timestamp)?,
+                None => timestamp,
+            }
+            .with_timezone(&chrono::offset::Utc);
 
             let values: Vec<String> = record.iter().map(String::from).collect();
 
             let record: (String, DateTime<chrono::Utc>, Vec<String>) = (entity, timestamp, values);
 
-            record
+            Ok(record)
         })
-        .collect();
-
-    let granularity = parse_interval(&parser_config.granularity).unwrap();
+        .collect::<Result<_, Error>>()?;
 
     let trend_store: TrendStore = load_trend_store(client, data_source, &parser_config.entity_type, &granularity)
         .await
@@ -125,8 +137,13 @@ pub async fn load_data<P: AsRef<Path>>(
         .map_err(|e| format!("Error loading trend store Id from database: {e}"))?;
 
     if create_partitions {
-        for record in &raw_data_package {
-            create_partitions_for_trend_store_and_timestamp(client, trend_store_id, record.1)
+        let timestamps: BTreeSet<DateTime<chrono::Utc>> = raw_data_package
+            .iter()
+            .map(|record| partition_start(trend_store.partition_size, &record.1))
+            .collect::<Result<_, Error>>()?;
+
+        for timestamp in timestamps {
+            create_partitions_for_trend_store_and_timestamp(client, trend_store_id, timestamp)
                 .await
                 .map_err(|e| format!("Error creating partition for timestamp: {e}"))?;
         }
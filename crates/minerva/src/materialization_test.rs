@@ -0,0 +1,559 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use postgres_protocol::escape::escape_identifier;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::Client;
+
+use super::audit::AuditContext;
+use super::database::{connect_to_db, create_database, drop_database, get_db_config};
+use super::error::{ConfigurationError, DatabaseError, Error, RuntimeError};
+use super::instance::MinervaInstance;
+use super::loading::{load_data, ParserConfig, TrendsFrom, TrendsFromHeader};
+use super::schema::create_schema;
+use super::trend_materialization::TrendMaterialization;
+use super::trend_store::create_partitions_for_timestamp;
+
+/// Name of the fixture definition file in a fixture directory
+pub const FIXTURE_DEFINITION_FILE: &str = "fixture.yaml";
+
+/// Names of the columns in the expected data file that identify a row
+pub const ENTITY_COLUMN: &str = "entity";
+pub const TIMESTAMP_COLUMN: &str = "timestamp";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FixtureData {
+    pub data_source: String,
+    pub entity_type: String,
+    pub granularity: String,
+    pub file: PathBuf,
+    #[serde(default = "default_entity_column")]
+    pub entity_column: String,
+    #[serde(default = "default_timestamp_column")]
+    pub timestamp_column: String,
+    #[serde(default)]
+    pub null_value: String,
+}
+
+fn default_entity_column() -> String {
+    ENTITY_COLUMN.to_string()
+}
+
+fn default_timestamp_column() -> String {
+    TIMESTAMP_COLUMN.to_string()
+}
+
+/// Definition of the data used to test a materialization and the expected result
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MaterializationFixture {
+    pub data: Vec<FixtureData>,
+    /// SQL scripts to run after loading the data, e.g. to fill relations
+    #[serde(default)]
+    pub setup: Vec<PathBuf>,
+    pub timestamps: Vec<DateTime<Utc>>,
+    pub expected: PathBuf,
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f64,
+}
+
+fn default_tolerance() -> f64 {
+    1e-6
+}
+
+pub fn load_fixture_from_dir(fixture_dir: &Path) -> Result<MaterializationFixture, Error> {
+    let path = fixture_dir.join(FIXTURE_DEFINITION_FILE);
+
+    let f = std::fs::File::open(&path).map_err(|e| {
+        ConfigurationError::from_msg(format!(
+            "Could not open fixture definition file '{}': {}",
+            path.display(),
+            e
+        ))
+    })?;
+
+    serde_yaml::from_reader(f).map_err(|e| {
+        ConfigurationError::from_msg(format!(
+            "Could not read fixture definition from file '{}': {}",
+            path.display(),
+            e
+        ))
+        .into()
+    })
+}
+
+/// A single row of trend data, keyed on entity name and timestamp
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataRow {
+    pub entity: String,
+    pub timestamp: DateTime<Utc>,
+    pub values: Vec<Option<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RowDifference {
+    Missing(DataRow),
+    Unexpected(DataRow),
+    Value {
+        entity: String,
+        timestamp: DateTime<Utc>,
+        column: String,
+        expected: Option<String>,
+        actual: Option<String>,
+    },
+}
+
+fn show_value(value: &Option<String>) -> &str {
+    match value {
+        Some(v) => v,
+        None => "NULL",
+    }
+}
+
+fn show_values(values: &[Option<String>]) -> String {
+    values
+        .iter()
+        .map(show_value)
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
+
+impl fmt::Display for RowDifference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RowDifference::Missing(row) => write!(
+                f,
+                "- {} {}: {}",
+                &row.entity,
+                row.timestamp.to_rfc3339(),
+                show_values(&row.values)
+            ),
+            RowDifference::Unexpected(row) => write!(
+                f,
+                "+ {} {}: {}",
+                &row.entity,
+                row.timestamp.to_rfc3339(),
+                show_values(&row.values)
+            ),
+            RowDifference::Value {
+                entity,
+                timestamp,
+                column,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "~ {} {} {}: expected {}, got {}",
+                entity,
+                timestamp.to_rfc3339(),
+                column,
+                show_value(expected),
+                show_value(actual)
+            ),
+        }
+    }
+}
+
+fn values_match(expected: &Option<String>, actual: &Option<String>, tolerance: f64) -> bool {
+    match (expected, actual) {
+        (None, None) => true,
+        (Some(e), Some(a)) => match (e.parse::<f64>(), a.parse::<f64>()) {
+            (Ok(e), Ok(a)) => (e - a).abs() <= tolerance,
+            _ => e == a,
+        },
+        _ => false,
+    }
+}
+
+/// Compare actual rows with expected rows, matching rows on entity and timestamp and numeric
+/// values within the specified tolerance.
+pub fn compare_rows(
+    columns: &[String],
+    expected: &[DataRow],
+    actual: &[DataRow],
+    tolerance: f64,
+) -> Vec<RowDifference> {
+    let mut actual_rows: BTreeMap<(String, DateTime<Utc>), &DataRow> = actual
+        .iter()
+        .map(|row| ((row.entity.clone(), row.timestamp), row))
+        .collect();
+
+    let mut differences: Vec<RowDifference> = Vec::new();
+
+    for expected_row in expected {
+        let key = (expected_row.entity.clone(), expected_row.timestamp);
+
+        match actual_rows.remove(&key) {
+            None => differences.push(RowDifference::Missing(expected_row.clone())),
+            Some(actual_row) => {
+                for (index, column) in columns.iter().enumerate() {
+                    let expected_value = &expected_row.values[index];
+                    let actual_value = &actual_row.values[index];
+
+                    if !values_match(expected_value, actual_value, tolerance) {
+                        differences.push(RowDifference::Value {
+                            entity: expected_row.entity.clone(),
+                            timestamp: expected_row.timestamp,
+                            column: column.clone(),
+                            expected: expected_value.clone(),
+                            actual: actual_value.clone(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    for (_, row) in actual_rows {
+        differences.push(RowDifference::Unexpected(row.clone()));
+    }
+
+    differences
+}
+
+/// Read the expected rows from a CSV file with an 'entity' and a 'timestamp' column, and a
+/// column for each trend to check. Empty values are interpreted as NULL.
+pub fn read_expected_rows(path: &Path) -> Result<(Vec<String>, Vec<DataRow>), Error> {
+    let mut reader = csv::Reader::from_path(path).map_err(|e| {
+        ConfigurationError::from_msg(format!(
+            "Could not open expected data file '{}': {}",
+            path.display(),
+            e
+        ))
+    })?;
+
+    let headers: Vec<String> = reader
+        .headers()
+        .map_err(|e| {
+            ConfigurationError::from_msg(format!(
+                "Could not read header of expected data file '{}': {}",
+                path.display(),
+                e
+            ))
+        })?
+        .iter()
+        .map(String::from)
+        .collect();
+
+    let entity_index = headers
+        .iter()
+        .position(|h| h == ENTITY_COLUMN)
+        .ok_or_else(|| {
+            ConfigurationError::from_msg(format!(
+                "No '{ENTITY_COLUMN}' column in expected data file '{}'",
+                path.display()
+            ))
+        })?;
+
+    let timestamp_index = headers
+        .iter()
+        .position(|h| h == TIMESTAMP_COLUMN)
+        .ok_or_else(|| {
+            ConfigurationError::from_msg(format!(
+                "No '{TIMESTAMP_COLUMN}' column in expected data file '{}'",
+                path.display()
+            ))
+        })?;
+
+    let value_indexes: Vec<usize> = (0..headers.len())
+        .filter(|i| *i != entity_index && *i != timestamp_index)
+        .collect();
+
+    let columns: Vec<String> = value_indexes.iter().map(|i| headers[*i].clone()).collect();
+
+    let mut rows: Vec<DataRow> = Vec::new();
+
+    for record in reader.records() {
+        let record = record.map_err(|e| {
+            ConfigurationError::from_msg(format!(
+                "Could not read record from expected data file '{}': {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+        let timestamp_txt = record.get(timestamp_index).unwrap_or_default();
+
+        let timestamp = DateTime::parse_from_rfc3339(timestamp_txt)
+            .map_err(|e| {
+                ConfigurationError::from_msg(format!(
+                    "Invalid timestamp '{timestamp_txt}' in expected data file: {e}"
+                ))
+            })?
+            .with_timezone(&Utc);
+
+        let values = value_indexes
+            .iter()
+            .map(|i| match record.get(*i) {
+                None | Some("") => None,
+                Some(value) => Some(value.to_string()),
+            })
+            .collect();
+
+        rows.push(DataRow {
+            entity: record.get(entity_index).unwrap_or_default().to_string(),
+            timestamp,
+            values,
+        });
+    }
+
+    Ok((columns, rows))
+}
+
+async fn load_target_rows(
+    client: &mut Client,
+    trend_store_part: &str,
+    columns: &[String],
+    timestamps: &[DateTime<Utc>],
+) -> Result<Vec<DataRow>, Error> {
+    let entity_type: String = client
+        .query_one(
+            concat!(
+                "SELECT et.name FROM trend_directory.trend_store_part tsp ",
+                "JOIN trend_directory.trend_store ts ON ts.id = tsp.trend_store_id ",
+                "JOIN directory.entity_type et ON et.id = ts.entity_type_id ",
+                "WHERE tsp.name = $1"
+            ),
+            &[&trend_store_part],
+        )
+        .await
+        .map_err(|e| {
+            DatabaseError::from_msg(format!(
+                "Could not find trend store part '{trend_store_part}': {e}"
+            ))
+        })?
+        .get(0);
+
+    let columns_part: String = columns
+        .iter()
+        .map(|column| format!(", t.{}::text", escape_identifier(column)))
+        .collect();
+
+    let query = format!(
+        "SELECT e.name, t.timestamp{} FROM trend.{} t JOIN entity.{} e ON e.id = t.entity_id WHERE t.timestamp = ANY($1)",
+        columns_part,
+        escape_identifier(trend_store_part),
+        escape_identifier(&entity_type),
+    );
+
+    let rows = client.query(&query, &[&timestamps]).await.map_err(|e| {
+        DatabaseError::from_msg(format!(
+            "Could not read materialized data from '{trend_store_part}': {e}"
+        ))
+    })?;
+
+    Ok(rows
+        .iter()
+        .map(|row| DataRow {
+            entity: row.get(0),
+            timestamp: row.get(1),
+            values: (0..columns.len()).map(|i| row.get(i + 2)).collect(),
+        })
+        .collect())
+}
+
+async fn run_in_database(
+    client: &mut Client,
+    instance: &MinervaInstance,
+    materialization: &TrendMaterialization,
+    fixture_dir: &Path,
+    fixture: &MaterializationFixture,
+) -> Result<Vec<RowDifference>, Error> {
+    create_schema(client)
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Could not create schema: {e}")))?;
+
+    // Only the constructs that a materialization can depend on are initialized, no custom
+    // scripts, triggers or other materializations.
+    let scratch_instance = MinervaInstance {
+        instance_root: None,
+        trend_stores: instance.trend_stores.clone(),
+        attribute_stores: instance.attribute_stores.clone(),
+        notification_stores: Vec::new(),
+        virtual_entities: instance.virtual_entities.clone(),
+        relations: instance.relations.clone(),
+        trend_materializations: vec![materialization.clone()],
+        triggers: Vec::new(),
+        entity_sets: Vec::new(),
+        entity_aggregations: Vec::new(),
+    };
+
+    scratch_instance.initialize(client, &AuditContext::cli()).await;
+
+    for data in &fixture.data {
+        let parser_config = ParserConfig {
+            entity_type: data.entity_type.clone(),
+            granularity: data.granularity.clone(),
+            trends: TrendsFrom::Header(TrendsFromHeader {
+                entity_column: data.entity_column.clone(),
+                timestamp_column: data.timestamp_column.clone(),
+            }),
+            extra: None,
+            null_value: data.null_value.clone(),
+        };
+
+        load_data(
+            client,
+            &data.data_source,
+            &parser_config,
+            fixture_dir.join(&data.file),
+            true,
+        )
+        .await?;
+    }
+
+    for setup_file in &fixture.setup {
+        let path = fixture_dir.join(setup_file);
+
+        let sql = std::fs::read_to_string(&path).map_err(|e| {
+            ConfigurationError::from_msg(format!(
+                "Could not read setup file '{}': {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+        client.batch_execute(&sql).await.map_err(|e| {
+            DatabaseError::from_msg(format!(
+                "Error running setup file '{}': {}",
+                path.display(),
+                e
+            ))
+        })?;
+    }
+
+    for timestamp in &fixture.timestamps {
+        create_partitions_for_timestamp(client, *timestamp).await?;
+
+        client
+            .query(
+                concat!(
+                    "SELECT trend_directory.materialize(m, $1) ",
+                    "FROM trend_directory.materialization m WHERE m::text = $2"
+                ),
+                &[timestamp, &materialization.name()],
+            )
+            .await
+            .map_err(|e| {
+                DatabaseError::from_msg(format!(
+                    "Error materializing '{}' for {}: {}",
+                    materialization.name(),
+                    timestamp.to_rfc3339(),
+                    e
+                ))
+            })?;
+    }
+
+    let (columns, expected_rows) = read_expected_rows(&fixture_dir.join(&fixture.expected))?;
+
+    let actual_rows =
+        load_target_rows(client, materialization.name(), &columns, &fixture.timestamps).await?;
+
+    Ok(compare_rows(
+        &columns,
+        &expected_rows,
+        &actual_rows,
+        fixture.tolerance,
+    ))
+}
+
+/// Test a materialization against fixture data in a scratch database
+///
+/// A new database is created with the Minerva schema and the trend stores, attribute stores,
+/// relations and virtual entities of the instance. The fixture data is loaded, the
+/// materialization is run for the fixture timestamps and the result is compared with the
+/// expected data. The differences are returned, so an empty list means the test passed. The
+/// scratch database is dropped afterwards unless `keep_database` is set.
+pub async fn test_materialization(
+    client: &mut Client,
+    instance: &MinervaInstance,
+    materialization: &TrendMaterialization,
+    fixture_dir: &Path,
+    keep_database: bool,
+) -> Result<Vec<RowDifference>, Error> {
+    let fixture = load_fixture_from_dir(fixture_dir)?;
+
+    let database_name = format!(
+        "minerva_test_{}_{}",
+        std::process::id(),
+        Utc::now().timestamp_millis()
+    );
+
+    create_database(client, &database_name)
+        .await
+        .map_err(DatabaseError::from_msg)?;
+
+    let mut config = get_db_config()?;
+    config.dbname(&database_name);
+
+    let result = match connect_to_db(&config).await {
+        Ok(mut scratch_client) => {
+            run_in_database(
+                &mut scratch_client,
+                instance,
+                materialization,
+                fixture_dir,
+                &fixture,
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+
+    if keep_database {
+        println!("Keeping test database '{database_name}'");
+    } else if let Err(e) = drop_database(client, &database_name).await {
+        return Err(RuntimeError::from_msg(format!(
+            "Test finished, but could not drop test database: {e}"
+        ))
+        .into());
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(entity: &str, values: &[Option<&str>]) -> DataRow {
+        DataRow {
+            entity: entity.to_string(),
+            timestamp: DateTime::parse_from_rfc3339("2023-03-25T14:00:00Z")
+                .unwrap()
+                .with_timezone(&Utc),
+            values: values.iter().map(|v| v.map(String::from)).collect(),
+        }
+    }
+
+    #[test]
+    fn compare_within_tolerance() {
+        let columns = vec!["power".to_string(), "state".to_string()];
+        let expected = vec![row("v1", &[Some("1.0"), Some("on")])];
+        let actual = vec![row("v1", &[Some("1.0000001"), Some("on")])];
+
+        assert!(compare_rows(&columns, &expected, &actual, 1e-6).is_empty());
+    }
+
+    #[test]
+    fn compare_reports_differences() {
+        let columns = vec!["power".to_string()];
+        let expected = vec![row("v1", &[Some("1.0")]), row("v2", &[None])];
+        let actual = vec![row("v1", &[Some("1.5")]), row("v3", &[Some("2")])];
+
+        let differences: Vec<String> = compare_rows(&columns, &expected, &actual, 1e-6)
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+
+        assert_eq!(
+            differences,
+            vec![
+                "~ v1 2023-03-25T14:00:00+00:00 power: expected 1.0, got 1.5",
+                "- v2 2023-03-25T14:00:00+00:00: NULL",
+                "+ v3 2023-03-25T14:00:00+00:00: 2",
+            ]
+        );
+    }
+}
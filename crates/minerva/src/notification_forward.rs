@@ -0,0 +1,830 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use postgres_protocol::escape::escape_identifier;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::AsyncWriteExt;
+use tokio_postgres::GenericClient;
+
+use super::error::{ConfigurationError, DatabaseError, Error, NotFoundError, RuntimeError};
+use super::trigger_exception::trigger_entity_names;
+
+fn default_batch_size() -> i64 {
+    100
+}
+
+fn default_max_attempts() -> u32 {
+    5
+}
+
+fn default_initial_backoff() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_max_backoff() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_syslog_socket() -> PathBuf {
+    PathBuf::from("/dev/log")
+}
+
+fn default_syslog_ident() -> String {
+    "minerva".to_string()
+}
+
+/// Definition of the sinks that notifications are forwarded to
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ForwarderConfig {
+    /// Maximum number of notifications that is forwarded per sink in one run
+    #[serde(default = "default_batch_size")]
+    pub batch_size: i64,
+    pub sinks: Vec<SinkConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SinkConfig {
+    /// Unique name of the sink, used to keep track of the forwarding progress
+    pub name: String,
+    /// Name of the notification store to forward the notifications of
+    pub notification_store: String,
+    /// Also forward the notifications that exist when the sink is run for the first time
+    #[serde(default)]
+    pub from_beginning: bool,
+    #[serde(default)]
+    pub filter: SinkFilter,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    pub target: SinkTarget,
+}
+
+/// Selection of the notifications that are forwarded to a sink
+///
+/// Empty lists match any notification.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SinkFilter {
+    /// Names of the trigger rules
+    #[serde(default)]
+    pub rules: Vec<String>,
+    /// Tags of which the trigger rule must have at least one
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub min_weight: Option<i32>,
+}
+
+impl SinkFilter {
+    pub fn matches(&self, notification: &ForwardedNotification) -> bool {
+        let rule_matches = self.rules.is_empty()
+            || notification
+                .rule
+                .as_ref()
+                .is_some_and(|rule| self.rules.contains(rule));
+
+        let tags_matches =
+            self.tags.is_empty() || notification.tags.iter().any(|tag| self.tags.contains(tag));
+
+        let weight_matches = self.min_weight.map_or(true, |min_weight| {
+            notification
+                .weight
+                .is_some_and(|weight| weight >= min_weight)
+        });
+
+        rule_matches && tags_matches && weight_matches
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RetryPolicy {
+    /// Number of delivery attempts of one notification before giving up
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(with = "humantime_serde", default = "default_initial_backoff")]
+    pub initial_backoff: Duration,
+    #[serde(with = "humantime_serde", default = "default_max_backoff")]
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: default_max_attempts(),
+            initial_backoff: default_initial_backoff(),
+            max_backoff: default_max_backoff(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `attempt`, doubling with every attempt up to the maximum
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogFacility {
+    #[default]
+    User,
+    Daemon,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl SyslogFacility {
+    fn code(&self) -> u8 {
+        match self {
+            SyslogFacility::User => 1,
+            SyslogFacility::Daemon => 3,
+            SyslogFacility::Local0 => 16,
+            SyslogFacility::Local1 => 17,
+            SyslogFacility::Local2 => 18,
+            SyslogFacility::Local3 => 19,
+            SyslogFacility::Local4 => 20,
+            SyslogFacility::Local5 => 21,
+            SyslogFacility::Local6 => 22,
+            SyslogFacility::Local7 => 23,
+        }
+    }
+}
+
+/// Destination of forwarded notifications, each notification is delivered as a JSON document
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SinkTarget {
+    /// HTTP POST of each notification to a URL
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+        #[serde(with = "humantime_serde", default = "default_timeout")]
+        timeout: Duration,
+    },
+    /// Append each notification as a line to a JSON Lines file
+    File { path: PathBuf },
+    /// Send each notification as a message to a local syslog socket
+    Syslog {
+        #[serde(default = "default_syslog_socket")]
+        socket: PathBuf,
+        #[serde(default)]
+        facility: SyslogFacility,
+        #[serde(default = "default_syslog_ident")]
+        ident: String,
+    },
+}
+
+impl fmt::Display for SinkTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SinkTarget::Webhook { url, .. } => write!(f, "webhook {url}"),
+            SinkTarget::File { path } => write!(f, "file {}", path.display()),
+            SinkTarget::Syslog { socket, .. } => write!(f, "syslog {}", socket.display()),
+        }
+    }
+}
+
+/// A notification as it is delivered to a sink
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ForwardedNotification {
+    pub id: i32,
+    pub notification_store: String,
+    pub timestamp: DateTime<Utc>,
+    /// Name of the trigger rule that created the notification
+    pub rule: Option<String>,
+    /// Tags of the trigger rule
+    pub tags: Vec<String>,
+    pub entity_id: i32,
+    /// Name of the entity, if it could be resolved
+    pub entity: Option<String>,
+    pub weight: Option<i32>,
+    pub details: Option<String>,
+    pub data: Option<Value>,
+    pub created: Option<DateTime<Utc>>,
+}
+
+/// Outcome of forwarding a batch of notifications to one sink
+#[derive(Debug, Clone, PartialEq)]
+pub enum ForwardResult {
+    Forwarded {
+        sink: String,
+        forwarded: usize,
+        skipped: usize,
+    },
+    /// Delivery failed after all retries or the notifications could not be loaded, the
+    /// notifications before the failure are not forwarded again
+    Failed {
+        sink: String,
+        forwarded: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ForwardResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ForwardResult::Forwarded {
+                sink,
+                forwarded,
+                skipped,
+            } => write!(
+                f,
+                "{sink}: forwarded {forwarded} notifications, skipped {skipped}"
+            ),
+            ForwardResult::Failed {
+                sink,
+                forwarded,
+                message,
+            } => write!(
+                f,
+                "{sink}: failed after forwarding {forwarded} notifications: {message}"
+            ),
+        }
+    }
+}
+
+pub fn load_forwarder_config(path: &Path) -> Result<ForwarderConfig, Error> {
+    let f = std::fs::File::open(path).map_err(|e| {
+        ConfigurationError::from_msg(format!(
+            "Could not open forwarder configuration file '{}': {}",
+            path.display(),
+            e
+        ))
+    })?;
+
+    let config: ForwarderConfig = serde_yaml::from_reader(f).map_err(|e| {
+        ConfigurationError::from_msg(format!(
+            "Could not read forwarder configuration from file '{}': {}",
+            path.display(),
+            e
+        ))
+    })?;
+
+    let mut names = BTreeSet::new();
+
+    for sink in &config.sinks {
+        if !names.insert(&sink.name) {
+            return Err(Error::Configuration(ConfigurationError::from_msg(format!(
+                "Duplicate sink name '{}'",
+                sink.name
+            ))));
+        }
+    }
+
+    Ok(config)
+}
+
+async fn notification_store_table<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    notification_store: &str,
+) -> Result<(i32, String), Error> {
+    let query = concat!(
+        "SELECT ns.id, notification_directory.table_name(ns)::text ",
+        "FROM notification_directory.notification_store ns ",
+        "JOIN directory.data_source ds ON ds.id = ns.data_source_id ",
+        "WHERE ds.name = $1"
+    );
+
+    let rows = client
+        .query(query, &[&notification_store])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error loading notification store: {e}")))?;
+
+    match rows.first() {
+        Some(row) => Ok((row.get(0), row.get(1))),
+        None => Err(Error::NotFound(NotFoundError::from_msg(format!(
+            "No notification store found named '{notification_store}'"
+        )))),
+    }
+}
+
+/// Load the notifications of a trigger notification store with an Id after `after_id`, in
+/// order of Id
+pub async fn load_pending_notifications<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    notification_store: &str,
+    after_id: i32,
+    limit: i64,
+) -> Result<Vec<ForwardedNotification>, Error> {
+    let (_, table_name) = notification_store_table(client, notification_store).await?;
+
+    let query = format!(
+        concat!(
+            "SELECT n.id, n.timestamp, r.name::text, ",
+            "array(SELECT t.name::text FROM trigger.rule_tag_link l ",
+            "JOIN directory.tag t ON t.id = l.tag_id WHERE l.rule_id = n.rule_id ORDER BY t.name), ",
+            "n.entity_id, n.weight, n.details, n.data, n.created ",
+            "FROM notification.{} n ",
+            "LEFT JOIN trigger.rule r ON r.id = n.rule_id ",
+            "WHERE n.id > $1 ",
+            "ORDER BY n.id ",
+            "LIMIT $2"
+        ),
+        escape_identifier(&table_name)
+    );
+
+    let rows = client
+        .query(&query, &[&after_id, &limit])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error loading notifications: {e}")))?;
+
+    let mut notifications = rows
+        .iter()
+        .map(|row| {
+            Ok(ForwardedNotification {
+                id: row.try_get(0)?,
+                notification_store: notification_store.to_string(),
+                timestamp: row.try_get(1)?,
+                rule: row.try_get(2)?,
+                tags: row.try_get(3)?,
+                entity_id: row.try_get(4)?,
+                entity: None,
+                weight: row.try_get(5)?,
+                details: row.try_get(6)?,
+                data: row.try_get(7)?,
+                created: row.try_get(8)?,
+            })
+        })
+        .collect::<Result<Vec<ForwardedNotification>, Error>>()?;
+
+    let rules: BTreeSet<String> = notifications
+        .iter()
+        .filter_map(|n| n.rule.clone())
+        .collect();
+
+    for rule in rules {
+        let entity_ids: Vec<i32> = notifications
+            .iter()
+            .filter(|n| n.rule.as_ref() == Some(&rule))
+            .map(|n| n.entity_id)
+            .collect();
+
+        let names = trigger_entity_names(client, &rule, &entity_ids).await?;
+
+        for notification in notifications
+            .iter_mut()
+            .filter(|n| n.rule.as_ref() == Some(&rule))
+        {
+            notification.entity = names.get(&notification.entity_id).cloned();
+        }
+    }
+
+    Ok(notifications)
+}
+
+/// Return the Id of the last notification handled by the sink
+///
+/// A sink that is run for the first time, or that is pointed to another notification store,
+/// starts at the current last notification unless it should forward from the beginning.
+async fn sink_position<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    sink: &SinkConfig,
+) -> Result<i32, Error> {
+    let (notification_store_id, table_name) =
+        notification_store_table(client, &sink.notification_store).await?;
+
+    let rows = client
+        .query(
+            "SELECT notification_store_id, last_notification_id FROM notification_directory.forward_state WHERE sink = $1",
+            &[&sink.name],
+        )
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error loading forward state: {e}")))?;
+
+    if let Some(row) = rows.first() {
+        let stored_notification_store_id: i32 = row.get(0);
+
+        if stored_notification_store_id == notification_store_id {
+            return Ok(row.get(1));
+        }
+    }
+
+    let position: i32 = if sink.from_beginning {
+        0
+    } else {
+        let query = format!(
+            "SELECT coalesce(max(id), 0) FROM notification.{}",
+            escape_identifier(&table_name)
+        );
+
+        let row = client.query_one(&query, &[]).await.map_err(|e| {
+            DatabaseError::from_msg(format!("Error loading last notification: {e}"))
+        })?;
+
+        row.get(0)
+    };
+
+    store_sink_position(client, sink, notification_store_id, position).await?;
+
+    Ok(position)
+}
+
+async fn store_sink_position<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    sink: &SinkConfig,
+    notification_store_id: i32,
+    last_notification_id: i32,
+) -> Result<(), Error> {
+    let query = concat!(
+        "INSERT INTO notification_directory.forward_state(sink, notification_store_id, last_notification_id) ",
+        "VALUES ($1, $2, $3) ",
+        "ON CONFLICT (sink) DO UPDATE SET ",
+        "notification_store_id = EXCLUDED.notification_store_id, ",
+        "last_notification_id = EXCLUDED.last_notification_id, ",
+        "modified = now()"
+    );
+
+    client
+        .execute(
+            query,
+            &[&sink.name, &notification_store_id, &last_notification_id],
+        )
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error storing forward state: {e}")))?;
+
+    Ok(())
+}
+
+/// Format a notification as an RFC 3164 message for a local syslog daemon
+fn syslog_message(facility: SyslogFacility, ident: &str, payload: &str) -> String {
+    // Severity 'notice'
+    let priority = u16::from(facility.code()) * 8 + 5;
+
+    format!(
+        "<{}>{} {}[{}]: {}",
+        priority,
+        Utc::now().format("%b %e %H:%M:%S"),
+        ident,
+        std::process::id(),
+        payload
+    )
+}
+
+/// Forwards notifications from notification stores to the configured sinks
+pub struct NotificationForwarder {
+    config: ForwarderConfig,
+    http_client: reqwest::Client,
+}
+
+impl NotificationForwarder {
+    pub fn new(config: ForwarderConfig) -> Self {
+        NotificationForwarder {
+            config,
+            http_client: reqwest::Client::new(),
+        }
+    }
+
+    /// Forward the pending notifications of all sinks once
+    pub async fn forward<T: GenericClient + Send + Sync>(
+        &self,
+        client: &mut T,
+    ) -> Vec<ForwardResult> {
+        let mut results = Vec::new();
+
+        for sink in &self.config.sinks {
+            let result = match self.forward_sink(client, sink).await {
+                Ok(result) => result,
+                Err(e) => ForwardResult::Failed {
+                    sink: sink.name.clone(),
+                    forwarded: 0,
+                    message: e.to_string(),
+                },
+            };
+
+            results.push(result);
+        }
+
+        results
+    }
+
+    async fn forward_sink<T: GenericClient + Send + Sync>(
+        &self,
+        client: &mut T,
+        sink: &SinkConfig,
+    ) -> Result<ForwardResult, Error> {
+        let (notification_store_id, _) =
+            notification_store_table(client, &sink.notification_store).await?;
+
+        let start_position = sink_position(client, sink).await?;
+
+        let notifications = load_pending_notifications(
+            client,
+            &sink.notification_store,
+            start_position,
+            self.config.batch_size,
+        )
+        .await?;
+
+        let mut position = start_position;
+        let mut forwarded: usize = 0;
+        let mut skipped: usize = 0;
+        let mut failure: Option<Error> = None;
+
+        for notification in &notifications {
+            if sink.filter.matches(notification) {
+                if let Err(e) = self.deliver_with_retry(sink, notification).await {
+                    failure = Some(e);
+                    break;
+                }
+
+                forwarded += 1;
+            } else {
+                skipped += 1;
+            }
+
+            position = notification.id;
+        }
+
+        if position != start_position {
+            store_sink_position(client, sink, notification_store_id, position).await?;
+        }
+
+        match failure {
+            Some(e) => Ok(ForwardResult::Failed {
+                sink: sink.name.clone(),
+                forwarded,
+                message: e.to_string(),
+            }),
+            None => Ok(ForwardResult::Forwarded {
+                sink: sink.name.clone(),
+                forwarded,
+                skipped,
+            }),
+        }
+    }
+
+    async fn deliver_with_retry(
+        &self,
+        sink: &SinkConfig,
+        notification: &ForwardedNotification,
+    ) -> Result<(), Error> {
+        let mut attempt: u32 = 1;
+
+        loop {
+            match self.deliver(&sink.target, notification).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt >= sink.retry.max_attempts => {
+                    return Err(Error::Runtime(RuntimeError::from_msg(format!(
+                        "Could not deliver notification {} to {} after {} attempts: {}",
+                        notification.id, sink.target, attempt, e
+                    ))))
+                }
+                Err(_) => {
+                    tokio::time::sleep(sink.retry.backoff(attempt)).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Deliver one notification to a sink target without retrying
+    pub async fn deliver(
+        &self,
+        target: &SinkTarget,
+        notification: &ForwardedNotification,
+    ) -> Result<(), Error> {
+        let payload = serde_json::to_string(notification).map_err(|e| {
+            RuntimeError::from_msg(format!("Could not serialize notification: {e}"))
+        })?;
+
+        match target {
+            SinkTarget::Webhook {
+                url,
+                headers,
+                timeout,
+            } => {
+                let mut request = self
+                    .http_client
+                    .post(url)
+                    .timeout(*timeout)
+                    .header(reqwest::header::CONTENT_TYPE, "application/json");
+
+                for (name, value) in headers {
+                    request = request.header(name, value);
+                }
+
+                let response = request
+                    .body(payload)
+                    .send()
+                    .await
+                    .map_err(|e| RuntimeError::from_msg(format!("Request failed: {e}")))?;
+
+                if !response.status().is_success() {
+                    return Err(Error::Runtime(RuntimeError::from_msg(format!(
+                        "Webhook responded with status {}",
+                        response.status()
+                    ))));
+                }
+            }
+            SinkTarget::File { path } => {
+                let mut file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .map_err(|e| {
+                        RuntimeError::from_msg(format!(
+                            "Could not open file '{}': {}",
+                            path.display(),
+                            e
+                        ))
+                    })?;
+
+                file.write_all(format!("{payload}\n").as_bytes())
+                    .await
+                    .map_err(|e| {
+                        RuntimeError::from_msg(format!(
+                            "Could not write to file '{}': {}",
+                            path.display(),
+                            e
+                        ))
+                    })?;
+            }
+            SinkTarget::Syslog {
+                socket,
+                facility,
+                ident,
+            } => {
+                let message = syslog_message(*facility, ident, &payload);
+
+                let unix_socket = tokio::net::UnixDatagram::unbound().map_err(|e| {
+                    RuntimeError::from_msg(format!("Could not create syslog socket: {e}"))
+                })?;
+
+                unix_socket
+                    .send_to(message.as_bytes(), socket)
+                    .await
+                    .map_err(|e| {
+                        RuntimeError::from_msg(format!(
+                            "Could not send to syslog socket '{}': {}",
+                            socket.display(),
+                            e
+                        ))
+                    })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    fn notification(rule: &str, tags: &[&str], weight: Option<i32>) -> ForwardedNotification {
+        ForwardedNotification {
+            id: 1,
+            notification_store: "trigger-notification".to_string(),
+            timestamp: "2023-03-25T14:00:00Z".parse().unwrap(),
+            rule: Some(rule.to_string()),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            entity_id: 1,
+            entity: Some("hillside14".to_string()),
+            weight,
+            details: None,
+            data: None,
+            created: None,
+        }
+    }
+
+    #[test]
+    fn filter_on_rule_tags_and_weight() {
+        let filter = SinkFilter {
+            rules: vec!["node/15m/highpowerusage".to_string()],
+            tags: vec!["online".to_string()],
+            min_weight: Some(100),
+        };
+
+        assert!(filter.matches(&notification(
+            "node/15m/highpowerusage",
+            &["online", "power"],
+            Some(500)
+        )));
+        assert!(!filter.matches(&notification(
+            "node/1h/highpowerusage",
+            &["online"],
+            Some(500)
+        )));
+        assert!(!filter.matches(&notification(
+            "node/15m/highpowerusage",
+            &["power"],
+            Some(500)
+        )));
+        assert!(!filter.matches(&notification(
+            "node/15m/highpowerusage",
+            &["online"],
+            Some(50)
+        )));
+        assert!(!filter.matches(&notification("node/15m/highpowerusage", &["online"], None)));
+        assert!(SinkFilter::default().matches(&notification("any", &[], None)));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_maximum() {
+        let retry = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+        };
+
+        assert_eq!(retry.backoff(1), Duration::from_secs(1));
+        assert_eq!(retry.backoff(2), Duration::from_secs(2));
+        assert_eq!(retry.backoff(3), Duration::from_secs(4));
+        assert_eq!(retry.backoff(4), Duration::from_secs(5));
+        assert_eq!(retry.backoff(40), Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn deliver_to_webhook() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        // Minimal stand-in for an HTTP endpoint that accepts one request
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 4096];
+
+            loop {
+                let count = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..count]);
+
+                let text = String::from_utf8_lossy(&request);
+
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length: usize = head
+                        .lines()
+                        .find_map(|line| {
+                            line.to_lowercase()
+                                .strip_prefix("content-length:")
+                                .map(|value| value.trim().parse().unwrap())
+                        })
+                        .unwrap_or(0);
+
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+            }
+
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+
+            String::from_utf8(request).unwrap()
+        });
+
+        let forwarder = NotificationForwarder::new(ForwarderConfig {
+            batch_size: default_batch_size(),
+            sinks: Vec::new(),
+        });
+
+        let target = SinkTarget::Webhook {
+            url: format!("http://{address}/notifications"),
+            headers: BTreeMap::from([("X-Source".to_string(), "minerva".to_string())]),
+            timeout: default_timeout(),
+        };
+
+        forwarder
+            .deliver(
+                &target,
+                &notification("node/15m/highpowerusage", &[], Some(500)),
+            )
+            .await
+            .unwrap();
+
+        let request = server.await.unwrap();
+        let (head, body) = request.split_once("\r\n\r\n").unwrap();
+
+        assert!(head.starts_with("POST /notifications HTTP/1.1"));
+        assert!(head.to_lowercase().contains("x-source: minerva"));
+
+        let payload: Value = serde_json::from_str(body).unwrap();
+
+        assert_eq!(payload["rule"], "node/15m/highpowerusage");
+        assert_eq!(payload["entity"], "hillside14");
+        assert_eq!(payload["weight"], 500);
+    }
+}
@@ -0,0 +1,456 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use postgres_protocol::escape::escape_identifier;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::{types::ToSql, Client, GenericClient, Row};
+
+use super::change::{Change, ChangeResult, GenericChange};
+use super::error::{ConfigurationError, DatabaseError, Error, NotFoundError};
+use super::granularity::Granularity;
+use super::trigger_exception::trigger_entity_names;
+
+/// Lifecycle state of an incident
+///
+/// An incident starts out open, can be acknowledged while it is being worked on and is closed
+/// when resolved. Closed incidents can not be changed anymore.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IncidentStatus {
+    Open,
+    Acknowledged,
+    Closed,
+}
+
+impl fmt::Display for IncidentStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IncidentStatus::Open => write!(f, "open"),
+            IncidentStatus::Acknowledged => write!(f, "acknowledged"),
+            IncidentStatus::Closed => write!(f, "closed"),
+        }
+    }
+}
+
+impl FromStr for IncidentStatus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "open" => Ok(IncidentStatus::Open),
+            "acknowledged" => Ok(IncidentStatus::Acknowledged),
+            "closed" => Ok(IncidentStatus::Closed),
+            _ => Err(Error::Configuration(ConfigurationError::from_msg(format!(
+                "Invalid incident status '{s}', expected open, acknowledged or closed"
+            )))),
+        }
+    }
+}
+
+/// Notifications of a trigger for one entity at consecutive timestamps
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Incident {
+    pub id: i32,
+    pub trigger: String,
+    pub entity_id: i32,
+    /// Name of the entity, if it could be resolved
+    pub entity: Option<String>,
+    pub first_timestamp: DateTime<Utc>,
+    pub last_timestamp: DateTime<Utc>,
+    pub notification_count: i32,
+    pub status: IncidentStatus,
+    pub assignee: Option<String>,
+    pub comment: Option<String>,
+    pub created: DateTime<Utc>,
+    pub modified: DateTime<Utc>,
+    pub closed: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IncidentFilter {
+    pub trigger: Option<String>,
+    pub entity: Option<String>,
+    pub status: Option<IncidentStatus>,
+}
+
+/// Number of incidents affected by processing the notifications of one trigger timestamp
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IncidentUpdate {
+    pub created: u64,
+    pub extended: u64,
+}
+
+const INCIDENT_COLUMNS: &str = concat!(
+    "i.id, r.name::text, i.entity_id, i.first_timestamp, i.last_timestamp, ",
+    "i.notification_count, i.status::text, i.assignee, i.comment, i.created, i.modified, i.closed"
+);
+
+fn incident_from_row(row: &Row) -> Result<Incident, Error> {
+    let status: String = row.try_get(6)?;
+
+    Ok(Incident {
+        id: row.try_get(0)?,
+        trigger: row.try_get(1)?,
+        entity_id: row.try_get(2)?,
+        entity: None,
+        first_timestamp: row.try_get(3)?,
+        last_timestamp: row.try_get(4)?,
+        notification_count: row.try_get(5)?,
+        status: status.parse()?,
+        assignee: row.try_get(7)?,
+        comment: row.try_get(8)?,
+        created: row.try_get(9)?,
+        modified: row.try_get(10)?,
+        closed: row.try_get(11)?,
+    })
+}
+
+/// Fill in the entity names of incidents, looking up the entities per trigger entity type
+async fn resolve_entity_names<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    incidents: &mut [Incident],
+) -> Result<(), Error> {
+    let triggers: BTreeSet<String> = incidents.iter().map(|i| i.trigger.clone()).collect();
+
+    for trigger in triggers {
+        let entity_ids: Vec<i32> = incidents
+            .iter()
+            .filter(|i| i.trigger == trigger)
+            .map(|i| i.entity_id)
+            .collect();
+
+        let names = trigger_entity_names(client, &trigger, &entity_ids).await?;
+
+        for incident in incidents.iter_mut().filter(|i| i.trigger == trigger) {
+            incident.entity = names.get(&incident.entity_id).cloned();
+        }
+    }
+
+    Ok(())
+}
+
+/// Load incidents matching the filter, most recently changed first
+pub async fn load_incidents<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    filter: &IncidentFilter,
+) -> Result<Vec<Incident>, Error> {
+    let status = filter.status.map(|status| status.to_string());
+
+    let query = format!(
+        concat!(
+            "SELECT {} FROM trigger.incident i ",
+            "JOIN trigger.rule r ON r.id = i.rule_id ",
+            "WHERE ($1::text IS NULL OR r.name = $1) ",
+            "AND ($2::text IS NULL OR i.status = $2::trigger.incident_status) ",
+            "ORDER BY i.modified DESC, i.id DESC"
+        ),
+        INCIDENT_COLUMNS
+    );
+
+    let rows = client
+        .query(&query, &[&filter.trigger, &status])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error loading incidents: {e}")))?;
+
+    let mut incidents = rows
+        .iter()
+        .map(incident_from_row)
+        .collect::<Result<Vec<Incident>, Error>>()?;
+
+    resolve_entity_names(client, &mut incidents).await?;
+
+    if let Some(entity) = &filter.entity {
+        incidents.retain(|incident| incident.entity.as_ref() == Some(entity));
+    }
+
+    Ok(incidents)
+}
+
+pub async fn load_incident<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    id: i32,
+) -> Result<Incident, Error> {
+    let query = format!(
+        "SELECT {} FROM trigger.incident i JOIN trigger.rule r ON r.id = i.rule_id WHERE i.id = $1",
+        INCIDENT_COLUMNS
+    );
+
+    let rows = client
+        .query(&query, &[&id])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error loading incident: {e}")))?;
+
+    let mut incidents = match rows.first() {
+        Some(row) => vec![incident_from_row(row)?],
+        None => {
+            return Err(Error::NotFound(NotFoundError::from_msg(format!(
+                "No incident found with Id {id}"
+            ))))
+        }
+    };
+
+    resolve_entity_names(client, &mut incidents).await?;
+
+    Ok(incidents.remove(0))
+}
+
+/// Combine the notifications of a trigger for a timestamp into incidents
+///
+/// A notification extends an incident of the same entity that is not closed and that had a
+/// notification at the preceding timestamp. Otherwise a new incident is started. Processing the
+/// same timestamp again does not change incidents that already include it.
+pub async fn update_incidents<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    trigger_name: &str,
+    timestamp: &DateTime<Utc>,
+) -> Result<IncidentUpdate, Error> {
+    let query = concat!(
+        "SELECT r.id, r.granularity::text, notification_directory.table_name(ns)::text ",
+        "FROM trigger.rule r ",
+        "JOIN notification_directory.notification_store ns ON ns.id = r.notification_store_id ",
+        "WHERE r.name = $1"
+    );
+
+    let rows = client
+        .query(query, &[&trigger_name])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error loading trigger: {e}")))?;
+
+    let (rule_id, granularity, notification_table): (i32, String, String) = match rows.first() {
+        Some(row) => (row.get(0), row.get(1), row.get(2)),
+        None => {
+            return Err(Error::NotFound(NotFoundError::from_msg(format!(
+                "No trigger with notification store found named '{trigger_name}'"
+            ))))
+        }
+    };
+
+    let previous_timestamp = granularity.parse::<Granularity>()?.pred(timestamp)?;
+
+    let notification_table = format!("notification.{}", escape_identifier(&notification_table));
+
+    let extend_query = format!(
+        concat!(
+            "UPDATE trigger.incident i ",
+            "SET last_timestamp = $3, notification_count = i.notification_count + 1, modified = now() ",
+            "FROM (SELECT DISTINCT entity_id FROM {} WHERE rule_id = $1 AND timestamp = $3) n ",
+            "WHERE i.rule_id = $1 AND i.entity_id = n.entity_id ",
+            "AND i.status <> 'closed' AND i.last_timestamp = $2"
+        ),
+        notification_table
+    );
+
+    let extend_args: &[&(dyn ToSql + Sync)] = &[&rule_id, &previous_timestamp, timestamp];
+
+    let extended = client
+        .execute(&extend_query, extend_args)
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error extending incidents: {e}")))?;
+
+    let create_query = format!(
+        concat!(
+            "INSERT INTO trigger.incident(rule_id, entity_id, first_timestamp, last_timestamp) ",
+            "SELECT DISTINCT $1::integer, n.entity_id, $2::timestamptz, $2::timestamptz FROM {} n ",
+            "WHERE n.rule_id = $1 AND n.timestamp = $2 AND NOT EXISTS (",
+            "SELECT 1 FROM trigger.incident i WHERE i.rule_id = $1 AND i.entity_id = n.entity_id ",
+            "AND $2 BETWEEN i.first_timestamp AND i.last_timestamp)"
+        ),
+        notification_table
+    );
+
+    let created = client
+        .execute(&create_query, &[&rule_id, timestamp])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error creating incidents: {e}")))?;
+
+    Ok(IncidentUpdate { created, extended })
+}
+
+/// Change the status, assignee or comment of an incident that is not closed
+async fn modify_incident<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    id: i32,
+    status: Option<IncidentStatus>,
+    assignee: Option<&str>,
+    comment: Option<&str>,
+) -> Result<(), Error> {
+    let transaction = client.transaction().await?;
+
+    let rows = transaction
+        .query(
+            "SELECT status::text FROM trigger.incident WHERE id = $1 FOR UPDATE",
+            &[&id],
+        )
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error loading incident: {e}")))?;
+
+    let current_status: IncidentStatus = match rows.first() {
+        Some(row) => row.get::<usize, String>(0).parse()?,
+        None => {
+            return Err(Error::NotFound(NotFoundError::from_msg(format!(
+                "No incident found with Id {id}"
+            ))))
+        }
+    };
+
+    if current_status == IncidentStatus::Closed {
+        return Err(Error::Configuration(ConfigurationError::from_msg(format!(
+            "Incident {id} is closed"
+        ))));
+    }
+
+    let status = status.map(|status| status.to_string());
+
+    let query = concat!(
+        "UPDATE trigger.incident SET ",
+        "status = coalesce($2::text::trigger.incident_status, status), ",
+        "assignee = coalesce($3, assignee), ",
+        "comment = coalesce($4, comment), ",
+        "modified = now(), ",
+        "closed = CASE WHEN $2::text = 'closed' THEN now() ELSE closed END ",
+        "WHERE id = $1"
+    );
+
+    transaction
+        .execute(query, &[&id, &status, &assignee, &comment])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error updating incident: {e}")))?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct AcknowledgeIncident {
+    pub id: i32,
+    pub assignee: Option<String>,
+    pub comment: Option<String>,
+}
+
+impl fmt::Display for AcknowledgeIncident {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AcknowledgeIncident({})", self.id)
+    }
+}
+
+#[async_trait]
+impl GenericChange for AcknowledgeIncident {
+    async fn generic_apply<T: GenericClient + Sync + Send>(&self, client: &mut T) -> ChangeResult {
+        modify_incident(
+            client,
+            self.id,
+            Some(IncidentStatus::Acknowledged),
+            self.assignee.as_deref(),
+            self.comment.as_deref(),
+        )
+        .await?;
+
+        Ok(format!("Acknowledged incident {}", self.id))
+    }
+}
+
+#[async_trait]
+impl Change for AcknowledgeIncident {
+    async fn apply(&self, client: &mut Client) -> ChangeResult {
+        self.generic_apply(client).await
+    }
+}
+
+#[derive(Serialize)]
+pub struct CloseIncident {
+    pub id: i32,
+    pub comment: Option<String>,
+}
+
+impl fmt::Display for CloseIncident {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CloseIncident({})", self.id)
+    }
+}
+
+#[async_trait]
+impl GenericChange for CloseIncident {
+    async fn generic_apply<T: GenericClient + Sync + Send>(&self, client: &mut T) -> ChangeResult {
+        modify_incident(
+            client,
+            self.id,
+            Some(IncidentStatus::Closed),
+            None,
+            self.comment.as_deref(),
+        )
+        .await?;
+
+        Ok(format!("Closed incident {}", self.id))
+    }
+}
+
+#[async_trait]
+impl Change for CloseIncident {
+    async fn apply(&self, client: &mut Client) -> ChangeResult {
+        self.generic_apply(client).await
+    }
+}
+
+#[derive(Serialize)]
+pub struct AssignIncident {
+    pub id: i32,
+    pub assignee: String,
+    pub comment: Option<String>,
+}
+
+impl fmt::Display for AssignIncident {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AssignIncident({}, {})", self.id, &self.assignee)
+    }
+}
+
+#[async_trait]
+impl GenericChange for AssignIncident {
+    async fn generic_apply<T: GenericClient + Sync + Send>(&self, client: &mut T) -> ChangeResult {
+        modify_incident(
+            client,
+            self.id,
+            None,
+            Some(&self.assignee),
+            self.comment.as_deref(),
+        )
+        .await?;
+
+        Ok(format!(
+            "Assigned incident {} to '{}'",
+            self.id, &self.assignee
+        ))
+    }
+}
+
+#[async_trait]
+impl Change for AssignIncident {
+    async fn apply(&self, client: &mut Client) -> ChangeResult {
+        self.generic_apply(client).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_status() {
+        for status in [
+            IncidentStatus::Open,
+            IncidentStatus::Acknowledged,
+            IncidentStatus::Closed,
+        ] {
+            assert_eq!(
+                status.to_string().parse::<IncidentStatus>().unwrap(),
+                status
+            );
+        }
+
+        assert!("resolved".parse::<IncidentStatus>().is_err());
+    }
+}
@@ -9,7 +9,7 @@ use async_trait::async_trait;
 type PostgresName = String;
 
 use super::change::{Change, ChangeResult};
-use super::error::{ConfigurationError, DatabaseError, Error, RuntimeError};
+use super::error::{ConfigurationError, DatabaseError, Error, NotFoundError, RuntimeError};
 
 #[derive(Debug, Serialize, Deserialize, Clone, ToSql)]
 #[postgres(name = "attribute_descr")]
@@ -24,6 +24,7 @@ fn default_empty_string() -> String {
     String::new()
 }
 
+#[derive(Serialize)]
 pub struct AddAttributes {
     pub notification_store: NotificationStore,
     pub attributes: Vec<Attribute>,
@@ -121,6 +122,7 @@ impl fmt::Display for NotificationStore {
     }
 }
 
+#[derive(Serialize)]
 pub struct AddNotificationStore {
     pub notification_store: NotificationStore,
 }
@@ -153,6 +155,48 @@ impl Change for AddNotificationStore {
     }
 }
 
+#[derive(Serialize)]
+pub struct DeleteNotificationStore {
+    pub data_source: String,
+}
+
+impl fmt::Display for DeleteNotificationStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DeleteNotificationStore({})", &self.data_source)
+    }
+}
+
+#[async_trait]
+impl Change for DeleteNotificationStore {
+    async fn apply(&self, client: &mut Client) -> ChangeResult {
+        let query = concat!(
+            "SELECT notification_directory.delete_notification_store(notification_store) ",
+            "FROM notification_directory.notification_store ",
+            "JOIN directory.data_source ON data_source.id = notification_store.data_source_id ",
+            "WHERE data_source.name = $1"
+        );
+
+        let rows = client
+            .query(query, &[&self.data_source])
+            .await
+            .map_err(|e| {
+                DatabaseError::from_msg(format!("Error deleting notification store: {e}"))
+            })?;
+
+        if rows.is_empty() {
+            return Err(Error::NotFound(NotFoundError::from_msg(format!(
+                "No notification store found for data source '{}'",
+                &self.data_source
+            ))));
+        }
+
+        Ok(format!(
+            "Deleted notification store '{}'",
+            &self.data_source
+        ))
+    }
+}
+
 pub async fn load_notification_stores(conn: &mut Client) -> Result<Vec<NotificationStore>, Error> {
     let mut notification_stores: Vec<NotificationStore> = Vec::new();
 
@@ -186,19 +230,23 @@ pub async fn load_notification_stores(conn: &mut Client) -> Result<Vec<Notificat
 pub async fn load_notification_store(
     conn: &mut Client,
     data_source: &str,
-    entity_type: &str,
 ) -> Result<NotificationStore, Error> {
     let query = concat!(
-        "SELECT attribute_store.id ",
-        "FROM attribute_directory.attribute_store ",
-        "JOIN directory.data_source ON data_source.id = attribute_store.data_source_id ",
+        "SELECT notification_store.id ",
+        "FROM notification_directory.notification_store ",
+        "JOIN directory.data_source ON data_source.id = notification_store.data_source_id ",
         "WHERE data_source.name = $1"
     );
 
     let result = conn
-        .query_one(query, &[&data_source, &entity_type])
+        .query_opt(query, &[&data_source])
         .await
-        .map_err(|e| DatabaseError::from_msg(format!("Could not load attribute stores: {e}")))?;
+        .map_err(|e| DatabaseError::from_msg(format!("Could not load notification store: {e}")))?
+        .ok_or_else(|| {
+            NotFoundError::from_msg(format!(
+                "No notification store found for data source '{data_source}'"
+            ))
+        })?;
 
     let attributes = load_attributes(conn, result.get::<usize, i32>(0)).await;
 
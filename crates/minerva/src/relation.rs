@@ -9,7 +9,15 @@ use async_trait::async_trait;
 use crate::change::ChangeResult;
 
 use super::change::Change;
-use super::error::{ConfigurationError, DatabaseError, Error, RuntimeError};
+use super::error::{ConfigurationError, DatabaseError, Error, NotFoundError, RuntimeError};
+
+const RELATION_QUERY: &str = concat!(
+    "SELECT type.name::text, pg_get_viewdef(pg_class.oid, true) ",
+    "FROM relation_directory.type ",
+    "JOIN pg_class ON pg_class.relname = type.name ",
+    "JOIN pg_namespace ON pg_namespace.oid = pg_class.relnamespace ",
+    "WHERE pg_namespace.nspname = 'relation_def'"
+);
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Relation {
@@ -61,6 +69,7 @@ pub fn load_relation_from_file(path: &PathBuf) -> Result<Relation, Error> {
     }
 }
 
+#[derive(Serialize)]
 pub struct AddRelation {
     pub relation: Relation,
 }
@@ -139,3 +148,76 @@ impl From<Relation> for AddRelation {
         AddRelation { relation }
     }
 }
+
+/// Load the relations that are defined by a view in the database
+pub async fn load_relations(client: &mut Client) -> Result<Vec<Relation>, Error> {
+    let query = format!("{RELATION_QUERY} ORDER BY type.name");
+
+    let rows = client
+        .query(&query, &[])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error loading relations: {e}")))?;
+
+    Ok(rows
+        .iter()
+        .map(|row| Relation {
+            name: row.get(0),
+            query: row.get(1),
+        })
+        .collect())
+}
+
+pub async fn load_relation(client: &mut Client, name: &str) -> Result<Relation, Error> {
+    let query = format!("{RELATION_QUERY} AND type.name = $1");
+
+    let row = client
+        .query_opt(&query, &[&name])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error loading relation: {e}")))?
+        .ok_or_else(|| NotFoundError::from_msg(format!("No relation found with name '{name}'")))?;
+
+    Ok(Relation {
+        name: row.get(0),
+        query: row.get(1),
+    })
+}
+
+#[derive(Serialize)]
+pub struct DeleteRelation {
+    pub name: String,
+}
+
+impl fmt::Display for DeleteRelation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DeleteRelation({})", &self.name)
+    }
+}
+
+#[async_trait]
+impl Change for DeleteRelation {
+    async fn apply(&self, client: &mut Client) -> ChangeResult {
+        let query = "SELECT relation_directory.remove($1)";
+
+        let removed: Option<String> = client
+            .query_one(query, &[&self.name])
+            .await
+            .map_err(|e| DatabaseError::from_msg(format!("Error removing relation: {e}")))?
+            .get(0);
+
+        if removed.is_none() {
+            return Err(Error::NotFound(NotFoundError::from_msg(format!(
+                "No relation found with name '{}'",
+                &self.name
+            ))));
+        }
+
+        let query = format!("DROP VIEW IF EXISTS relation_def.\"{}\"", self.name);
+
+        client
+            .execute(&query, &[])
+            .await
+            .map_err(|e| DatabaseError::from_msg(format!("Error dropping relation view: {e}")))?;
+
+        Ok(format!("Deleted relation '{}'", &self.name))
+    }
+}
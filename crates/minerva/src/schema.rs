@@ -1,4 +1,8 @@
-use tokio_postgres::Client;
+use std::fmt;
+
+use tokio_postgres::{Client, GenericClient};
+
+use super::error::{DatabaseError, Error, RuntimeError};
 
 pub fn schema() -> &'static str {
     include_str!("schema.sql")
@@ -11,3 +15,132 @@ pub async fn create_schema<'a>(client: &'a mut Client) -> Result<(), String> {
 
     Ok(())
 }
+
+/// Version of a Minerva database schema as reported by `system.version()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SchemaVersion {
+    pub major: i16,
+    pub minor: i16,
+    pub patch: i16,
+}
+
+impl fmt::Display for SchemaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl SchemaVersion {
+    /// Schemas with the same major version are compatible
+    pub fn is_compatible_with(&self, other: &SchemaVersion) -> bool {
+        self.major == other.major
+    }
+}
+
+/// Version of the schema created by `create_schema`
+pub const SCHEMA_VERSION: SchemaVersion = SchemaVersion {
+    major: 6,
+    minor: 1,
+    patch: 0,
+};
+
+/// Scripts that upgrade a database from the preceding schema version to the listed version,
+/// in ascending order of version
+const UPGRADES: &[(SchemaVersion, &str)] = &[(
+    SchemaVersion {
+        major: 6,
+        minor: 1,
+        patch: 0,
+    },
+    include_str!("upgrade/6.1.0.sql"),
+)];
+
+pub async fn get_schema_version<T: GenericClient + Send + Sync>(
+    client: &T,
+) -> Result<SchemaVersion, Error> {
+    let row = client
+        .query_one("SELECT major, minor, patch FROM system.version()", &[])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Could not read schema version: {e}")))?;
+
+    Ok(SchemaVersion {
+        major: row.get(0),
+        minor: row.get(1),
+        patch: row.get(2),
+    })
+}
+
+/// Upgrade the schema of an existing database to `SCHEMA_VERSION` and return the versions
+/// that were applied. All upgrades are applied in one transaction.
+pub async fn upgrade_schema(client: &mut Client) -> Result<Vec<SchemaVersion>, Error> {
+    let current_version = get_schema_version(client).await?;
+
+    if !current_version.is_compatible_with(&SCHEMA_VERSION) {
+        return Err(Error::Runtime(RuntimeError::from_msg(format!(
+            "Cannot upgrade schema version {current_version} to {SCHEMA_VERSION}"
+        ))));
+    }
+
+    let transaction = client.transaction().await?;
+    let mut applied = Vec::new();
+
+    for (version, script) in UPGRADES {
+        if *version > current_version {
+            transaction.batch_execute(script).await.map_err(|e| {
+                DatabaseError::from_msg(format!("Could not upgrade schema to {version}: {e}"))
+            })?;
+
+            applied.push(*version);
+        }
+    }
+
+    transaction.commit().await?;
+
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schema_version_matches_schema() {
+        let version_query = format!(
+            "SELECT ({},{},{})::system.version_tuple;",
+            SCHEMA_VERSION.major, SCHEMA_VERSION.minor, SCHEMA_VERSION.patch
+        );
+
+        assert!(schema().contains(&version_query));
+    }
+
+    #[test]
+    fn upgrades_end_at_schema_version() {
+        assert!(UPGRADES.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+        let (last_version, _) = UPGRADES.last().unwrap();
+
+        assert_eq!(*last_version, SCHEMA_VERSION);
+
+        for (version, script) in UPGRADES {
+            let version_query = format!(
+                "SELECT ({},{},{})::system.version_tuple;",
+                version.major, version.minor, version.patch
+            );
+
+            assert!(script.contains(&version_query));
+        }
+    }
+
+    #[test]
+    fn compatible_versions() {
+        let version = |major, minor, patch| SchemaVersion {
+            major,
+            minor,
+            patch,
+        };
+
+        assert!(SCHEMA_VERSION.is_compatible_with(&version(6, 1, 3)));
+        assert!(!SCHEMA_VERSION.is_compatible_with(&version(5, 9, 0)));
+        assert_eq!(version(6, 1, 3).to_string(), "6.1.3");
+    }
+}
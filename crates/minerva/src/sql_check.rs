@@ -0,0 +1,490 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use postgres_protocol::escape::escape_identifier;
+use regex::{Captures, Regex};
+use serde_json::Value;
+use tokio_postgres::{types::Type, GenericClient};
+
+use super::change::GenericChange;
+use super::error::Error;
+use super::trend_materialization::TrendMaterialization;
+use super::trigger::{trigger_exists, AddTrigger, DeleteTrigger, Trigger};
+
+/// Schemas containing the trend tables and their partitions
+const TREND_SCHEMAS: [&str; 2] = ["trend", "trend_partition"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlCheckIssue {
+    /// The object could not be created or its SQL could not be planned or executed
+    Error { object: String, message: String },
+    /// The plan of the object's SQL contains a sequential scan on a trend table
+    SequentialScan { object: String, relation: String },
+}
+
+impl SqlCheckIssue {
+    pub fn is_error(&self) -> bool {
+        matches!(self, SqlCheckIssue::Error { .. })
+    }
+}
+
+impl fmt::Display for SqlCheckIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SqlCheckIssue::Error { object, message } => write!(f, "error in {object}: {message}"),
+            SqlCheckIssue::SequentialScan { object, relation } => {
+                write!(f, "sequential scan in {object} on {relation}")
+            }
+        }
+    }
+}
+
+/// Returns the schema qualified names of all trend relations that are read using a sequential
+/// scan in an `EXPLAIN (VERBOSE, FORMAT JSON)` plan, in order of appearance and without
+/// duplicates.
+pub fn find_sequential_scans(plan: &Value) -> Vec<String> {
+    let mut relations: Vec<String> = Vec::new();
+
+    collect_sequential_scans(plan, &mut relations);
+
+    relations
+}
+
+fn collect_sequential_scans(node: &Value, relations: &mut Vec<String>) {
+    match node {
+        Value::Array(items) => {
+            for item in items {
+                collect_sequential_scans(item, relations);
+            }
+        }
+        Value::Object(map) => {
+            if map.get("Node Type").and_then(Value::as_str) == Some("Seq Scan") {
+                let schema = map.get("Schema").and_then(Value::as_str).unwrap_or("");
+                let relation = map
+                    .get("Relation Name")
+                    .and_then(Value::as_str)
+                    .unwrap_or("");
+
+                if TREND_SCHEMAS.contains(&schema) {
+                    let name = format!("{}.{}", schema, escape_identifier(relation));
+
+                    if !relations.contains(&name) {
+                        relations.push(name);
+                    }
+                }
+            }
+
+            if let Some(plan) = map.get("Plan") {
+                collect_sequential_scans(plan, relations);
+            }
+
+            if let Some(plans) = map.get("Plans") {
+                collect_sequential_scans(plans, relations);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Extracts the query from a PL/pgSQL body of the form `RETURN QUERY EXECUTE $tag$...$tag$ USING
+/// ...`, which is the form used for function materializations and trigger KPI functions. The
+/// parameters of the dynamic query are replaced by the expressions of the `USING` clause, so that
+/// the resulting query only refers to the function argument `$1`.
+pub fn extract_dynamic_query(src: &str) -> Option<String> {
+    let upper = src.to_uppercase();
+    let execute_pos = upper.find("EXECUTE")?;
+    let rest = src[execute_pos + "EXECUTE".len()..].trim_start();
+
+    if !rest.starts_with('$') {
+        return None;
+    }
+
+    let tag_end = rest[1..].find('$')? + 2;
+    let tag = &rest[..tag_end];
+    let body = &rest[tag_end..];
+    let body_end = body.find(tag)?;
+    let query = body[..body_end].trim();
+
+    let after = body[body_end + tag.len()..].trim_start();
+
+    let using_args = if after.to_uppercase().starts_with("USING") {
+        let args = &after["USING".len()..];
+        let args = match args.find(';') {
+            Some(end) => &args[..end],
+            None => args,
+        };
+
+        split_arguments(args)
+    } else {
+        Vec::new()
+    };
+
+    let parameter_regex = Regex::new(r"\$(\d+)").unwrap();
+
+    let query = parameter_regex.replace_all(query, |captures: &Captures| {
+        let index: usize = captures[1].parse().unwrap_or(0);
+
+        match index.checked_sub(1).and_then(|i| using_args.get(i)) {
+            Some(arg) => format!("({arg})"),
+            None => captures[0].to_string(),
+        }
+    });
+
+    Some(query.to_string())
+}
+
+/// Split a list of SQL expressions on the commas that are not nested in parentheses or quotes
+fn split_arguments(args: &str) -> Vec<String> {
+    let mut result: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut depth: usize = 0;
+    let mut in_quotes = false;
+
+    for c in args.chars() {
+        match c {
+            '\'' => in_quotes = !in_quotes,
+            '(' if !in_quotes => depth += 1,
+            ')' if !in_quotes => depth = depth.saturating_sub(1),
+            ',' if !in_quotes && depth == 0 => {
+                result.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+
+        current.push(c);
+    }
+
+    if !current.trim().is_empty() {
+        result.push(current.trim().to_string());
+    }
+
+    result
+}
+
+fn strip_statement(sql: &str) -> &str {
+    sql.trim().trim_end_matches(';').trim_end()
+}
+
+/// Run a statement in a savepoint, turning a failure into an issue for the object
+async fn try_execute<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    object: &str,
+    statements: &[String],
+    issues: &mut Vec<SqlCheckIssue>,
+) -> Result<bool, Error> {
+    let savepoint = client.transaction().await?;
+
+    for statement in statements {
+        if let Err(e) = savepoint.batch_execute(statement).await {
+            issues.push(SqlCheckIssue::Error {
+                object: object.to_string(),
+                message: format_db_error(&e),
+            });
+
+            savepoint.rollback().await?;
+
+            return Ok(false);
+        }
+    }
+
+    savepoint.commit().await?;
+
+    Ok(true)
+}
+
+/// Plan a query taking the sample timestamp as its only parameter and report sequential scans
+async fn explain<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    object: &str,
+    query: &str,
+    timestamp: &DateTime<Utc>,
+    issues: &mut Vec<SqlCheckIssue>,
+) -> Result<(), Error> {
+    let savepoint = client.transaction().await?;
+
+    let explain_query = format!("EXPLAIN (VERBOSE, FORMAT JSON) {}", strip_statement(query));
+
+    let result = match savepoint
+        .prepare_typed(&explain_query, &[Type::TIMESTAMPTZ])
+        .await
+    {
+        Ok(statement) => savepoint.query_one(&statement, &[timestamp]).await,
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(row) => {
+            let plan: Value = row.get(0);
+
+            for relation in find_sequential_scans(&plan) {
+                issues.push(SqlCheckIssue::SequentialScan {
+                    object: object.to_string(),
+                    relation,
+                });
+            }
+        }
+        Err(e) => issues.push(SqlCheckIssue::Error {
+            object: object.to_string(),
+            message: format_db_error(&e),
+        }),
+    }
+
+    savepoint.rollback().await?;
+
+    Ok(())
+}
+
+fn format_db_error(e: &tokio_postgres::Error) -> String {
+    match e.as_db_error() {
+        Some(db_error) => match db_error.hint() {
+            Some(hint) => format!("{} ({})", db_error.message(), hint),
+            None => db_error.message().to_string(),
+        },
+        None => e.to_string(),
+    }
+}
+
+/// Create all database objects of a materialization and plan their queries for the sample
+/// timestamp. All changes are rolled back.
+pub async fn check_materialization_sql<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    materialization: &TrendMaterialization,
+    timestamp: &DateTime<Utc>,
+) -> Result<Vec<SqlCheckIssue>, Error> {
+    let mut issues: Vec<SqlCheckIssue> = Vec::new();
+    let mut transaction = client.transaction().await?;
+
+    let fingerprint_function_name = format!("{}_fingerprint", materialization.name());
+
+    match materialization {
+        TrendMaterialization::View(m) => {
+            let view_ident = format!(
+                "trend.{}",
+                escape_identifier(&format!("_{}", m.target_trend_store_part))
+            );
+
+            let created = try_execute(
+                &mut transaction,
+                "view",
+                &[
+                    format!("DROP VIEW IF EXISTS {view_ident} CASCADE"),
+                    format!("CREATE VIEW {} AS {}", view_ident, m.view),
+                ],
+                &mut issues,
+            )
+            .await?;
+
+            if created {
+                let query = format!("SELECT * FROM {view_ident} WHERE timestamp = $1");
+
+                explain(&mut transaction, "view", &query, timestamp, &mut issues).await?;
+            }
+        }
+        TrendMaterialization::Function(m) => {
+            let drop_query = format!(
+                "DROP FUNCTION IF EXISTS trend.{}(timestamp with time zone) CASCADE",
+                escape_identifier(&m.target_trend_store_part)
+            );
+
+            let mut savepoint = transaction.transaction().await?;
+            savepoint.batch_execute(&drop_query).await?;
+
+            let created = match m.create_function(&mut savepoint).await {
+                Ok(_) => {
+                    savepoint.commit().await?;
+                    true
+                }
+                Err(e) => {
+                    issues.push(SqlCheckIssue::Error {
+                        object: "function".to_string(),
+                        message: e.to_string(),
+                    });
+                    savepoint.rollback().await?;
+                    false
+                }
+            };
+
+            if created {
+                let query = if m.function.language.eq_ignore_ascii_case("sql") {
+                    m.function.src.clone()
+                } else {
+                    match extract_dynamic_query(&m.function.src) {
+                        Some(query) => query,
+                        None => format!(
+                            "SELECT * FROM trend.{}($1)",
+                            escape_identifier(&m.target_trend_store_part)
+                        ),
+                    }
+                };
+
+                explain(&mut transaction, "function", &query, timestamp, &mut issues).await?;
+            }
+        }
+    }
+
+    let drop_query = format!(
+        "DROP FUNCTION IF EXISTS trend.{}(timestamp with time zone) CASCADE",
+        escape_identifier(&fingerprint_function_name)
+    );
+
+    let mut savepoint = transaction.transaction().await?;
+    savepoint.batch_execute(&drop_query).await?;
+
+    let create_result = match materialization {
+        TrendMaterialization::View(m) => m.create_fingerprint_function(&mut savepoint).await,
+        TrendMaterialization::Function(m) => m.create_fingerprint_function(&mut savepoint).await,
+    };
+
+    match create_result {
+        Ok(_) => savepoint.commit().await?,
+        Err(e) => {
+            issues.push(SqlCheckIssue::Error {
+                object: "fingerprint function".to_string(),
+                message: e.to_string(),
+            });
+            savepoint.rollback().await?;
+        }
+    }
+
+    let fingerprint_src = match materialization {
+        TrendMaterialization::View(m) => &m.fingerprint_function,
+        TrendMaterialization::Function(m) => &m.fingerprint_function,
+    };
+
+    explain(
+        &mut transaction,
+        "fingerprint function",
+        fingerprint_src,
+        timestamp,
+        &mut issues,
+    )
+    .await?;
+
+    transaction.rollback().await?;
+
+    Ok(issues)
+}
+
+/// Create all database objects of a trigger, plan its KPI query and generate its notifications
+/// for the sample timestamp. All changes are rolled back.
+pub async fn check_trigger_sql<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    trigger: &Trigger,
+    timestamp: &DateTime<Utc>,
+) -> Result<Vec<SqlCheckIssue>, Error> {
+    let mut issues: Vec<SqlCheckIssue> = Vec::new();
+    let mut transaction = client.transaction().await?;
+
+    if trigger_exists(&trigger.name, &mut transaction).await? {
+        // The exception threshold functions depend on the exception threshold table, which
+        // prevents the rule cleanup from dropping it, so drop them together up front.
+        let query = format!(
+            "DROP TABLE IF EXISTS trigger_rule.{} CASCADE",
+            escape_identifier(&format!("{}_exception_threshold", trigger.name))
+        );
+
+        transaction.batch_execute(&query).await?;
+
+        DeleteTrigger {
+            trigger_name: trigger.name.clone(),
+        }
+        .generic_apply(&mut transaction)
+        .await?;
+    }
+
+    let create_result = AddTrigger {
+        trigger: trigger.clone(),
+        verify: false,
+        enable: false,
+    }
+    .generic_apply(&mut transaction)
+    .await;
+
+    if let Err(e) = create_result {
+        issues.push(SqlCheckIssue::Error {
+            object: "trigger".to_string(),
+            message: e.to_string(),
+        });
+
+        transaction.rollback().await?;
+
+        return Ok(issues);
+    }
+
+    if let Some(query) = extract_dynamic_query(&trigger.kpi_function) {
+        explain(
+            &mut transaction,
+            "KPI function",
+            &query,
+            timestamp,
+            &mut issues,
+        )
+        .await?;
+    }
+
+    // Generating the notifications evaluates the KPI function, condition, weight, notification
+    // and data expressions, which are only fully validated when executed.
+    let query = format!(
+        "SELECT count(*) FROM trigger_rule.{}('{}'::timestamptz)",
+        escape_identifier(&format!("{}_create_notification", trigger.name)),
+        timestamp.to_rfc3339()
+    );
+
+    try_execute(&mut transaction, "notification", &[query], &mut issues).await?;
+
+    transaction.rollback().await?;
+
+    Ok(issues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_dynamic_query() {
+        let src = "BEGIN\n    RETURN QUERY EXECUTE $query$\n    SELECT * FROM trend.\"a\" WHERE timestamp = $1\n    $query$ USING $1;\nEND;";
+
+        assert_eq!(
+            extract_dynamic_query(src),
+            Some("SELECT * FROM trend.\"a\" WHERE timestamp = ($1)".to_string())
+        );
+
+        assert_eq!(
+            extract_dynamic_query("BEGIN RETURN QUERY SELECT 1; END;"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_extract_dynamic_query_using() {
+        let src = "BEGIN\nRETURN QUERY EXECUTE $query$\n  SELECT $2 AS timestamp FROM t WHERE $1 < timestamp AND timestamp <= $2\n$query$ USING $1 - interval '1h', $1;\nEND;";
+
+        assert_eq!(
+            extract_dynamic_query(src),
+            Some("SELECT ($1) AS timestamp FROM t WHERE ($1 - interval '1h') < timestamp AND timestamp <= ($1)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_find_sequential_scans() {
+        let plan = serde_json::json!([{
+            "Plan": {
+                "Node Type": "Append",
+                "Plans": [
+                    {"Node Type": "Seq Scan", "Schema": "trend_partition", "Relation Name": "a_1"},
+                    {"Node Type": "Seq Scan", "Schema": "trend_partition", "Relation Name": "a_1"},
+                    {"Node Type": "Seq Scan", "Schema": "directory", "Relation Name": "entity_type"},
+                    {"Node Type": "Index Scan", "Schema": "trend_partition", "Relation Name": "b_1"}
+                ]
+            }
+        }]);
+
+        assert_eq!(
+            find_sequential_scans(&plan),
+            vec!["trend_partition.\"a_1\"".to_string()]
+        );
+    }
+}
@@ -0,0 +1,489 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use postgres_protocol::escape::escape_identifier;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::GenericClient;
+
+use super::error::{ConfigurationError, DatabaseError, Error, NotFoundError, RuntimeError};
+use super::granularity::Granularity;
+
+/// Selection of trend data
+///
+/// Trends are selected by name, by trend store part or both. Without trend names all trends of
+/// the trend store part are selected. Entity Ids and names are combined, without any entity all
+/// entities are selected. Rows are selected for timestamps from `start` up to but not including
+/// `end`.
+#[derive(Debug, Clone)]
+pub struct TrendDataQuery {
+    pub trend_store_part: Option<String>,
+    pub trends: Vec<String>,
+    pub entity_type: Option<String>,
+    pub granularity: Option<String>,
+    pub entity_ids: Vec<i32>,
+    pub entity_names: Vec<String>,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub offset: i64,
+    pub limit: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TrendDataRow {
+    pub entity_id: i32,
+    pub entity: Option<String>,
+    pub timestamp: DateTime<Utc>,
+    pub values: BTreeMap<String, Value>,
+}
+
+/// One page of trend data rows, ordered by timestamp and entity Id
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TrendDataPage {
+    pub entity_type: String,
+    pub granularity: String,
+    pub trend_store_parts: Vec<String>,
+    pub trends: Vec<String>,
+    pub rows: Vec<TrendDataRow>,
+    pub offset: i64,
+    pub limit: i64,
+    /// Offset of the next page, if there are more rows
+    pub next_offset: Option<i64>,
+}
+
+impl TrendDataPage {
+    /// Render the rows as CSV with a header, one column per trend
+    pub fn to_csv(&self) -> Result<String, Error> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+
+        let mut header = vec![
+            "entity_id".to_string(),
+            "entity".to_string(),
+            "timestamp".to_string(),
+        ];
+        header.extend(self.trends.iter().cloned());
+
+        let csv_error = |e: csv::Error| RuntimeError::from_msg(format!("Could not write CSV: {e}"));
+
+        writer.write_record(&header).map_err(csv_error)?;
+
+        for row in &self.rows {
+            let mut record = vec![
+                row.entity_id.to_string(),
+                row.entity.clone().unwrap_or_default(),
+                row.timestamp.to_rfc3339(),
+            ];
+
+            record.extend(self.trends.iter().map(|trend| match row.values.get(trend) {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(value)) => value.clone(),
+                Some(value) => value.to_string(),
+            }));
+
+            writer.write_record(&record).map_err(csv_error)?;
+        }
+
+        let data = writer
+            .into_inner()
+            .map_err(|e| RuntimeError::from_msg(format!("Could not write CSV: {e}")))?;
+
+        String::from_utf8(data)
+            .map_err(|e| Error::Runtime(RuntimeError::from_msg(format!("Invalid CSV data: {e}"))))
+    }
+}
+
+/// A trend as found in the trend store metadata
+struct TrendLocation {
+    trend_store_part: String,
+    trend: String,
+    entity_type: String,
+    granularity: String,
+}
+
+async fn find_trends<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    query: &TrendDataQuery,
+) -> Result<Vec<TrendLocation>, Error> {
+    let sql = concat!(
+        "SELECT tsp.name::text, t.name::text, et.name::text, ts.granularity::text ",
+        "FROM (",
+        "SELECT id, trend_store_part_id, name, false AS generated FROM trend_directory.table_trend ",
+        "UNION ALL ",
+        "SELECT id, trend_store_part_id, name, true FROM trend_directory.generated_table_trend",
+        ") t ",
+        "JOIN trend_directory.trend_store_part tsp ON tsp.id = t.trend_store_part_id ",
+        "JOIN trend_directory.trend_store ts ON ts.id = tsp.trend_store_id ",
+        "JOIN directory.entity_type et ON et.id = ts.entity_type_id ",
+        "WHERE ($1::text IS NULL OR tsp.name = $1) ",
+        "AND (cardinality($2::text[]) = 0 OR t.name = ANY($2)) ",
+        "AND ($3::text IS NULL OR et.name = $3) ",
+        "ORDER BY tsp.name, t.generated, t.id"
+    );
+
+    let rows = client
+        .query(
+            sql,
+            &[&query.trend_store_part, &query.trends, &query.entity_type],
+        )
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error loading trends: {e}")))?;
+
+    let granularity: Option<Granularity> = query
+        .granularity
+        .as_ref()
+        .map(|granularity| granularity.parse())
+        .transpose()
+        .map_err(|e: Error| {
+            ConfigurationError::from_msg(format!(
+                "Invalid granularity '{}': {}",
+                query.granularity.as_deref().unwrap_or_default(),
+                e
+            ))
+        })?;
+
+    let mut locations = Vec::new();
+
+    for row in rows {
+        let location = TrendLocation {
+            trend_store_part: row.get(0),
+            trend: row.get(1),
+            entity_type: row.get(2),
+            granularity: row.get(3),
+        };
+
+        if let Some(granularity) = &granularity {
+            // Trend stores with granularities that are not understood never match
+            match location.granularity.parse::<Granularity>() {
+                Ok(g) if g == *granularity => {}
+                _ => continue,
+            }
+        }
+
+        locations.push(location);
+    }
+
+    Ok(locations)
+}
+
+/// Select for each requested trend the trend store part to read it from
+///
+/// All trends must come from trend stores with the same entity type and granularity, a trend
+/// found in more than one combination of those has to be disambiguated by the caller.
+fn select_trends(
+    query: &TrendDataQuery,
+    locations: Vec<TrendLocation>,
+) -> Result<Vec<TrendLocation>, Error> {
+    if query.trends.is_empty() && query.trend_store_part.is_none() {
+        return Err(Error::Configuration(ConfigurationError::from_msg(
+            "Specify trends, a trend store part or both".to_string(),
+        )));
+    }
+
+    let mut selected: Vec<TrendLocation> = Vec::new();
+
+    if query.trends.is_empty() {
+        selected = locations;
+
+        if selected.is_empty() {
+            return Err(Error::NotFound(NotFoundError::from_msg(format!(
+                "No trends found for trend store part '{}'",
+                query.trend_store_part.as_deref().unwrap_or_default()
+            ))));
+        }
+    } else {
+        for trend in &query.trends {
+            let candidates: Vec<&TrendLocation> =
+                locations.iter().filter(|l| &l.trend == trend).collect();
+
+            let first = match candidates.first() {
+                Some(first) => *first,
+                None => {
+                    return Err(Error::NotFound(NotFoundError::from_msg(format!(
+                        "No trend found named '{trend}'"
+                    ))))
+                }
+            };
+
+            if candidates
+                .iter()
+                .any(|c| c.entity_type != first.entity_type || c.granularity != first.granularity)
+            {
+                let options: Vec<String> = candidates
+                    .iter()
+                    .map(|c| {
+                        format!(
+                            "{} ({}, {})",
+                            c.trend_store_part, c.entity_type, c.granularity
+                        )
+                    })
+                    .collect();
+
+                return Err(Error::Configuration(ConfigurationError::from_msg(format!(
+                    "Trend '{}' is ambiguous, specify entity type or granularity: {}",
+                    trend,
+                    options.join(", ")
+                ))));
+            }
+
+            if !selected.iter().any(|s| &s.trend == trend) {
+                selected.push(TrendLocation {
+                    trend_store_part: first.trend_store_part.clone(),
+                    trend: first.trend.clone(),
+                    entity_type: first.entity_type.clone(),
+                    granularity: first.granularity.clone(),
+                });
+            }
+        }
+    }
+
+    let first = &selected[0];
+
+    if let Some(other) = selected
+        .iter()
+        .find(|s| s.entity_type != first.entity_type || s.granularity != first.granularity)
+    {
+        return Err(Error::Configuration(ConfigurationError::from_msg(format!(
+            "Trends '{}' ({}, {}) and '{}' ({}, {}) have a different entity type or granularity",
+            first.trend,
+            first.entity_type,
+            first.granularity,
+            other.trend,
+            other.entity_type,
+            other.granularity
+        ))));
+    }
+
+    Ok(selected)
+}
+
+async fn resolve_entity_ids<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    entity_type: &str,
+    names: &[String],
+) -> Result<Vec<i32>, Error> {
+    let sql = format!(
+        "SELECT id, name FROM entity.{} WHERE name = ANY($1)",
+        escape_identifier(entity_type)
+    );
+
+    let rows = client
+        .query(&sql, &[&names])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Could not look up entities: {e}")))?;
+
+    let found: BTreeMap<String, i32> = rows.iter().map(|row| (row.get(1), row.get(0))).collect();
+
+    let unknown: Vec<&str> = names
+        .iter()
+        .filter(|name| !found.contains_key(*name))
+        .map(|name| name.as_str())
+        .collect();
+
+    if !unknown.is_empty() {
+        return Err(Error::NotFound(NotFoundError::from_msg(format!(
+            "No {} entities found named: {}",
+            entity_type,
+            unknown.join(", ")
+        ))));
+    }
+
+    Ok(found.into_values().collect())
+}
+
+/// Load a page of trend data
+///
+/// The trends are resolved using the trend store metadata. Trends from different trend store
+/// parts are combined into one row per entity and timestamp.
+pub async fn query_trend_data<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    query: &TrendDataQuery,
+) -> Result<TrendDataPage, Error> {
+    if query.limit < 1 {
+        return Err(Error::Configuration(ConfigurationError::from_msg(
+            "Limit must be at least 1".to_string(),
+        )));
+    }
+
+    if query.offset < 0 {
+        return Err(Error::Configuration(ConfigurationError::from_msg(
+            "Offset can not be negative".to_string(),
+        )));
+    }
+
+    let locations = find_trends(client, query).await?;
+    let selected = select_trends(query, locations)?;
+
+    let entity_type = selected[0].entity_type.clone();
+    let granularity = selected[0].granularity.clone();
+
+    let mut entity_ids = query.entity_ids.clone();
+
+    if !query.entity_names.is_empty() {
+        entity_ids.extend(resolve_entity_ids(client, &entity_type, &query.entity_names).await?);
+    }
+
+    let filter_entities = !query.entity_ids.is_empty() || !query.entity_names.is_empty();
+
+    let mut parts: Vec<String> = Vec::new();
+
+    for location in &selected {
+        if !parts.contains(&location.trend_store_part) {
+            parts.push(location.trend_store_part.clone());
+        }
+    }
+
+    let keys = parts
+        .iter()
+        .map(|part| {
+            format!(
+                concat!(
+                    "SELECT entity_id, timestamp FROM trend.{} ",
+                    "WHERE timestamp >= $1 AND timestamp < $2 ",
+                    "AND (NOT $3 OR entity_id = ANY($4))"
+                ),
+                escape_identifier(part)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(" UNION ");
+
+    let columns: String = selected
+        .iter()
+        .map(|location| {
+            let alias = parts
+                .iter()
+                .position(|part| part == &location.trend_store_part)
+                .unwrap();
+
+            format!(
+                ", to_jsonb(p{}.{})",
+                alias,
+                escape_identifier(&location.trend)
+            )
+        })
+        .collect();
+
+    let joins: String = parts
+        .iter()
+        .enumerate()
+        .map(|(index, part)| {
+            format!(
+                " LEFT JOIN trend.{} p{} ON p{}.entity_id = k.entity_id AND p{}.timestamp = k.timestamp",
+                escape_identifier(part),
+                index,
+                index,
+                index
+            )
+        })
+        .collect();
+
+    let sql = format!(
+        concat!(
+            "SELECT k.entity_id, e.name::text, k.timestamp{} ",
+            "FROM ({}) k{} ",
+            "LEFT JOIN entity.{} e ON e.id = k.entity_id ",
+            "ORDER BY k.timestamp, k.entity_id ",
+            "LIMIT $5 OFFSET $6"
+        ),
+        columns,
+        keys,
+        joins,
+        escape_identifier(&entity_type)
+    );
+
+    // One row more than requested tells whether there is a next page
+    let fetch_limit = query.limit + 1;
+
+    let params: &[&(dyn ToSql + Sync)] = &[
+        &query.start,
+        &query.end,
+        &filter_entities,
+        &entity_ids,
+        &fetch_limit,
+        &query.offset,
+    ];
+
+    let rows = client
+        .query(&sql, params)
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error loading trend data: {e}")))?;
+
+    let next_offset = if rows.len() as i64 > query.limit {
+        Some(query.offset + query.limit)
+    } else {
+        None
+    };
+
+    let rows = rows
+        .iter()
+        .take(query.limit as usize)
+        .map(|row| {
+            let values = selected
+                .iter()
+                .enumerate()
+                .map(|(index, location)| {
+                    let value: Option<Value> = row.try_get(3 + index)?;
+
+                    Ok((location.trend.clone(), value.unwrap_or(Value::Null)))
+                })
+                .collect::<Result<BTreeMap<String, Value>, Error>>()?;
+
+            Ok(TrendDataRow {
+                entity_id: row.try_get(0)?,
+                entity: row.try_get(1)?,
+                timestamp: row.try_get(2)?,
+                values,
+            })
+        })
+        .collect::<Result<Vec<TrendDataRow>, Error>>()?;
+
+    Ok(TrendDataPage {
+        entity_type,
+        granularity,
+        trend_store_parts: parts,
+        trends: selected
+            .into_iter()
+            .map(|location| location.trend)
+            .collect(),
+        rows,
+        offset: query.offset,
+        limit: query.limit,
+        next_offset,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_has_column_per_trend() {
+        let page = TrendDataPage {
+            entity_type: "node".to_string(),
+            granularity: "00:15:00".to_string(),
+            trend_store_parts: vec!["hub_node_main_15m".to_string()],
+            trends: vec!["power_kwh".to_string(), "comment".to_string()],
+            rows: vec![TrendDataRow {
+                entity_id: 1,
+                entity: Some("hillside14".to_string()),
+                timestamp: "2023-03-25T14:00:00Z".parse().unwrap(),
+                values: BTreeMap::from([
+                    ("power_kwh".to_string(), serde_json::json!(55.8)),
+                    ("comment".to_string(), serde_json::json!("high, again")),
+                ]),
+            }],
+            offset: 0,
+            limit: 10,
+            next_offset: None,
+        };
+
+        assert_eq!(
+            page.to_csv().unwrap(),
+            concat!(
+                "entity_id,entity,timestamp,power_kwh,comment\n",
+                "1,hillside14,2023-03-25T14:00:00+00:00,55.8,\"high, again\"\n"
+            )
+        );
+    }
+}
@@ -10,12 +10,13 @@ use std::time::Duration;
 use postgres_protocol::escape::escape_identifier;
 use tokio_postgres::{types::ToSql, types::Type, Client, GenericClient};
 
+use chrono::{DateTime, Utc};
 use humantime::format_duration;
 
 use async_trait::async_trait;
 
 use super::change::{Change, ChangeResult, GenericChange};
-use super::error::{DatabaseError, Error, RuntimeError};
+use super::error::{DatabaseError, Error, NotFoundError, RuntimeError};
 use super::interval::parse_interval;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -146,7 +147,7 @@ impl TrendViewMaterialization {
         format!("{}_fingerprint", self.target_trend_store_part)
     }
 
-    async fn create_fingerprint_function<T: GenericClient + Send + Sync>(
+    pub(crate) async fn create_fingerprint_function<T: GenericClient + Send + Sync>(
         &self,
         client: &mut T,
     ) -> Result<(), Error> {
@@ -300,6 +301,7 @@ impl TrendViewMaterialization {
     }
 }
 
+#[derive(Serialize)]
 pub struct UpdateTrendViewMaterializationAttributes {
     pub trend_view_materialization: TrendViewMaterialization,
 }
@@ -332,6 +334,7 @@ impl fmt::Display for UpdateTrendViewMaterializationAttributes {
     }
 }
 
+#[derive(Serialize)]
 pub struct UpdateView {
     pub trend_view_materialization: TrendViewMaterialization,
 }
@@ -430,7 +433,7 @@ impl TrendFunctionMaterialization {
         }
     }
 
-    async fn create_function<T: GenericClient + Send + Sync>(
+    pub(crate) async fn create_function<T: GenericClient + Send + Sync>(
         &self,
         client: &mut T,
     ) -> Result<(), Error> {
@@ -490,7 +493,7 @@ impl TrendFunctionMaterialization {
         format!("{}_fingerprint", self.target_trend_store_part)
     }
 
-    async fn create_fingerprint_function<T: GenericClient + Send + Sync>(
+    pub(crate) async fn create_fingerprint_function<T: GenericClient + Send + Sync>(
         &self,
         client: &mut T,
     ) -> Result<(), Error> {
@@ -1092,6 +1095,7 @@ pub async fn get_function_return_type<T: GenericClient + Send + Sync>(
     Some(format!("TABLE (\n{}\n)\n", columns_part))
 }
 
+#[derive(Serialize)]
 pub struct AddTrendMaterialization {
     pub trend_materialization: TrendMaterialization,
 }
@@ -1139,6 +1143,7 @@ impl From<TrendMaterialization> for AddTrendMaterialization {
     }
 }
 
+#[derive(Serialize)]
 pub struct UpdateTrendMaterialization {
     pub trend_materialization: TrendMaterialization,
 }
@@ -1178,6 +1183,155 @@ impl Change for UpdateTrendMaterialization {
     }
 }
 
+async fn set_materialization_enabled<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    name: &str,
+    enabled: bool,
+) -> Result<(), Error> {
+    let query = "UPDATE trend_directory.materialization SET enabled = $1 WHERE materialization::text = $2";
+
+    let count = client
+        .execute(query, &[&enabled, &name])
+        .await
+        .map_err(|e| {
+            DatabaseError::from_msg(format!(
+                "Error setting enabled state of materialization '{name}': {e}"
+            ))
+        })?;
+
+    if count == 0 {
+        return Err(Error::NotFound(NotFoundError::from_msg(format!(
+            "No materialization found matching name '{name}'"
+        ))));
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct EnableTrendMaterialization {
+    pub name: String,
+}
+
+impl fmt::Display for EnableTrendMaterialization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EnableTrendMaterialization({})", &self.name)
+    }
+}
+
+#[async_trait]
+impl GenericChange for EnableTrendMaterialization {
+    async fn generic_apply<T: GenericClient + Send + Sync>(&self, client: &mut T) -> ChangeResult {
+        let mut transaction = client.transaction().await?;
+
+        set_materialization_enabled(&mut transaction, &self.name, true).await?;
+
+        let query = concat!(
+            "DELETE FROM trend_directory.materialization_disable md ",
+            "USING trend_directory.materialization m ",
+            "WHERE md.materialization_id = m.id AND m::text = $1"
+        );
+
+        transaction
+            .execute(query, &[&self.name])
+            .await
+            .map_err(|e| {
+                DatabaseError::from_msg(format!(
+                    "Error removing disable record of materialization '{}': {e}",
+                    &self.name
+                ))
+            })?;
+
+        transaction.commit().await?;
+
+        Ok(format!("Enabled materialization '{}'", &self.name))
+    }
+}
+
+#[async_trait]
+impl Change for EnableTrendMaterialization {
+    async fn apply(&self, client: &mut Client) -> ChangeResult {
+        self.generic_apply(client).await
+    }
+}
+
+/// Disable a materialization, optionally recording why and until when
+///
+/// When `expires` is set, the materialization is enabled again by
+/// [`enable_expired_materializations`] once that moment has passed. This is also done by
+/// `trend_directory.materialize` on every run of any materialization.
+#[derive(Serialize)]
+pub struct DisableTrendMaterialization {
+    pub name: String,
+    pub reason: Option<String>,
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl fmt::Display for DisableTrendMaterialization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DisableTrendMaterialization({})", &self.name)
+    }
+}
+
+#[async_trait]
+impl GenericChange for DisableTrendMaterialization {
+    async fn generic_apply<T: GenericClient + Send + Sync>(&self, client: &mut T) -> ChangeResult {
+        let mut transaction = client.transaction().await?;
+
+        set_materialization_enabled(&mut transaction, &self.name, false).await?;
+
+        let query = concat!(
+            "INSERT INTO trend_directory.materialization_disable(materialization_id, reason, disabled_at, expires) ",
+            "SELECT m.id, $2, now(), $3 FROM trend_directory.materialization m WHERE m::text = $1 ",
+            "ON CONFLICT (materialization_id) DO UPDATE ",
+            "SET reason = EXCLUDED.reason, disabled_at = EXCLUDED.disabled_at, expires = EXCLUDED.expires"
+        );
+
+        transaction
+            .execute(query, &[&self.name, &self.reason, &self.expires])
+            .await
+            .map_err(|e| {
+                DatabaseError::from_msg(format!(
+                    "Error recording disable of materialization '{}': {e}",
+                    &self.name
+                ))
+            })?;
+
+        transaction.commit().await?;
+
+        let message = match &self.expires {
+            Some(expires) => format!(
+                "Disabled materialization '{}' until {}",
+                &self.name,
+                expires.to_rfc3339()
+            ),
+            None => format!("Disabled materialization '{}'", &self.name),
+        };
+
+        Ok(message)
+    }
+}
+
+#[async_trait]
+impl Change for DisableTrendMaterialization {
+    async fn apply(&self, client: &mut Client) -> ChangeResult {
+        self.generic_apply(client).await
+    }
+}
+
+/// Enable all materializations of which the disable period has expired and return their names
+pub async fn enable_expired_materializations<T: GenericClient + Send + Sync>(
+    client: &mut T,
+) -> Result<Vec<String>, Error> {
+    let query = "SELECT name FROM trend_directory.enable_expired_materializations() AS name";
+
+    let rows = client.query(query, &[]).await.map_err(|e| {
+        DatabaseError::from_msg(format!("Error enabling expired materializations: {e}"))
+    })?;
+
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
 pub async fn populate_source_fingerprint<T: GenericClient + Send + Sync>(
     client: &mut T,
     materialization: &str,
@@ -1252,3 +1406,146 @@ pub async fn reset_source_fingerprint<T: GenericClient + Send + Sync>(
 
     Ok(())
 }
+
+/// Execution metrics of a materialization
+///
+/// The totals come from `trend_directory.materialization_metrics` and cover the full lifetime of
+/// the materialization. The other values only cover the requested time window and are derived
+/// from the logged materialization jobs and the materialization state.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MaterializationMetrics {
+    pub materialization: String,
+    pub enabled: bool,
+    pub total_execution_count: i32,
+    #[serde(with = "humantime_serde")]
+    pub total_duration: Duration,
+    pub execution_count: i64,
+    #[serde(with = "humantime_serde")]
+    pub duration: Duration,
+    #[serde(with = "humantime_serde")]
+    pub average_duration: Duration,
+    #[serde(with = "humantime_serde")]
+    pub max_duration: Duration,
+    /// Number of rows in the target trend store part that were produced by the jobs in the window
+    /// and have not been replaced by a later materialization yet
+    pub rows: i64,
+    /// Number of timestamps in the window that are due for materialization but not processed.
+    /// A failed materialization rolls back its job, so this is where failures show up.
+    pub failures: i64,
+}
+
+fn duration_from_seconds(seconds: f64) -> Duration {
+    Duration::from_secs_f64(seconds.max(0.0))
+}
+
+/// Load the execution metrics of all materializations for the window [from, to)
+pub async fn load_materialization_metrics<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    from: &DateTime<Utc>,
+    to: &DateTime<Utc>,
+) -> Result<Vec<MaterializationMetrics>, Error> {
+    let query = concat!(
+        "SELECT m::text, m.enabled, ",
+        "coalesce(mm.execution_count, 0), ",
+        "extract(epoch FROM coalesce(mm.total_duration, '0s'))::float8, ",
+        "count(j.id), ",
+        "extract(epoch FROM coalesce(sum(j.finished - j.started), '0s'))::float8, ",
+        "extract(epoch FROM coalesce(max(j.finished - j.started), '0s'))::float8, ",
+        "coalesce(array_agg(j.id) FILTER (WHERE j.id IS NOT NULL), '{}'), ",
+        "coalesce(array_agg(DISTINCT (j.action->>'timestamp')::timestamptz) FILTER (WHERE j.id IS NOT NULL), '{}'), ",
+        "(",
+        "SELECT count(*) FROM trend_directory.materialization_state ms ",
+        "WHERE ms.materialization_id = m.id ",
+        "AND ms.timestamp >= $1 AND ms.timestamp < $2 ",
+        "AND ms.source_fingerprint IS DISTINCT FROM ms.processed_fingerprint ",
+        "AND ms.timestamp < now() - m.processing_delay ",
+        "AND ms.max_modified < now() - m.stability_delay",
+        ") ",
+        "FROM trend_directory.materialization m ",
+        "LEFT JOIN trend_directory.materialization_metrics mm ON mm.materialization_id = m.id ",
+        "LEFT JOIN logging.job j ON j.started >= $1 AND j.started < $2 AND j.finished IS NOT NULL ",
+        "AND coalesce(j.action->>'view_materialization', j.action->>'function_materialization') = m::text ",
+        "GROUP BY m.id, mm.execution_count, mm.total_duration ",
+        "ORDER BY m::text"
+    );
+
+    let rows = client.query(query, &[from, to]).await.map_err(|e| {
+        DatabaseError::from_msg(format!("Error loading materialization metrics: {e}"))
+    })?;
+
+    let mut metrics: Vec<MaterializationMetrics> = Vec::new();
+
+    // The rows produced by the jobs are counted for all materializations at once, in a single
+    // query with one subquery per target table
+    let mut row_count_queries: Vec<String> = Vec::new();
+    let mut job_filters: Vec<(Vec<DateTime<Utc>>, Vec<i64>)> = Vec::new();
+
+    for row in rows {
+        let materialization: String = row.get(0);
+        let execution_count: i64 = row.get(4);
+        let duration = duration_from_seconds(row.get(5));
+        let job_ids: Vec<i64> = row.get(7);
+        let timestamps: Vec<DateTime<Utc>> = row.get(8);
+
+        let average_duration = if execution_count > 0 {
+            duration / (execution_count as u32)
+        } else {
+            Duration::ZERO
+        };
+
+        // The target trend store part of a materialization has the same name as the
+        // materialization itself.
+        if !job_ids.is_empty() {
+            row_count_queries.push(format!(
+                "SELECT {}::integer, count(*) FROM trend.{} WHERE timestamp = ANY(${}) AND job_id = ANY(${})",
+                metrics.len(),
+                escape_identifier(&materialization),
+                2 * job_filters.len() + 1,
+                2 * job_filters.len() + 2,
+            ));
+
+            job_filters.push((timestamps, job_ids));
+        }
+
+        metrics.push(MaterializationMetrics {
+            materialization,
+            enabled: row.get(1),
+            total_execution_count: row.get(2),
+            total_duration: duration_from_seconds(row.get(3)),
+            execution_count,
+            duration,
+            average_duration,
+            max_duration: duration_from_seconds(row.get(6)),
+            rows: 0,
+            failures: row.get(9),
+        });
+    }
+
+    if !row_count_queries.is_empty() {
+        let query = row_count_queries.join(" UNION ALL ");
+
+        let query_args: Vec<&(dyn ToSql + Sync)> = job_filters
+            .iter()
+            .flat_map(|(timestamps, job_ids)| {
+                [
+                    timestamps as &(dyn ToSql + Sync),
+                    job_ids as &(dyn ToSql + Sync),
+                ]
+            })
+            .collect();
+
+        let rows = client.query(&query, &query_args).await.map_err(|e| {
+            DatabaseError::from_msg(format!(
+                "Error counting rows produced by materializations: {e}"
+            ))
+        })?;
+
+        for row in rows {
+            let index: i32 = row.get(0);
+
+            metrics[index as usize].rows = row.get(1);
+        }
+    }
+
+    Ok(metrics)
+}
@@ -18,7 +18,7 @@ use tokio_postgres::{binary_copy::BinaryCopyInWriter, Client, GenericClient, Row
 
 use lazy_static::lazy_static;
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeZone, Utc};
 use rust_decimal::prelude::*;
 use rust_decimal::Decimal;
 
@@ -34,6 +34,7 @@ use crate::meas_value::{
 
 use super::change::Change;
 use super::error::{ConfigurationError, DatabaseError, Error, RuntimeError};
+use super::granularity::Granularity;
 use super::interval::parse_interval;
 
 type PostgresName = String;
@@ -1497,6 +1498,43 @@ pub async fn create_partitions_for_trend_store(
     Ok(())
 }
 
+/// Return the start of the partition that contains the timestamp
+///
+/// Partitions are consecutive multiples of the partition size since the Unix epoch, as
+/// calculated by `trend_directory.timestamp_to_index`. Partition sizes of up to a day coincide
+/// with the UTC periods of Granularity; weeks, months and other intervals do not, so these fall
+/// back to plain epoch arithmetic.
+pub fn partition_start(
+    partition_size: Duration,
+    timestamp: &DateTime<Utc>,
+) -> Result<DateTime<Utc>, Error> {
+    match Granularity::try_from(partition_size) {
+        Ok(granularity @ (Granularity::Fixed(_) | Granularity::Day)) => {
+            granularity.truncate(timestamp)
+        }
+        _ => {
+            let size = partition_size.as_secs() as i64;
+
+            if size == 0 {
+                return Err(Error::Runtime(RuntimeError::from_msg(format!(
+                    "Invalid partition size: {}",
+                    format_duration(partition_size)
+                ))));
+            }
+
+            let epoch = timestamp.timestamp();
+
+            Utc.timestamp_opt(epoch - epoch.rem_euclid(size), 0)
+                .single()
+                .ok_or_else(|| {
+                    Error::Runtime(RuntimeError::from_msg(format!(
+                        "Partition start of {timestamp} out of range"
+                    )))
+                })
+        }
+    }
+}
+
 pub async fn create_partitions_for_trend_store_and_timestamp(
     client: &mut Client,
     trend_store_id: i32,
@@ -1672,4 +1710,21 @@ mod tests {
             _ => assert!(false),
         }
     }
+
+    #[test]
+    fn partition_starts() {
+        let timestamp: DateTime<Utc> = "2023-03-25T14:35:00Z".parse().unwrap();
+        let start = |partition_size: &str| {
+            partition_start(parse_interval(partition_size).unwrap(), &timestamp)
+                .unwrap()
+                .to_rfc3339()
+        };
+
+        assert_eq!(start("1h"), "2023-03-25T14:00:00+00:00");
+        assert_eq!(start("1d"), "2023-03-25T00:00:00+00:00");
+        // 4 day partitions start at multiples of 4 days since the epoch
+        assert_eq!(start("4d"), "2023-03-24T00:00:00+00:00");
+        // Week partitions start on Thursday, like the epoch
+        assert_eq!(start("7d"), "2023-03-23T00:00:00+00:00");
+    }
 }
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fmt;
 use std::path::PathBuf;
 use std::str::FromStr;
@@ -6,7 +7,7 @@ use std::time::Duration;
 use postgres_types::ToSql;
 use serde::{Deserialize, Serialize};
 
-use chrono::{DateTime, TimeZone, Timelike};
+use chrono::{DateTime, TimeZone, Utc};
 use postgres_protocol::escape::{escape_identifier, escape_literal};
 use tokio_postgres::{Client, GenericClient, Row};
 
@@ -15,8 +16,13 @@ use async_trait::async_trait;
 use crate::interval::parse_interval;
 
 use super::change::{Change, ChangeResult, GenericChange};
-use super::error::{ConfigurationError, DatabaseError, Error, RuntimeError};
+use super::error::{ConfigurationError, DatabaseError, Error, NotFoundError, RuntimeError};
+use super::granularity::Granularity;
+use super::notification_incident::update_incidents;
 use super::notification_store::notification_store_exists;
+use super::trigger_exception::{
+    add_threshold_exception, load_threshold_exceptions, ThresholdException,
+};
 
 type PostgresName = String;
 
@@ -63,6 +69,8 @@ pub struct Trigger {
     pub description: String,
     #[serde(with = "humantime_serde")]
     pub granularity: Duration,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exceptions: Vec<ThresholdException>,
 }
 
 impl fmt::Display for Trigger {
@@ -119,6 +127,7 @@ pub async fn list_triggers(
     triggers
 }
 
+#[derive(Serialize)]
 pub struct AddTrigger {
     pub trigger: Trigger,
     pub verify: bool,
@@ -166,6 +175,8 @@ impl GenericChange for AddTrigger {
 
         link_trend_stores(&self.trigger, &mut transaction).await?;
 
+        add_exceptions(&self.trigger, &[], &mut transaction).await?;
+
         set_description(&self.trigger, &mut transaction).await?;
 
         set_enabled(&mut transaction, &self.trigger.name, self.enable).await?;
@@ -274,7 +285,7 @@ async fn create_kpi_function<T: GenericClient + Sync + Send>(
     let type_name = format!("{}_kpi", &trigger.name);
 
     let query = format!(
-        "CREATE FUNCTION trigger_rule.{}(timestamp with time zone) RETURNS SETOF trigger_rule.{} AS $trigger${}$trigger$ LANGUAGE plpgsql STABLE;",
+        "CREATE OR REPLACE FUNCTION trigger_rule.{}(timestamp with time zone) RETURNS SETOF trigger_rule.{} AS $trigger${}$trigger$ LANGUAGE plpgsql STABLE;",
         &escape_identifier(&function_name),
         &escape_identifier(&type_name),
         &trigger.kpi_function,
@@ -575,6 +586,70 @@ async fn set_description<T: GenericClient + Sync + Send>(
     ))
 }
 
+/// Load the threshold exceptions of a trigger that is about to be rebuilt, so that they can be
+/// restored afterwards.
+async fn load_existing_exceptions<T: GenericClient + Sync + Send>(
+    trigger_name: &str,
+    client: &mut T,
+) -> Result<Vec<ThresholdException>, Error> {
+    if load_trend_store_links(client, trigger_name).await?.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    Ok(load_threshold_exceptions(client, trigger_name)
+        .await?
+        .into_iter()
+        .map(|record| record.exception)
+        .collect())
+}
+
+/// Add the exceptions of the trigger definition, and restore previously existing exceptions for
+/// entities that are not in the definition. Overrides of thresholds that no longer exist are
+/// dropped.
+async fn add_exceptions<T: GenericClient + Sync + Send>(
+    trigger: &Trigger,
+    existing_exceptions: &[ThresholdException],
+    client: &mut T,
+) -> ChangeResult {
+    let restored_exceptions = existing_exceptions
+        .iter()
+        .filter(|existing| {
+            !trigger
+                .exceptions
+                .iter()
+                .any(|exception| exception.entity == existing.entity)
+        })
+        .map(|existing| {
+            let mut exception = existing.clone();
+
+            exception.thresholds.retain(|name, _| {
+                trigger
+                    .thresholds
+                    .iter()
+                    .any(|threshold| &threshold.name == name)
+            });
+
+            exception
+        });
+
+    let exceptions: Vec<ThresholdException> = trigger
+        .exceptions
+        .iter()
+        .cloned()
+        .chain(restored_exceptions)
+        .collect();
+
+    for exception in exceptions.iter() {
+        add_threshold_exception(client, &trigger.name, exception).await?;
+    }
+
+    Ok(format!(
+        "Added {} threshold exception(s) for trigger '{}'",
+        exceptions.len(),
+        &trigger.name
+    ))
+}
+
 async fn set_enabled<T: GenericClient + Sync + Send>(
     client: &mut T,
     trigger_name: &str,
@@ -596,77 +671,7 @@ async fn set_enabled<T: GenericClient + Sync + Send>(
     ))
 }
 
-/// Truncate a reference timestamp to the nearest timestamp for a specified granularity.
-fn truncate_timestamp_for_granularity<Tz>(
-    granularity: Duration,
-    ref_timestamp: &DateTime<Tz>,
-) -> Result<DateTime<Tz>, Error>
-where
-    Tz: chrono::TimeZone,
-{
-    let granularity_text: String = humantime::format_duration(granularity).to_string();
-
-    match granularity_text.as_str() {
-        "15m" => {
-            let date = ref_timestamp.date_naive();
-
-            let gran_minutes: u32 = 15;
-
-            let time = ref_timestamp.time();
-
-            let remainder = time.minute() % gran_minutes;
-
-            let minutes = time.minute() - remainder;
-
-            let timestamp = date
-                .and_hms_opt(ref_timestamp.time().hour(), minutes, 0)
-                .unwrap()
-                .and_local_timezone(ref_timestamp.timezone())
-                .unwrap();
-
-            Ok(timestamp)
-        }
-        "1h" => {
-            let date = ref_timestamp.date_naive();
-
-            let timestamp = date
-                .and_hms_opt(ref_timestamp.time().hour(), 0, 0)
-                .unwrap()
-                .and_local_timezone(ref_timestamp.timezone())
-                .unwrap();
-
-            Ok(timestamp)
-        }
-        "1day" => {
-            let date = ref_timestamp.date_naive();
-
-            let timestamp = date
-                .and_hms_opt(0, 0, 0)
-                .unwrap()
-                .and_local_timezone(ref_timestamp.timezone())
-                .unwrap();
-
-            Ok(timestamp)
-        }
-        "7days" => {
-            let date = ref_timestamp.date_naive();
-
-            let timestamp = date
-                .and_hms_opt(0, 0, 0)
-                .unwrap()
-                .and_local_timezone(ref_timestamp.timezone())
-                .unwrap();
-
-            Ok(timestamp)
-        }
-        _ => Err(Error::Runtime(RuntimeError::from_msg(format!(
-            "Unsupported granularity: {}",
-            &granularity_text
-        )))),
-    }
-}
-
-async fn trigger_exists<T: GenericClient + Sync + Send>(
+pub(crate) async fn trigger_exists<T: GenericClient + Sync + Send>(
     trigger_name: &str,
     client: &mut T,
 ) -> Result<bool, Error> {
@@ -695,7 +700,7 @@ async fn run_checks<T: GenericClient + Sync + Send>(
     let reference_timestamp = chrono::offset::Local::now();
 
     let check_timestamp =
-        truncate_timestamp_for_granularity(trigger.granularity, &reference_timestamp)?;
+        Granularity::try_from(trigger.granularity)?.truncate(&reference_timestamp)?;
 
     client
         .execute(&query, &[&check_timestamp])
@@ -731,6 +736,7 @@ impl Change for AddTrigger {
     }
 }
 
+#[derive(Serialize)]
 pub struct DeleteTrigger {
     pub trigger_name: String,
 }
@@ -757,7 +763,7 @@ impl GenericChange for DeleteTrigger {
         let count: i64 = row.get(0);
 
         if count == 0 {
-            return Err(Error::Runtime(RuntimeError::from_msg(format!(
+            return Err(Error::NotFound(NotFoundError::from_msg(format!(
                 "No trigger found matching name '{}'",
                 &self.trigger_name
             ))));
@@ -817,6 +823,7 @@ pub fn load_trigger_from_file(path: &PathBuf) -> Result<Trigger, Error> {
     }
 }
 
+#[derive(Serialize)]
 pub struct UpdateTrigger {
     pub trigger: Trigger,
     pub verify: bool,
@@ -833,6 +840,8 @@ impl GenericChange for UpdateTrigger {
     async fn generic_apply<T: GenericClient + Sync + Send>(&self, client: &mut T) -> ChangeResult {
         let mut transaction = client.transaction().await?;
 
+        let existing_exceptions = load_existing_exceptions(&self.trigger.name, &mut transaction).await?;
+
         // Tear down
         drop_notification_data_function(&self.trigger, &mut transaction).await?;
 
@@ -862,6 +871,8 @@ impl GenericChange for UpdateTrigger {
 
         link_trend_stores(&self.trigger, &mut transaction).await?;
 
+        add_exceptions(&self.trigger, &existing_exceptions, &mut transaction).await?;
+
         set_description(&self.trigger, &mut transaction).await?;
 
         let mut check_result: String = "No check has run".to_string();
@@ -888,6 +899,7 @@ impl Change for UpdateTrigger {
     }
 }
 
+#[derive(Serialize)]
 pub struct RenameTrigger {
     pub trigger: Trigger,
     pub verify: bool,
@@ -914,12 +926,14 @@ impl GenericChange for RenameTrigger {
         let mut transaction = client.transaction().await?;
 
         if !trigger_exists(&self.old_name, &mut transaction).await? {
-            return Err(Error::Runtime(RuntimeError::from_msg(format!(
+            return Err(Error::NotFound(NotFoundError::from_msg(format!(
                 "No trigger with name '{}'",
                 &self.old_name
             ))));
         }
 
+        let existing_exceptions = load_existing_exceptions(&self.old_name, &mut transaction).await?;
+
         let mut old_trigger = self.trigger.clone();
 
         old_trigger.name = self.old_name.clone();
@@ -957,6 +971,8 @@ impl GenericChange for RenameTrigger {
 
         link_trend_stores(&self.trigger, &mut transaction).await?;
 
+        add_exceptions(&self.trigger, &existing_exceptions, &mut transaction).await?;
+
         let mut check_result: String = "No check has run".to_string();
 
         if self.verify {
@@ -987,6 +1003,7 @@ impl Change for RenameTrigger {
     }
 }
 
+#[derive(Serialize)]
 pub struct VerifyTrigger {
     pub trigger_name: String,
 }
@@ -1015,6 +1032,7 @@ impl Change for VerifyTrigger {
     }
 }
 
+#[derive(Serialize)]
 pub struct EnableTrigger {
     pub trigger_name: String,
 }
@@ -1045,6 +1063,7 @@ impl Change for EnableTrigger {
     }
 }
 
+#[derive(Serialize)]
 pub struct DisableTrigger {
     pub trigger_name: String,
 }
@@ -1075,6 +1094,376 @@ impl Change for DisableTrigger {
     }
 }
 
+async fn set_tags<T: GenericClient + Sync + Send>(
+    trigger: &Trigger,
+    client: &mut T,
+) -> ChangeResult {
+    let query = "SELECT tag FROM unnest($1::text[]) tag WHERE NOT EXISTS (SELECT 1 FROM directory.tag WHERE tag.name = tag)";
+
+    let rows = client
+        .query(query, &[&trigger.tags])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error checking tags: {e}")))?;
+
+    if !rows.is_empty() {
+        let missing: Vec<String> = rows.iter().map(|row| row.get(0)).collect();
+
+        return Err(Error::Configuration(ConfigurationError::from_msg(format!(
+            "No tags found named: {}",
+            missing.join(", ")
+        ))));
+    }
+
+    let query = "DELETE FROM trigger.rule_tag_link USING trigger.rule WHERE rule_id = rule.id AND rule.name = $1";
+
+    client
+        .execute(query, &[&trigger.name])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error unlinking tags: {e}")))?;
+
+    let query = concat!(
+        "INSERT INTO trigger.rule_tag_link(rule_id, tag_id) ",
+        "SELECT rule.id, tag.id FROM trigger.rule, directory.tag ",
+        "WHERE rule.name = $1 AND tag.name = ANY($2) ",
+        "ON CONFLICT DO NOTHING"
+    );
+
+    client
+        .execute(query, &[&trigger.name, &trigger.tags])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error linking tags: {e}")))?;
+
+    Ok(format!("Set tags for trigger '{}'", &trigger.name))
+}
+
+/// A difference between two versions of a trigger definition
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum TriggerDifference {
+    KpiData,
+    KpiFunction,
+    /// Thresholds were added, removed or changed data type
+    ThresholdDefinitions,
+    ThresholdValues,
+    Condition,
+    Weight,
+    NotificationMessage,
+    NotificationData,
+    Tags,
+    TrendStoreLinks,
+    Granularity,
+    NotificationStore,
+    Description,
+}
+
+impl TriggerDifference {
+    /// Return true if the difference can only be applied by rebuilding the whole trigger
+    pub fn requires_rebuild(&self) -> bool {
+        matches!(
+            self,
+            TriggerDifference::KpiData
+                | TriggerDifference::ThresholdDefinitions
+                | TriggerDifference::TrendStoreLinks
+                | TriggerDifference::Granularity
+                | TriggerDifference::NotificationStore
+        )
+    }
+}
+
+impl fmt::Display for TriggerDifference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TriggerDifference::KpiData => "kpi_data",
+            TriggerDifference::KpiFunction => "kpi_function",
+            TriggerDifference::ThresholdDefinitions => "threshold definitions",
+            TriggerDifference::ThresholdValues => "threshold values",
+            TriggerDifference::Condition => "condition",
+            TriggerDifference::Weight => "weight",
+            TriggerDifference::NotificationMessage => "notification",
+            TriggerDifference::NotificationData => "data",
+            TriggerDifference::Tags => "tags",
+            TriggerDifference::TrendStoreLinks => "trend_store_links",
+            TriggerDifference::Granularity => "granularity",
+            TriggerDifference::NotificationStore => "notification_store",
+            TriggerDifference::Description => "description",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// Evaluate a trigger for the timestamps without creating notifications
+///
+/// Returns a row per notification that the trigger would create, with the entity name (or Id
+/// when the entity no longer exists), timestamp, weight, details and data of the notification.
+pub(crate) async fn evaluate_trigger<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    trigger_name: &str,
+    entity_type: &str,
+    timestamps: &[DateTime<Utc>],
+) -> Result<Vec<Row>, Error> {
+    let query = format!(
+        concat!(
+            "SELECT coalesce(e.name, n.entity_id::text), n.timestamp, n.weight, n.details, n.data::jsonb ",
+            "FROM unnest($1::timestamptz[]) ts, LATERAL trigger_rule.{}(ts) n ",
+            "LEFT JOIN entity.{} e ON e.id = n.entity_id ",
+            "WHERE n.data IS NOT NULL"
+        ),
+        escape_identifier(&format!("{trigger_name}_create_notification")),
+        escape_identifier(entity_type),
+    );
+
+    let rows = client.query(&query, &[&timestamps]).await.map_err(|e| {
+        DatabaseError::from_msg(format!("Error evaluating trigger '{trigger_name}': {e}"))
+    })?;
+
+    Ok(rows)
+}
+
+/// Map PostgreSQL type aliases to the internal type names as found in the catalog
+pub(crate) fn normalize_data_type(data_type: &str) -> String {
+    let data_type = data_type.trim().to_lowercase();
+
+    match data_type.as_str() {
+        "integer" | "int" => "int4",
+        "smallint" => "int2",
+        "bigint" => "int8",
+        "real" => "float4",
+        "double precision" => "float8",
+        "boolean" => "bool",
+        "character varying" => "varchar",
+        "timestamp with time zone" => "timestamptz",
+        "timestamp without time zone" | "timestamp" => "timestamp",
+        data_type => data_type,
+    }
+    .to_string()
+}
+
+/// Normalize a threshold value expression, so that a quoted literal matches the value as text
+fn normalize_threshold_value(value: &str) -> String {
+    let value = value.trim();
+
+    if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+        value[1..value.len() - 1].replace("''", "'")
+    } else {
+        value.to_string()
+    }
+}
+
+fn threshold_values_equal(a: &str, b: &str) -> bool {
+    let (a, b) = (normalize_threshold_value(a), normalize_threshold_value(b));
+
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn sorted<T: Ord + Clone>(items: impl Iterator<Item = T>) -> Vec<T> {
+    let mut items: Vec<T> = items.collect();
+
+    items.sort();
+
+    items
+}
+
+impl Trigger {
+    /// Return the differences between this trigger and another version of its definition
+    ///
+    /// SQL sources are compared ignoring surrounding whitespace. Mapping functions and threshold
+    /// exceptions are not compared, because they are not part of a trigger loaded from the
+    /// database or are managed separately.
+    pub fn differences(&self, other: &Trigger) -> Vec<TriggerDifference> {
+        let mut differences = Vec::new();
+
+        let kpi_data = |trigger: &Trigger| -> Vec<(String, String)> {
+            trigger
+                .kpi_data
+                .iter()
+                .map(|column| (column.name.clone(), normalize_data_type(&column.data_type)))
+                .collect()
+        };
+
+        if kpi_data(self) != kpi_data(other) {
+            differences.push(TriggerDifference::KpiData);
+        }
+
+        if self.kpi_function.trim() != other.kpi_function.trim() {
+            differences.push(TriggerDifference::KpiFunction);
+        }
+
+        let threshold_definitions = |trigger: &Trigger| -> Vec<(String, String)> {
+            sorted(trigger.thresholds.iter().map(|threshold| {
+                (
+                    threshold.name.clone(),
+                    normalize_data_type(&threshold.data_type),
+                )
+            }))
+        };
+
+        if threshold_definitions(self) != threshold_definitions(other) {
+            differences.push(TriggerDifference::ThresholdDefinitions);
+        } else if self.thresholds.iter().any(|threshold| {
+            other
+                .thresholds
+                .iter()
+                .find(|other_threshold| other_threshold.name == threshold.name)
+                .map_or(true, |other_threshold| {
+                    !threshold_values_equal(&threshold.value, &other_threshold.value)
+                })
+        }) {
+            differences.push(TriggerDifference::ThresholdValues);
+        }
+
+        if self.condition.trim() != other.condition.trim() {
+            differences.push(TriggerDifference::Condition);
+        }
+
+        if self.weight.trim() != other.weight.trim() {
+            differences.push(TriggerDifference::Weight);
+        }
+
+        if self.notification.trim() != other.notification.trim() {
+            differences.push(TriggerDifference::NotificationMessage);
+        }
+
+        if self.data.trim() != other.data.trim() {
+            differences.push(TriggerDifference::NotificationData);
+        }
+
+        if sorted(self.tags.iter()) != sorted(other.tags.iter()) {
+            differences.push(TriggerDifference::Tags);
+        }
+
+        let links = |trigger: &Trigger| -> Vec<(String, String)> {
+            sorted(
+                trigger
+                    .trend_store_links
+                    .iter()
+                    .map(|link| (link.part_name.clone(), link.mapping_function.clone())),
+            )
+        };
+
+        if links(self) != links(other) {
+            differences.push(TriggerDifference::TrendStoreLinks);
+        }
+
+        if self.granularity != other.granularity {
+            differences.push(TriggerDifference::Granularity);
+        }
+
+        if self.notification_store != other.notification_store {
+            differences.push(TriggerDifference::NotificationStore);
+        }
+
+        if self.description.trim() != other.description.trim() {
+            differences.push(TriggerDifference::Description);
+        }
+
+        differences
+    }
+
+    /// Return the changes required to update this trigger to the other definition
+    ///
+    /// When any of the differences affects the structure of the trigger, a full rebuild using
+    /// `UpdateTrigger` is returned. Otherwise only the changed parts are updated.
+    pub fn diff(&self, other: &Trigger) -> Vec<Box<dyn Change + Send>> {
+        let differences = self.differences(other);
+
+        if differences.is_empty() {
+            Vec::new()
+        } else if differences.iter().any(TriggerDifference::requires_rebuild) {
+            vec![Box::new(UpdateTrigger {
+                trigger: other.clone(),
+                verify: false,
+            })]
+        } else {
+            vec![Box::new(ModifyTrigger {
+                trigger: other.clone(),
+                differences,
+            })]
+        }
+    }
+}
+
+/// Update only the parts of a trigger that differ, without rebuilding it
+#[derive(Serialize)]
+pub struct ModifyTrigger {
+    pub trigger: Trigger,
+    pub differences: Vec<TriggerDifference>,
+}
+
+impl fmt::Display for ModifyTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ModifyTrigger({}, {})",
+            &self.trigger.name,
+            self.differences
+                .iter()
+                .map(|difference| difference.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        )
+    }
+}
+
+#[async_trait]
+impl GenericChange for ModifyTrigger {
+    async fn generic_apply<T: GenericClient + Sync + Send>(&self, client: &mut T) -> ChangeResult {
+        let mut transaction = client.transaction().await?;
+
+        for difference in &self.differences {
+            match difference {
+                TriggerDifference::KpiFunction => {
+                    create_kpi_function(&self.trigger, &mut transaction).await?
+                }
+                TriggerDifference::ThresholdValues => {
+                    set_thresholds(&self.trigger, &mut transaction).await?
+                }
+                TriggerDifference::Condition => {
+                    set_condition(&self.trigger, &mut transaction).await?
+                }
+                TriggerDifference::Weight => set_weight(&self.trigger, &mut transaction).await?,
+                TriggerDifference::NotificationMessage => {
+                    define_notification_message(&self.trigger, &mut transaction).await?
+                }
+                TriggerDifference::NotificationData => {
+                    define_notification_data(&self.trigger, &mut transaction).await?
+                }
+                TriggerDifference::Tags => set_tags(&self.trigger, &mut transaction).await?,
+                TriggerDifference::Description => {
+                    set_description(&self.trigger, &mut transaction).await?
+                }
+                difference => {
+                    return Err(Error::Runtime(RuntimeError::from_msg(format!(
+                        "Changing the {} of trigger '{}' requires a full update",
+                        difference, &self.trigger.name
+                    ))))
+                }
+            };
+        }
+
+        transaction.commit().await?;
+
+        Ok(format!(
+            "Updated {} of trigger '{}'",
+            self.differences
+                .iter()
+                .map(|difference| difference.to_string())
+                .collect::<Vec<String>>()
+                .join(", "),
+            &self.trigger.name
+        ))
+    }
+}
+
+#[async_trait]
+impl Change for ModifyTrigger {
+    async fn apply(&self, client: &mut Client) -> ChangeResult {
+        self.generic_apply(client).await
+    }
+}
+
 fn extract_rule_from_src(src: &str) -> Result<String, Error> {
     let condition_regex = regex::Regex::from_str(r".*\(\$1\) WHERE ((?s).*);[ ]*$").unwrap();
 
@@ -1094,7 +1483,7 @@ fn extract_rule_from_src(src: &str) -> Result<String, Error> {
 
 #[cfg(test)]
 mod tests {
-    use super::extract_rule_from_src;
+    use super::*;
 
     #[test]
     fn test_rule_extraction_single_line() {
@@ -1118,6 +1507,154 @@ mod tests {
         "packet_drop_amount" > "packet_drop_amount_min""#
         );
     }
+
+    fn trigger() -> Trigger {
+        Trigger {
+            name: "node/15m/highpowerusage".to_string(),
+            kpi_data: vec![KPIDataColumn {
+                name: "power_kwh".to_string(),
+                data_type: "numeric".to_string(),
+            }],
+            kpi_function: "BEGIN RETURN; END;".to_string(),
+            thresholds: vec![Threshold {
+                name: "max_power".to_string(),
+                data_type: "numeric".to_string(),
+                value: "0.05".to_string(),
+            }],
+            condition: "power_kwh > max_power".to_string(),
+            weight: "SELECT 1".to_string(),
+            notification: "SELECT 'HighPowerUsage'".to_string(),
+            tags: vec!["online".to_string()],
+            fingerprint: "SELECT now()::text".to_string(),
+            notification_store: "trigger-notification".to_string(),
+            data: "SELECT '{}'::json".to_string(),
+            trend_store_links: vec![TrendStoreLink {
+                part_name: "hub_node_main_15m".to_string(),
+                mapping_function: "mapping_id".to_string(),
+            }],
+            mapping_functions: Vec::new(),
+            description: String::new(),
+            granularity: Duration::from_secs(900),
+            exceptions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn differences_of_expressions() {
+        let current = trigger();
+        let mut other = trigger();
+
+        other.thresholds[0].value = "0.050".to_string();
+        other.weight = "SELECT 1\n".to_string();
+        other.kpi_data[0].data_type = "NUMERIC".to_string();
+        assert!(current.differences(&other).is_empty());
+        assert!(current.diff(&other).is_empty());
+
+        other.thresholds[0].value = "0.1".to_string();
+        other.condition = "power_kwh >= max_power".to_string();
+
+        assert_eq!(
+            current.differences(&other),
+            vec![
+                TriggerDifference::ThresholdValues,
+                TriggerDifference::Condition
+            ]
+        );
+
+        let changes = current.diff(&other);
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(
+            changes[0].to_string(),
+            "ModifyTrigger(node/15m/highpowerusage, threshold values, condition)"
+        );
+    }
+
+    #[test]
+    fn structural_differences_require_rebuild() {
+        let current = trigger();
+        let mut other = trigger();
+
+        other.kpi_data[0].data_type = "integer".to_string();
+        other.tags = Vec::new();
+
+        assert_eq!(
+            current.differences(&other),
+            vec![TriggerDifference::KpiData, TriggerDifference::Tags]
+        );
+        assert_eq!(
+            current.diff(&other)[0].to_string(),
+            "UpdateTrigger(Trigger(node/15m/highpowerusage))"
+        );
+    }
+
+    #[test]
+    fn unwrap_generated_sources() {
+        assert_eq!(
+            unwrap_function_source(
+                "\nSELECT (SELECT 'HighPowerUsage')::text\n",
+                NOTIFICATION_MESSAGE_WRAPPER
+            ),
+            "SELECT 'HighPowerUsage'"
+        );
+        assert_eq!(
+            unwrap_function_source(
+                "\nDECLARE\n  data json;\nBEGIN\nSELECT (SELECT '{}'::json) INTO data;\nRETURN data;\nEND;\n",
+                NOTIFICATION_DATA_WRAPPER
+            ),
+            "SELECT '{}'::json"
+        );
+        assert_eq!(
+            unwrap_function_source("SELECT 1", WEIGHT_WRAPPER),
+            "SELECT 1"
+        );
+        assert_eq!(
+            mapping_function_name("trend.mapping_id(timestamp with time zone)"),
+            "mapping_id"
+        );
+        assert_eq!(
+            mapping_function_name("trend.\"mapping-15m\"(timestamp with time zone)"),
+            "mapping-15m"
+        );
+    }
+}
+
+/// The SQL around the weight expression in the generated weight function
+const WEIGHT_WRAPPER: (&str, &str) = ("SELECT (", ")");
+/// The SQL around the notification expression in the generated message function
+const NOTIFICATION_MESSAGE_WRAPPER: (&str, &str) = ("SELECT (", ")::text");
+/// The SQL around the data expression in the generated data function
+const NOTIFICATION_DATA_WRAPPER: (&str, &str) = (
+    "DECLARE\n  data json;\nBEGIN\nSELECT (",
+    ") INTO data;\nRETURN data;\nEND;",
+);
+
+/// Extract the original expression from the source of a function generated from it, or return
+/// the source unchanged if it was not generated in the expected way.
+fn unwrap_function_source(src: &str, (prefix, suffix): (&str, &str)) -> String {
+    let trimmed = src.trim();
+
+    match trimmed
+        .strip_prefix(prefix)
+        .and_then(|rest| rest.strip_suffix(suffix))
+    {
+        Some(expression) => expression.to_string(),
+        None => src.to_string(),
+    }
+}
+
+/// Convert a mapping function as shown by `regprocedure` to the function name as used in trigger
+/// definitions, e.g. `trend.mapping_id(timestamp with time zone)` to `mapping_id`.
+fn mapping_function_name(regprocedure: &str) -> String {
+    let name = regprocedure
+        .strip_suffix("(timestamp with time zone)")
+        .unwrap_or(regprocedure);
+    let name = name.strip_prefix("trend.").unwrap_or(name);
+
+    match name.strip_prefix('"').and_then(|n| n.strip_suffix('"')) {
+        Some(quoted) => quoted.replace("\"\"", "\""),
+        None => name.to_string(),
+    }
 }
 
 pub async fn load_trigger<T: GenericClient + Send + Sync>(
@@ -1132,9 +1669,10 @@ pub async fn load_trigger<T: GenericClient + Send + Sync>(
     );
 
     let row = conn
-        .query_one(query, &[&String::from(name)])
+        .query_opt(query, &[&String::from(name)])
         .await
-        .map_err(|e| DatabaseError::from_msg(format!("Could not load trigger: {e}")))?;
+        .map_err(|e| DatabaseError::from_msg(format!("Could not load trigger: {e}")))?
+        .ok_or_else(|| NotFoundError::from_msg(format!("No trigger with name '{name}'")))?;
 
     let granularity_str: String = row.try_get(1)?;
 
@@ -1160,6 +1698,11 @@ pub async fn load_trigger<T: GenericClient + Send + Sync>(
     )
     .await?;
 
+    let notification = unwrap_function_source(
+        &notification_function_source,
+        NOTIFICATION_MESSAGE_WRAPPER,
+    );
+
     let data_function_source = load_function_src(
         conn,
         "trigger_rule",
@@ -1167,6 +1710,8 @@ pub async fn load_trigger<T: GenericClient + Send + Sync>(
     )
     .await?;
 
+    let data = unwrap_function_source(&data_function_source, NOTIFICATION_DATA_WRAPPER);
+
     let condition_function_source = load_function_src(conn, "trigger_rule", name).await?;
 
     let condition = extract_rule_from_src(&condition_function_source)?;
@@ -1174,6 +1719,8 @@ pub async fn load_trigger<T: GenericClient + Send + Sync>(
     let weight_function_source =
         load_function_src(conn, "trigger_rule", &format!("{}_weight", &name)).await?;
 
+    let weight = unwrap_function_source(&weight_function_source, WEIGHT_WRAPPER);
+
     let thresholds = load_thresholds(conn, name).await?;
 
     let tags = load_tags(conn, name).await?;
@@ -1183,22 +1730,25 @@ pub async fn load_trigger<T: GenericClient + Send + Sync>(
 
     let trend_store_links = load_trend_store_links(conn, name).await?;
 
+    let exceptions = load_existing_exceptions(name, conn).await?;
+
     Ok(Trigger {
         name: String::from(name),
         condition,
-        data: data_function_source,
+        data,
         fingerprint: fingerprint_function_source,
         granularity,
         kpi_data: kpi_data_columns,
         kpi_function: kpi_function_source,
         mapping_functions: Vec::<MappingFunction>::new(),
-        notification: notification_function_source,
+        notification,
         notification_store: notification_store.unwrap_or("UNDEFINED".into()),
         tags,
         thresholds,
         trend_store_links,
-        weight: weight_function_source,
+        weight,
         description: description.unwrap_or("".to_string()),
+        exceptions,
     })
 }
 
@@ -1255,6 +1805,8 @@ where
     Ok(notifications)
 }
 
+#[derive(Serialize)]
+#[serde(bound = "")]
 pub struct CreateNotifications<Tz: TimeZone> {
     pub trigger_name: String,
     pub timestamp: Option<DateTime<Tz>>,
@@ -1280,6 +1832,15 @@ where
             create_notifications(&mut transaction, &self.trigger_name, self.timestamp.clone())
                 .await?;
 
+        if let Some(timestamp) = &self.timestamp {
+            update_incidents(
+                &mut transaction,
+                &self.trigger_name,
+                &timestamp.with_timezone(&Utc),
+            )
+            .await?;
+        }
+
         transaction.commit().await?;
 
         Ok(message)
@@ -1352,7 +1913,8 @@ async fn load_kpi_data_columns<T: GenericClient + Send + Sync>(
         "select attname, typname ",
         "from pg_class c join pg_attribute a on attrelid = c.oid ",
         "join pg_type t on t.oid = atttypid ",
-        "where relname = $1 and attname not in ('timestamp', 'entity_id')"
+        "where relname = $1 and attname not in ('timestamp', 'entity_id') ",
+        "order by attnum"
     );
 
     let rows = conn
@@ -1415,7 +1977,7 @@ async fn load_trend_store_links<T: GenericClient + Send + Sync>(
         .iter()
         .map(|row| TrendStoreLink {
             part_name: row.get(0),
-            mapping_function: row.get(1),
+            mapping_function: mapping_function_name(row.get(1)),
         })
         .collect();
 
@@ -1537,6 +2099,96 @@ pub async fn load_thresholds_with_client(
     Ok(thresholds)
 }
 
+/// Load the thresholds of multiple triggers with one query for the columns of all threshold
+/// views and one query for their values.
+pub async fn load_thresholds_for_triggers<T: GenericClient + Send + Sync>(
+    conn: &mut T,
+    trigger_names: &[String],
+) -> Result<HashMap<String, Vec<Threshold>>, Error> {
+    let mut thresholds: HashMap<String, Vec<Threshold>> = HashMap::new();
+
+    if trigger_names.is_empty() {
+        return Ok(thresholds);
+    }
+
+    let view_names: Vec<String> = trigger_names
+        .iter()
+        .map(|name| format!("{name}_threshold"))
+        .collect();
+
+    let query = concat!(
+        "SELECT c.relname::text, attname::text, typname::text ",
+        "FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace ",
+        "JOIN pg_attribute a ON attrelid = c.oid ",
+        "JOIN pg_type t ON t.oid = atttypid ",
+        "WHERE n.nspname = 'trigger_rule' AND c.relname = ANY($1) AND attnum > 0 AND NOT attisdropped ",
+        "ORDER BY c.relname, attnum"
+    );
+
+    let rows = conn
+        .query(query, &[&view_names])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Could not load threshold columns: {e}")))?;
+
+    let mut columns: HashMap<String, Vec<(String, String)>> = HashMap::new();
+
+    for row in &rows {
+        columns
+            .entry(row.get(0))
+            .or_default()
+            .push((row.get(1), row.get(2)));
+    }
+
+    let values_query = view_names
+        .iter()
+        .enumerate()
+        .map(|(index, view_name)| {
+            format!(
+                "SELECT {index}, ARRAY[{}]::text[] FROM trigger_rule.{}",
+                columns
+                    .get(view_name)
+                    .map(|view_columns| view_columns
+                        .iter()
+                        .map(|(name, _)| format!("{}::text", escape_identifier(name)))
+                        .collect::<Vec<String>>()
+                        .join(","))
+                    .unwrap_or_default(),
+                escape_identifier(view_name),
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(" UNION ALL ");
+
+    let values_rows = conn
+        .query(&values_query, &[])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Could not load threshold values: {e}")))?;
+
+    for row in values_rows {
+        let index: i32 = row.get(0);
+        let values: Vec<Option<String>> = row.get(1);
+        let trigger_name = &trigger_names[index as usize];
+        let view_columns = columns
+            .remove(&view_names[index as usize])
+            .unwrap_or_default();
+
+        thresholds.insert(
+            trigger_name.clone(),
+            view_columns
+                .into_iter()
+                .zip(values)
+                .map(|((name, data_type), value)| Threshold {
+                    name,
+                    data_type,
+                    value: value.unwrap_or_default(),
+                })
+                .collect(),
+        );
+    }
+
+    Ok(thresholds)
+}
+
 pub fn dump_trigger(trigger: &Trigger) -> String {
     serde_json::to_string_pretty(trigger).unwrap()
 }
@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use postgres_protocol::escape::escape_literal;
+use tokio_postgres::{Client, GenericClient};
+
+use super::error::{ConfigurationError, Error};
+use super::granularity::Granularity;
+use super::trigger::{evaluate_trigger, load_trigger, normalize_data_type, set_thresholds};
+use super::trigger_exception::trigger_entity_type;
+
+/// A notification that the trigger would have created
+#[derive(Debug, Clone, PartialEq)]
+pub struct BacktestNotification {
+    pub entity: String,
+    pub timestamp: DateTime<Utc>,
+    pub weight: i32,
+}
+
+/// Notification counts of one evaluation of a trigger over a range of timestamps
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BacktestReport {
+    /// Number of notifications per evaluated timestamp, including timestamps without any
+    pub per_timestamp: BTreeMap<DateTime<Utc>, usize>,
+    pub per_entity: BTreeMap<String, usize>,
+    /// Number of notifications per weight
+    pub weights: BTreeMap<i32, usize>,
+    pub total: usize,
+}
+
+impl BacktestReport {
+    pub fn from_notifications(
+        timestamps: &[DateTime<Utc>],
+        notifications: &[BacktestNotification],
+    ) -> BacktestReport {
+        let mut report = BacktestReport {
+            per_timestamp: timestamps.iter().map(|timestamp| (*timestamp, 0)).collect(),
+            ..Default::default()
+        };
+
+        for notification in notifications {
+            *report
+                .per_timestamp
+                .entry(notification.timestamp)
+                .or_default() += 1;
+            *report
+                .per_entity
+                .entry(notification.entity.clone())
+                .or_default() += 1;
+            *report.weights.entry(notification.weight).or_default() += 1;
+            report.total += 1;
+        }
+
+        report
+    }
+}
+
+/// Result of a backtest, with the evaluation using the proposed thresholds if any were given
+#[derive(Debug, Clone)]
+pub struct BacktestResult {
+    pub timestamps: Vec<DateTime<Utc>>,
+    pub current: BacktestReport,
+    pub proposed: Option<BacktestReport>,
+}
+
+async fn evaluate_notifications<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    trigger_name: &str,
+    entity_type: &str,
+    timestamps: &[DateTime<Utc>],
+) -> Result<Vec<BacktestNotification>, Error> {
+    let rows = evaluate_trigger(client, trigger_name, entity_type, timestamps).await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| BacktestNotification {
+            entity: row.get(0),
+            timestamp: row.get(1),
+            weight: row.get(2),
+        })
+        .collect())
+}
+
+/// SQL expression for a proposed threshold value of the threshold's data type
+///
+/// The type is not quoted as an identifier, because types like 'double precision' are not
+/// single identifiers.
+fn threshold_value_sql(value: &str, data_type: &str) -> String {
+    format!(
+        "{}::{}",
+        escape_literal(value),
+        normalize_data_type(data_type)
+    )
+}
+
+/// Evaluate a trigger for every timestamp in a range without creating notifications
+///
+/// When proposed threshold values are given, the trigger is evaluated a second time with those
+/// values. The thresholds are only changed inside a transaction that is always rolled back.
+pub async fn backtest_trigger(
+    client: &mut Client,
+    trigger_name: &str,
+    from: &DateTime<Utc>,
+    to: &DateTime<Utc>,
+    proposed_thresholds: &BTreeMap<String, String>,
+) -> Result<BacktestResult, Error> {
+    let mut transaction = client.transaction().await?;
+
+    let mut trigger = load_trigger(&mut transaction, trigger_name).await?;
+    let entity_type = trigger_entity_type(&mut transaction, trigger_name).await?;
+
+    let timestamps = Granularity::try_from(trigger.granularity)?.range(from, to)?;
+
+    let notifications =
+        evaluate_notifications(&mut transaction, trigger_name, &entity_type, &timestamps).await?;
+    let current = BacktestReport::from_notifications(&timestamps, &notifications);
+
+    let proposed = if proposed_thresholds.is_empty() {
+        None
+    } else {
+        for (name, value) in proposed_thresholds {
+            let threshold = trigger
+                .thresholds
+                .iter_mut()
+                .find(|threshold| &threshold.name == name)
+                .ok_or_else(|| {
+                    ConfigurationError::from_msg(format!(
+                        "Trigger '{trigger_name}' has no threshold named '{name}'"
+                    ))
+                })?;
+
+            threshold.value = threshold_value_sql(value, &threshold.data_type);
+        }
+
+        set_thresholds(&trigger, &mut transaction).await?;
+
+        let notifications =
+            evaluate_notifications(&mut transaction, trigger_name, &entity_type, &timestamps)
+                .await?;
+
+        Some(BacktestReport::from_notifications(
+            &timestamps,
+            &notifications,
+        ))
+    };
+
+    transaction.rollback().await?;
+
+    Ok(BacktestResult {
+        timestamps,
+        current,
+        proposed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn report_from_notifications() {
+        let t1 = Utc.with_ymd_and_hms(2023, 3, 25, 13, 0, 0).unwrap();
+        let t2 = Utc.with_ymd_and_hms(2023, 3, 25, 14, 0, 0).unwrap();
+
+        let notifications = vec![
+            BacktestNotification {
+                entity: "hillside14".to_string(),
+                timestamp: t2,
+                weight: 100,
+            },
+            BacktestNotification {
+                entity: "hillside15".to_string(),
+                timestamp: t2,
+                weight: 100,
+            },
+            BacktestNotification {
+                entity: "hillside14".to_string(),
+                timestamp: t2,
+                weight: 50,
+            },
+        ];
+
+        let report = BacktestReport::from_notifications(&[t1, t2], &notifications);
+
+        assert_eq!(report.total, 3);
+        assert_eq!(report.per_timestamp.get(&t1), Some(&0));
+        assert_eq!(report.per_timestamp.get(&t2), Some(&3));
+        assert_eq!(report.per_entity.get("hillside14"), Some(&2));
+        assert_eq!(report.weights.get(&100), Some(&2));
+        assert_eq!(report.weights.get(&50), Some(&1));
+    }
+
+    #[test]
+    fn threshold_values() {
+        assert_eq!(
+            threshold_value_sql("0.75", "double precision"),
+            "'0.75'::float8"
+        );
+        assert_eq!(threshold_value_sql("high", "text"), "'high'::text");
+        assert_eq!(
+            threshold_value_sql("1 day", "interval"),
+            "'1 day'::interval"
+        );
+        assert_eq!(
+            threshold_value_sql("it's", "character varying"),
+            "'it''s'::varchar"
+        );
+    }
+}
@@ -0,0 +1,339 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use postgres_protocol::escape::escape_identifier;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::{types::ToSql, Client, GenericClient};
+
+use super::change::{Change, ChangeResult, GenericChange};
+use super::error::{ConfigurationError, DatabaseError, Error, NotFoundError};
+use super::trigger::load_thresholds;
+
+/// Per-entity override of the threshold values of a trigger
+///
+/// Thresholds that are not overridden keep their global value. Without a start or expiry, the
+/// exception applies indefinitely in that direction.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ThresholdException {
+    pub entity: String,
+    pub thresholds: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remark: Option<String>,
+}
+
+/// A threshold exception as stored for a trigger
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ThresholdExceptionRecord {
+    pub id: i32,
+    pub created: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    pub exception: ThresholdException,
+}
+
+fn exception_table_name(trigger_name: &str) -> String {
+    format!("{trigger_name}_exception_threshold")
+}
+
+/// Return the name of the entity type of the trend store parts the trigger is linked to
+pub(crate) async fn trigger_entity_type<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    trigger_name: &str,
+) -> Result<String, Error> {
+    let query = concat!(
+        "SELECT et.name ",
+        "FROM trigger.rule r ",
+        "JOIN trigger.rule_trend_store_link l ON l.rule_id = r.id ",
+        "JOIN trend_directory.trend_store_part tsp ON tsp.id = l.trend_store_part_id ",
+        "JOIN trend_directory.trend_store ts ON ts.id = tsp.trend_store_id ",
+        "JOIN directory.entity_type et ON et.id = ts.entity_type_id ",
+        "WHERE r.name = $1 ",
+        "LIMIT 1"
+    );
+
+    let rows = client.query(query, &[&trigger_name]).await.map_err(|e| {
+        DatabaseError::from_msg(format!(
+            "Could not determine entity type of trigger '{trigger_name}': {e}"
+        ))
+    })?;
+
+    match rows.first() {
+        Some(row) => Ok(row.get(0)),
+        None => Err(Error::NotFound(NotFoundError::from_msg(format!(
+            "No trigger with linked trend store found named '{trigger_name}'"
+        )))),
+    }
+}
+
+/// Look up the names of entities of the entity type of a trigger by their Ids
+///
+/// Triggers without linked trend store have no known entity type and yield no names.
+pub(crate) async fn trigger_entity_names<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    trigger_name: &str,
+    entity_ids: &[i32],
+) -> Result<BTreeMap<i32, String>, Error> {
+    let entity_type = match trigger_entity_type(client, trigger_name).await {
+        Ok(entity_type) => entity_type,
+        Err(Error::NotFound(_)) => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e),
+    };
+
+    let query = format!(
+        "SELECT id, name FROM entity.{} WHERE id = ANY($1)",
+        escape_identifier(&entity_type)
+    );
+
+    let rows = client
+        .query(&query, &[&entity_ids])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Could not look up entities: {e}")))?;
+
+    Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+}
+
+/// Add a threshold exception for an existing entity and return the Id of the exception record
+pub async fn add_threshold_exception<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    trigger_name: &str,
+    exception: &ThresholdException,
+) -> Result<i32, Error> {
+    let entity_type = trigger_entity_type(client, trigger_name).await?;
+
+    let query = format!(
+        "SELECT id FROM entity.{} WHERE name = $1",
+        escape_identifier(&entity_type)
+    );
+
+    let rows = client
+        .query(&query, &[&exception.entity])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Could not look up entity: {e}")))?;
+
+    let entity_id: i32 = match rows.first() {
+        Some(row) => row.get(0),
+        None => {
+            return Err(Error::NotFound(NotFoundError::from_msg(format!(
+                "No {} entity found named '{}'",
+                &entity_type, &exception.entity
+            ))))
+        }
+    };
+
+    let thresholds = load_thresholds(client, trigger_name).await?;
+
+    let mut columns: Vec<String> = vec![
+        "entity_id".to_string(),
+        "start".to_string(),
+        "expires".to_string(),
+        "remark".to_string(),
+    ];
+    let mut values: Vec<String> = vec![
+        "$1".to_string(),
+        "COALESCE($2::timestamptz, '-infinity')".to_string(),
+        "COALESCE($3::timestamptz, 'infinity')".to_string(),
+        "$4::text".to_string(),
+    ];
+    let mut args: Vec<&(dyn ToSql + Sync)> = vec![
+        &entity_id,
+        &exception.start,
+        &exception.expires,
+        &exception.remark,
+    ];
+
+    for (name, value) in exception.thresholds.iter() {
+        let threshold = thresholds
+            .iter()
+            .find(|threshold| &threshold.name == name)
+            .ok_or_else(|| {
+                ConfigurationError::from_msg(format!(
+                    "Trigger '{trigger_name}' has no threshold named '{name}'"
+                ))
+            })?;
+
+        columns.push(escape_identifier(name));
+        values.push(format!(
+            "${}::text::{}",
+            args.len() + 1,
+            escape_identifier(&threshold.data_type)
+        ));
+        args.push(value);
+    }
+
+    let query = format!(
+        "INSERT INTO trigger_rule.{}({}) VALUES ({}) RETURNING id",
+        escape_identifier(&exception_table_name(trigger_name)),
+        columns.join(", "),
+        values.join(", "),
+    );
+
+    let row = client
+        .query_one(&query, &args)
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error adding threshold exception: {e}")))?;
+
+    Ok(row.get(0))
+}
+
+/// Load the threshold exceptions of a trigger, ordered by entity
+pub async fn load_threshold_exceptions<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    trigger_name: &str,
+) -> Result<Vec<ThresholdExceptionRecord>, Error> {
+    let entity_type = trigger_entity_type(client, trigger_name).await?;
+    let thresholds = load_thresholds(client, trigger_name).await?;
+
+    let threshold_columns: String = thresholds
+        .iter()
+        .map(|threshold| format!(", exc.{}::text", escape_identifier(&threshold.name)))
+        .collect();
+
+    let query = format!(
+        concat!(
+            "SELECT exc.id, e.name, exc.created, ",
+            "NULLIF(exc.start, '-infinity'), NULLIF(exc.expires, 'infinity'), exc.remark{} ",
+            "FROM trigger_rule.{} exc ",
+            "JOIN entity.{} e ON e.id = exc.entity_id ",
+            "ORDER BY e.name, exc.id"
+        ),
+        threshold_columns,
+        escape_identifier(&exception_table_name(trigger_name)),
+        escape_identifier(&entity_type),
+    );
+
+    let rows = client
+        .query(&query, &[])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error loading threshold exceptions: {e}")))?;
+
+    let exceptions = rows
+        .iter()
+        .map(|row| {
+            let exception_thresholds = thresholds
+                .iter()
+                .enumerate()
+                .filter_map(|(index, threshold)| {
+                    row.get::<usize, Option<String>>(6 + index)
+                        .map(|value| (threshold.name.clone(), value))
+                })
+                .collect();
+
+            ThresholdExceptionRecord {
+                id: row.get(0),
+                created: row.get(2),
+                exception: ThresholdException {
+                    entity: row.get(1),
+                    start: row.get(3),
+                    expires: row.get(4),
+                    remark: row.get(5),
+                    thresholds: exception_thresholds,
+                },
+            }
+        })
+        .collect();
+
+    Ok(exceptions)
+}
+
+/// Remove all threshold exceptions of a trigger for an entity and return the number removed
+pub async fn remove_threshold_exceptions<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    trigger_name: &str,
+    entity: &str,
+) -> Result<u64, Error> {
+    let entity_type = trigger_entity_type(client, trigger_name).await?;
+
+    let query = format!(
+        "DELETE FROM trigger_rule.{} exc USING entity.{} e WHERE e.id = exc.entity_id AND e.name = $1",
+        escape_identifier(&exception_table_name(trigger_name)),
+        escape_identifier(&entity_type),
+    );
+
+    client.execute(&query, &[&entity]).await.map_err(|e| {
+        Error::Database(DatabaseError::from_msg(format!(
+            "Error removing threshold exceptions: {e}"
+        )))
+    })
+}
+
+#[derive(Serialize)]
+pub struct AddThresholdException {
+    pub trigger_name: String,
+    pub exception: ThresholdException,
+}
+
+impl fmt::Display for AddThresholdException {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "AddThresholdException({}, {})",
+            &self.trigger_name, &self.exception.entity
+        )
+    }
+}
+
+#[async_trait]
+impl GenericChange for AddThresholdException {
+    async fn generic_apply<T: GenericClient + Sync + Send>(&self, client: &mut T) -> ChangeResult {
+        add_threshold_exception(client, &self.trigger_name, &self.exception).await?;
+
+        Ok(format!(
+            "Added threshold exception for '{}' to trigger '{}'",
+            &self.exception.entity, &self.trigger_name
+        ))
+    }
+}
+
+#[async_trait]
+impl Change for AddThresholdException {
+    async fn apply(&self, client: &mut Client) -> ChangeResult {
+        self.generic_apply(client).await
+    }
+}
+
+#[derive(Serialize)]
+pub struct RemoveThresholdExceptions {
+    pub trigger_name: String,
+    pub entity: String,
+}
+
+impl fmt::Display for RemoveThresholdExceptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "RemoveThresholdExceptions({}, {})",
+            &self.trigger_name, &self.entity
+        )
+    }
+}
+
+#[async_trait]
+impl GenericChange for RemoveThresholdExceptions {
+    async fn generic_apply<T: GenericClient + Sync + Send>(&self, client: &mut T) -> ChangeResult {
+        let count = remove_threshold_exceptions(client, &self.trigger_name, &self.entity).await?;
+
+        if count == 0 {
+            return Err(Error::NotFound(NotFoundError::from_msg(format!(
+                "No threshold exceptions found for '{}' in trigger '{}'",
+                &self.entity, &self.trigger_name
+            ))));
+        }
+
+        Ok(format!(
+            "Removed {} threshold exception(s) for '{}' from trigger '{}'",
+            count, &self.entity, &self.trigger_name
+        ))
+    }
+}
+
+#[async_trait]
+impl Change for RemoveThresholdExceptions {
+    async fn apply(&self, client: &mut Client) -> ChangeResult {
+        self.generic_apply(client).await
+    }
+}
@@ -0,0 +1,372 @@
+use std::fmt;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use postgres_protocol::escape::escape_identifier;
+use serde_json::json;
+use tokio_postgres::{Client, GenericClient};
+
+use super::error::{DatabaseError, Error, RuntimeError};
+use super::granularity::Granularity;
+use super::job::{end_job, start_job};
+use super::notification_incident::update_incidents;
+
+/// An enabled trigger as seen by the notification runner
+#[derive(Debug, Clone)]
+pub struct ScheduledTrigger {
+    pub id: i32,
+    pub name: String,
+    pub granularity: Granularity,
+}
+
+/// Outcome of processing one trigger timestamp
+#[derive(Debug, Clone, PartialEq)]
+pub enum TriggerRunResult {
+    /// Notifications were created, with the number of new notifications
+    Created {
+        trigger: String,
+        timestamp: DateTime<Utc>,
+        notification_count: i32,
+        job_id: i64,
+    },
+    /// The fingerprint did not change since the last run
+    Unchanged {
+        trigger: String,
+        timestamp: DateTime<Utc>,
+    },
+    Failed {
+        trigger: String,
+        timestamp: Option<DateTime<Utc>>,
+        message: String,
+    },
+}
+
+impl fmt::Display for TriggerRunResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TriggerRunResult::Created {
+                trigger,
+                timestamp,
+                notification_count,
+                job_id,
+            } => write!(
+                f,
+                "{} {}: created {} notifications (job {})",
+                trigger,
+                timestamp.to_rfc3339(),
+                notification_count,
+                job_id
+            ),
+            TriggerRunResult::Unchanged { trigger, timestamp } => {
+                write!(f, "{} {}: unchanged", trigger, timestamp.to_rfc3339())
+            }
+            TriggerRunResult::Failed {
+                trigger,
+                timestamp: Some(timestamp),
+                message,
+            } => write!(f, "{} {}: {}", trigger, timestamp.to_rfc3339(), message),
+            TriggerRunResult::Failed {
+                trigger,
+                timestamp: None,
+                message,
+            } => write!(f, "{trigger}: {message}"),
+        }
+    }
+}
+
+/// Return the timestamps that are due for a trigger at the moment `now`, oldest first
+///
+/// The most recent due timestamp is `now` truncated to the granularity. Older timestamps are
+/// included as long as they fall within the catch-up window, so that periods missed during
+/// downtime are processed after a restart. Periods are aligned in the timezone of `now`, so
+/// that daily triggers run for local days.
+pub fn due_timestamps<Tz: TimeZone>(
+    granularity: &Granularity,
+    now: &DateTime<Tz>,
+    catch_up: Duration,
+) -> Result<Vec<DateTime<Utc>>, Error> {
+    let window = chrono::Duration::from_std(catch_up).map_err(|e| {
+        RuntimeError::from_msg(format!("Unsupported catch-up window {catch_up:?}: {e}"))
+    })?;
+
+    let earliest = granularity.truncate(now)?.min(now.clone() - window);
+
+    Ok(granularity
+        .range(&earliest, now)?
+        .iter()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .collect())
+}
+
+/// Load all enabled triggers that have a granularity
+///
+/// Triggers with a granularity that cannot be parsed are skipped and reported as failed
+/// results, so that they do not prevent the other triggers from running.
+pub async fn load_scheduled_triggers<T: GenericClient + Send + Sync>(
+    client: &mut T,
+) -> Result<(Vec<ScheduledTrigger>, Vec<TriggerRunResult>), Error> {
+    let query = concat!(
+        "SELECT id, name::text, granularity::text ",
+        "FROM trigger.rule ",
+        "WHERE enabled AND granularity IS NOT NULL ",
+        "ORDER BY name"
+    );
+
+    let rows = client
+        .query(query, &[])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error loading enabled triggers: {e}")))?;
+
+    let mut triggers = Vec::new();
+    let mut skipped = Vec::new();
+
+    for row in rows {
+        let name: String = row.get(1);
+        let granularity_text: String = row.get(2);
+
+        match granularity_text.parse::<Granularity>() {
+            Ok(granularity) => triggers.push(ScheduledTrigger {
+                id: row.get(0),
+                name,
+                granularity,
+            }),
+            Err(e) => skipped.push(TriggerRunResult::Failed {
+                trigger: name,
+                timestamp: None,
+                message: format!("Skipped, invalid granularity '{granularity_text}': {e}"),
+            }),
+        }
+    }
+
+    Ok((triggers, skipped))
+}
+
+async fn trigger_fingerprint<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    trigger_name: &str,
+    timestamp: &DateTime<Utc>,
+) -> Result<Option<String>, Error> {
+    let query = format!(
+        "SELECT trigger_rule.{}($1::timestamptz)",
+        escape_identifier(&format!("{trigger_name}_fingerprint"))
+    );
+
+    let row = client.query_one(&query, &[timestamp]).await.map_err(|e| {
+        DatabaseError::from_msg(format!(
+            "Error calculating fingerprint of trigger '{trigger_name}': {e}"
+        ))
+    })?;
+
+    Ok(row.get(0))
+}
+
+async fn processed_fingerprint<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    trigger: &ScheduledTrigger,
+    timestamp: &DateTime<Utc>,
+) -> Result<Option<String>, Error> {
+    let query = "SELECT fingerprint FROM trigger.rule_state WHERE rule_id = $1 AND timestamp = $2";
+
+    let rows = client
+        .query(query, &[&trigger.id, timestamp])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error loading trigger state: {e}")))?;
+
+    Ok(rows.first().and_then(|row| row.get(0)))
+}
+
+async fn run_trigger_timestamp(
+    client: &mut Client,
+    trigger: &ScheduledTrigger,
+    timestamp: &DateTime<Utc>,
+) -> Result<TriggerRunResult, Error> {
+    let fingerprint = trigger_fingerprint(client, &trigger.name, timestamp).await?;
+
+    if fingerprint.is_some()
+        && fingerprint == processed_fingerprint(client, trigger, timestamp).await?
+    {
+        return Ok(TriggerRunResult::Unchanged {
+            trigger: trigger.name.clone(),
+            timestamp: *timestamp,
+        });
+    }
+
+    let mut transaction = client.transaction().await?;
+
+    let description = json!({
+        "create-notifications": &trigger.name,
+        "timestamp": timestamp.to_rfc3339(),
+    });
+
+    let job_id = start_job(&mut transaction, &description).await?;
+
+    let row = transaction
+        .query_one(
+            "SELECT trigger.create_notifications($1::name, $2::timestamptz)",
+            &[&trigger.name, timestamp],
+        )
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error creating notifications: {e}")))?;
+
+    let notification_count: i32 = row.try_get(0)?;
+
+    update_incidents(&mut transaction, &trigger.name, timestamp).await?;
+
+    let query = concat!(
+        "INSERT INTO trigger.rule_state(rule_id, timestamp, fingerprint, processed, notification_count, job_id) ",
+        "VALUES ($1, $2, $3, now(), $4, $5) ",
+        "ON CONFLICT (rule_id, timestamp) DO UPDATE SET ",
+        "fingerprint = EXCLUDED.fingerprint, processed = EXCLUDED.processed, ",
+        "notification_count = EXCLUDED.notification_count, job_id = EXCLUDED.job_id"
+    );
+
+    transaction
+        .execute(
+            query,
+            &[
+                &trigger.id,
+                timestamp,
+                &fingerprint,
+                &notification_count,
+                &job_id,
+            ],
+        )
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error storing trigger state: {e}")))?;
+
+    end_job(&mut transaction, job_id).await?;
+
+    transaction.commit().await?;
+
+    Ok(TriggerRunResult::Created {
+        trigger: trigger.name.clone(),
+        timestamp: *timestamp,
+        notification_count,
+        job_id,
+    })
+}
+
+/// Create notifications for all due timestamps of one trigger
+///
+/// Each timestamp is processed in its own transaction, so a failure for one timestamp does not
+/// prevent the others from being processed.
+pub async fn run_trigger<Tz: TimeZone>(
+    client: &mut Client,
+    trigger: &ScheduledTrigger,
+    now: &DateTime<Tz>,
+    catch_up: Duration,
+) -> Vec<TriggerRunResult> {
+    let timestamps = match due_timestamps(&trigger.granularity, now, catch_up) {
+        Ok(timestamps) => timestamps,
+        Err(e) => {
+            return vec![TriggerRunResult::Failed {
+                trigger: trigger.name.clone(),
+                timestamp: None,
+                message: e.to_string(),
+            }]
+        }
+    };
+
+    let mut results = Vec::new();
+
+    for timestamp in timestamps {
+        let result = run_trigger_timestamp(client, trigger, &timestamp)
+            .await
+            .unwrap_or_else(|e| TriggerRunResult::Failed {
+                trigger: trigger.name.clone(),
+                timestamp: Some(timestamp),
+                message: e.to_string(),
+            });
+
+        results.push(result);
+    }
+
+    results
+}
+
+/// Create notifications for all due timestamps of all enabled triggers
+pub async fn run_enabled_triggers<Tz: TimeZone>(
+    client: &mut Client,
+    now: &DateTime<Tz>,
+    catch_up: Duration,
+) -> Result<Vec<TriggerRunResult>, Error> {
+    let (triggers, mut results) = load_scheduled_triggers(client).await?;
+
+    for trigger in triggers {
+        results.extend(run_trigger(client, &trigger, now, catch_up).await);
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::FixedOffset;
+
+    use super::*;
+
+    #[test]
+    fn due_timestamps_without_catch_up() {
+        let now = Utc.with_ymd_and_hms(2023, 3, 25, 14, 7, 12).unwrap();
+
+        let timestamps =
+            due_timestamps(&"15m".parse().unwrap(), &now, Duration::from_secs(0)).unwrap();
+
+        assert_eq!(
+            timestamps,
+            vec![Utc.with_ymd_and_hms(2023, 3, 25, 14, 0, 0).unwrap()]
+        );
+    }
+
+    #[test]
+    fn due_timestamps_with_catch_up() {
+        let now = Utc.with_ymd_and_hms(2023, 3, 25, 14, 7, 12).unwrap();
+
+        let timestamps =
+            due_timestamps(&"1h".parse().unwrap(), &now, Duration::from_secs(3 * 3600)).unwrap();
+
+        assert_eq!(
+            timestamps,
+            vec![
+                Utc.with_ymd_and_hms(2023, 3, 25, 12, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2023, 3, 25, 13, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2023, 3, 25, 14, 0, 0).unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn due_timestamps_calendar_months() {
+        let now = Utc.with_ymd_and_hms(2023, 3, 25, 14, 7, 12).unwrap();
+
+        let timestamps = due_timestamps(
+            &Granularity::Months(1),
+            &now,
+            Duration::from_secs(60 * 86400),
+        )
+        .unwrap();
+
+        assert_eq!(
+            timestamps,
+            vec![
+                Utc.with_ymd_and_hms(2023, 2, 1, 0, 0, 0).unwrap(),
+                Utc.with_ymd_and_hms(2023, 3, 1, 0, 0, 0).unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn due_timestamps_in_timezone() {
+        let timezone = FixedOffset::east_opt(2 * 3600).unwrap();
+        let now = timezone.with_ymd_and_hms(2023, 3, 25, 1, 30, 0).unwrap();
+
+        let timestamps =
+            due_timestamps(&"1d".parse().unwrap(), &now, Duration::from_secs(0)).unwrap();
+
+        // Local midnight, which is before midnight in UTC
+        assert_eq!(
+            timestamps,
+            vec![Utc.with_ymd_and_hms(2023, 3, 24, 22, 0, 0).unwrap()]
+        );
+    }
+}
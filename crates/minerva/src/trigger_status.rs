@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use postgres_protocol::escape::escape_identifier;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::GenericClient;
+
+use super::error::{DatabaseError, Error, RuntimeError};
+use super::granularity::Granularity;
+
+/// Selection of triggers to report on
+#[derive(Debug, Clone, Default)]
+pub struct TriggerStatusFilter {
+    /// Only triggers with this tag
+    pub tag: Option<String>,
+    /// Only enabled or only disabled triggers
+    pub enabled: Option<bool>,
+    /// Only triggers with these Ids
+    pub ids: Option<Vec<i32>>,
+}
+
+/// Number of notifications a trigger created for one period
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PeriodNotificationCount {
+    pub timestamp: DateTime<Utc>,
+    pub count: i64,
+}
+
+/// Definition summary and runtime information of a trigger
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TriggerStatus {
+    pub id: i32,
+    pub name: String,
+    pub enabled: bool,
+    pub description: Option<String>,
+    pub granularity: Option<String>,
+    pub default_interval: Option<String>,
+    pub notification_store: Option<String>,
+    pub tags: Vec<String>,
+    pub trend_store_parts: Vec<String>,
+    /// Most recent timestamp processed by the notification runner
+    pub last_evaluated: Option<DateTime<Utc>>,
+    /// Time at which the most recent timestamp was processed
+    pub last_processed: Option<DateTime<Utc>>,
+    /// Timestamp of the most recent notification of the trigger
+    pub last_notification: Option<DateTime<Utc>>,
+    /// Notifications per period for the most recent periods, oldest first
+    pub notification_counts: Vec<PeriodNotificationCount>,
+}
+
+/// Maximum number of recent periods that notifications can be counted for
+pub const MAX_PERIODS: u32 = 1000;
+
+/// Return the starts of the `count` most recent periods up to and including the one that
+/// contains `now`, oldest first
+pub fn recent_periods(
+    granularity: &Granularity,
+    now: &DateTime<Utc>,
+    count: u32,
+) -> Result<Vec<DateTime<Utc>>, Error> {
+    if count > MAX_PERIODS {
+        return Err(Error::Runtime(RuntimeError::from_msg(format!(
+            "Number of periods {count} exceeds the maximum of {MAX_PERIODS}"
+        ))));
+    }
+
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+
+    let first = granularity.offset(now, 1 - count as i32)?;
+
+    granularity.range(&first, now)
+}
+
+/// Load the status of all triggers matching the filter, ordered by name
+///
+/// Notifications are counted for the `periods` most recent periods of the trigger granularity
+/// up to `now`.
+pub async fn load_trigger_status<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    filter: &TriggerStatusFilter,
+    now: &DateTime<Utc>,
+    periods: u32,
+) -> Result<Vec<TriggerStatus>, Error> {
+    let query = concat!(
+        "SELECT r.id, r.name::text, r.enabled, r.description, r.granularity::text, ",
+        "r.default_interval::text, ds.name::text, ",
+        "array(SELECT t.name::text FROM trigger.rule_tag_link tl ",
+        "JOIN directory.tag t ON t.id = tl.tag_id WHERE tl.rule_id = r.id ORDER BY t.name), ",
+        "array(SELECT tsp.name::text FROM trigger.rule_trend_store_link l ",
+        "JOIN trend_directory.trend_store_part tsp ON tsp.id = l.trend_store_part_id ",
+        "WHERE l.rule_id = r.id ORDER BY tsp.name), ",
+        "rs.timestamp, rs.processed, ",
+        "notification_directory.table_name(ns)::text ",
+        "FROM trigger.rule r ",
+        "LEFT JOIN notification_directory.notification_store ns ON ns.id = r.notification_store_id ",
+        "LEFT JOIN directory.data_source ds ON ds.id = ns.data_source_id ",
+        "LEFT JOIN LATERAL (",
+        "SELECT timestamp, processed FROM trigger.rule_state ",
+        "WHERE rule_id = r.id ORDER BY timestamp DESC LIMIT 1",
+        ") rs ON true ",
+        "WHERE ($1::bool IS NULL OR r.enabled = $1) ",
+        "AND ($2::text IS NULL OR EXISTS (",
+        "SELECT 1 FROM trigger.rule_tag_link tl JOIN directory.tag t ON t.id = tl.tag_id ",
+        "WHERE tl.rule_id = r.id AND t.name = $2)) ",
+        "AND ($3::int[] IS NULL OR r.id = ANY($3)) ",
+        "ORDER BY r.name"
+    );
+
+    let rows = client
+        .query(query, &[&filter.enabled, &filter.tag, &filter.ids])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error loading triggers: {e}")))?;
+
+    let mut triggers = Vec::new();
+    // Trigger indexes per notification store table, to count notifications per table
+    let mut tables: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+
+    for row in &rows {
+        let table_name: Option<String> = row.try_get(11)?;
+
+        if let Some(table_name) = table_name {
+            tables.entry(table_name).or_default().push(triggers.len());
+        }
+
+        triggers.push(TriggerStatus {
+            id: row.try_get(0)?,
+            name: row.try_get(1)?,
+            enabled: row.try_get(2)?,
+            description: row.try_get(3)?,
+            granularity: row.try_get(4)?,
+            default_interval: row.try_get(5)?,
+            notification_store: row.try_get(6)?,
+            tags: row.try_get(7)?,
+            trend_store_parts: row.try_get(8)?,
+            last_evaluated: row.try_get(9)?,
+            last_processed: row.try_get(10)?,
+            last_notification: None,
+            notification_counts: Vec::new(),
+        });
+    }
+
+    // Triggers without a granularity that is understood get no counts
+    let period_starts: Vec<Vec<DateTime<Utc>>> = triggers
+        .iter()
+        .map(|trigger| {
+            trigger
+                .granularity
+                .as_ref()
+                .and_then(|granularity| granularity.parse::<Granularity>().ok())
+                .map(|granularity| recent_periods(&granularity, now, periods))
+                .transpose()
+                .map(Option::unwrap_or_default)
+        })
+        .collect::<Result<_, Error>>()?;
+
+    for (table_name, indexes) in tables {
+        let rule_ids: Vec<i32> = indexes.iter().map(|i| triggers[*i].id).collect();
+        let timestamps: Vec<DateTime<Utc>> = indexes
+            .iter()
+            .flat_map(|i| period_starts[*i].iter().cloned())
+            .collect();
+
+        let table = format!("notification.{}", escape_identifier(&table_name));
+
+        let last_rows = client
+            .query(
+                &format!(
+                    "SELECT rule_id, max(timestamp) FROM {table} WHERE rule_id = ANY($1) GROUP BY rule_id"
+                ),
+                &[&rule_ids],
+            )
+            .await
+            .map_err(|e| {
+                DatabaseError::from_msg(format!("Error loading last notifications: {e}"))
+            })?;
+
+        let last_notifications: BTreeMap<i32, DateTime<Utc>> = last_rows
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+
+        let count_rows = client
+            .query(
+                &format!(
+                    concat!(
+                        "SELECT rule_id, timestamp, count(*) FROM {} ",
+                        "WHERE rule_id = ANY($1) AND timestamp = ANY($2) ",
+                        "GROUP BY rule_id, timestamp"
+                    ),
+                    table
+                ),
+                &[&rule_ids, &timestamps],
+            )
+            .await
+            .map_err(|e| DatabaseError::from_msg(format!("Error counting notifications: {e}")))?;
+
+        let counts: BTreeMap<(i32, DateTime<Utc>), i64> = count_rows
+            .iter()
+            .map(|row| ((row.get(0), row.get(1)), row.get(2)))
+            .collect();
+
+        for i in indexes {
+            let trigger = &mut triggers[i];
+
+            trigger.last_notification = last_notifications.get(&trigger.id).copied();
+            trigger.notification_counts = period_starts[i]
+                .iter()
+                .map(|timestamp| PeriodNotificationCount {
+                    timestamp: *timestamp,
+                    count: counts.get(&(trigger.id, *timestamp)).copied().unwrap_or(0),
+                })
+                .collect();
+        }
+    }
+
+    Ok(triggers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_periods_end_at_current_period() {
+        let granularity: Granularity = "15m".parse().unwrap();
+        let now: DateTime<Utc> = "2023-03-25T14:20:00Z".parse().unwrap();
+
+        let periods = recent_periods(&granularity, &now, 3).unwrap();
+
+        assert_eq!(
+            periods,
+            vec![
+                "2023-03-25T13:45:00Z".parse::<DateTime<Utc>>().unwrap(),
+                "2023-03-25T14:00:00Z".parse::<DateTime<Utc>>().unwrap(),
+                "2023-03-25T14:15:00Z".parse::<DateTime<Utc>>().unwrap(),
+            ]
+        );
+        assert!(recent_periods(&granularity, &now, 0).unwrap().is_empty());
+        assert_eq!(
+            recent_periods(&granularity, &now, MAX_PERIODS)
+                .unwrap()
+                .len(),
+            MAX_PERIODS as usize
+        );
+        assert!(recent_periods(&granularity, &now, u32::MAX).is_err());
+    }
+}
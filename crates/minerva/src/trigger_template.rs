@@ -0,0 +1,246 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use super::error::{ConfigurationError, Error};
+use super::trigger::Trigger;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TemplateParameter {
+    pub name: String,
+    /// Value used for instances that do not specify the parameter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// A trigger definition with `{{ parameter }}` placeholders and the list of parameter sets to
+/// instantiate it with
+///
+/// Placeholders can be used in any string value of the trigger definition, including the SQL
+/// fragments.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TriggerTemplate {
+    pub parameters: Vec<TemplateParameter>,
+    pub instances: Vec<BTreeMap<String, String>>,
+    pub trigger: Value,
+}
+
+fn placeholder_regex() -> Regex {
+    Regex::new(r"\{\{\s*([A-Za-z_][A-Za-z0-9_]*)\s*\}\}").unwrap()
+}
+
+fn substitute(
+    value: &Value,
+    placeholder_re: &Regex,
+    parameters: &BTreeMap<String, String>,
+) -> Result<Value, Error> {
+    match value {
+        Value::String(text) => {
+            let mut unknown: Option<String> = None;
+
+            let substituted = placeholder_re.replace_all(text, |caps: &Captures| match parameters
+                .get(&caps[1])
+            {
+                Some(value) => value.clone(),
+                None => {
+                    unknown.get_or_insert_with(|| caps[1].to_string());
+                    caps[0].to_string()
+                }
+            });
+
+            match unknown {
+                Some(name) => Err(Error::Configuration(ConfigurationError::from_msg(format!(
+                    "Undeclared template parameter '{name}'"
+                )))),
+                None => Ok(Value::String(substituted.into_owned())),
+            }
+        }
+        Value::Sequence(items) => Ok(Value::Sequence(
+            items
+                .iter()
+                .map(|item| substitute(item, placeholder_re, parameters))
+                .collect::<Result<Vec<Value>, Error>>()?,
+        )),
+        Value::Mapping(mapping) => {
+            let mut result = serde_yaml::Mapping::new();
+
+            for (key, value) in mapping {
+                result.insert(key.clone(), substitute(value, placeholder_re, parameters)?);
+            }
+
+            Ok(Value::Mapping(result))
+        }
+        value => Ok(value.clone()),
+    }
+}
+
+impl TriggerTemplate {
+    /// Combine the values of an instance with the parameter defaults
+    fn instance_parameters(
+        &self,
+        instance: &BTreeMap<String, String>,
+    ) -> Result<BTreeMap<String, String>, Error> {
+        if let Some(name) = instance
+            .keys()
+            .find(|name| !self.parameters.iter().any(|p| &p.name == *name))
+        {
+            return Err(Error::Configuration(ConfigurationError::from_msg(format!(
+                "Unknown template parameter '{name}'"
+            ))));
+        }
+
+        self.parameters
+            .iter()
+            .map(
+                |parameter| match instance.get(&parameter.name).or(parameter.default.as_ref()) {
+                    Some(value) => Ok((parameter.name.clone(), value.clone())),
+                    None => Err(Error::Configuration(ConfigurationError::from_msg(format!(
+                        "No value for template parameter '{}'",
+                        &parameter.name
+                    )))),
+                },
+            )
+            .collect()
+    }
+
+    /// Return the trigger definitions for all instances of the template
+    pub fn expand(&self) -> Result<Vec<Trigger>, Error> {
+        let placeholder_re = placeholder_regex();
+
+        self.instances
+            .iter()
+            .enumerate()
+            .map(|(index, instance)| {
+                let parameters = self.instance_parameters(instance).map_err(|e| {
+                    ConfigurationError::from_msg(format!("Template instance {}: {e}", index + 1))
+                })?;
+
+                let value =
+                    substitute(&self.trigger, &placeholder_re, &parameters).map_err(|e| {
+                        ConfigurationError::from_msg(format!(
+                            "Template instance {}: {e}",
+                            index + 1
+                        ))
+                    })?;
+
+                serde_yaml::from_value(value).map_err(|e| {
+                    Error::Configuration(ConfigurationError::from_msg(format!(
+                        "Template instance {} is not a valid trigger definition: {e}",
+                        index + 1
+                    )))
+                })
+            })
+            .collect()
+    }
+}
+
+pub fn load_trigger_template_from_file(path: &Path) -> Result<TriggerTemplate, Error> {
+    let f = std::fs::File::open(path).map_err(|e| {
+        ConfigurationError::from_msg(format!(
+            "Could not open trigger template file '{}': {}",
+            path.display(),
+            e
+        ))
+    })?;
+
+    serde_yaml::from_reader(f).map_err(|e| {
+        Error::Configuration(ConfigurationError::from_msg(format!(
+            "Could not read trigger template from file '{}': {}",
+            path.display(),
+            e
+        )))
+    })
+}
+
+/// Load a trigger template and return all triggers it expands to
+pub fn load_triggers_from_template_file(path: &Path) -> Result<Vec<Trigger>, Error> {
+    load_trigger_template_from_file(path)?
+        .expand()
+        .map_err(|e| {
+            Error::Configuration(ConfigurationError::from_msg(format!(
+                "Could not expand trigger template '{}': {}",
+                path.display(),
+                e
+            )))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = r#"
+parameters:
+  - name: granularity
+  - name: max_power
+    default: "0.05"
+instances:
+  - granularity: 15m
+  - granularity: 1h
+    max_power: 0.2
+trigger:
+  name: node/{{ granularity }}/highpowerusage
+  kpi_data:
+    - name: power_kwh
+      data_type: numeric
+  kpi_function: SELECT 1
+  thresholds:
+    - name: max_power
+      data_type: numeric
+      value: "{{max_power}}"
+  condition: power_kwh > max_power
+  weight: SELECT 1
+  notification: SELECT 'HighPowerUsage'
+  data: SELECT '{}'::json
+  tags: []
+  fingerprint: SELECT now()::text
+  notification_store: trigger-notification
+  trend_store_links:
+    - part_name: hub_node_main_15m
+      mapping_function: mapping_id
+  mapping_functions: []
+  granularity: "{{ granularity }}"
+  description: High power usage per {{ granularity }}
+"#;
+
+    #[test]
+    fn expand_template() {
+        let template: TriggerTemplate = serde_yaml::from_str(TEMPLATE).unwrap();
+
+        let triggers = template.expand().unwrap();
+
+        assert_eq!(triggers.len(), 2);
+        assert_eq!(triggers[0].name, "node/15m/highpowerusage");
+        assert_eq!(triggers[0].thresholds[0].value, "0.05");
+        assert_eq!(triggers[0].granularity.as_secs(), 900);
+        assert_eq!(triggers[1].name, "node/1h/highpowerusage");
+        assert_eq!(triggers[1].thresholds[0].value, "0.2");
+        assert_eq!(triggers[1].description, "High power usage per 1h");
+    }
+
+    #[test]
+    fn missing_and_unknown_parameters() {
+        let mut template: TriggerTemplate = serde_yaml::from_str(TEMPLATE).unwrap();
+
+        template.instances = vec![BTreeMap::new()];
+        assert!(template.expand().is_err());
+
+        template.instances = vec![BTreeMap::from([
+            ("granularity".to_string(), "15m".to_string()),
+            ("part".to_string(), "x".to_string()),
+        ])];
+        assert!(template.expand().is_err());
+
+        template.instances = vec![BTreeMap::from([(
+            "granularity".to_string(),
+            "15m".to_string(),
+        )])];
+        template.parameters.retain(|p| p.name != "max_power");
+        assert!(template.expand().is_err());
+    }
+}
@@ -0,0 +1,510 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use postgres_protocol::escape::escape_identifier;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio_postgres::{Client, GenericClient};
+
+use super::change::GenericChange;
+use super::error::{ConfigurationError, DatabaseError, Error};
+use super::job::start_job;
+use super::materialization_test::{
+    compare_rows, DataRow, RowDifference, ENTITY_COLUMN, FIXTURE_DEFINITION_FILE, TIMESTAMP_COLUMN,
+};
+use super::trigger::{evaluate_trigger, AddTrigger, DeleteTrigger, Trigger};
+use super::trigger_exception::trigger_entity_type;
+
+/// Trend data for one of the trend store parts linked to the trigger
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TriggerFixtureData {
+    pub part: String,
+    /// CSV file with an entity column, a timestamp column and a column for each trend
+    pub file: PathBuf,
+    #[serde(default = "default_entity_column")]
+    pub entity_column: String,
+    #[serde(default = "default_timestamp_column")]
+    pub timestamp_column: String,
+    #[serde(default)]
+    pub null_value: String,
+}
+
+fn default_entity_column() -> String {
+    ENTITY_COLUMN.to_string()
+}
+
+fn default_timestamp_column() -> String {
+    TIMESTAMP_COLUMN.to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ExpectedNotification {
+    pub entity: String,
+    pub timestamp: DateTime<Utc>,
+    pub weight: i32,
+    pub details: String,
+    pub data: Value,
+}
+
+/// Definition of the data used to test a trigger and the notifications it should produce
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TriggerFixture {
+    pub data: Vec<TriggerFixtureData>,
+    /// SQL scripts to run after loading the data, e.g. to fill attribute stores
+    #[serde(default)]
+    pub setup: Vec<PathBuf>,
+    pub timestamps: Vec<DateTime<Utc>>,
+    pub expected: Vec<ExpectedNotification>,
+}
+
+pub fn load_trigger_fixture_from_dir(fixture_dir: &Path) -> Result<TriggerFixture, Error> {
+    let path = fixture_dir.join(FIXTURE_DEFINITION_FILE);
+
+    let f = std::fs::File::open(&path).map_err(|e| {
+        ConfigurationError::from_msg(format!(
+            "Could not open fixture definition file '{}': {}",
+            path.display(),
+            e
+        ))
+    })?;
+
+    serde_yaml::from_reader(f).map_err(|e| {
+        ConfigurationError::from_msg(format!(
+            "Could not read trigger fixture definition from file '{}': {}",
+            path.display(),
+            e
+        ))
+        .into()
+    })
+}
+
+/// Columns of a notification that are compared, besides the entity and timestamp
+fn notification_columns() -> Vec<String> {
+    vec![
+        "weight".to_string(),
+        "details".to_string(),
+        "data".to_string(),
+    ]
+}
+
+fn notification_row(
+    entity: String,
+    timestamp: DateTime<Utc>,
+    weight: i32,
+    details: Option<String>,
+    data: Option<Value>,
+) -> DataRow {
+    DataRow {
+        entity,
+        timestamp,
+        values: vec![
+            Some(weight.to_string()),
+            details,
+            // Serializing the parsed JSON makes the comparison independent of formatting
+            data.map(|data| data.to_string()),
+        ],
+    }
+}
+
+impl ExpectedNotification {
+    fn to_row(&self) -> DataRow {
+        notification_row(
+            self.entity.clone(),
+            self.timestamp,
+            self.weight,
+            Some(self.details.clone()),
+            Some(self.data.clone()),
+        )
+    }
+}
+
+async fn part_entity_type<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    trend_store_part: &str,
+) -> Result<String, Error> {
+    let row = client
+        .query_one(
+            concat!(
+                "SELECT et.name FROM trend_directory.trend_store_part tsp ",
+                "JOIN trend_directory.trend_store ts ON ts.id = tsp.trend_store_id ",
+                "JOIN directory.entity_type et ON et.id = ts.entity_type_id ",
+                "WHERE tsp.name = $1"
+            ),
+            &[&trend_store_part],
+        )
+        .await
+        .map_err(|e| {
+            DatabaseError::from_msg(format!(
+                "Could not find trend store part '{trend_store_part}': {e}"
+            ))
+        })?;
+
+    Ok(row.get(0))
+}
+
+/// Replace the data of a trend store part for the timestamps in the fixture file with the data
+/// from that file
+async fn load_fixture_data<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    data: &TriggerFixtureData,
+    fixture_dir: &Path,
+    job_id: i64,
+) -> Result<(), Error> {
+    let path = fixture_dir.join(&data.file);
+
+    let mut reader = csv::Reader::from_path(&path).map_err(|e| {
+        ConfigurationError::from_msg(format!(
+            "Could not open fixture data file '{}': {}",
+            path.display(),
+            e
+        ))
+    })?;
+
+    let headers: Vec<String> = reader
+        .headers()
+        .map_err(|e| {
+            ConfigurationError::from_msg(format!(
+                "Could not read header of fixture data file '{}': {}",
+                path.display(),
+                e
+            ))
+        })?
+        .iter()
+        .map(String::from)
+        .collect();
+
+    let find_column = |name: &str| {
+        headers.iter().position(|h| h == name).ok_or_else(|| {
+            ConfigurationError::from_msg(format!(
+                "No '{name}' column in fixture data file '{}'",
+                path.display()
+            ))
+        })
+    };
+
+    let entity_index = find_column(&data.entity_column)?;
+    let timestamp_index = find_column(&data.timestamp_column)?;
+
+    let trend_indexes: Vec<usize> = (0..headers.len())
+        .filter(|i| *i != entity_index && *i != timestamp_index)
+        .collect();
+
+    let mut records: Vec<(String, DateTime<Utc>, Value)> = Vec::new();
+
+    for record in reader.records() {
+        let record = record.map_err(|e| {
+            ConfigurationError::from_msg(format!(
+                "Could not read record from fixture data file '{}': {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+        let timestamp_txt = record.get(timestamp_index).unwrap_or_default();
+
+        let timestamp = DateTime::parse_from_rfc3339(timestamp_txt)
+            .map_err(|e| {
+                ConfigurationError::from_msg(format!(
+                    "Invalid timestamp '{timestamp_txt}' in fixture data file: {e}"
+                ))
+            })?
+            .with_timezone(&Utc);
+
+        let values: serde_json::Map<String, Value> = trend_indexes
+            .iter()
+            .map(|i| {
+                let value = match record.get(*i) {
+                    Some(value) if value != data.null_value => json!(value),
+                    _ => Value::Null,
+                };
+
+                (headers[*i].clone(), value)
+            })
+            .collect();
+
+        records.push((
+            record.get(entity_index).unwrap_or_default().to_string(),
+            timestamp,
+            Value::Object(values),
+        ));
+    }
+
+    let entity_type = part_entity_type(client, &data.part).await?;
+    let part_table = escape_identifier(&data.part);
+
+    let timestamps: BTreeSet<DateTime<Utc>> = records.iter().map(|record| record.1).collect();
+
+    for timestamp in &timestamps {
+        let query = concat!(
+            "SELECT trend_directory.create_partition(tsp, $2::timestamptz) ",
+            "FROM trend_directory.trend_store_part tsp ",
+            "JOIN trend_directory.trend_store ts ON ts.id = tsp.trend_store_id ",
+            "WHERE tsp.name = $1 AND NOT EXISTS (",
+            "SELECT 1 FROM trend_directory.partition p ",
+            "WHERE p.trend_store_part_id = tsp.id ",
+            "AND p.index = trend_directory.timestamp_to_index(ts.partition_size, $2::timestamptz))"
+        );
+
+        client
+            .execute(query, &[&data.part, timestamp])
+            .await
+            .map_err(|e| DatabaseError::from_msg(format!("Error creating partition: {e}")))?;
+    }
+
+    let timestamps: Vec<DateTime<Utc>> = timestamps.into_iter().collect();
+
+    client
+        .execute(
+            &format!("DELETE FROM trend.{part_table} WHERE timestamp = ANY($1)"),
+            &[&timestamps],
+        )
+        .await
+        .map_err(|e| {
+            DatabaseError::from_msg(format!(
+                "Error removing existing data from '{}': {e}",
+                &data.part
+            ))
+        })?;
+
+    let trend_columns: Vec<String> = trend_indexes
+        .iter()
+        .map(|i| escape_identifier(&headers[*i]))
+        .collect();
+
+    let insert_query = format!(
+        concat!(
+            "INSERT INTO trend.{}(entity_id, timestamp, created, job_id{}) ",
+            "SELECT $1, $2, now(), $3{} ",
+            "FROM json_populate_record(NULL::trend.{}, $4) r"
+        ),
+        part_table,
+        trend_columns
+            .iter()
+            .map(|c| format!(", {c}"))
+            .collect::<String>(),
+        trend_columns
+            .iter()
+            .map(|c| format!(", r.{c}"))
+            .collect::<String>(),
+        part_table,
+    );
+
+    let entity_query = format!(
+        "INSERT INTO entity.{}(name) VALUES ($1) ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name RETURNING id",
+        escape_identifier(&entity_type)
+    );
+
+    let mut entity_ids: BTreeMap<String, i32> = BTreeMap::new();
+
+    for (entity, timestamp, values) in &records {
+        let entity_id = match entity_ids.get(entity) {
+            Some(entity_id) => *entity_id,
+            None => {
+                let row = client
+                    .query_one(&entity_query, &[entity])
+                    .await
+                    .map_err(|e| {
+                        DatabaseError::from_msg(format!("Could not create entity '{entity}': {e}"))
+                    })?;
+
+                entity_ids.insert(entity.clone(), row.get(0));
+
+                row.get(0)
+            }
+        };
+
+        client
+            .execute(&insert_query, &[&entity_id, timestamp, &job_id, values])
+            .await
+            .map_err(|e| {
+                DatabaseError::from_msg(format!(
+                    "Error loading fixture data into '{}' for '{}' at {}: {e}",
+                    &data.part,
+                    entity,
+                    timestamp.to_rfc3339()
+                ))
+            })?;
+    }
+
+    Ok(())
+}
+
+async fn load_notifications<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    trigger_name: &str,
+    entity_type: &str,
+    timestamps: &[DateTime<Utc>],
+) -> Result<Vec<DataRow>, Error> {
+    let rows = evaluate_trigger(client, trigger_name, entity_type, timestamps).await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| notification_row(row.get(0), row.get(1), row.get(2), row.get(3), row.get(4)))
+        .collect())
+}
+
+async fn run_in_transaction<T: GenericClient + Send + Sync>(
+    client: &mut T,
+    trigger: &Trigger,
+    fixture_dir: &Path,
+    fixture: &TriggerFixture,
+) -> Result<Vec<RowDifference>, Error> {
+    for data in &fixture.data {
+        if !trigger
+            .trend_store_links
+            .iter()
+            .any(|link| link.part_name == data.part)
+        {
+            return Err(Error::Configuration(ConfigurationError::from_msg(format!(
+                "Trend store part '{}' of the fixture data is not linked to trigger '{}'",
+                &data.part, &trigger.name
+            ))));
+        }
+    }
+
+    let row = client
+        .query_one(
+            "SELECT count(*) FROM trigger.rule WHERE name = $1",
+            &[&trigger.name],
+        )
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error checking for trigger: {e}")))?;
+
+    let count: i64 = row.get(0);
+
+    // The definition under test replaces an existing version of the trigger
+    if count > 0 {
+        DeleteTrigger {
+            trigger_name: trigger.name.clone(),
+        }
+        .generic_apply(client)
+        .await?;
+    }
+
+    AddTrigger {
+        trigger: trigger.clone(),
+        verify: false,
+        enable: false,
+    }
+    .generic_apply(client)
+    .await?;
+
+    let job_id = start_job(client, &json!({"trigger-test": &trigger.name})).await?;
+
+    for data in &fixture.data {
+        load_fixture_data(client, data, fixture_dir, job_id).await?;
+    }
+
+    for setup_file in &fixture.setup {
+        let path = fixture_dir.join(setup_file);
+
+        let sql = std::fs::read_to_string(&path).map_err(|e| {
+            ConfigurationError::from_msg(format!(
+                "Could not read setup file '{}': {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+        client.batch_execute(&sql).await.map_err(|e| {
+            DatabaseError::from_msg(format!(
+                "Error running setup file '{}': {}",
+                path.display(),
+                e
+            ))
+        })?;
+    }
+
+    let entity_type = trigger_entity_type(client, &trigger.name).await?;
+
+    let actual_rows =
+        load_notifications(client, &trigger.name, &entity_type, &fixture.timestamps).await?;
+
+    let expected_rows: Vec<DataRow> = fixture
+        .expected
+        .iter()
+        .map(ExpectedNotification::to_row)
+        .collect();
+
+    Ok(compare_rows(
+        &notification_columns(),
+        &expected_rows,
+        &actual_rows,
+        0.0,
+    ))
+}
+
+/// Test the notifications of a trigger against fixture data
+///
+/// The trigger is created, the fixture data is loaded into the linked trend store parts and the
+/// notification function of the trigger is called for the fixture timestamps, all in one
+/// transaction that is rolled back afterwards. The differences with the expected notifications
+/// are returned, so an empty list means the test passed.
+pub async fn test_trigger(
+    client: &mut Client,
+    trigger: &Trigger,
+    fixture_dir: &Path,
+) -> Result<Vec<RowDifference>, Error> {
+    let fixture = load_trigger_fixture_from_dir(fixture_dir)?;
+
+    let mut transaction = client.transaction().await?;
+
+    let result = run_in_transaction(&mut transaction, trigger, fixture_dir, &fixture).await;
+
+    transaction.rollback().await?;
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_comparison_ignores_formatting() {
+        let timestamp = DateTime::parse_from_rfc3339("2023-03-25T14:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        let fixture: TriggerFixture = serde_yaml::from_str(concat!(
+            "data: []\n",
+            "timestamps: [2023-03-25T14:00:00Z]\n",
+            "expected:\n",
+            "  - entity: hillside14\n",
+            "    timestamp: 2023-03-25T14:00:00Z\n",
+            "    weight: 100\n",
+            "    details: '55.8'\n",
+            "    data: {power_kwh: 55.8, max_power: 0.05}\n",
+        ))
+        .unwrap();
+
+        let expected: Vec<DataRow> = fixture.expected.iter().map(|n| n.to_row()).collect();
+
+        let actual = vec![notification_row(
+            "hillside14".to_string(),
+            timestamp,
+            100,
+            Some("55.8".to_string()),
+            serde_json::from_str(r#"{"max_power":0.05,  "power_kwh":55.8}"#).ok(),
+        )];
+
+        assert!(compare_rows(&notification_columns(), &expected, &actual, 0.0).is_empty());
+
+        let actual = vec![notification_row(
+            "hillside14".to_string(),
+            timestamp,
+            50,
+            Some("55.8".to_string()),
+            serde_json::from_str(r#"{"max_power":0.05,"power_kwh":55.8}"#).ok(),
+        )];
+
+        assert_eq!(
+            compare_rows(&notification_columns(), &expected, &actual, 0.0)
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<String>>(),
+            vec!["~ hillside14 2023-03-25T14:00:00+00:00 weight: expected 100, got 50"]
+        );
+    }
+}
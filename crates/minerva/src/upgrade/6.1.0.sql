@@ -0,0 +1,316 @@
+-- Upgrade of a Minerva database from schema version 6.0.0 to 6.1.0
+--
+-- Statements are written so that they can also be applied to databases that
+-- were created from a development version of the 6.1.0 schema.
+
+CREATE OR REPLACE FUNCTION "system"."version"()
+    RETURNS system.version_tuple
+AS $$
+SELECT (6,1,0)::system.version_tuple;
+$$ LANGUAGE sql IMMUTABLE;
+
+
+-- Manually disabled materializations
+
+CREATE TABLE IF NOT EXISTS "trend_directory"."materialization_disable"
+(
+  "materialization_id" integer NOT NULL,
+  "reason" text,
+  "disabled_at" timestamp with time zone NOT NULL DEFAULT now(),
+  "expires" timestamp with time zone,
+  PRIMARY KEY (materialization_id),
+  CONSTRAINT "materialization_disable_materialization_id_fkey"
+    FOREIGN KEY (materialization_id)
+    REFERENCES "trend_directory"."materialization" (id) ON DELETE CASCADE
+);
+
+COMMENT ON TABLE "trend_directory"."materialization_disable" IS 'Reason and optional expiry of materializations that have been disabled
+manually. Materializations with an expired record are enabled again by
+``trend_directory.enable_expired_materializations``.';
+
+COMMENT ON COLUMN "trend_directory"."materialization_disable"."materialization_id" IS 'The ID of the disabled materialization';
+
+COMMENT ON COLUMN "trend_directory"."materialization_disable"."expires" IS 'The moment after which the materialization is enabled again, or NULL to keep it disabled';
+
+GRANT SELECT ON TABLE "trend_directory"."materialization_disable" TO minerva;
+
+GRANT INSERT,UPDATE,DELETE ON TABLE "trend_directory"."materialization_disable" TO minerva_writer;
+
+CREATE OR REPLACE FUNCTION "trend_directory"."enable_expired_materializations"()
+    RETURNS SETOF text
+AS $$
+WITH expired AS (
+    DELETE FROM trend_directory.materialization_disable
+    WHERE expires <= now()
+    RETURNING materialization_id
+)
+UPDATE trend_directory.materialization
+SET enabled = true
+FROM expired
+WHERE materialization.id = expired.materialization_id
+RETURNING materialization::text;
+$$ LANGUAGE sql VOLATILE;
+
+COMMENT ON FUNCTION "trend_directory"."enable_expired_materializations"() IS 'Enable all materializations of which the disable period has expired and return their names.';
+
+
+-- Per-entity threshold exceptions of trigger rules
+
+CREATE OR REPLACE FUNCTION "trigger"."drop_exception_threshold_table_sql"(trigger.rule)
+    RETURNS text
+AS $$
+SELECT format('DROP TABLE IF EXISTS trigger_rule.%I CASCADE', trigger.exception_threshold_table_name($1))
+$$ LANGUAGE sql IMMUTABLE;
+
+
+-- Scheduled notification creation state of trigger rules
+
+CREATE TABLE IF NOT EXISTS "trigger"."rule_state"
+(
+  "rule_id" integer NOT NULL,
+  "timestamp" timestamp with time zone NOT NULL,
+  "fingerprint" text,
+  "processed" timestamp with time zone NOT NULL DEFAULT now(),
+  "notification_count" integer NOT NULL DEFAULT 0,
+  "job_id" bigint,
+  PRIMARY KEY (rule_id, "timestamp"),
+  CONSTRAINT "rule_state_rule_id_fkey"
+    FOREIGN KEY (rule_id)
+    REFERENCES "trigger"."rule" (id) ON DELETE CASCADE
+);
+
+COMMENT ON TABLE "trigger"."rule_state" IS 'Processing state of scheduled notification creation per trigger rule and
+timestamp. A timestamp is processed again when the fingerprint of the rule
+for that timestamp differs from the stored fingerprint.';
+
+COMMENT ON COLUMN "trigger"."rule_state"."fingerprint" IS 'Result of the fingerprint function of the rule at the time of processing';
+
+COMMENT ON COLUMN "trigger"."rule_state"."job_id" IS 'The ID of the logging job of the most recent run for this timestamp';
+
+GRANT SELECT ON TABLE "trigger"."rule_state" TO minerva;
+
+GRANT INSERT,UPDATE,DELETE ON TABLE "trigger"."rule_state" TO minerva_writer;
+
+
+-- Notification incidents
+
+DO $$
+BEGIN
+    CREATE TYPE "trigger"."incident_status" AS ENUM (
+      'open',
+      'acknowledged',
+      'closed'
+    );
+EXCEPTION
+    WHEN duplicate_object THEN NULL;
+END
+$$;
+
+CREATE TABLE IF NOT EXISTS "trigger"."incident"
+(
+  "id" serial NOT NULL,
+  "rule_id" integer NOT NULL,
+  "entity_id" integer NOT NULL,
+  "first_timestamp" timestamp with time zone NOT NULL,
+  "last_timestamp" timestamp with time zone NOT NULL,
+  "notification_count" integer NOT NULL DEFAULT 1,
+  "status" trigger.incident_status NOT NULL DEFAULT 'open',
+  "assignee" text,
+  "comment" text,
+  "created" timestamp with time zone NOT NULL DEFAULT now(),
+  "modified" timestamp with time zone NOT NULL DEFAULT now(),
+  "closed" timestamp with time zone,
+  PRIMARY KEY (id),
+  CONSTRAINT "incident_rule_id_fkey"
+    FOREIGN KEY (rule_id)
+    REFERENCES "trigger"."rule" (id) ON DELETE CASCADE
+);
+
+COMMENT ON TABLE "trigger"."incident" IS 'Notifications of a rule for the same entity at consecutive timestamps,
+combined into one incident that can be acknowledged and closed. A closed
+incident is never extended, a new notification after closing starts a new
+incident.';
+
+COMMENT ON COLUMN "trigger"."incident"."last_timestamp" IS 'Timestamp of the most recent notification that is part of the incident';
+
+CREATE INDEX IF NOT EXISTS "incident_rule_id_entity_id_idx" ON "trigger"."incident" USING btree (rule_id, entity_id);
+
+CREATE INDEX IF NOT EXISTS "incident_status_idx" ON "trigger"."incident" USING btree (status);
+
+GRANT SELECT ON TABLE "trigger"."incident" TO minerva;
+
+GRANT INSERT,UPDATE,DELETE ON TABLE "trigger"."incident" TO minerva_writer;
+
+
+-- Notification forwarding progress
+
+CREATE TABLE IF NOT EXISTS "notification_directory"."forward_state"
+(
+  "sink" text NOT NULL,
+  "notification_store_id" integer NOT NULL,
+  "last_notification_id" integer NOT NULL,
+  "modified" timestamp with time zone NOT NULL DEFAULT now(),
+  PRIMARY KEY (sink),
+  CONSTRAINT "forward_state_notification_store_id_fkey"
+    FOREIGN KEY (notification_store_id)
+    REFERENCES "notification_directory"."notification_store" (id) ON DELETE CASCADE
+);
+
+COMMENT ON TABLE "notification_directory"."forward_state" IS 'Progress of forwarding notifications to external sinks. Each sink
+forwards the notifications of one notification_store in order of Id and
+resumes after the last forwarded notification.';
+
+COMMENT ON COLUMN "notification_directory"."forward_state"."last_notification_id" IS 'Id of the most recent notification that was forwarded or skipped by the sink';
+
+GRANT SELECT ON TABLE "notification_directory"."forward_state" TO minerva;
+
+GRANT INSERT,UPDATE,DELETE ON TABLE "notification_directory"."forward_state" TO minerva_writer;
+
+
+-- Audit log of configuration changes
+
+CREATE TABLE IF NOT EXISTS "logging"."audit"
+(
+  "id" bigserial NOT NULL,
+  "timestamp" timestamp with time zone NOT NULL DEFAULT clock_timestamp(),
+  "actor" text NOT NULL,
+  "db_user" text NOT NULL DEFAULT session_user,
+  "source" text NOT NULL,
+  "change" text NOT NULL,
+  "payload" jsonb,
+  "success" boolean NOT NULL,
+  "message" text,
+  PRIMARY KEY (id)
+);
+
+COMMENT ON TABLE "logging"."audit" IS 'Every configuration change applied through the Minerva admin tools, successful or not.';
+
+COMMENT ON COLUMN "logging"."audit"."actor" IS 'User on behalf of which the change was applied: the OS user for the CLI or the authenticated user for the service';
+
+COMMENT ON COLUMN "logging"."audit"."source" IS 'Tool that applied the change: ''cli'' or ''service''';
+
+COMMENT ON COLUMN "logging"."audit"."message" IS 'Result message of the change or the error when it failed';
+
+CREATE INDEX IF NOT EXISTS "audit_timestamp_idx" ON "logging"."audit" USING btree ("timestamp");
+
+GRANT SELECT ON TABLE "logging"."audit" TO minerva;
+
+REVOKE UPDATE,DELETE ON TABLE "logging"."audit" FROM minerva_writer;
+
+GRANT INSERT ON TABLE "logging"."audit" TO minerva_writer;
+
+
+-- Events on the 'minerva_event' channel
+
+CREATE OR REPLACE FUNCTION "logging"."notify_change"()
+    RETURNS trigger
+AS $$
+BEGIN
+    IF NEW.success THEN
+        PERFORM pg_notify('minerva_event', json_build_object(
+            'type', 'change',
+            'subject', left(NEW.change, 1000),
+            'audit_id', NEW.id,
+            'actor', NEW.actor,
+            'source', NEW.source,
+            'timestamp', NEW.timestamp
+        )::text);
+    END IF;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql VOLATILE;
+
+COMMENT ON FUNCTION "logging"."notify_change"() IS 'Send a notification on the ''minerva_event'' channel for a successfully applied change.';
+
+DROP TRIGGER IF EXISTS notify_change_on_audit ON "logging"."audit";
+
+CREATE TRIGGER notify_change_on_audit
+  AFTER INSERT ON "logging"."audit"
+  FOR EACH ROW
+  EXECUTE PROCEDURE "logging"."notify_change"();
+
+CREATE OR REPLACE FUNCTION "trend_directory"."materialize"("materialization_id" integer, "timestamp" timestamp with time zone)
+    RETURNS trend_directory.transfer_result
+AS $$
+DECLARE
+    mat trend_directory.materialization;
+    start timestamp with time zone;
+    duration interval;
+    columns_part text;
+    result trend_directory.transfer_result;
+BEGIN
+    -- Enable materializations of which the disable period has expired, so that
+    -- this does not depend on a service running periodically
+    PERFORM trend_directory.enable_expired_materializations();
+
+    SELECT * FROM trend_directory.materialization WHERE id = $1 INTO mat;
+
+    start = clock_timestamp();
+
+    -- Remove all records in the target table for the timestamp to materialize
+    PERFORM trend_directory.clear_trend_store_part(
+        mat.dst_trend_store_part_id, $2
+    );
+
+    result.row_count = trend_directory.transfer($1, $2);
+
+    -- Update the state of this materialization
+    UPDATE trend_directory.materialization_state vms
+    SET processed_fingerprint = vms.source_fingerprint
+    WHERE vms.materialization_id = $1 AND vms.timestamp = $2;
+
+    -- Log the change in the target trend store part
+    PERFORM trend_directory.mark_modified(mat.dst_trend_store_part_id, $2, now());
+
+    duration = clock_timestamp() - start;
+
+    UPDATE trend_directory.materialization_metrics
+    SET execution_count = execution_count + 1, total_duration = total_duration + duration
+    WHERE materialization_metrics.materialization_id = $1;
+
+    PERFORM pg_notify('minerva_event', json_build_object(
+        'type', 'materialization',
+        'subject', trend_store_part.name,
+        'materialization_id', $1,
+        'timestamp', $2,
+        'row_count', result.row_count,
+        'duration', duration
+    )::text)
+    FROM trend_directory.trend_store_part
+    WHERE trend_store_part.id = mat.dst_trend_store_part_id;
+
+    RETURN result;
+END;
+$$ LANGUAGE plpgsql VOLATILE;
+
+CREATE OR REPLACE FUNCTION "trigger"."create_notifications"(trigger.rule, notification_directory.notification_store, timestamp with time zone)
+    RETURNS integer
+AS $$
+DECLARE
+    num_rows integer;
+BEGIN
+    EXECUTE format(
+$query$
+INSERT INTO notification.%I(entity_id, timestamp, created, rule_id, weight, details, data)
+(SELECT entity_id, timestamp, now(), $1, weight, details, data FROM trigger_rule.%I($2) WHERE data IS NOT NULL)
+$query$,
+        notification_directory.staging_table_name($2), trigger.notification_fn_name($1)
+    )
+    USING $1.id, $3;
+
+    SELECT trigger.transfer_notifications_from_staging($2) INTO num_rows;
+
+    IF num_rows > 0 THEN
+        PERFORM pg_notify('minerva_event', json_build_object(
+            'type', 'notification',
+            'subject', $1.name,
+            'notification_store', notification_directory.table_name($2),
+            'timestamp', $3,
+            'count', num_rows
+        )::text);
+    END IF;
+
+    RETURN num_rows;
+END;
+$$ LANGUAGE plpgsql VOLATILE;
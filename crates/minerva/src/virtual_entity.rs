@@ -6,7 +6,7 @@ use serde::{Deserialize, Serialize};
 use tokio_postgres::Client;
 
 use super::change::{Change, ChangeResult};
-use super::error::{ConfigurationError, DatabaseError, Error};
+use super::error::{ConfigurationError, DatabaseError, Error, NotFoundError};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct VirtualEntity {
@@ -44,6 +44,7 @@ pub fn load_virtual_entity_from_file(path: &PathBuf) -> Result<VirtualEntity, Er
     Ok(virtual_entity)
 }
 
+#[derive(Serialize)]
 pub struct AddVirtualEntity {
     pub virtual_entity: VirtualEntity,
 }
@@ -73,3 +74,77 @@ impl From<VirtualEntity> for AddVirtualEntity {
         AddVirtualEntity { virtual_entity }
     }
 }
+
+/// Load the views of the virtual entities in the database
+///
+/// Only the view definition is available, not the full SQL it was created with.
+pub async fn load_virtual_entities(client: &mut Client) -> Result<Vec<VirtualEntity>, Error> {
+    let query = concat!(
+        "SELECT viewname::text, definition FROM pg_views ",
+        "WHERE schemaname = 'virtual_entity' ORDER BY viewname"
+    );
+
+    let rows = client
+        .query(query, &[])
+        .await
+        .map_err(|e| DatabaseError::from_msg(format!("Error loading virtual entities: {e}")))?;
+
+    Ok(rows
+        .iter()
+        .map(|row| {
+            let name: String = row.get(0);
+            let definition: String = row.get(1);
+
+            VirtualEntity {
+                sql: format!("CREATE OR REPLACE VIEW virtual_entity.\"{name}\" AS\n{definition}"),
+                name,
+            }
+        })
+        .collect())
+}
+
+/// Remove the view of a virtual entity
+///
+/// The entity type and its entities are kept, because data may still refer to them.
+#[derive(Serialize)]
+pub struct DeleteVirtualEntity {
+    pub name: String,
+}
+
+impl fmt::Display for DeleteVirtualEntity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DeleteVirtualEntity({})", &self.name)
+    }
+}
+
+#[async_trait]
+impl Change for DeleteVirtualEntity {
+    async fn apply(&self, client: &mut Client) -> ChangeResult {
+        let row = client
+            .query_one(
+                "SELECT count(*) FROM pg_views WHERE schemaname = 'virtual_entity' AND viewname = $1",
+                &[&self.name],
+            )
+            .await
+            .map_err(|e| {
+                DatabaseError::from_msg(format!("Error checking for virtual entity: {e}"))
+            })?;
+
+        let count: i64 = row.get(0);
+
+        if count == 0 {
+            return Err(Error::NotFound(NotFoundError::from_msg(format!(
+                "No virtual entity found with name '{}'",
+                &self.name
+            ))));
+        }
+
+        let query = format!("DROP VIEW virtual_entity.\"{}\"", self.name);
+
+        client.execute(&query, &[]).await.map_err(|e| {
+            DatabaseError::from_msg(format!("Error dropping virtual entity view: {e}"))
+        })?;
+
+        Ok(format!("Deleted virtual entity '{}'", &self.name))
+    }
+}